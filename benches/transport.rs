@@ -0,0 +1,71 @@
+//! Compares the cost of carrying out a session over a connection stored as a
+//! `tokio_util::either::Either` (as `confab` did before version 0.4.0) versus
+//! a boxed trait object (as `confab` does now).
+//!
+//! Each iteration sends a batch of lines through a `Framed` wrapping the
+//! connection and reads them back out of the other end of an in-memory pipe.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{duplex, AsyncRead, AsyncWrite, DuplexStream};
+use tokio::runtime::Builder;
+use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::either::Either;
+
+/// Mirror of the `Transport` trait in `src/transport.rs`
+trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+const LINE: &str = "The quick brown fox jumps over the lazy dog.";
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(conn: S, peer: DuplexStream, lines: u64) {
+    let mut ours = Framed::new(conn, LinesCodec::new());
+    let mut theirs = Framed::new(peer, LinesCodec::new());
+    let send = async {
+        for _ in 0..lines {
+            ours.feed(LINE).await.expect("sending should succeed");
+        }
+        SinkExt::<&str>::flush(&mut ours)
+            .await
+            .expect("flushing should succeed");
+    };
+    let recv = async {
+        for _ in 0..lines {
+            assert!(
+                matches!(theirs.next().await, Some(Ok(_))),
+                "receiving should succeed"
+            );
+        }
+    };
+    tokio::join!(send, recv);
+}
+
+fn bench_transport(c: &mut Criterion) {
+    let rt = Builder::new_current_thread()
+        .build()
+        .expect("building the runtime should succeed");
+    let mut group = c.benchmark_group("transport");
+    for lines in [1, 100, 10_000] {
+        group.throughput(Throughput::Elements(lines));
+        group.bench_with_input(BenchmarkId::new("either", lines), &lines, |b, &n| {
+            b.to_async(&rt).iter(|| async move {
+                let (conn, peer) = duplex(BUFFER_SIZE);
+                let conn: Either<DuplexStream, DuplexStream> = Either::Left(conn);
+                exchange(conn, peer, n).await;
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("boxed", lines), &lines, |b, &n| {
+            b.to_async(&rt).iter(|| async move {
+                let (conn, peer) = duplex(BUFFER_SIZE);
+                let conn: Box<dyn Transport> = Box::new(conn);
+                exchange(conn, peer, n).await;
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_transport);
+criterion_main!(benches);
@@ -0,0 +1,728 @@
+use crate::errors::InterfaceError;
+use crate::events::{fmt_ms, no_banner_message, repeat_summary_message, GAP_MARKER};
+use crate::repeat::Latency;
+use crate::sniff::BinaryProtocol;
+use crate::stats::{Counter, Rate, StatsSnapshot, Throughput};
+use crate::util::{chomp, display_vis, HMS_FMT};
+use anyhow::Context;
+use confab::transcript::{self, ClosedBy, Direction, Event};
+use regex::Regex;
+use std::fmt;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use time::OffsetDateTime;
+use unicode_width::UnicodeWidthStr;
+
+/// What `confab transcript` does with a transcript
+#[derive(Clone, Debug)]
+pub(crate) enum Operation {
+    /// Summarize the session's traffic & response times
+    Stats,
+    /// Show only the events whose messages match a regex
+    Grep { regex: Regex, show_times: bool },
+    /// Show every event as the console would have
+    ToText { show_times: bool },
+}
+
+/// Perform an operation on the transcript at `path`, writing the results to
+/// `out`
+pub(crate) fn run<W: Write>(path: &Path, op: &Operation, mut out: W) -> anyhow::Result<()> {
+    let mut stats = TranscriptStats::default();
+    let mut renderer = TextRenderer::default();
+    for ev in transcript::read(path) {
+        let ev = ev.with_context(|| format!("failed to process {}", path.display()))?;
+        match op {
+            Operation::Stats => stats.observe(&ev),
+            Operation::Grep { regex, show_times } => {
+                if let Some(msg) = renderer.render(&ev) {
+                    if regex.is_match(&msg.text) {
+                        writeln!(out, "{}", msg.display(*show_times))
+                            .map_err(InterfaceError::Write)?;
+                    }
+                }
+            }
+            Operation::ToText { show_times } => {
+                if let Some(msg) = renderer.render(&ev) {
+                    writeln!(out, "{}", msg.display(*show_times)).map_err(InterfaceError::Write)?;
+                }
+            }
+        }
+    }
+    if matches!(op, Operation::Stats) {
+        write!(out, "{stats}").map_err(InterfaceError::Write)?;
+    }
+    out.flush().map_err(InterfaceError::Write)?;
+    Ok(())
+}
+
+/// Compare the lines sent & received in two transcripts, writing the aligned
+/// sequences to `out` with the lines that appear in only one transcript
+/// marked with `-` (for `old`) or `+` (for `new`).  Returns `true` if the
+/// transcripts differ.
+pub(crate) fn diff<W: Write>(old: &Path, new: &Path, mut out: W) -> anyhow::Result<bool> {
+    let old_lines = traffic(old)?;
+    let new_lines = traffic(new)?;
+    let mut differ = false;
+    writeln!(out, "--- {}", old.display()).map_err(InterfaceError::Write)?;
+    writeln!(out, "+++ {}", new.display()).map_err(InterfaceError::Write)?;
+    for r in diff::slice(&old_lines, &new_lines) {
+        let (mark, line) = match r {
+            diff::Result::Both(line, _) => (' ', line),
+            diff::Result::Left(line) => ('-', line),
+            diff::Result::Right(line) => ('+', line),
+        };
+        differ |= mark != ' ';
+        writeln!(out, "{mark} {line}").map_err(InterfaceError::Write)?;
+    }
+    out.flush().map_err(InterfaceError::Write)?;
+    Ok(differ)
+}
+
+/// Return the lines sent & received in the transcript at `path` as console
+/// messages without timestamps
+fn traffic(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut renderer = TextRenderer::default();
+    let mut lines = Vec::new();
+    for ev in transcript::read(path) {
+        let ev = ev.with_context(|| format!("failed to process {}", path.display()))?;
+        let is_traffic = matches!(ev, Event::Send { .. } | Event::Recv { .. });
+        if let Some(msg) = renderer.render(&ev).filter(|_| is_traffic) {
+            lines.push(msg.display(false));
+        }
+    }
+    Ok(lines)
+}
+
+/// A transcript event rendered as a console message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Message {
+    timestamp: OffsetDateTime,
+    sigil: char,
+    /// The text of the message, without any timestamp or sigil.  Lines after
+    /// the first are continuation lines.
+    text: String,
+}
+
+impl Message {
+    fn display(&self, show_times: bool) -> String {
+        let mut prefix = String::new();
+        if show_times {
+            let time = self
+                .timestamp
+                .format(&HMS_FMT)
+                .expect("formatting a datetime as HMS should not fail");
+            prefix = format!("[{time}] ");
+        }
+        prefix.push(self.sigil);
+        prefix.push(' ');
+        let indent = prefix.width();
+        let mut s = prefix;
+        for (i, line) in self.text.split('\n').enumerate() {
+            if i > 0 {
+                // Line up continuation lines with the start of the message
+                s.push('\n');
+                s.push_str(&" ".repeat(indent));
+            }
+            s.push_str(line);
+        }
+        s
+    }
+}
+
+/// Renders transcript events the way `confab --verbose` displays the
+/// corresponding events on the console
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct TextRenderer {
+    /// The port from the most recent "connection-start" event, used to show
+    /// the peer's full address on connecting
+    port: Option<u16>,
+    /// Whether the last "recv" event was broken off at `--max-line-length`
+    partial: bool,
+}
+
+impl TextRenderer {
+    /// Render an event as a message.  Returns `None` for events of types
+    /// unknown to this version of `confab`.
+    pub(crate) fn render(&mut self, ev: &Event) -> Option<Message> {
+        let (sigil, text) = match ev {
+            Event::SessionStart { confab_version, .. } => {
+                ('*', format!("Starting confab {confab_version}"))
+            }
+            Event::ConnectionStart { port, .. } => {
+                self.port = Some(*port);
+                ('*', String::from("Connecting ..."))
+            }
+            Event::ListenStart { ip, port, .. } => (
+                '*',
+                format!("Listening on {} ...", SocketAddr::from((*ip, *port))),
+            ),
+            Event::Accepted {
+                peer_ip, peer_port, ..
+            } => (
+                '*',
+                format!(
+                    "Accepted connection from {}",
+                    SocketAddr::from((*peer_ip, *peer_port))
+                ),
+            ),
+            Event::SerialOpened { device, baud, .. } => {
+                ('*', format!("Opened {device} at {baud} baud"))
+            }
+            Event::ProcessStarted { command, pid, .. } => {
+                let command = command.join(" ");
+                let msg = match pid {
+                    Some(pid) => format!("Started {command} (PID {pid})"),
+                    None => format!("Started {command}"),
+                };
+                ('*', msg)
+            }
+            Event::DnsResolved {
+                host,
+                addresses,
+                overridden,
+                ..
+            } => {
+                let addrs = addresses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let source = if *overridden { " (from --resolve)" } else { "" };
+                ('*', format!("Resolved {host} to {addrs}{source}"))
+            }
+            Event::ConnectAttempt { ip, port, .. } => (
+                '*',
+                format!("Trying {} ...", SocketAddr::from((*ip, *port))),
+            ),
+            Event::ConnectFailed { ip, port, data, .. } => (
+                '!',
+                format!(
+                    "Failed to connect to {}: {data}",
+                    SocketAddr::from((*ip, *port))
+                ),
+            ),
+            Event::ConnectionComplete {
+                peer_ip,
+                tcp_nodelay,
+                tcp_keepalive,
+                ttl,
+                ..
+            } => {
+                let mut msg = match self.port {
+                    Some(port) => format!("Connected to {}", SocketAddr::from((*peer_ip, port))),
+                    None => format!("Connected to {peer_ip}"),
+                };
+                let mut opts = Vec::new();
+                if *tcp_nodelay {
+                    opts.push(String::from("TCP_NODELAY"));
+                }
+                if let Some(keepalive) = tcp_keepalive {
+                    opts.push(format!("keepalive {keepalive}s"));
+                }
+                if let Some(ttl) = ttl {
+                    opts.push(format!("TTL {ttl}"));
+                }
+                if !opts.is_empty() {
+                    msg.push_str(" (");
+                    msg.push_str(&opts.join(", "));
+                    msg.push(')');
+                }
+                ('*', msg)
+            }
+            Event::TlsStart { .. } => ('*', String::from("Initializing TLS ...")),
+            Event::PreTlsData { data, .. } => (
+                '*',
+                format!("Received before TLS: {}", plain_vis(chomp(data))),
+            ),
+            Event::CertificateTrusted { fingerprint, .. } => (
+                '*',
+                format!("Trusting server certificate on first use: {fingerprint}"),
+            ),
+            Event::TlsComplete { resumed, .. } => {
+                let msg = if *resumed == Some(true) {
+                    "TLS established (resumed session)"
+                } else {
+                    "TLS established"
+                };
+                ('*', String::from(msg))
+            }
+            Event::Timing {
+                dns_ms,
+                connect_ms,
+                tls_ms,
+                total_ms,
+                ..
+            } => {
+                let mut msg = format!(
+                    "Timing: DNS {}, connect {}",
+                    fmt_ms(millis(*dns_ms)),
+                    fmt_ms(millis(*connect_ms))
+                );
+                if let Some(tls) = tls_ms {
+                    msg.push_str(", TLS ");
+                    msg.push_str(&fmt_ms(millis(*tls)));
+                }
+                msg.push_str(", total ");
+                msg.push_str(&fmt_ms(millis(*total_ms)));
+                ('*', msg)
+            }
+            Event::Recv { data, partial, .. } => {
+                let mut text = plain_vis(chomp(data));
+                // Mark where a line was split at `--max-line-length`, as the
+                // console does
+                if self.partial {
+                    text.insert_str(0, GAP_MARKER);
+                }
+                if *partial {
+                    text.push_str(GAP_MARKER);
+                }
+                self.partial = *partial;
+                ('<', text)
+            }
+            Event::Send { data, .. } => ('>', plain_vis(chomp(data))),
+            Event::ShutdownWrite { .. } => (
+                '*',
+                String::from("Shut down sending; waiting for the server to close the connection"),
+            ),
+            Event::BudgetExceeded {
+                direction, limit, ..
+            } => {
+                let what = match direction {
+                    Direction::Send => "Send",
+                    Direction::Recv => "Receive",
+                };
+                (
+                    '*',
+                    format!("{what} budget of {limit} bytes exhausted; closing connection"),
+                )
+            }
+            Event::SendTooLong {
+                length,
+                limit,
+                refused,
+                ..
+            } => {
+                let mut msg =
+                    format!("Line of {length} bytes exceeds maximum send length of {limit}");
+                if *refused {
+                    msg.push_str("; not sent");
+                }
+                ('*', msg)
+            }
+            Event::LinesHidden { count, .. } => {
+                ('*', format!("Received lines not displayed: {count}"))
+            }
+            Event::MemoryEvicted { count, .. } => (
+                '*',
+                format!("Items discarded to stay under memory cap: {count}"),
+            ),
+            Event::EncodingError { offset, bytes, .. } => (
+                '!',
+                format!("Received invalid UTF-8 sequence {bytes} at byte {offset} of line"),
+            ),
+            Event::ProtocolMismatch { protocol, .. } => (
+                '!',
+                match BinaryProtocol::from_name(protocol) {
+                    Some(proto) => String::from(proto.warning()),
+                    None => format!("Received data looks like {protocol}, not lines of text"),
+                },
+            ),
+            Event::NoBanner { wait_ms, .. } => ('!', no_banner_message(millis(*wait_ms))),
+            Event::Throughput {
+                window_ms,
+                lines_sent_per_sec,
+                bytes_sent_per_sec,
+                lines_recv_per_sec,
+                bytes_recv_per_sec,
+                ..
+            } => {
+                let throughput = Throughput {
+                    sent: Rate {
+                        lines_per_sec: *lines_sent_per_sec,
+                        bytes_per_sec: *bytes_sent_per_sec,
+                    },
+                    recv: Rate {
+                        lines_per_sec: *lines_recv_per_sec,
+                        bytes_per_sec: *bytes_recv_per_sec,
+                    },
+                };
+                (
+                    '*',
+                    format!(
+                        "Throughput over the last {}s: received {}; sent {}",
+                        millis(*window_ms).as_secs(),
+                        throughput.recv,
+                        throughput.sent
+                    ),
+                )
+            }
+            Event::RepeatSummary {
+                data,
+                sent,
+                responses,
+                latency_min_ms,
+                latency_avg_ms,
+                latency_p95_ms,
+                ..
+            } => {
+                let latency = match (latency_min_ms, latency_avg_ms, latency_p95_ms) {
+                    (Some(min), Some(avg), Some(p95)) => Some(Latency {
+                        min: millis(*min),
+                        avg: millis(*avg),
+                        p95: millis(*p95),
+                    }),
+                    _ => None,
+                };
+                (
+                    '*',
+                    repeat_summary_message(data, *sent, *responses, latency),
+                )
+            }
+            Event::SettingChanged { setting, value, .. } => {
+                ('*', format!("Set {setting} to {value}"))
+            }
+            Event::Annotation { data, .. } => ('*', format!("Note: {data}")),
+            Event::SessionStats {
+                lines_sent,
+                bytes_sent,
+                lines_recv,
+                bytes_recv,
+                duration_ms,
+                idle_ms,
+                ..
+            } => {
+                let stats = StatsSnapshot {
+                    sent: Counter {
+                        lines: *lines_sent,
+                        bytes: *bytes_sent,
+                    },
+                    recv: Counter {
+                        lines: *lines_recv,
+                        bytes: *bytes_recv,
+                    },
+                    duration: millis(*duration_ms),
+                    idle: idle_ms.map(millis),
+                };
+                ('*', stats.to_string())
+            }
+            Event::Disconnect {
+                closed_by,
+                duration_ms,
+                lines_sent,
+                lines_recv,
+                ..
+            } => {
+                let by = match closed_by {
+                    Some(ClosedBy::Peer) => " by peer",
+                    _ => "",
+                };
+                let msg = match (duration_ms, lines_sent, lines_recv) {
+                    (Some(duration_ms), Some(lines_sent), Some(lines_recv)) => format!(
+                        "Disconnected{by} after {:.1}s (sent {lines_sent} lines, received {lines_recv} lines)",
+                        millis(*duration_ms).as_secs_f64()
+                    ),
+                    // Transcripts written before these fields were introduced
+                    _ => format!("Disconnected{by}"),
+                };
+                ('*', msg)
+            }
+            Event::Error { data, details, .. } => {
+                let mut text = data.clone();
+                for line in details {
+                    text.push_str("\n  ");
+                    text.push_str(line);
+                }
+                ('!', text)
+            }
+            _ => return None,
+        };
+        Some(Message {
+            timestamp: ev.timestamp(),
+            sigil,
+            text,
+        })
+    }
+}
+
+/// Summary statistics for a transcript, as shown by `confab transcript
+/// --stats`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct TranscriptStats {
+    events: u64,
+    first: Option<OffsetDateTime>,
+    last: Option<OffsetDateTime>,
+    sent: Counter,
+    recv: Counter,
+    errors: u64,
+    /// Time of the first line sent since a line was last received
+    awaiting: Option<OffsetDateTime>,
+    /// Time between each run of sent lines and the next line received
+    latencies: Vec<Duration>,
+}
+
+impl TranscriptStats {
+    /// Upper bounds of the latency histogram's buckets.  Latencies at or
+    /// above the last bound are counted in a final bucket.
+    const BUCKETS: [(Duration, &'static str); 5] = [
+        (Duration::from_millis(1), "< 1ms"),
+        (Duration::from_millis(10), "< 10ms"),
+        (Duration::from_millis(100), "< 100ms"),
+        (Duration::from_secs(1), "< 1s"),
+        (Duration::from_secs(10), "< 10s"),
+    ];
+
+    /// Maximum width of a histogram bar
+    const BAR_WIDTH: usize = 40;
+
+    pub(crate) fn observe(&mut self, ev: &Event) {
+        let timestamp = ev.timestamp();
+        self.events += 1;
+        self.first.get_or_insert(timestamp);
+        self.last = Some(timestamp);
+        match ev {
+            Event::Send { data, .. } => {
+                self.sent.lines += 1;
+                self.sent.bytes += bytes(data);
+                self.awaiting.get_or_insert(timestamp);
+            }
+            Event::Recv { data, partial, .. } => {
+                if !partial {
+                    self.recv.lines += 1;
+                }
+                self.recv.bytes += bytes(data);
+                if let Some(sent) = self.awaiting.take() {
+                    self.latencies.push(since(sent, timestamp));
+                }
+            }
+            Event::Error { .. } => self.errors += 1,
+            _ => (),
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => since(first, last),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Count the latencies in each bucket of the histogram
+    fn histogram(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = Self::BUCKETS.map(|(_, label)| (label, 0)).to_vec();
+        counts.push((">= 10s", 0));
+        for &lat in &self.latencies {
+            let i = Self::BUCKETS
+                .iter()
+                .position(|&(bound, _)| lat < bound)
+                .unwrap_or(Self::BUCKETS.len());
+            counts[i].1 += 1;
+        }
+        counts
+    }
+}
+
+impl fmt::Display for TranscriptStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Events:    {}", self.events)?;
+        writeln!(f, "Duration:  {:.1}s", self.duration().as_secs_f64())?;
+        writeln!(
+            f,
+            "Sent:      {} lines ({} bytes)",
+            self.sent.lines, self.sent.bytes
+        )?;
+        writeln!(
+            f,
+            "Received:  {} lines ({} bytes)",
+            self.recv.lines, self.recv.bytes
+        )?;
+        writeln!(f, "Errors:    {}", self.errors)?;
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let (Some(min), Some(max)) = (latencies.first(), latencies.last()) else {
+            return writeln!(f, "Latency:   no replies");
+        };
+        let median = latencies[latencies.len() / 2];
+        writeln!(f, "Replies:   {}", latencies.len())?;
+        writeln!(
+            f,
+            "Latency:   min {}, median {}, max {}",
+            fmt_ms(*min),
+            fmt_ms(median),
+            fmt_ms(*max)
+        )?;
+        let histogram = self.histogram();
+        let most = histogram.iter().map(|&(_, n)| n).max().unwrap_or(1);
+        for (label, n) in histogram {
+            let bar = "#".repeat(n * Self::BAR_WIDTH / most);
+            writeln!(f, "{}", format!("  {label:<8} {n:>6} {bar}").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Return `s` with nonprintable characters replaced by visible
+/// representations, as on the console but without styling
+fn plain_vis(s: &str) -> String {
+    display_vis(s)
+        .iter()
+        .map(|chunk| chunk.content().as_str())
+        .collect()
+}
+
+/// Convert a (possibly invalid) number of milliseconds from a transcript to a
+/// `Duration`
+fn millis(ms: f64) -> Duration {
+    Duration::try_from_secs_f64(ms / 1000.0).unwrap_or_default()
+}
+
+/// Return the time from `start` to `end`, or zero if `end` is earlier
+fn since(start: OffsetDateTime, end: OffsetDateTime) -> Duration {
+    Duration::try_from(end - start).unwrap_or_default()
+}
+
+fn bytes(data: &str) -> u64 {
+    u64::try_from(data.len()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TRANSCRIPT: &str = concat!(
+        r#"{"timestamp": "2024-05-01T12:34:56.000000-04:00", "event": "connection-start", "format_version": 3, "host": "localhost", "port": 8080}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:56.002000-04:00", "event": "connection-complete", "peer_ip": "127.0.0.1", "tcp_nodelay": true}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:56.005000-04:00", "event": "recv", "data": "Welcome\r\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:57.000000-04:00", "event": "send", "data": "Hello\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:57.000500-04:00", "event": "send", "data": "Again\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:57.050000-04:00", "event": "recv", "data": "You sent: \"Hello\"\u0007\r\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:57.060000-04:00", "event": "recv", "data": "You sent: \"Again\"\r\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:58.000000-04:00", "event": "annotation", "data": "bug here"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:58.500000-04:00", "event": "error", "data": "failed to receive line from server", "details": ["Connection reset"]}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:59.000000-04:00", "event": "disconnect", "closed_by": "peer", "duration_ms": 2998.0, "lines_sent": 2, "lines_recv": 3}"#,
+        "\n",
+    );
+
+    fn write_transcript(src: &str) -> tempfile::NamedTempFile {
+        let mut tmpfile = tempfile::NamedTempFile::new().unwrap();
+        tmpfile.write_all(src.as_bytes()).unwrap();
+        tmpfile.flush().unwrap();
+        tmpfile
+    }
+
+    fn run_on(op: &Operation) -> String {
+        let tmpfile = write_transcript(TRANSCRIPT);
+        let mut out = Vec::new();
+        run(tmpfile.path(), op, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(
+            run_on(&Operation::ToText { show_times: false }),
+            concat!(
+                "* Connecting ...\n",
+                "* Connected to 127.0.0.1:8080 (TCP_NODELAY)\n",
+                "< Welcome\n",
+                "> Hello\n",
+                "> Again\n",
+                "< You sent: \"Hello\"^G\n",
+                "< You sent: \"Again\"\n",
+                "* Note: bug here\n",
+                "! failed to receive line from server\n",
+                "    Connection reset\n",
+                "* Disconnected by peer after 3.0s (sent 2 lines, received 3 lines)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_grep() {
+        let op = Operation::Grep {
+            regex: Regex::new(r#"^You sent: "\w+""#).unwrap(),
+            show_times: true,
+        };
+        assert_eq!(
+            run_on(&op),
+            concat!(
+                "[12:34:57] < You sent: \"Hello\"^G\n",
+                "[12:34:57] < You sent: \"Again\"\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            run_on(&Operation::Stats),
+            concat!(
+                "Events:    10\n",
+                "Duration:  3.0s\n",
+                "Sent:      2 lines (12 bytes)\n",
+                "Received:  3 lines (48 bytes)\n",
+                "Errors:    1\n",
+                "Replies:   1\n",
+                "Latency:   min 50.0ms, median 50.0ms, max 50.0ms\n",
+                "  < 1ms         0\n",
+                "  < 10ms        0\n",
+                "  < 100ms       1 ########################################\n",
+                "  < 1s          0\n",
+                "  < 10s         0\n",
+                "  >= 10s        0\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let other = TRANSCRIPT
+            .replace(r#"You sent: \"Again\""#, r#"You sent: \"again\""#)
+            .replace("Welcome", "Hi");
+        let old = write_transcript(TRANSCRIPT);
+        let new = write_transcript(&other);
+        let mut out = Vec::new();
+        assert!(diff(old.path(), new.path(), &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let body = out.lines().skip(2).collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "- < Welcome",
+                "+ < Hi",
+                "  > Hello",
+                "  > Again",
+                "  < You sent: \"Hello\"^G",
+                "- < You sent: \"Again\"",
+                "+ < You sent: \"again\"",
+            ]
+        );
+        let mut out = Vec::new();
+        assert!(!diff(old.path(), old.path(), &mut out).unwrap());
+    }
+
+    #[test]
+    fn test_partial_recv() {
+        let mut renderer = TextRenderer::default();
+        let first = serde_json::from_str::<Event>(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "recv", "data": "abc", "partial": true}"#,
+        )
+        .unwrap();
+        let second = serde_json::from_str::<Event>(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "recv", "data": "def\n"}"#,
+        )
+        .unwrap();
+        assert_eq!(renderer.render(&first).unwrap().text, "abc…");
+        assert_eq!(renderer.render(&second).unwrap().text, "…def");
+    }
+}
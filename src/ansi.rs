@@ -0,0 +1,216 @@
+use crate::util::display_vis;
+use clap::ValueEnum;
+use crossterm::style::{StyledContent, Stylize};
+
+/// Escape sequence that resets all graphic rendition attributes
+const RESET: &str = "\x1B[0m";
+
+/// How ANSI escape sequences in received lines are displayed, as set by
+/// `--ansi`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum AnsiMode {
+    /// Write color & style sequences to the terminal so that they take
+    /// effect; other escape sequences are still shown visibly
+    Pass,
+    /// Remove escape sequences
+    Strip,
+    /// Show escape sequences visibly, e.g., as `^[[31m`
+    #[default]
+    Vis,
+}
+
+/// A span of a string as divided up by `segments()`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    /// A complete escape sequence.  `sgr` is true if it is a Select Graphic
+    /// Rendition sequence, i.e., one that only sets colors & text styles.
+    Escape {
+        seq: &'a str,
+        sgr: bool,
+    },
+}
+
+/// Divide `s` into escape sequences and the text between them.  An ESC that
+/// does not start a complete escape sequence is treated as text.
+fn segments(s: &str) -> Vec<Segment<'_>> {
+    let mut segs = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    while let Some(offset) = s[i..].find('\x1B') {
+        let start = i + offset;
+        if let Some((len, sgr)) = escape_len(&s[start..]) {
+            if text_start < start {
+                segs.push(Segment::Text(&s[text_start..start]));
+            }
+            segs.push(Segment::Escape {
+                seq: &s[start..(start + len)],
+                sgr,
+            });
+            i = start + len;
+            text_start = i;
+        } else {
+            i = start + 1;
+        }
+    }
+    if text_start < s.len() {
+        segs.push(Segment::Text(&s[text_start..]));
+    }
+    segs
+}
+
+/// If `s` (which starts with ESC) starts with a complete escape sequence,
+/// return the sequence's length in bytes and whether it is an SGR sequence
+fn escape_len(s: &str) -> Option<(usize, bool)> {
+    let bs = s.as_bytes();
+    match bs.get(1)? {
+        b'[' => {
+            // CSI: parameter bytes, then intermediate bytes, then a final
+            // byte
+            let mut j = 2;
+            while bs.get(j).is_some_and(|b| (0x30..=0x3F).contains(b)) {
+                j += 1;
+            }
+            let params = &bs[2..j];
+            while bs.get(j).is_some_and(|b| (0x20..=0x2F).contains(b)) {
+                j += 1;
+            }
+            let end = *bs.get(j)?;
+            let sgr = end == b'm'
+                && j == params.len() + 2
+                && params
+                    .iter()
+                    .all(|&b| b.is_ascii_digit() || b == b';' || b == b':');
+            (0x40..=0x7E).contains(&end).then_some((j + 1, sgr))
+        }
+        b']' => {
+            // OSC: terminated by BEL or by ST (ESC \)
+            let mut j = 2;
+            loop {
+                match bs.get(j)? {
+                    0x07 => return Some((j + 1, false)),
+                    0x1B if bs.get(j + 1) == Some(&b'\\') => return Some((j + 2, false)),
+                    _ => j += 1,
+                }
+            }
+        }
+        _ => {
+            // Any other escape: intermediate bytes followed by a final byte
+            let mut j = 1;
+            while bs.get(j).is_some_and(|b| (0x20..=0x2F).contains(b)) {
+                j += 1;
+            }
+            let end = *bs.get(j)?;
+            (0x30..=0x7E).contains(&end).then_some((j + 1, false))
+        }
+    }
+}
+
+/// Return the chunks for displaying a received line with its escape
+/// sequences handled according to `mode`.  Under `AnsiMode::Pass`, SGR
+/// sequences are returned as chunks of their own, and a reset sequence is
+/// appended if there were any so that the server's styling does not leak
+/// past the end of the line.
+pub(crate) fn display_ansi(s: &str, mode: AnsiMode) -> Vec<StyledContent<String>> {
+    if mode == AnsiMode::Vis {
+        return display_vis(s);
+    }
+    let mut chunks = Vec::new();
+    let mut styled = false;
+    for seg in segments(s) {
+        match seg {
+            Segment::Text(text) => chunks.extend(display_vis(text)),
+            Segment::Escape { seq, sgr: true } if mode == AnsiMode::Pass => {
+                chunks.push(String::from(seq).stylize());
+                styled = true;
+            }
+            Segment::Escape { seq, .. } if mode == AnsiMode::Pass => {
+                chunks.extend(display_vis(seq));
+            }
+            Segment::Escape { .. } => (),
+        }
+    }
+    if styled {
+        chunks.push(String::from(RESET).stylize());
+    }
+    chunks
+}
+
+/// Returns true if `chunk` is an escape sequence passed through by
+/// `display_ansi()`.  (All other chunks have their control characters made
+/// visible, so they never contain a raw ESC.)  Such chunks take up no space
+/// on the terminal.
+pub(crate) fn is_escape(chunk: &str) -> bool {
+    chunk.starts_with('\x1B')
+}
+
+/// Escape sequence to write after a line containing passed-through escape
+/// sequences is cut short
+pub(crate) fn reset_after(line: &[StyledContent<String>]) -> &'static str {
+    if line.iter().any(|c| is_escape(c.content())) {
+        RESET
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn render(s: &str, mode: AnsiMode) -> String {
+        display_ansi(s, mode)
+            .into_iter()
+            .map(|c| c.content().clone())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("plain", vec![Segment::Text("plain")])]
+    #[case("\x1B[1;31mError\x1B[0m: bad", vec![
+        Segment::Escape { seq: "\x1B[1;31m", sgr: true },
+        Segment::Text("Error"),
+        Segment::Escape { seq: "\x1B[0m", sgr: true },
+        Segment::Text(": bad"),
+    ])]
+    #[case("\x1B[2Jcleared", vec![
+        Segment::Escape { seq: "\x1B[2J", sgr: false },
+        Segment::Text("cleared"),
+    ])]
+    #[case("\x1B[?25lhidden", vec![
+        Segment::Escape { seq: "\x1B[?25l", sgr: false },
+        Segment::Text("hidden"),
+    ])]
+    #[case("\x1B]0;title\x07after", vec![
+        Segment::Escape { seq: "\x1B]0;title\x07", sgr: false },
+        Segment::Text("after"),
+    ])]
+    #[case("\x1B]8;;https://example.com\x1B\\link", vec![
+        Segment::Escape { seq: "\x1B]8;;https://example.com\x1B\\", sgr: false },
+        Segment::Text("link"),
+    ])]
+    #[case("a\x1B(Bb", vec![
+        Segment::Text("a"),
+        Segment::Escape { seq: "\x1B(B", sgr: false },
+        Segment::Text("b"),
+    ])]
+    #[case("unterminated \x1B[31", vec![Segment::Text("unterminated \x1B[31")])]
+    #[case("trailing \x1B", vec![Segment::Text("trailing \x1B")])]
+    fn test_segments(#[case] s: &str, #[case] segs: Vec<Segment<'_>>) {
+        assert_eq!(segments(s), segs);
+    }
+
+    #[rstest]
+    #[case(AnsiMode::Vis, "^[[1;31mError^[[0m: ^[[2Jbad")]
+    #[case(AnsiMode::Strip, "Error: bad")]
+    #[case(AnsiMode::Pass, "\x1B[1;31mError\x1B[0m: ^[[2Jbad\x1B[0m")]
+    fn test_display_ansi(#[case] mode: AnsiMode, #[case] shown: &str) {
+        assert_eq!(render("\x1B[1;31mError\x1B[0m: \x1B[2Jbad", mode), shown);
+    }
+
+    #[test]
+    fn test_pass_no_escapes() {
+        assert_eq!(render("tab\there\x00", AnsiMode::Pass), "tab\there^@");
+    }
+}
@@ -0,0 +1,71 @@
+use regex::Regex;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The patterns given with `--assert-recv` that received lines must match,
+/// in order, within `--assert-timeout` of connecting
+#[derive(Clone, Debug)]
+pub(crate) struct Assertions {
+    /// The patterns that have not been matched yet, in the order that they
+    /// must be matched
+    pending: VecDeque<Regex>,
+    pub(crate) timeout: Duration,
+}
+
+impl Assertions {
+    pub(crate) fn new(patterns: Vec<Regex>, timeout: Duration) -> Assertions {
+        Assertions {
+            pending: patterns.into(),
+            timeout,
+        }
+    }
+
+    /// Check a received line against the next pattern, if any.  Returns
+    /// `true` if the line matched it, in which case the following pattern
+    /// becomes the next one.
+    pub(crate) fn observe(&mut self, line: &str) -> bool {
+        if self.pending.front().is_some_and(|rgx| rgx.is_match(line)) {
+            self.pending.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the next pattern that a received line must match, or `None` if
+    /// all of the patterns have been matched
+    pub(crate) fn next_pattern(&self) -> Option<&str> {
+        self.pending.front().map(Regex::as_str)
+    }
+
+    /// Returns `true` if all of the patterns have been matched
+    pub(crate) fn is_satisfied(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_order() {
+        let mut asserts = Assertions::new(
+            vec![
+                Regex::new(r"^220\b").unwrap(),
+                Regex::new(r"^250\b").unwrap(),
+            ],
+            Duration::from_secs(10),
+        );
+        assert_eq!(asserts.next_pattern(), Some(r"^220\b"));
+        assert!(!asserts.observe("250 OK"));
+        assert!(asserts.observe("220 mail.example.com ESMTP"));
+        assert!(!asserts.observe("220 mail.example.com ESMTP"));
+        assert_eq!(asserts.next_pattern(), Some(r"^250\b"));
+        assert!(!asserts.is_satisfied());
+        assert!(asserts.observe("250 OK"));
+        assert!(asserts.is_satisfied());
+        assert_eq!(asserts.next_pattern(), None);
+        assert!(!asserts.observe("250 OK"));
+    }
+}
@@ -0,0 +1,181 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A `NAME=REGEX` rule given with `--capture`: whenever a received line
+/// matches `pattern`, the text matched by its first capture group is stored
+/// in the session variable `name`
+#[derive(Clone, Debug)]
+pub(crate) struct Capture {
+    pub(crate) name: String,
+    pub(crate) pattern: Regex,
+}
+
+impl FromStr for Capture {
+    type Err = CaptureError;
+
+    fn from_str(s: &str) -> Result<Capture, CaptureError> {
+        let Some((name, pattern)) = s.split_once('=') else {
+            return Err(CaptureError::Syntax);
+        };
+        if !is_var_name(name) {
+            return Err(CaptureError::Name(name.to_owned()));
+        }
+        let pattern = Regex::new(pattern).map_err(|e| CaptureError::Regex(e.to_string()))?;
+        if pattern.captures_len() < 2 {
+            return Err(CaptureError::NoGroup);
+        }
+        Ok(Capture {
+            name: name.to_owned(),
+            pattern,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum CaptureError {
+    #[error(r#"expected "NAME=REGEX""#)]
+    Syntax,
+    #[error("invalid variable name: {0:?}")]
+    Name(String),
+    #[error("invalid regular expression: {0}")]
+    Regex(String),
+    #[error("regular expression must contain a capture group")]
+    NoGroup,
+}
+
+/// The session variables set by `--capture` rules, which can be referenced
+/// in sent lines as `${NAME}`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Variables {
+    captures: Vec<Capture>,
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    pub(crate) fn new(captures: Vec<Capture>) -> Variables {
+        Variables {
+            captures,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Set the variables to the given values, as saved by a previous run of a
+    /// `--session`
+    pub(crate) fn restore(&mut self, values: impl IntoIterator<Item = (String, String)>) {
+        self.values.extend(values);
+    }
+
+    /// Return the variables that have been captured so far
+    pub(crate) fn values(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Apply the capture rules to a received line (without its line ending),
+    /// updating the variables of any rules that match
+    pub(crate) fn capture(&mut self, line: &str) {
+        for cap in &self.captures {
+            if let Some(m) = cap.pattern.captures(line).and_then(|c| c.get(1)) {
+                self.values.insert(cap.name.clone(), m.as_str().to_owned());
+            }
+        }
+    }
+
+    /// Replace each `${NAME}` in `line` with the value of the variable `NAME`.
+    /// References to names that are not the subject of any `--capture` rule
+    /// are left as-is.  If a line references a variable that has not been
+    /// captured yet, an error is returned.
+    pub(crate) fn expand(&self, line: String) -> Result<String, ExpandError> {
+        if self.captures.is_empty() || !line.contains("${") {
+            return Ok(line);
+        }
+        let mut out = String::with_capacity(line.len());
+        let mut rest = &*line;
+        while let Some(i) = rest.find("${") {
+            out.push_str(&rest[..i]);
+            let after = &rest[(i + 2)..];
+            match after.split_once('}') {
+                Some((name, tail)) if self.captures.iter().any(|c| c.name == name) => {
+                    let Some(value) = self.values.get(name) else {
+                        return Err(ExpandError(name.to_owned()));
+                    };
+                    out.push_str(value);
+                    rest = tail;
+                }
+                _ => {
+                    out.push_str("${");
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("variable {0:?} has not been captured yet")]
+pub(crate) struct ExpandError(String);
+
+fn is_var_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn vars(rules: &[&str]) -> Variables {
+        Variables::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[rstest]
+    #[case("TOKEN", CaptureError::Syntax)]
+    #[case("1TOKEN=x(y)", CaptureError::Name(String::from("1TOKEN")))]
+    #[case("=x(y)", CaptureError::Name(String::new()))]
+    #[case("TOKEN=[a-z]+", CaptureError::NoGroup)]
+    fn test_parse_bad_capture(#[case] s: &str, #[case] err: CaptureError) {
+        assert_eq!(s.parse::<Capture>().unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_parse_capture() {
+        let cap = "SESSION_ID=^id=(\\w+)$".parse::<Capture>().unwrap();
+        assert_eq!(cap.name, "SESSION_ID");
+        assert_eq!(cap.pattern.as_str(), "^id=(\\w+)$");
+    }
+
+    #[test]
+    fn test_capture_and_expand() {
+        let mut vs = vars(&["TOKEN=token: ([0-9a-f]+)", "USER=^Hello, ([A-Za-z]+)"]);
+        assert_eq!(
+            vs.expand(String::from("AUTH ${TOKEN}")),
+            Err(ExpandError(String::from("TOKEN")))
+        );
+        vs.capture("Hello, Alice! Your token: 1f2e");
+        assert_eq!(
+            vs.expand(String::from("AUTH ${USER} ${TOKEN} ${OTHER} ${TOKEN")),
+            Ok(String::from("AUTH Alice 1f2e ${OTHER} ${TOKEN"))
+        );
+        vs.capture("New token: abc9");
+        assert_eq!(
+            vs.expand(String::from("${TOKEN}${TOKEN}")),
+            Ok(String::from("abc9abc9"))
+        );
+    }
+
+    #[test]
+    fn test_expand_without_captures() {
+        let vs = Variables::default();
+        assert_eq!(
+            vs.expand(String::from("${HOME}")),
+            Ok(String::from("${HOME}"))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use crate::util::{now, write_json_str, JsonStrMap};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Terminal size to record in the cast header if the actual size cannot be
+/// determined
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// A recording of the session's terminal output in asciinema's asciicast v2
+/// format
+#[derive(Debug)]
+pub(crate) struct CastFile {
+    fp: File,
+    start: Instant,
+}
+
+impl CastFile {
+    /// Create the file at `path` (truncating it if it already exists) and
+    /// write the asciicast header to it
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> io::Result<CastFile> {
+        let (width, height) = crossterm::terminal::size().unwrap_or(DEFAULT_SIZE);
+        CastFile::new(File::create(path)?, width, height)
+    }
+
+    fn new(mut fp: File, width: u16, height: u16) -> io::Result<CastFile> {
+        let header = JsonStrMap::new()
+            .raw_field("version", "2")
+            .raw_field("width", &width.to_string())
+            .raw_field("height", &height.to_string())
+            .raw_field("timestamp", &now().unix_timestamp().to_string())
+            .finish();
+        writeln!(fp, "{header}")?;
+        Ok(CastFile {
+            fp,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record a chunk of text written to the terminal.  Newlines are
+    /// converted to CR LF, as the terminal would be in raw mode on playback.
+    pub(crate) fn write_output(&mut self, text: &str) -> io::Result<()> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let mut data = String::new();
+        write_json_str(&text.replace('\n', "\r\n"), &mut data)
+            .expect("formatting a String should not fail");
+        writeln!(self.fp, "[{elapsed:.6}, \"o\", {data}]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek};
+
+    #[test]
+    fn test_cast_file() {
+        let mut fp = tempfile::tempfile().unwrap();
+        let mut cast = CastFile::new(fp.try_clone().unwrap(), 100, 40).unwrap();
+        cast.write_output("< Hello, \"world\"\n").unwrap();
+        fp.rewind().unwrap();
+        let mut s = String::new();
+        fp.read_to_string(&mut s).unwrap();
+        let mut lines = s.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with(r#"{"version": 2, "width": 100, "height": 40, "timestamp": "#));
+        let event = lines.next().unwrap();
+        assert!(event.starts_with('['));
+        assert!(event.ends_with(r#", "o", "< Hello, \"world\"\r\n"]"#));
+        assert_eq!(lines.next(), None);
+    }
+}
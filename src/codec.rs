@@ -9,12 +9,27 @@
 //!
 //! - The Decoder does not strip line endings from returned values.
 //!
-//! - The caller must append the line ending before passing the value to the
-//!   Encoder.
+//! - The caller must append the line ending (as done by
+//!   `ConfabCodec::prepare_line()`) before passing the value to the Encoder.
 //!
 //! - Decoder: `max_length` now includes the terminating newline.
 //!
-//! - Conversion between bytes & strings is handled by `CharEncoding`.
+//! - Decoder: Lines that are broken off at `max_length` are marked as
+//!   `RecvLine::partial`, and the line after each one as `RecvLine::resumed`.
+//!
+//! - Conversion between bytes & strings is handled by `CharEncoding`.  The
+//!   Decoder's error type is `CodecError`, which can also report lines that
+//!   could not be decoded under `--encoding utf8-strict`.
+//!
+//! - The Decoder returns a `RecvLine` that records the bytes the line
+//!   occupied on the wire alongside the decoded string.
+//!
+//! - Decoder: Lines can be terminated by CR, CR LF, or any of LF, CR LF, and
+//!   CR instead of just LF, as determined by `LineEnding`.
+//!
+//! - Under `Framing::LengthPrefixed`, data is instead split into frames
+//!   preceded by big-endian lengths, which are decoded into hex dumps, and
+//!   the Encoder prepends a length to each value.
 //!
 //! [1]: https://github.com/tokio-rs/tokio/blob/a03e0420249d1740668f608a5a16f1fa614be2c7/tokio-util/src/codec/lines_codec.rs
 
@@ -44,13 +59,210 @@
 // IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::util::{latin1ify, CharEncoding};
-use bytes::{BufMut, BytesMut};
-use std::{cmp, io};
+use crate::util::{hex_bytes, CharEncoding, DecodeError};
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::ValueEnum;
+use encoding_rs::{DecoderResult, Encoding};
+use std::str::FromStr;
+use std::{cmp, io, mem};
+use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder};
 
+/// A line of text received from the remote server
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct RecvLine {
+    /// The decoded line, including the line ending (if any)
+    pub(crate) text: String,
+    /// The line as it was received, before decoding
+    pub(crate) raw: Bytes,
+    /// If the line could not be decoded under `--encoding utf8-strict`, the
+    /// details of the problem.  `text` then contains replacement characters.
+    pub(crate) invalid: Option<DecodeError>,
+    /// Whether the line was broken off at the maximum line length rather
+    /// than ending with a line terminator, with its remainder in the next line
+    pub(crate) partial: bool,
+    /// Whether the line is the remainder of a line that was broken off at the
+    /// maximum line length
+    pub(crate) resumed: bool,
+}
+
+impl RecvLine {
+    /// Decode a received line.  If `fatal` is true, a line that cannot be
+    /// decoded results in an error; otherwise, it is returned with the error
+    /// recorded in `invalid`.
+    fn decode(
+        encoding: CharEncoding,
+        line: BytesMut,
+        fatal: bool,
+    ) -> Result<RecvLine, DecodeError> {
+        let raw = line.freeze();
+        match encoding.decode(raw.to_vec()) {
+            Ok(text) => Ok(RecvLine {
+                text,
+                raw,
+                invalid: None,
+                partial: false,
+                resumed: false,
+            }),
+            Err(e) if fatal => Err(e),
+            Err(e) => Ok(RecvLine {
+                text: e.lossy.clone(),
+                raw,
+                invalid: Some(e),
+                partial: false,
+                resumed: false,
+            }),
+        }
+    }
+
+    /// Construct a `RecvLine` for a binary frame whose first `header` bytes
+    /// are a length prefix, showing the rest of the frame as a hex dump
+    fn frame(frame: BytesMut, header: usize) -> RecvLine {
+        let raw = frame.freeze();
+        let payload = raw.get(header..).unwrap_or_default();
+        let mut text = hexdump(payload);
+        if raw.len() < header {
+            text.push_str(" (incomplete length prefix)");
+        }
+        text.push('\n');
+        RecvLine {
+            text,
+            raw,
+            invalid: None,
+            partial: false,
+            resumed: false,
+        }
+    }
+
+    /// Record whether the line was broken off at the maximum line length and
+    /// whether it continues a line that was
+    fn split(self, partial: bool, resumed: bool) -> RecvLine {
+        RecvLine {
+            partial,
+            resumed,
+            ..self
+        }
+    }
+
+    /// The number of bytes that the line occupied before decoding
+    pub(crate) fn wire_len(&self) -> usize {
+        self.raw.len()
+    }
+}
+
+/// The line terminators at which the decoder splits received data into lines
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum LineEnding {
+    /// Lines end with LF (which may be preceded by CR)
+    #[default]
+    Lf,
+    /// Lines end with CR LF; bare LFs and CRs do not end lines
+    Crlf,
+    /// Lines end with CR
+    Cr,
+    /// Lines end with LF, CR LF, or a CR that is not followed by LF
+    Any,
+}
+
+impl LineEnding {
+    /// Search `buf` for the end of the first line, starting at index `start`.
+    /// Returns `Ok(i)` if the line (including its terminator) ends just
+    /// before index `i`.  Otherwise, returns `Err(j)`, where `j` is the index
+    /// at which to resume searching once more data has been received.
+    fn find(self, buf: &[u8], start: usize) -> Result<usize, usize> {
+        let found = match self {
+            LineEnding::Lf => buf[start..].iter().position(|&b| b == b'\n'),
+            LineEnding::Cr => buf[start..].iter().position(|&b| b == b'\r'),
+            LineEnding::Crlf => buf[start..]
+                .iter()
+                .enumerate()
+                .position(|(i, &b)| b == b'\n' && start + i > 0 && buf[start + i - 1] == b'\r'),
+            LineEnding::Any => {
+                match buf[start..].iter().position(|&b| b == b'\n' || b == b'\r') {
+                    Some(offset) if buf[start + offset] == b'\r' => {
+                        let i = start + offset;
+                        // A CR at the end of the buffer may be the start of
+                        // a CR LF, so wait for the next byte.
+                        return match buf.get(i + 1) {
+                            Some(b'\n') => Ok(i + 2),
+                            Some(_) => Ok(i + 1),
+                            None => Err(i),
+                        };
+                    }
+                    found => found,
+                }
+            }
+        };
+        match found {
+            Some(offset) => Ok(start + offset + 1),
+            None => Err(buf.len()),
+        }
+    }
+}
+
+/// The line ending appended to each line sent to the server
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum SendEnding {
+    /// Terminate lines with LF
+    #[default]
+    Lf,
+    /// Terminate lines with CR LF
+    Crlf,
+    /// Terminate lines with CR
+    Cr,
+    /// Do not terminate lines
+    None,
+}
+
+impl SendEnding {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            SendEnding::Lf => "\n",
+            SendEnding::Crlf => "\r\n",
+            SendEnding::Cr => "\r",
+            SendEnding::None => "",
+        }
+    }
+}
+
+/// How received data is split into units for display
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) enum Framing {
+    /// Data is split into lines at the terminators given by `LineEnding`
+    #[default]
+    Lines,
+    /// Data is split into frames, each one preceded by its length in bytes
+    /// as a big-endian integer of the given width
+    LengthPrefixed(usize),
+}
+
+impl FromStr for Framing {
+    type Err = FramingError;
+
+    fn from_str(s: &str) -> Result<Framing, FramingError> {
+        if s == "lines" {
+            return Ok(Framing::Lines);
+        }
+        let Some(width) = s.strip_prefix("length-prefixed:") else {
+            return Err(FramingError::Unknown);
+        };
+        match width.parse::<usize>() {
+            Ok(n) if (1..=8).contains(&n) => Ok(Framing::LengthPrefixed(n)),
+            _ => Err(FramingError::Width(width.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum FramingError {
+    #[error(r#"expected "lines" or "length-prefixed:<N>""#)]
+    Unknown,
+    #[error("invalid length prefix width {0:?}; must be from 1 through 8")]
+    Width(String),
+}
+
 /// A simple [`Decoder`] and [`Encoder`] implementation that splits up data into lines.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub(crate) struct ConfabCodec {
     // Stored index of the next index to examine for a `\n` character.
     // This is used to optimize searching.
@@ -67,8 +279,21 @@ pub(crate) struct ConfabCodec {
     /// Character encoding for converting between strings and bytes
     encoding: CharEncoding,
 
-    /// Whether prepared lines should end in CR LF (true) or LF (false)
-    crlf: bool,
+    /// The line ending appended to prepared lines
+    send_ending: SendEnding,
+
+    /// The terminators at which received data is split into lines
+    line_ending: LineEnding,
+
+    framing: Framing,
+
+    /// Whether a line that cannot be decoded is an error rather than being
+    /// returned with `RecvLine::invalid` set
+    fatal_decode_errors: bool,
+
+    /// Whether the last line returned was broken off at `max_length`, making
+    /// the next line its continuation
+    split: bool,
 }
 
 impl ConfabCodec {
@@ -84,7 +309,11 @@ impl ConfabCodec {
             next_index: 0,
             max_length: usize::MAX,
             encoding: CharEncoding::Utf8,
-            crlf: false,
+            send_ending: SendEnding::Lf,
+            line_ending: LineEnding::Lf,
+            framing: Framing::Lines,
+            fatal_decode_errors: false,
+            split: false,
         }
     }
 
@@ -104,75 +333,184 @@ impl ConfabCodec {
         }
     }
 
+    /// Change the maximum line length.  The search for the end of the current
+    /// line is restarted, as it may have already passed the new limit.
+    pub(crate) fn max_length(self, max_length: usize) -> ConfabCodec {
+        ConfabCodec {
+            max_length,
+            next_index: 0,
+            ..self
+        }
+    }
+
     pub(crate) fn encoding(self, encoding: CharEncoding) -> ConfabCodec {
         ConfabCodec { encoding, ..self }
     }
 
-    pub(crate) fn crlf(self, crlf: bool) -> ConfabCodec {
-        ConfabCodec { crlf, ..self }
+    pub(crate) fn send_ending(self, send_ending: SendEnding) -> ConfabCodec {
+        ConfabCodec {
+            send_ending,
+            ..self
+        }
+    }
+
+    pub(crate) fn line_ending(self, line_ending: LineEnding) -> ConfabCodec {
+        ConfabCodec {
+            line_ending,
+            ..self
+        }
+    }
+
+    pub(crate) fn framing(self, framing: Framing) -> ConfabCodec {
+        ConfabCodec { framing, ..self }
     }
 
-    /// Prepare a line that is about to be sent through the codec.  If
-    /// `encoding` is `CharEncoding::Latin`, non-Latin-1 characters are
-    /// converted to question marks.  A line ending — either LF or CR LF,
-    /// depending on the value of `crlf` — is then appended to the line.
+    pub(crate) fn fatal_decode_errors(self, fatal_decode_errors: bool) -> ConfabCodec {
+        ConfabCodec {
+            fatal_decode_errors,
+            ..self
+        }
+    }
+
+    /// Prepare a line that is about to be sent through the codec.  Any
+    /// characters that cannot be represented in the codec's encoding are
+    /// converted to question marks.  The codec's `SendEnding` (if not
+    /// `None`) is then appended to the line.
     ///
     /// These conversions need to be done outside of encoding proper so that
     /// they can be reflected in reported events.
     pub(crate) fn prepare_line(&self, mut line: String) -> String {
-        if self.encoding == CharEncoding::Latin1 {
-            line = latin1ify(line);
-        }
-        if self.crlf {
-            line.push_str("\r\n");
-        } else {
-            line.push('\n');
-        }
+        line = self.encoding.replace_unencodable(line);
+        line.push_str(self.send_ending.as_str());
         line
     }
-}
 
-impl Decoder for ConfabCodec {
-    type Item = String;
-    type Error = io::Error;
+    /// Returns the number of bytes that `line` will occupy once encoded
+    pub(crate) fn encoded_len(&self, line: &str) -> usize {
+        let header = match self.framing {
+            Framing::Lines => 0,
+            Framing::LengthPrefixed(n) => n,
+        };
+        header + self.line_len(line)
+    }
+
+    /// Returns the number of bytes that `line` will occupy once encoded, not
+    /// counting any length prefix
+    pub(crate) fn line_len(&self, line: &str) -> usize {
+        self.encoding.encode(line).len()
+    }
+
+    /// Returns the exact bytes that sending `line` puts on the wire,
+    /// including any length prefix
+    pub(crate) fn wire_bytes(&self, line: &str) -> Result<Bytes, io::Error> {
+        let mut buf = BytesMut::new();
+        self.encode_into(line, &mut buf)?;
+        Ok(buf.freeze())
+    }
+
+    fn encode_into(&self, line: &str, buf: &mut BytesMut) -> Result<(), io::Error> {
+        let line = self.encoding.encode(line);
+        if let Framing::LengthPrefixed(header) = self.framing {
+            let length = u64::try_from(line.len()).unwrap_or(u64::MAX);
+            if header < 8 && length >> (header * 8) != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "frame of {length} bytes is too long for a {header}-byte length prefix"
+                    ),
+                ));
+            }
+            buf.reserve(header + line.len());
+            buf.put_slice(&length.to_be_bytes()[(8 - header)..]);
+        } else {
+            buf.reserve(line.len());
+        }
+        buf.put(&*line);
+        Ok(())
+    }
 
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+    fn decode_line(&mut self, buf: &mut BytesMut) -> Result<Option<RecvLine>, DecodeError> {
         // Determine how far into the buffer we'll search for a newline. If
         // there's no max_length set, we'll read to the end of the buffer.
         let read_to = cmp::min(self.max_length, buf.len());
-        let newline_offset = buf[self.next_index..read_to]
-            .iter()
-            .position(|b| *b == b'\n');
-        match newline_offset {
-            Some(offset) => {
+        match self.line_ending.find(&buf[..read_to], self.next_index) {
+            Ok(end) => {
                 // Found a line!
-                let newline_index = offset + self.next_index;
                 self.next_index = 0;
-                let line = buf.split_to(newline_index + 1);
-                let line = self.encoding.decode(line.into());
-                Ok(Some(line))
+                let line = buf.split_to(end);
+                let resumed = mem::take(&mut self.split);
+                RecvLine::decode(self.encoding, line, self.fatal_decode_errors)
+                    .map(|line| Some(line.split(false, resumed)))
             }
-            None if buf.len() >= self.max_length => {
+            Err(_) if buf.len() >= self.max_length => {
                 self.next_index = 0;
-                let i = if self.encoding.is_utf8() {
-                    find_final_char_boundary(&buf[..self.max_length])
-                } else {
-                    self.max_length
+                let head = &buf[..self.max_length];
+                let i = match self.encoding {
+                    CharEncoding::Utf8 | CharEncoding::Utf8Strict | CharEncoding::Utf8Latin1 => {
+                        find_final_char_boundary(head)
+                    }
+                    CharEncoding::Latin1 => self.max_length,
+                    CharEncoding::Other(enc) => find_final_boundary_in(enc, head),
                 };
+                // Don't produce an empty line if the limit is too small to
+                // hold a whole character
+                let i = if i == 0 { self.max_length } else { i };
                 let line = buf.split_to(i);
-                let line = self.encoding.decode(line.into());
-                Ok(Some(line))
+                let resumed = mem::replace(&mut self.split, true);
+                RecvLine::decode(self.encoding, line, self.fatal_decode_errors)
+                    .map(|line| Some(line.split(true, resumed)))
             }
-            None => {
+            Err(resume) => {
                 // We didn't find a line or reach the length limit, so the next
                 // call will resume searching at the current offset.
-                self.next_index = read_to;
+                self.next_index = resume;
                 Ok(None)
             }
         }
     }
 
-    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+    /// Decode a frame preceded by a `header`-byte big-endian length
+    fn decode_frame(
+        &self,
+        header: usize,
+        buf: &mut BytesMut,
+    ) -> Result<Option<RecvLine>, io::Error> {
+        let Some(prefix) = buf.get(..header) else {
+            return Ok(None);
+        };
+        let length = prefix
+            .iter()
+            .fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        let total = usize::try_from(length)
+            .ok()
+            .and_then(|n| n.checked_add(header))
+            .filter(|&n| n <= self.max_length)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame length {length} exceeds maximum length"),
+                )
+            })?;
+        if buf.len() < total {
+            buf.reserve(total - buf.len());
+            return Ok(None);
+        }
+        Ok(Some(RecvLine::frame(buf.split_to(total), header)))
+    }
+}
+
+impl Decoder for ConfabCodec {
+    type Item = RecvLine;
+    type Error = CodecError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RecvLine>, CodecError> {
+        match self.framing {
+            Framing::Lines => Ok(self.decode_line(buf)?),
+            Framing::LengthPrefixed(header) => Ok(self.decode_frame(header, buf)?),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<RecvLine>, CodecError> {
         Ok(match self.decode(buf)? {
             Some(frame) => Some(frame),
             None => {
@@ -181,15 +519,29 @@ impl Decoder for ConfabCodec {
                     None
                 } else {
                     let line = buf.split_to(buf.len());
-                    let line = self.encoding.decode(line.into());
                     self.next_index = 0;
-                    Some(line)
+                    match self.framing {
+                        Framing::Lines => Some(
+                            RecvLine::decode(self.encoding, line, self.fatal_decode_errors)?
+                                .split(false, mem::take(&mut self.split)),
+                        ),
+                        Framing::LengthPrefixed(header) => Some(RecvLine::frame(line, header)),
+                    }
                 }
             }
         })
     }
 }
 
+/// Error returned when decoding received data
+#[derive(Debug, Error)]
+pub(crate) enum CodecError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+}
+
 impl<T> Encoder<T> for ConfabCodec
 where
     T: AsRef<str>,
@@ -197,10 +549,7 @@ where
     type Error = io::Error;
 
     fn encode(&mut self, line: T, buf: &mut BytesMut) -> Result<(), io::Error> {
-        let line = self.encoding.encode(line.as_ref());
-        buf.reserve(line.len());
-        buf.put(&*line);
-        Ok(())
+        self.encode_into(line.as_ref(), buf)
     }
 }
 
@@ -210,6 +559,32 @@ impl Default for ConfabCodec {
     }
 }
 
+/// Format binary data as a line giving its length, the hex values of its
+/// bytes, and its printable ASCII characters, e.g.:
+///
+/// ```text
+/// [5 bytes] 48 65 6c 6c 6f  |Hello|
+/// ```
+fn hexdump(data: &[u8]) -> String {
+    let hex = hex_bytes(data);
+    let ascii = data
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                char::from(b)
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+    let unit = if data.len() == 1 { "byte" } else { "bytes" };
+    if data.is_empty() {
+        format!("[0 {unit}]")
+    } else {
+        format!("[{} {unit}] {hex}  |{ascii}|", data.len())
+    }
+}
+
 /// If `buf` ends in an incomplete UTF-8 sequence (that is, a sequence that is
 /// not a valid UTF-8 sequence but which could become one by appending
 /// continuation bytes, ignoring the problem of overlong encodings), return the
@@ -232,9 +607,38 @@ fn find_final_char_boundary(buf: &[u8]) -> usize {
     buf.len()
 }
 
+/// Like `find_final_char_boundary()`, but for an arbitrary ASCII-compatible
+/// encoding: if `buf` ends partway through a multibyte sequence, return the
+/// index of the start of that sequence; otherwise, return the length of
+/// `buf`.
+fn find_final_boundary_in(encoding: &'static Encoding, buf: &[u8]) -> usize {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut out = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length_without_replacement(buf.len())
+            .unwrap_or(buf.len()),
+    );
+    let mut pos = 0;
+    loop {
+        let (r, read) = decoder.decode_to_string_without_replacement(&buf[pos..], &mut out, true);
+        pos += read;
+        match r {
+            DecoderResult::InputEmpty => return buf.len(),
+            // Since `last` is true, a sequence that is cut off by the end of
+            // the input is reported as malformed, with nothing after it.
+            DecoderResult::Malformed(bad, 0) if pos == buf.len() => {
+                return buf.len() - usize::from(bad);
+            }
+            DecoderResult::Malformed(..) => out.clear(),
+            DecoderResult::OutputFull => out.reserve(buf.len() - pos + 4),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_matches::assert_matches;
     use rstest::rstest;
 
     #[rstest]
@@ -266,7 +670,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("This is test text.\nAnd so is this.\n");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "This is test text.\n"
         );
         assert_eq!(buf, "And so is this.\n");
@@ -277,7 +681,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("123456789.abcdefghi.123456789.a\nbcdef");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.a\n"
         );
         assert_eq!(buf, "bcdef");
@@ -288,7 +692,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("123456789.abcdefghi.123456789.ab\ncdef");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.ab"
         );
         assert_eq!(buf, "\ncdef");
@@ -299,7 +703,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("123456789.abcdefghi.123456789.abcdef\n");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.ab"
         );
         assert_eq!(buf, "cdef\n");
@@ -310,7 +714,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("123456789.abcdefghi.123456789.ab");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.ab"
         );
         assert_eq!(buf, "");
@@ -321,7 +725,7 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from("123456789.abcdefghi.123456789.abc");
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.ab"
         );
         assert_eq!(buf, "c");
@@ -336,12 +740,43 @@ mod tests {
         assert_eq!(codec.next_index, 31);
     }
 
+    #[test]
+    fn test_decode_split_marks() {
+        let mut codec = ConfabCodec::new_with_max_length(8);
+        let mut buf = BytesMut::from("abcdefghijklmnopqrst\nuvw\n");
+        let mut lines = Vec::new();
+        while let Some(line) = codec.decode(&mut buf).unwrap() {
+            lines.push((line.text, line.partial, line.resumed));
+        }
+        assert_eq!(
+            lines,
+            [
+                (String::from("abcdefgh"), true, false),
+                (String::from("ijklmnop"), true, true),
+                (String::from("qrst\n"), false, true),
+                (String::from("uvw\n"), false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_eof_after_split() {
+        let mut codec = ConfabCodec::new_with_max_length(8);
+        let mut buf = BytesMut::from("abcdefghij");
+        let line = codec.decode_eof(&mut buf).unwrap().unwrap();
+        assert!(line.partial);
+        let line = codec.decode_eof(&mut buf).unwrap().unwrap();
+        assert_eq!(line.text, "ij");
+        assert!(!line.partial);
+        assert!(line.resumed);
+    }
+
     #[test]
     fn test_decode_over_max_length_straddling_utf8() {
         let mut codec = ConfabCodec::new_with_max_length(32);
         let mut buf = BytesMut::from(&b"123456789.abcdefghi.123456789.\xE2\x98\x83"[..]);
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789."
         );
         assert_eq!(buf, &b"\xE2\x98\x83"[..]);
@@ -352,9 +787,160 @@ mod tests {
         let mut codec = ConfabCodec::new_with_max_length(32).encoding(CharEncoding::Latin1);
         let mut buf = BytesMut::from(&b"123456789.abcdefghi.123456789.\xE2\x98\x83"[..]);
         assert_eq!(
-            codec.decode(&mut buf).unwrap().unwrap(),
+            codec.decode(&mut buf).unwrap().unwrap().text,
             "123456789.abcdefghi.123456789.\u{e2}\u{98}"
         );
         assert_eq!(buf, &b"\x83"[..]);
     }
+
+    #[test]
+    fn test_decode_over_max_length_straddling_shift_jis() {
+        let enc = "shift_jis".parse::<CharEncoding>().unwrap();
+        let mut codec = ConfabCodec::new_with_max_length(8).encoding(enc);
+        let mut buf = BytesMut::from(&b"abc\x93\xfa\x96{\x8c\xea\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().text, "abc日本");
+        assert_eq!(buf, &b"\x8c\xea\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().text, "語\n");
+    }
+
+    #[rstest]
+    #[case(b"", 0)]
+    #[case(b"abc", 3)]
+    #[case(b"abc\x93\xfa", 5)]
+    #[case(b"abc\x93", 3)]
+    #[case(b"\x93\xfa\x93", 2)]
+    fn test_find_final_boundary_in_shift_jis(#[case] buf: &[u8], #[case] i: usize) {
+        assert_eq!(find_final_boundary_in(encoding_rs::SHIFT_JIS, buf), i);
+    }
+
+    fn decode_all(line_ending: LineEnding, chunks: &[&str]) -> Vec<String> {
+        let mut codec = ConfabCodec::new_with_max_length(32).line_ending(line_ending);
+        let mut buf = BytesMut::new();
+        let mut lines = Vec::new();
+        for chunk in chunks {
+            buf.extend_from_slice(chunk.as_bytes());
+            while let Some(line) = codec.decode(&mut buf).unwrap() {
+                lines.push(line.text);
+            }
+        }
+        while let Some(line) = codec.decode_eof(&mut buf).unwrap() {
+            lines.push(line.text);
+        }
+        lines
+    }
+
+    #[rstest]
+    #[case(LineEnding::Lf, &["a\rb\r\nc\nd"], &["a\rb\r\n", "c\n", "d"])]
+    #[case(LineEnding::Crlf, &["a\rb\r\nc\nd"], &["a\rb\r\n", "c\nd"])]
+    #[case(LineEnding::Crlf, &["a\r", "\nb"], &["a\r\n", "b"])]
+    #[case(LineEnding::Cr, &["a\rb\r\nc\nd"], &["a\r", "b\r", "\nc\nd"])]
+    #[case(LineEnding::Any, &["a\rb\r\nc\nd"], &["a\r", "b\r\n", "c\n", "d"])]
+    #[case(LineEnding::Any, &["a\r", "\nb\r", "c"], &["a\r\n", "b\r", "c"])]
+    #[case(LineEnding::Any, &["a\r"], &["a\r"])]
+    fn test_decode_line_ending(
+        #[case] line_ending: LineEnding,
+        #[case] chunks: &[&str],
+        #[case] lines: &[&str],
+    ) {
+        assert_eq!(decode_all(line_ending, chunks), lines);
+    }
+
+    #[rstest]
+    #[case(SendEnding::Lf, "HELO\n")]
+    #[case(SendEnding::Crlf, "HELO\r\n")]
+    #[case(SendEnding::Cr, "HELO\r")]
+    #[case(SendEnding::None, "HELO")]
+    fn test_prepare_line(#[case] send_ending: SendEnding, #[case] prepared: &str) {
+        let codec = ConfabCodec::new().send_ending(send_ending);
+        assert_eq!(codec.prepare_line(String::from("HELO")), prepared);
+    }
+
+    #[rstest]
+    #[case("lines", Framing::Lines)]
+    #[case("length-prefixed:2", Framing::LengthPrefixed(2))]
+    #[case("length-prefixed:8", Framing::LengthPrefixed(8))]
+    fn test_parse_framing(#[case] s: &str, #[case] framing: Framing) {
+        assert_eq!(s.parse::<Framing>(), Ok(framing));
+    }
+
+    #[rstest]
+    #[case("length-prefixed", FramingError::Unknown)]
+    #[case("length-prefixed:0", FramingError::Width(String::from("0")))]
+    #[case("length-prefixed:9", FramingError::Width(String::from("9")))]
+    fn test_parse_bad_framing(#[case] s: &str, #[case] err: FramingError) {
+        assert_eq!(s.parse::<Framing>(), Err(err));
+    }
+
+    #[test]
+    fn test_decode_utf8strict() {
+        let mut codec = ConfabCodec::new().encoding(CharEncoding::Utf8Strict);
+        let mut buf = BytesMut::from(&b"caf\xC3\xA9\ncaf\xE9\n"[..]);
+        let line = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(line.text, "café\n");
+        assert_eq!(line.invalid, None);
+        let line = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(line.text, "caf\u{fffd}\n");
+        assert_matches!(line.invalid, Some(DecodeError { offset: 3, ref bytes, .. }) if bytes == b"\xE9");
+        assert_eq!(line.wire_len(), 5);
+    }
+
+    #[test]
+    fn test_decode_utf8strict_fatal() {
+        let mut codec = ConfabCodec::new()
+            .encoding(CharEncoding::Utf8Strict)
+            .fatal_decode_errors(true);
+        let mut buf = BytesMut::from(&b"caf\xE9\nok\n"[..]);
+        assert_matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::Decode(DecodeError { offset: 3, .. }))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().text, "ok\n");
+    }
+
+    #[test]
+    fn test_decode_length_prefixed() {
+        let mut codec = ConfabCodec::new_with_max_length(32).framing(Framing::LengthPrefixed(2));
+        let mut buf = BytesMut::from(&b"\x00\x05Hel"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"lo\x00\x02\x01\xFF\x00");
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.text, "[5 bytes] 48 65 6c 6c 6f  |Hello|\n");
+        assert_eq!(frame.wire_len(), 7);
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.text, "[2 bytes] 01 ff  |..|\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        let frame = codec.decode_eof(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.text, "[0 bytes] (incomplete length prefix)\n");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_length_prefixed_too_long() {
+        let mut codec = ConfabCodec::new_with_max_length(32).framing(Framing::LengthPrefixed(1));
+        let mut buf = BytesMut::from(&b"\x40abc"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_encode_length_prefixed() {
+        let mut codec = ConfabCodec::new().framing(Framing::LengthPrefixed(2));
+        let mut buf = BytesMut::new();
+        codec.encode("Hi", &mut buf).unwrap();
+        assert_eq!(buf, &b"\x00\x02Hi"[..]);
+        assert_eq!(codec.encoded_len("Hi"), 4);
+        assert_eq!(codec.wire_bytes("Hi").unwrap(), &b"\x00\x02Hi"[..]);
+        let mut codec = ConfabCodec::new().framing(Framing::LengthPrefixed(1));
+        assert!(codec.encode("x".repeat(256), &mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn test_decode_wire_len_latin1() {
+        let mut codec = ConfabCodec::new_with_max_length(32).encoding(CharEncoding::Latin1);
+        let mut buf = BytesMut::from(&b"Libert\xE9\n"[..]);
+        let line = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(line.text, "Libert\u{e9}\n");
+        assert_eq!(line.wire_len(), 8);
+        assert_eq!(line.raw, &b"Libert\xE9\n"[..]);
+        assert_eq!(codec.wire_bytes(&line.text).unwrap(), line.raw);
+    }
 }
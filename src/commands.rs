@@ -0,0 +1,388 @@
+use crate::input::Input;
+use crate::util::{CharEncoding, CharEncodingLookupError};
+use regex::Regex;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A command entered at the prompt with a leading `/`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Command {
+    /// Display traffic statistics for the session
+    Stats,
+    /// List the lines waiting to be sent
+    Queue,
+    /// Remove the line at the given (one-based) position in the send queue
+    QueueDrop(NonZeroUsize),
+    /// Remove all lines from the send queue
+    QueueClear,
+    /// Shut down the write side of the connection
+    ShutdownWrite,
+    /// Send the `--break-sequence` text to the server
+    Break,
+    /// Re-display the events in the scrollback whose messages match the
+    /// given pattern
+    Search(SearchPattern),
+    /// Re-display the last N events in the scrollback
+    Last(NonZeroUsize),
+    /// Change a setting of the line codec for the rest of the session
+    Set(Setting),
+    /// Record an annotation in the transcript
+    Note(String),
+    /// Start collecting lines to send all at once
+    Compose,
+    /// Close the connection without exiting
+    Disconnect,
+    /// Connect again, either to the given host & port or to the previous
+    /// target, closing the current connection first if there is one
+    Connect(Option<(String, u16)>),
+    /// Send a line the given number of times, measuring the response time
+    /// for each
+    Repeat(NonZeroU32, String),
+    /// Stop a `/repeat` in progress
+    RepeatStop,
+    /// Display the lines held back during the most recent burst under
+    /// `--burst-quiet`
+    ShowBurst,
+}
+
+/// A codec setting that can be changed with `/set`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Setting {
+    /// The character encoding of sent & received lines
+    Encoding(CharEncoding),
+    /// Whether to terminate sent lines with CR LF instead of LF
+    Crlf(bool),
+    /// The maximum length in bytes of each received line
+    MaxLineLength(NonZeroUsize),
+}
+
+impl Setting {
+    /// Parse the name & value of a setting as given to `/set`
+    pub(crate) fn parse(name: &str, value: &str) -> Result<Setting, CommandError> {
+        match (name, value) {
+            ("encoding", enc) => enc
+                .parse::<CharEncoding>()
+                .map(Setting::Encoding)
+                .map_err(CommandError::Encoding),
+            ("crlf", "on") => Ok(Setting::Crlf(true)),
+            ("crlf", "off") => Ok(Setting::Crlf(false)),
+            ("max-line-length", n) => n
+                .parse::<NonZeroUsize>()
+                .map(Setting::MaxLineLength)
+                .map_err(|_| CommandError::Usage(SET_USAGE)),
+            _ => Err(CommandError::Usage(SET_USAGE)),
+        }
+    }
+
+    /// The name of the setting as given to `/set`
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Setting::Encoding(_) => "encoding",
+            Setting::Crlf(_) => "crlf",
+            Setting::MaxLineLength(_) => "max-line-length",
+        }
+    }
+
+    /// The new value of the setting, formatted as given to `/set`
+    pub(crate) fn value(self) -> String {
+        match self {
+            Setting::Encoding(encoding) => encoding.to_string(),
+            Setting::Crlf(true) => String::from("on"),
+            Setting::Crlf(false) => String::from("off"),
+            Setting::MaxLineLength(n) => n.to_string(),
+        }
+    }
+}
+
+/// A regular expression given to `/search`
+#[derive(Clone, Debug)]
+pub(crate) struct SearchPattern(pub(crate) Regex);
+
+impl PartialEq for SearchPattern {
+    fn eq(&self, other: &SearchPattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for SearchPattern {}
+
+impl FromStr for Command {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Command, CommandError> {
+        let mut words = s.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err(CommandError::Empty);
+        };
+        match name {
+            "stats" | "shutdown-write" | "break" | "disconnect" | "compose" | "show-burst" => {
+                if words.next().is_some() {
+                    return Err(CommandError::Arguments(name.to_owned()));
+                }
+                match name {
+                    "stats" => Ok(Command::Stats),
+                    "shutdown-write" => Ok(Command::ShutdownWrite),
+                    "disconnect" => Ok(Command::Disconnect),
+                    "compose" => Ok(Command::Compose),
+                    "show-burst" => Ok(Command::ShowBurst),
+                    _ => Ok(Command::Break),
+                }
+            }
+            "queue" => {
+                let args = words.collect::<Vec<_>>();
+                match args[..] {
+                    [] => Ok(Command::Queue),
+                    ["clear"] => Ok(Command::QueueClear),
+                    ["drop", n] => n
+                        .parse::<NonZeroUsize>()
+                        .map(Command::QueueDrop)
+                        .map_err(|_| CommandError::Usage(QUEUE_USAGE)),
+                    _ => Err(CommandError::Usage(QUEUE_USAGE)),
+                }
+            }
+            "search" => {
+                let pattern = rest_of_line(s, name);
+                if pattern.is_empty() {
+                    return Err(CommandError::Usage(SEARCH_USAGE));
+                }
+                Regex::new(pattern)
+                    .map(|rgx| Command::Search(SearchPattern(rgx)))
+                    .map_err(|e| CommandError::Regex(e.to_string()))
+            }
+            "last" => {
+                let args = words.collect::<Vec<_>>();
+                match args[..] {
+                    [n] => n
+                        .parse::<NonZeroUsize>()
+                        .map(Command::Last)
+                        .map_err(|_| CommandError::Usage(LAST_USAGE)),
+                    _ => Err(CommandError::Usage(LAST_USAGE)),
+                }
+            }
+            "set" => {
+                let args = words.collect::<Vec<_>>();
+                match args[..] {
+                    [name, value] => Setting::parse(name, value).map(Command::Set),
+                    _ => Err(CommandError::Usage(SET_USAGE)),
+                }
+            }
+            "note" => {
+                let text = rest_of_line(s, name);
+                if text.is_empty() {
+                    return Err(CommandError::Usage(NOTE_USAGE));
+                }
+                Ok(Command::Note(text.to_owned()))
+            }
+            "connect" => {
+                let args = words.collect::<Vec<_>>();
+                match args[..] {
+                    [] => Ok(Command::Connect(None)),
+                    [host, port] => port
+                        .parse::<u16>()
+                        .map(|port| Command::Connect(Some((host.to_owned(), port))))
+                        .map_err(|_| CommandError::Usage(CONNECT_USAGE)),
+                    _ => Err(CommandError::Usage(CONNECT_USAGE)),
+                }
+            }
+            "repeat" => match words.next() {
+                Some("stop") if words.next().is_none() => Ok(Command::RepeatStop),
+                Some(n) => {
+                    let count = n
+                        .parse::<NonZeroU32>()
+                        .map_err(|_| CommandError::Usage(REPEAT_USAGE))?;
+                    let line = rest_of_line(rest_of_line(s, name), n);
+                    if line.is_empty() {
+                        return Err(CommandError::Usage(REPEAT_USAGE));
+                    }
+                    Ok(Command::Repeat(count, line.to_owned()))
+                }
+                None => Err(CommandError::Usage(REPEAT_USAGE)),
+            },
+            _ => Err(CommandError::Unknown(name.to_owned())),
+        }
+    }
+}
+
+/// Return the argument of a command that takes the rest of the line, which
+/// may contain whitespace, as a single argument
+fn rest_of_line<'a>(s: &'a str, name: &str) -> &'a str {
+    s.trim_start().strip_prefix(name).unwrap_or_default().trim()
+}
+
+const QUEUE_USAGE: &str = "/queue [clear | drop <N>]";
+
+const SEARCH_USAGE: &str = "/search <REGEX>";
+
+const LAST_USAGE: &str = "/last <N>";
+
+const NOTE_USAGE: &str = "/note <TEXT>";
+
+const CONNECT_USAGE: &str = "/connect [<HOST> <PORT>]";
+
+const REPEAT_USAGE: &str = "/repeat <N> <LINE> | stop";
+
+const SET_USAGE: &str = "/set encoding <NAME> | crlf on|off | max-line-length <N>";
+
+/// The names of all commands, for use in tab completion
+pub(crate) const COMMAND_NAMES: &[&str] = &[
+    "break",
+    "compose",
+    "connect",
+    "disconnect",
+    "last",
+    "note",
+    "queue",
+    "repeat",
+    "search",
+    "set",
+    "show-burst",
+    "shutdown-write",
+    "stats",
+];
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum CommandError {
+    #[error("no command given; to send a line starting with \"/\", begin it with \"//\"")]
+    Empty,
+    #[error("unknown command: /{0}")]
+    Unknown(String),
+    #[error("/{0} does not take any arguments")]
+    Arguments(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("invalid regular expression: {0}")]
+    Regex(String),
+    #[error(transparent)]
+    Encoding(CharEncodingLookupError),
+}
+
+/// Classify a line entered at the prompt as either a command or a line to
+/// send.  A line starting with `//` is sent with the first `/` removed.
+pub(crate) fn parse_line(line: String) -> Input {
+    match line.strip_prefix('/') {
+        Some(rest) if rest.starts_with('/') => Input::Line(rest.to_owned()),
+        Some(rest) => Input::Command(rest.parse()),
+        None => Input::Line(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("hello", Input::Line("hello".into()))]
+    #[case("", Input::Line(String::new()))]
+    #[case("//etc/passwd", Input::Line("/etc/passwd".into()))]
+    #[case("/stats", Input::Command(Ok(Command::Stats)))]
+    #[case("/ stats ", Input::Command(Ok(Command::Stats)))]
+    #[case("/", Input::Command(Err(CommandError::Empty)))]
+    #[case("/quit", Input::Command(Err(CommandError::Unknown("quit".into()))))]
+    #[case(
+        "/stats now",
+        Input::Command(Err(CommandError::Arguments("stats".into())))
+    )]
+    #[case("/queue", Input::Command(Ok(Command::Queue)))]
+    #[case("/queue clear", Input::Command(Ok(Command::QueueClear)))]
+    #[case(
+        "/queue drop 2",
+        Input::Command(Ok(Command::QueueDrop(NonZeroUsize::new(2).unwrap())))
+    )]
+    #[case("/queue drop 0", Input::Command(Err(CommandError::Usage(QUEUE_USAGE))))]
+    #[case("/queue drop", Input::Command(Err(CommandError::Usage(QUEUE_USAGE))))]
+    #[case(
+        "/queue clear 1",
+        Input::Command(Err(CommandError::Usage(QUEUE_USAGE)))
+    )]
+    #[case("/queue list", Input::Command(Err(CommandError::Usage(QUEUE_USAGE))))]
+    #[case("/shutdown-write", Input::Command(Ok(Command::ShutdownWrite)))]
+    #[case("/break", Input::Command(Ok(Command::Break)))]
+    #[case(
+        "/break now",
+        Input::Command(Err(CommandError::Arguments("break".into())))
+    )]
+    #[case(
+        "/shutdown-write now",
+        Input::Command(Err(CommandError::Arguments("shutdown-write".into())))
+    )]
+    #[case("/last 5", Input::Command(Ok(Command::Last(NonZeroUsize::new(5).unwrap()))))]
+    #[case("/last", Input::Command(Err(CommandError::Usage(LAST_USAGE))))]
+    #[case("/last 0", Input::Command(Err(CommandError::Usage(LAST_USAGE))))]
+    #[case("/last 1 2", Input::Command(Err(CommandError::Usage(LAST_USAGE))))]
+    #[case(
+        "/search ^250 [A-Z]+ ",
+        Input::Command(Ok(Command::Search(SearchPattern(Regex::new("^250 [A-Z]+").unwrap()))))
+    )]
+    #[case("/search", Input::Command(Err(CommandError::Usage(SEARCH_USAGE))))]
+    #[case(
+        "/note  bug happens here ",
+        Input::Command(Ok(Command::Note("bug happens here".into())))
+    )]
+    #[case("/note", Input::Command(Err(CommandError::Usage(NOTE_USAGE))))]
+    #[case("/searchable", Input::Command(Err(CommandError::Unknown("searchable".into()))))]
+    #[case(
+        "/set encoding latin1",
+        Input::Command(Ok(Command::Set(Setting::Encoding(CharEncoding::Latin1))))
+    )]
+    #[case(
+        "/set encoding klingon",
+        Input::Command(Err(CommandError::Encoding(CharEncodingLookupError::Unknown)))
+    )]
+    #[case("/set crlf on", Input::Command(Ok(Command::Set(Setting::Crlf(true)))))]
+    #[case(
+        "/set crlf off",
+        Input::Command(Ok(Command::Set(Setting::Crlf(false))))
+    )]
+    #[case("/set crlf yes", Input::Command(Err(CommandError::Usage(SET_USAGE))))]
+    #[case(
+        "/set max-line-length 1024",
+        Input::Command(Ok(Command::Set(Setting::MaxLineLength(NonZeroUsize::new(1024).unwrap()))))
+    )]
+    #[case(
+        "/set max-line-length 0",
+        Input::Command(Err(CommandError::Usage(SET_USAGE)))
+    )]
+    #[case("/set", Input::Command(Err(CommandError::Usage(SET_USAGE))))]
+    #[case("/compose", Input::Command(Ok(Command::Compose)))]
+    #[case("/show-burst", Input::Command(Ok(Command::ShowBurst)))]
+    #[case(
+        "/compose now",
+        Input::Command(Err(CommandError::Arguments("compose".into())))
+    )]
+    #[case("/disconnect", Input::Command(Ok(Command::Disconnect)))]
+    #[case(
+        "/disconnect now",
+        Input::Command(Err(CommandError::Arguments("disconnect".into())))
+    )]
+    #[case("/connect", Input::Command(Ok(Command::Connect(None))))]
+    #[case(
+        "/connect example.com 25",
+        Input::Command(Ok(Command::Connect(Some(("example.com".into(), 25)))))
+    )]
+    #[case(
+        "/connect example.com",
+        Input::Command(Err(CommandError::Usage(CONNECT_USAGE)))
+    )]
+    #[case(
+        "/connect example.com smtp",
+        Input::Command(Err(CommandError::Usage(CONNECT_USAGE)))
+    )]
+    #[case("/set color on", Input::Command(Err(CommandError::Usage(SET_USAGE))))]
+    #[case(
+        "/repeat 100  PING  now ",
+        Input::Command(Ok(Command::Repeat(NonZeroU32::new(100).unwrap(), "PING  now".into())))
+    )]
+    #[case("/repeat stop", Input::Command(Ok(Command::RepeatStop)))]
+    #[case(
+        "/repeat 0 PING",
+        Input::Command(Err(CommandError::Usage(REPEAT_USAGE)))
+    )]
+    #[case("/repeat 5", Input::Command(Err(CommandError::Usage(REPEAT_USAGE))))]
+    #[case("/repeat PING", Input::Command(Err(CommandError::Usage(REPEAT_USAGE))))]
+    #[case("/repeat", Input::Command(Err(CommandError::Usage(REPEAT_USAGE))))]
+    fn test_parse_line(#[case] line: &str, #[case] input: Input) {
+        assert_eq!(parse_line(line.to_owned()), input);
+    }
+}
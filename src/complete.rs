@@ -0,0 +1,232 @@
+use crate::commands::COMMAND_NAMES;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Maximum number of distinct words received from the server that are
+/// remembered for completion
+const TOKEN_INDEX_SIZE: usize = 1000;
+
+/// Words received from the server shorter than this many characters are not
+/// worth remembering for completion
+const MIN_TOKEN_LEN: usize = 3;
+
+/// A rolling index of the distinct words recently received from the server,
+/// shared between the `Reporter`, which records them, and the line editor,
+/// which completes them
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TokenIndex(Arc<Mutex<VecDeque<String>>>);
+
+impl TokenIndex {
+    /// Record the words in a line received from the server.  Words are
+    /// delimited by whitespace and stripped of surrounding quotes, brackets,
+    /// and punctuation.
+    pub(crate) fn record(&self, line: &str) {
+        let mut tokens = self.lock();
+        for word in line
+            .split_whitespace()
+            .map(trim_word)
+            .filter(|w| w.chars().count() >= MIN_TOKEN_LEN && !w.contains(char::is_control))
+        {
+            if let Some(i) = tokens.iter().position(|t| t == word) {
+                tokens.remove(i);
+            }
+            tokens.push_back(word.to_owned());
+            if tokens.len() > TOKEN_INDEX_SIZE {
+                tokens.pop_front();
+            }
+        }
+    }
+
+    /// Return the recorded words that start with `prefix`, most recent first
+    fn matching(&self, prefix: &str) -> Vec<String> {
+        self.lock()
+            .iter()
+            .rev()
+            .filter(|t| t.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<String>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn trim_word(word: &str) -> &str {
+    word.trim_matches(|c: char| {
+        matches!(
+            c,
+            '"' | '\''
+                | '`'
+                | '('
+                | ')'
+                | '['
+                | ']'
+                | '{'
+                | '}'
+                | '<'
+                | '>'
+                | ','
+                | ';'
+                | ':'
+                | '.'
+                | '!'
+                | '?'
+        )
+    })
+}
+
+/// Supplies completions for the word before the cursor in the line editor
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Completer {
+    tokens: TokenIndex,
+}
+
+impl Completer {
+    pub(crate) fn new(tokens: TokenIndex) -> Completer {
+        Completer { tokens }
+    }
+
+    /// Return the possible completions of `word`, most likely first.
+    ///
+    /// If `word` is the first word on the line and starts with `/`, it is
+    /// completed to the name of a command.  Otherwise, it is completed to a
+    /// word recently received from the server or to a word from `history`,
+    /// which should yield the entries of the input history, newest first.
+    pub(crate) fn complete<'a, I>(&self, word: &str, first: bool, history: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        if word.is_empty() {
+            return Vec::new();
+        }
+        let mut candidates = Vec::new();
+        if let Some(name) = word.strip_prefix('/').filter(|_| first) {
+            candidates.extend(
+                COMMAND_NAMES
+                    .iter()
+                    .filter(|c| c.starts_with(name))
+                    .map(|c| format!("/{c}")),
+            );
+        } else {
+            let from_history = history
+                .into_iter()
+                .flat_map(str::split_whitespace)
+                .filter(|w| w.starts_with(word))
+                .map(String::from);
+            for c in self.tokens.matching(word).into_iter().chain(from_history) {
+                if !candidates.contains(&c) {
+                    candidates.push(c);
+                }
+            }
+        }
+        candidates.retain(|c| c != word);
+        candidates
+    }
+}
+
+/// Return the longest string that all of `candidates` start with
+pub(crate) fn common_prefix(candidates: &[String]) -> &str {
+    let Some((first, rest)) = candidates.split_first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for c in rest {
+        len = first
+            .char_indices()
+            .zip(c.chars())
+            .take_while(|&((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(len);
+    }
+    &first[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_record() {
+        let tokens = TokenIndex::default();
+        tokens.record("Joined #rust-beginners (topic: \"Welcome!\")\r\n");
+        tokens.record("User ab joined #rust\r\n");
+        assert_eq!(
+            tokens.matching(""),
+            [
+                "#rust",
+                "joined",
+                "User",
+                "Welcome",
+                "topic",
+                "#rust-beginners",
+                "Joined"
+            ]
+        );
+        tokens.record("Joined again");
+        assert_eq!(tokens.matching("J"), ["Joined"]);
+        assert_eq!(tokens.matching("#rust"), ["#rust", "#rust-beginners"]);
+    }
+
+    #[test]
+    fn test_token_index_size() {
+        let tokens = TokenIndex::default();
+        for i in 0..=TOKEN_INDEX_SIZE {
+            tokens.record(&format!("obj{i:04}"));
+        }
+        let all = tokens.matching("obj");
+        assert_eq!(all.len(), TOKEN_INDEX_SIZE);
+        assert_eq!(all.last().map(String::as_str), Some("obj0001"));
+    }
+
+    #[test]
+    fn test_complete_command() {
+        let completer = Completer::default();
+        assert_eq!(
+            completer.complete("/s", true, []),
+            [
+                "/search",
+                "/set",
+                "/show-burst",
+                "/shutdown-write",
+                "/stats"
+            ]
+        );
+        assert_eq!(completer.complete("/q", true, []), ["/queue"]);
+        assert!(completer.complete("/queue", true, []).is_empty());
+        assert!(completer.complete("/s", false, []).is_empty());
+    }
+
+    #[test]
+    fn test_complete_words() {
+        let tokens = TokenIndex::default();
+        let completer = Completer::new(tokens.clone());
+        tokens.record("Created object obj-1234");
+        assert_eq!(
+            completer.complete("ob", false, ["GET obj-0001", "GET ob"]),
+            ["obj-1234", "object", "obj-0001"]
+        );
+        assert_eq!(
+            completer.complete("obj-1", true, ["GET obj-1234"]),
+            ["obj-1234"]
+        );
+        assert!(completer.complete("", false, ["GET obj"]).is_empty());
+    }
+
+    #[rstest]
+    #[case(&[], "")]
+    #[case(&["foo"], "foo")]
+    #[case(&["foobar", "foobaz", "foo"], "foo")]
+    #[case(&["/shutdown-write", "/stats"], "/s")]
+    #[case(&["caf\u{E9}s", "caf\u{E9}"], "caf\u{E9}")]
+    #[case(&["abc", "xyz"], "")]
+    fn test_common_prefix(#[case] candidates: &[&str], #[case] prefix: &str) {
+        let candidates = candidates
+            .iter()
+            .map(|&s| String::from(s))
+            .collect::<Vec<_>>();
+        assert_eq!(common_prefix(&candidates), prefix);
+    }
+}
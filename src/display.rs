@@ -0,0 +1,360 @@
+use crate::memory::{MemoryBudget, RetainedQueue};
+use std::collections::VecDeque;
+use std::mem;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Period over which the rate of received lines is measured for
+/// `--burst-quiet`
+const BURST_WINDOW: Duration = Duration::from_secs(1);
+
+/// Maximum number of lines of a burst kept for `/show-burst`; any further
+/// lines are only counted
+const BURST_LIMIT: usize = 10_000;
+
+/// Policy for deciding which received lines are displayed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum RecvPolicy {
+    /// Display every line
+    #[default]
+    All,
+    /// Display every Nth line, starting with the first
+    Sample(NonZeroU64),
+    /// Display only the first N lines
+    Head(u64),
+}
+
+/// Applies a `RecvPolicy` to the stream of received lines and keeps count of
+/// the lines that were not displayed
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct RecvSampler {
+    policy: RecvPolicy,
+    seen: u64,
+    hidden: u64,
+}
+
+impl RecvSampler {
+    pub(crate) fn new(policy: RecvPolicy) -> RecvSampler {
+        RecvSampler {
+            policy,
+            seen: 0,
+            hidden: 0,
+        }
+    }
+
+    /// Register a received line and return whether it should be displayed
+    pub(crate) fn admit(&mut self) -> bool {
+        let admit = match self.policy {
+            RecvPolicy::All => true,
+            RecvPolicy::Sample(n) => self.seen % n.get() == 0,
+            RecvPolicy::Head(n) => self.seen < n,
+        };
+        self.seen += 1;
+        if !admit {
+            self.hidden += 1;
+        }
+        admit
+    }
+
+    /// Returns the number of received lines that were not displayed
+    pub(crate) fn hidden(&self) -> u64 {
+        self.hidden
+    }
+}
+
+/// A sampling rate of the form "1/N"
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct SampleRate(pub(crate) NonZeroU64);
+
+impl FromStr for SampleRate {
+    type Err = SampleRateError;
+
+    fn from_str(s: &str) -> Result<SampleRate, SampleRateError> {
+        let n = s.strip_prefix("1/").ok_or(SampleRateError)?;
+        n.parse::<NonZeroU64>()
+            .map(SampleRate)
+            .map_err(|_| SampleRateError)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+#[error(r#"invalid sampling rate; expected "1/N" with N a positive integer"#)]
+pub(crate) struct SampleRateError;
+
+/// Detects bursts of received lines arriving faster than the `--burst-quiet`
+/// rate and holds back the lines of each burst from display so that they can
+/// be shown afterwards with `/show-burst`.  The held-back lines count against
+/// the session's memory budget.
+#[derive(Debug)]
+pub(crate) struct BurstQuiet {
+    /// Number of lines per second above which lines are held back
+    rate: NonZeroU32,
+    /// Arrival times of the lines received within the last `BURST_WINDOW`
+    recent: VecDeque<Instant>,
+    /// Whether a burst is in progress
+    active: bool,
+    /// Number of lines held back during the current or most recent burst
+    count: u64,
+    /// The rendered text of the first `BURST_LIMIT` lines held back during
+    /// the current or most recent burst, less any discarded to stay within
+    /// the memory budget
+    held: RetainedQueue<String>,
+}
+
+/// Whether a received line arrived during a burst
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum BurstState {
+    /// The line is not part of a burst and should be displayed
+    Calm,
+    /// The line starts a new burst and should be held back
+    Started,
+    /// The line continues the burst in progress and should be held back
+    Ongoing,
+}
+
+impl BurstQuiet {
+    pub(crate) fn new(rate: NonZeroU32, budget: MemoryBudget) -> BurstQuiet {
+        BurstQuiet {
+            rate,
+            recent: VecDeque::new(),
+            active: false,
+            count: 0,
+            held: RetainedQueue::new(budget),
+        }
+    }
+
+    /// Register a line received at `now` and return whether it is part of a
+    /// burst.  [`BurstQuiet::end()`] should be called first so that a burst
+    /// that has died down is not continued.
+    pub(crate) fn observe(&mut self, now: Instant) -> BurstState {
+        self.recent.push_back(now);
+        self.prune(now);
+        if self.active {
+            BurstState::Ongoing
+        } else if self.recent.len() > self.limit() {
+            self.active = true;
+            self.count = 0;
+            self.held.clear();
+            BurstState::Started
+        } else {
+            BurstState::Calm
+        }
+    }
+
+    /// Hold back the rendered text of a line that was part of a burst
+    pub(crate) fn hold(&mut self, text: String) {
+        self.count += 1;
+        if self.held.len() < BURST_LIMIT {
+            self.held.push_back(text);
+        }
+    }
+
+    /// If a burst is in progress but the rate of received lines has fallen
+    /// to half the threshold or below as of `now`, end the burst and return
+    /// the number of lines held back during it.  (Ending as soon as the rate
+    /// is back at the threshold would let a steady stream of lines flap in
+    /// and out of bursts.)
+    pub(crate) fn end(&mut self, now: Instant) -> Option<u64> {
+        if !self.active {
+            return None;
+        }
+        self.prune(now);
+        if self.recent.len() > self.calm_limit() {
+            return None;
+        }
+        self.finish()
+    }
+
+    /// End any burst in progress, returning the number of lines held back
+    /// during it
+    pub(crate) fn finish(&mut self) -> Option<u64> {
+        mem::take(&mut self.active).then_some(self.count)
+    }
+
+    /// Returns when the burst in progress will have died down if no more
+    /// lines are received, or `None` if there is no burst in progress
+    pub(crate) fn end_at(&self) -> Option<Instant> {
+        if !self.active {
+            return None;
+        }
+        self.recent
+            .iter()
+            .rev()
+            .nth(self.calm_limit())
+            .map(|&t| t + BURST_WINDOW)
+    }
+
+    /// Returns the rendered text of the lines held back during the current
+    /// or most recent burst that were kept, along with the total number of
+    /// lines held back
+    pub(crate) fn held(&self) -> (Vec<String>, u64) {
+        (self.held.iter().cloned().collect(), self.count)
+    }
+
+    fn limit(&self) -> usize {
+        usize::try_from(self.rate.get()).unwrap_or(usize::MAX)
+    }
+
+    /// The number of lines within `BURST_WINDOW` at or below which a burst
+    /// is over
+    fn calm_limit(&self) -> usize {
+        self.limit() / 2
+    }
+
+    /// Forget the arrival times of lines received more than `BURST_WINDOW`
+    /// before `now`
+    fn prune(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) >= BURST_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+}
+
+/// Return the message displayed when a burst ends, giving the number of
+/// lines that were held back, e.g., "… 1,243 lines received"
+pub(crate) fn burst_message(count: u64) -> String {
+    let digits = count.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    let noun = if count == 1 { "line" } else { "lines" };
+    format!("\u{2026} {grouped} {noun} received; use /show-burst to display them")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("1/1", 1)]
+    #[case("1/10", 10)]
+    fn test_parse_sample_rate(#[case] s: &str, #[case] n: u64) {
+        assert_eq!(s.parse::<SampleRate>().unwrap().0.get(), n);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("10")]
+    #[case("1/0")]
+    #[case("2/10")]
+    #[case("1/-3")]
+    fn test_parse_bad_sample_rate(#[case] s: &str) {
+        assert_eq!(s.parse::<SampleRate>(), Err(SampleRateError));
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut sampler = RecvSampler::new(RecvPolicy::Sample(NonZeroU64::new(3).unwrap()));
+        let admitted = std::iter::repeat_with(|| sampler.admit())
+            .take(7)
+            .collect::<Vec<_>>();
+        assert_eq!(admitted, [true, false, false, true, false, false, true]);
+        assert_eq!(sampler.hidden(), 4);
+    }
+
+    #[test]
+    fn test_head() {
+        let mut sampler = RecvSampler::new(RecvPolicy::Head(2));
+        let admitted = std::iter::repeat_with(|| sampler.admit())
+            .take(4)
+            .collect::<Vec<_>>();
+        assert_eq!(admitted, [true, true, false, false]);
+        assert_eq!(sampler.hidden(), 2);
+    }
+
+    #[test]
+    fn test_burst() {
+        let mut burst = BurstQuiet::new(NonZeroU32::new(3).unwrap(), MemoryBudget::default());
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let mut states = Vec::new();
+        for t in [0, 100, 200, 300, 400] {
+            assert_eq!(burst.end(ms(t)), None);
+            let state = burst.observe(ms(t));
+            if state != BurstState::Calm {
+                burst.hold(format!("< line {t}\n"));
+            }
+            states.push(state);
+        }
+        assert_eq!(
+            states,
+            [
+                BurstState::Calm,
+                BurstState::Calm,
+                BurstState::Calm,
+                BurstState::Started,
+                BurstState::Ongoing
+            ]
+        );
+        // With no more lines, the window holds one line or fewer (half the
+        // threshold) once the line at 300ms falls out of it
+        assert_eq!(burst.end_at(), Some(ms(1300)));
+        assert_eq!(burst.end(ms(1100)), None);
+        assert_eq!(burst.end(ms(1300)), Some(2));
+        assert_eq!(burst.end_at(), None);
+        assert_eq!(
+            burst.held(),
+            (
+                vec!["< line 300\n".to_owned(), "< line 400\n".to_owned()],
+                2
+            )
+        );
+        assert_eq!(burst.observe(ms(1200)), BurstState::Calm);
+        assert_eq!(burst.finish(), None);
+    }
+
+    #[test]
+    fn test_burst_finish() {
+        let mut burst = BurstQuiet::new(NonZeroU32::new(1).unwrap(), MemoryBudget::default());
+        let now = Instant::now();
+        assert_eq!(burst.observe(now), BurstState::Calm);
+        assert_eq!(burst.observe(now), BurstState::Started);
+        burst.hold(String::from("< x\n"));
+        assert_eq!(burst.finish(), Some(1));
+        assert_eq!(burst.finish(), None);
+    }
+
+    #[test]
+    fn test_burst_memory_budget() {
+        let budget = MemoryBudget::new(Some(10));
+        let mut burst = BurstQuiet::new(NonZeroU32::new(1).unwrap(), budget.clone());
+        let now = Instant::now();
+        assert_eq!(burst.observe(now), BurstState::Calm);
+        assert_eq!(burst.observe(now), BurstState::Started);
+        for text in ["< foo\n", "< bar\n", "< baz\n"] {
+            burst.hold(String::from(text));
+        }
+        assert_eq!(burst.held(), (vec![String::from("< baz\n")], 3));
+        assert_eq!(budget.evictions(), 2);
+    }
+
+    #[rstest]
+    #[case(1, "\u{2026} 1 line received; use /show-burst to display them")]
+    #[case(999, "\u{2026} 999 lines received; use /show-burst to display them")]
+    #[case(1243, "\u{2026} 1,243 lines received; use /show-burst to display them")]
+    #[case(
+        1234567,
+        "\u{2026} 1,234,567 lines received; use /show-burst to display them"
+    )]
+    fn test_burst_message(#[case] count: u64, #[case] msg: &str) {
+        assert_eq!(burst_message(count), msg);
+    }
+
+    #[test]
+    fn test_all() {
+        let mut sampler = RecvSampler::default();
+        assert!((0..5).all(|_| sampler.admit()));
+        assert_eq!(sampler.hidden(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::errors::{error_chain, InetError, SUCCESS_EXIT};
+use crate::events::fmt_ms;
+use crate::runner::Connector;
+use crate::tls;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// A stage of connecting to a server, as checked by `confab doctor`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Step {
+    Dns,
+    Connect,
+    Tls,
+}
+
+impl Step {
+    fn label(self) -> &'static str {
+        match self {
+            Step::Dns => "DNS lookup",
+            Step::Connect => "TCP connect",
+            Step::Tls => "TLS handshake",
+        }
+    }
+}
+
+/// The outcome of one step
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Check {
+    pub(crate) step: Step,
+    pub(crate) elapsed: Duration,
+    /// A description of what the step found, or the error it failed with
+    pub(crate) outcome: Result<String, String>,
+}
+
+/// The results of checking connectivity to a server
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Diagnosis {
+    pub(crate) endpoint: String,
+    pub(crate) checks: Vec<Check>,
+    /// Steps that were not attempted because an earlier step failed
+    pub(crate) skipped: Vec<Step>,
+    pub(crate) total: Duration,
+    /// Exit status for the program
+    pub(crate) exit_code: u8,
+}
+
+impl Diagnosis {
+    fn new(connector: &Connector) -> Diagnosis {
+        Diagnosis {
+            endpoint: format!("{}:{}", connector.host, connector.port),
+            checks: Vec::new(),
+            skipped: Vec::new(),
+            total: Duration::ZERO,
+            exit_code: SUCCESS_EXIT,
+        }
+    }
+
+    /// Record the outcome of a step.  Returns the step's value if it
+    /// succeeded.
+    fn record<T>(
+        &mut self,
+        step: Step,
+        start: Instant,
+        r: Result<T, InetError>,
+        describe: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        let elapsed = start.elapsed();
+        match r {
+            Ok(value) => {
+                self.checks.push(Check {
+                    step,
+                    elapsed,
+                    outcome: Ok(describe(&value)),
+                });
+                Some(value)
+            }
+            Err(e) => {
+                self.exit_code = e.exit_code();
+                self.checks.push(Check {
+                    step,
+                    elapsed,
+                    outcome: Err(error_chain(&e)),
+                });
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checking connection to {} ...", self.endpoint)?;
+        writeln!(f)?;
+        for check in &self.checks {
+            let (status, detail) = match &check.outcome {
+                Ok(detail) => ("ok", detail),
+                Err(detail) => ("FAILED", detail),
+            };
+            writeln!(
+                f,
+                "{:<14} {:<7} {:>9}  {detail}",
+                check.step.label(),
+                status,
+                fmt_ms(check.elapsed)
+            )?;
+        }
+        for step in &self.skipped {
+            writeln!(f, "{:<14} {:<7} {:>9}", step.label(), "skipped", "-")?;
+        }
+        writeln!(f, "{:<14} {:<7} {:>9}", "Total", "", fmt_ms(self.total))
+    }
+}
+
+/// Go through the steps of connecting to the server described by `connector`
+/// — DNS resolution, TCP connection, and (if enabled) the TLS handshake — and
+/// report how long each one took and whether it succeeded
+pub(crate) async fn diagnose(connector: &Connector) -> Diagnosis {
+    let mut diag = Diagnosis::new(connector);
+    let begin = Instant::now();
+    diagnose_steps(connector, &mut diag).await;
+    diag.total = begin.elapsed();
+    let attempted = diag.checks.iter().map(|c| c.step).collect::<Vec<_>>();
+    diag.skipped = [Step::Dns, Step::Connect, Step::Tls]
+        .into_iter()
+        .filter(|&s| !attempted.contains(&s) && (s != Step::Tls || connector.tls))
+        .collect();
+    diag
+}
+
+async fn diagnose_steps(connector: &Connector, diag: &mut Diagnosis) {
+    let start = Instant::now();
+    let r = connector.resolve().await;
+    let Some(addrs) = diag.record(Step::Dns, start, r, |addrs| {
+        let mut detail = addrs
+            .iter()
+            .map(|a| a.ip().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if connector.override_for().is_some() {
+            detail.push_str(" (from --resolve)");
+        }
+        detail
+    }) else {
+        return;
+    };
+    let start = Instant::now();
+    let r = match TcpStream::connect(&*addrs).await {
+        Ok(conn) => connector
+            .apply_socket_options(&conn)
+            .map_err(InetError::SocketOptions)
+            .and_then(|()| {
+                let peer = conn.peer_addr().map_err(InetError::PeerAddr)?;
+                Ok((conn, peer))
+            }),
+        Err(e) => Err(InetError::Connect(e)),
+    };
+    let Some((conn, _)) = diag.record(Step::Connect, start, r, |(_, peer): &(_, SocketAddr)| {
+        format!("connected to {peer}")
+    }) else {
+        return;
+    };
+    if connector.tls {
+        let start = Instant::now();
+        let (conn, _) = tls::CleartextGuard::new(conn);
+        let servername = connector.servername.as_ref().unwrap_or(&connector.host);
+        let r = tls::connect(conn, servername, connector.sni, &connector.tls_context)
+            .await
+            .map_err(InetError::Tls);
+        diag.record(Step::Tls, start, r, |_| {
+            format!("certificate valid for {servername}")
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::CONNECT_ERROR_EXIT;
+
+    #[test]
+    fn test_display() {
+        let diag = Diagnosis {
+            endpoint: String::from("example.com:443"),
+            checks: vec![
+                Check {
+                    step: Step::Dns,
+                    elapsed: Duration::from_micros(1234),
+                    outcome: Ok(String::from("192.0.2.1")),
+                },
+                Check {
+                    step: Step::Connect,
+                    elapsed: Duration::from_millis(20),
+                    outcome: Err(String::from(
+                        "failed to connect to server: Connection refused",
+                    )),
+                },
+            ],
+            skipped: vec![Step::Tls],
+            total: Duration::from_micros(21234),
+            exit_code: CONNECT_ERROR_EXIT,
+        };
+        assert_eq!(
+            diag.to_string(),
+            concat!(
+                "Checking connection to example.com:443 ...\n",
+                "\n",
+                "DNS lookup     ok          1.2ms  192.0.2.1\n",
+                "TCP connect    FAILED     20.0ms  failed to connect to server: Connection refused\n",
+                "TLS handshake  skipped         -\n",
+                "Total                     21.2ms\n",
+            )
+        );
+    }
+}
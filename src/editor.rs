@@ -0,0 +1,1320 @@
+use crate::complete::{common_prefix, Completer};
+use crate::keymap::{Action, Binding, EditMode, Key, KeyMap};
+use crate::tui::{Screen, Scroll};
+use crate::util::{cursor_position, end_position};
+use crossterm::{
+    cursor::{MoveTo, MoveToColumn, MoveUp},
+    event::{DisableBracketedPaste, EnableBracketedPaste, Event, EventStream, KeyEventKind},
+    style::Print,
+    terminal::{self, Clear, ClearType},
+    ExecutableCommand, QueueableCommand,
+};
+use futures_util::StreamExt;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, Stdout, Write};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc;
+use tokio_util::sync::PollSender;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum number of chunks of output written to a `SharedWriter` that may be
+/// waiting to be printed by the `Editor`
+const OUTPUT_BUFFER: usize = 64;
+
+/// Maximum number of entries kept in the input history
+const MAX_HISTORY: usize = 1000;
+
+/// Maximum number of killed pieces of text kept for yanking
+const MAX_KILLS: usize = 32;
+
+/// Events returned by [`Editor::readline()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum EditorEvent {
+    /// The user entered a line of text
+    Line(String),
+    /// The user pasted in one or more complete lines of text at once
+    Paste(Vec<String>),
+    /// The user pressed Ctrl-D, or the editor can no longer be used
+    Eof,
+    /// The user pressed Ctrl-C
+    Interrupted,
+}
+
+/// A line editor that reads lines of input from the terminal while output is
+/// written above the prompt concurrently.
+///
+/// Output written to the associated [`SharedWriter`] is only printed while
+/// [`Editor::readline()`] or [`Editor::flush()`] is running.  The terminal is
+/// in raw mode with bracketed paste enabled for as long as the `Editor`
+/// exists.
+///
+/// If the `Editor` is created with `tui` set, the prompt is instead shown at
+/// the bottom of a full-screen [`Screen`], with output shown in a scrollable
+/// pane above it.
+pub(crate) struct Editor {
+    term: Stdout,
+    events: EventStream,
+    receiver: mpsc::Receiver<Vec<u8>>,
+    line: LineState,
+    screen: Option<Screen>,
+}
+
+impl Editor {
+    /// Put the terminal into raw mode and display the prompt
+    pub(crate) fn new(
+        prompt: String,
+        keymap: KeyMap,
+        completer: Completer,
+        tui: bool,
+    ) -> io::Result<(Editor, SharedWriter)> {
+        let (sender, receiver) = mpsc::channel(OUTPUT_BUFFER);
+        terminal::enable_raw_mode()?;
+        // Not every terminal supports bracketed paste, in which case pasted
+        // lines are simply read as though they had been typed.
+        let _ = io::stdout().execute(EnableBracketedPaste);
+        let (width, _) = terminal::size()?;
+        let mut editor = Editor {
+            term: io::stdout(),
+            events: EventStream::new(),
+            receiver,
+            line: LineState::new(prompt, keymap, completer, width),
+            screen: None,
+        };
+        if tui {
+            editor.screen = Some(Screen::new()?);
+            editor.draw()?;
+        } else {
+            editor.line.render(&mut editor.term)?;
+            editor.term.flush()?;
+        }
+        let writer = SharedWriter {
+            buffer: Vec::new(),
+            sender: PollSender::new(sender),
+        };
+        Ok((editor, writer))
+    }
+
+    /// Print output & handle key presses until the user enters a line or
+    /// presses Ctrl-C or Ctrl-D.  Once all `SharedWriter`s have been dropped
+    /// and their output printed, this returns `EditorEvent::Eof`.
+    ///
+    /// This method is cancel-safe.
+    pub(crate) async fn readline(&mut self) -> io::Result<EditorEvent> {
+        loop {
+            tokio::select! {
+                event = self.events.next() => match event {
+                    Some(Ok(event)) => {
+                        if let Some(ev) = self.handle_event(event)? {
+                            return Ok(ev);
+                        }
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(EditorEvent::Eof),
+                },
+                buf = self.receiver.recv() => match buf {
+                    Some(buf) => self.print_data(&buf)?,
+                    None => return Ok(EditorEvent::Eof),
+                },
+            }
+        }
+    }
+
+    /// Print all output written so far and erase the prompt
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        if let Some(screen) = self.screen.as_mut() {
+            while let Ok(buf) = self.receiver.try_recv() {
+                screen.push_output(&buf);
+            }
+            return self.draw();
+        }
+        while let Ok(buf) = self.receiver.try_recv() {
+            self.line.print_data(&buf, &mut self.term)?;
+        }
+        self.line.clear(&mut self.term)?;
+        self.term.flush()
+    }
+
+    fn handle_event(&mut self, event: Event) -> io::Result<Option<EditorEvent>> {
+        let Some(screen) = self.screen.as_mut() else {
+            let r = self.line.handle_event(event, &mut self.term);
+            self.term.flush()?;
+            return r;
+        };
+        let r = match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                let r = self.line.handle_key(Key::from(key));
+                if let Some(scroll) = self.line.scroll.take() {
+                    screen.scroll(scroll);
+                }
+                if std::mem::take(&mut self.line.clear_screen) {
+                    screen.clear()?;
+                }
+                if let Some(listing) = self.line.listing.take() {
+                    screen.push_output(listing.as_bytes());
+                }
+                r
+            }
+            Event::Paste(text) => self.line.paste(&text),
+            Event::Resize(..) => None,
+            _ => return Ok(None),
+        };
+        self.draw()?;
+        Ok(r)
+    }
+
+    fn print_data(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(screen) = self.screen.as_mut() {
+            screen.push_output(buf);
+            self.draw()
+        } else {
+            self.line.print_data(buf, &mut self.term)?;
+            self.term.flush()
+        }
+    }
+
+    /// Redraw the full-screen interface, if any
+    fn draw(&mut self) -> io::Result<()> {
+        match self.screen.as_mut() {
+            Some(screen) => {
+                screen.draw(&self.line.shown_prompt(), &self.line.line, self.line.cursor)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Change the prompt shown before the line being edited and redraw it
+    pub(crate) fn set_prompt(&mut self, prompt: String) -> io::Result<()> {
+        self.line.prompt = prompt;
+        if self.screen.is_some() {
+            return self.draw();
+        }
+        self.line.clear(&mut self.term)?;
+        self.line.render(&mut self.term)?;
+        self.term.flush()
+    }
+
+    pub(crate) fn add_history_entry(&mut self, entry: String) {
+        self.line.history.add(entry);
+    }
+
+    /// Return the entries in the input history, oldest first
+    pub(crate) fn history(&self) -> Vec<String> {
+        self.line.history.entries.iter().cloned().collect()
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        let _ = io::stdout().execute(DisableBracketedPaste);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A handle for writing output above an [`Editor`]'s prompt.  Output is sent
+/// to the editor whenever the writer is flushed.
+#[derive(Debug)]
+pub(crate) struct SharedWriter {
+    buffer: Vec<u8>,
+    sender: PollSender<Vec<u8>>,
+}
+
+impl AsyncWrite for SharedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.buffer.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.sender.poll_reserve(cx)).map_err(|_| editor_closed())?;
+        this.sender
+            .send_item(std::mem::take(&mut this.buffer))
+            .map_err(|_| editor_closed())?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+fn editor_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "line editor has closed")
+}
+
+/// A pending Vi operator awaiting a motion
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+}
+
+impl Operator {
+    fn action(self) -> Action {
+        match self {
+            Operator::Delete => Action::ViDeleteTo,
+            Operator::Change => Action::ViChangeTo,
+        }
+    }
+}
+
+/// The line being edited and the state of its display on the terminal.
+///
+/// The terminal cursor is only ever moved relative to its current position,
+/// so that the editor works within a scrolling region of any size.
+#[derive(Clone, Debug)]
+struct LineState {
+    prompt: String,
+    line: String,
+    /// Byte offset of the cursor in `line`, always on a grapheme boundary
+    cursor: usize,
+    keymap: KeyMap,
+    history: History,
+    /// The incremental history search in progress, if any
+    search: Option<Search>,
+    completer: Completer,
+    /// Whether the last key pressed performed completion
+    completing: bool,
+    kill_ring: KillRing,
+    /// Whether the last key pressed killed text
+    killing: bool,
+    /// If the last key pressed yanked text, this is the byte range in `line`
+    /// of the yanked text
+    yanked: Option<(usize, usize)>,
+    /// Completions to list above the prompt
+    listing: Option<String>,
+    /// Set when the user asks to scroll the output pane, which only exists
+    /// under `--tui`
+    scroll: Option<Scroll>,
+    /// Whether Vi command mode is active
+    command_mode: bool,
+    pending: Option<Operator>,
+    /// Set when the screen needs to be cleared before redrawing
+    clear_screen: bool,
+    /// Terminal row of the cursor, relative to the row the prompt starts on
+    row: usize,
+    /// Terminal width in columns
+    width: usize,
+    /// If the last output printed did not end in a newline, this is the
+    /// column at which it ended, on the row just above the prompt
+    partial_output: Option<usize>,
+}
+
+impl LineState {
+    fn new(prompt: String, keymap: KeyMap, completer: Completer, width: u16) -> LineState {
+        LineState {
+            prompt,
+            line: String::new(),
+            cursor: 0,
+            keymap,
+            history: History::default(),
+            search: None,
+            completer,
+            completing: false,
+            kill_ring: KillRing::default(),
+            killing: false,
+            yanked: None,
+            listing: None,
+            scroll: None,
+            command_mode: false,
+            pending: None,
+            clear_screen: false,
+            row: 0,
+            width: usize::from(width).max(1),
+            partial_output: None,
+        }
+    }
+
+    /// Erase the prompt & line, leaving the cursor at the start of the row
+    /// where the prompt began
+    fn clear<W: Write>(&mut self, term: &mut W) -> io::Result<()> {
+        if self.row > 0 {
+            term.queue(MoveUp(to_u16(self.row)))?;
+        }
+        term.queue(MoveToColumn(0))?
+            .queue(Clear(ClearType::FromCursorDown))?;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Draw the prompt & line, starting at the cursor's current position,
+    /// which must be at the start of a row, and then move the cursor to its
+    /// place in the line
+    fn render<W: Write>(&mut self, term: &mut W) -> io::Result<()> {
+        let prompt = self.shown_prompt().into_owned();
+        term.queue(Print(&prompt))?.queue(Print(&self.line))?;
+        let (mut end_row, end_column) =
+            end_position(&format!("{prompt}{}", self.line), 0, self.width);
+        if end_column == self.width {
+            // The terminal leaves the cursor on the last column of a full row
+            // until something more is printed; move it to the next row
+            // ourselves so that row counting is consistent.
+            term.queue(Print("\r\n"))?;
+            end_row += 1;
+        }
+        let (row, column) = cursor_position(
+            &format!("{prompt}{}", &self.line[..self.cursor]),
+            &self.line[self.cursor..],
+            self.width,
+        );
+        self.row = row;
+        let up = end_row - row;
+        if up > 0 {
+            term.queue(MoveUp(to_u16(up)))?;
+        }
+        term.queue(MoveToColumn(to_u16(column)))?;
+        Ok(())
+    }
+
+    /// The prompt to display before the line, which shows the query while a
+    /// history search is in progress
+    fn shown_prompt(&self) -> Cow<'_, str> {
+        match self.search.as_ref() {
+            Some(search) => {
+                let failed = if search.failed { "failed " } else { "" };
+                Cow::Owned(format!("({failed}reverse-i-search)'{}': ", search.query))
+            }
+            None => Cow::Borrowed(&self.prompt),
+        }
+    }
+
+    /// Print output above the prompt
+    fn print_data<W: Write>(&mut self, data: &[u8], term: &mut W) -> io::Result<()> {
+        self.clear(term)?;
+        let partial = self.partial_output.take();
+        if let Some(column) = partial {
+            term.queue(MoveUp(1))?.queue(MoveToColumn(to_u16(column)))?;
+        }
+        // Newlines in raw mode don't return the cursor to the first column.
+        for chunk in data.split_inclusive(|&b| b == b'\n') {
+            term.write_all(chunk)?;
+            if chunk.ends_with(b"\n") {
+                term.queue(MoveToColumn(0))?;
+            }
+        }
+        if let Some(tail) = data
+            .rsplit(|&b| b == b'\n')
+            .next()
+            .filter(|t| !t.is_empty())
+        {
+            let start = if data.contains(&b'\n') {
+                0
+            } else {
+                partial.unwrap_or_default()
+            };
+            let (_, column) = end_position(&String::from_utf8_lossy(tail), start, self.width);
+            let column = column % self.width;
+            if column > 0 {
+                self.partial_output = Some(column);
+            }
+            term.queue(Print("\r\n"))?;
+        }
+        self.render(term)
+    }
+
+    fn handle_event<W: Write>(
+        &mut self,
+        event: Event,
+        term: &mut W,
+    ) -> io::Result<Option<EditorEvent>> {
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                let r = self.handle_key(Key::from(key));
+                // There is no output pane to scroll outside of `--tui`.
+                self.scroll = None;
+                self.clear(term)?;
+                if std::mem::take(&mut self.clear_screen) {
+                    term.queue(Clear(ClearType::All))?.queue(MoveTo(0, 0))?;
+                }
+                self.render(term)?;
+                if let Some(listing) = self.listing.take() {
+                    self.print_data(listing.as_bytes(), term)?;
+                }
+                Ok(r)
+            }
+            Event::Paste(text) => {
+                self.clear(term)?;
+                let r = self.paste(&text);
+                self.render(term)?;
+                Ok(r)
+            }
+            Event::Resize(width, _) => {
+                self.clear(term)?;
+                self.width = usize::from(width).max(1);
+                self.render(term)?;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Update the line in response to a key press
+    fn handle_key(&mut self, key: Key) -> Option<EditorEvent> {
+        let completing = std::mem::take(&mut self.completing);
+        let killing = std::mem::take(&mut self.killing);
+        let yanked = self.yanked.take();
+        if self.search.is_some() && self.search_key(key) {
+            return None;
+        }
+        if let Some(op) = self.pending.take() {
+            self.apply_operator(op, key);
+            return None;
+        }
+        match self.keymap.get(key, self.command_mode).cloned() {
+            Some(Binding::Action(Action::Complete)) => {
+                self.complete(completing);
+                None
+            }
+            Some(Binding::Action(action)) => {
+                let r = self.perform(action, killing, yanked);
+                if self.command_mode {
+                    self.clamp_cursor();
+                }
+                r
+            }
+            Some(Binding::Insert(text)) => {
+                self.insert(&text);
+                None
+            }
+            Some(Binding::Submit(text)) => Some(EditorEvent::Line(text)),
+            None => {
+                if let Some(c) = key.printable().filter(|_| !self.command_mode) {
+                    self.insert(c.encode_utf8(&mut [0; 4]));
+                }
+                None
+            }
+        }
+    }
+
+    /// Carry out an editing action.  `killing` and `yanked` are the values of
+    /// the fields of the same names as of before the key was pressed.
+    fn perform(
+        &mut self,
+        action: Action,
+        killing: bool,
+        yanked: Option<(usize, usize)>,
+    ) -> Option<EditorEvent> {
+        match action {
+            Action::AcceptLine => {
+                let line = std::mem::take(&mut self.line);
+                self.reset();
+                return Some(EditorEvent::Line(line));
+            }
+            Action::Interrupt => {
+                self.line.clear();
+                self.reset();
+                return Some(EditorEvent::Interrupted);
+            }
+            Action::EndOfFile => {
+                self.line.clear();
+                self.reset();
+                return Some(EditorEvent::Eof);
+            }
+            Action::BackwardChar => self.cursor = self.prev_grapheme(),
+            Action::ForwardChar => self.cursor = self.next_grapheme(),
+            Action::BackwardWord => self.cursor = self.prev_word(),
+            Action::ForwardWord => self.cursor = self.word_end(),
+            Action::ViNextWord => self.cursor = self.next_word(),
+            Action::BeginningOfLine => self.cursor = 0,
+            Action::EndOfLine => self.cursor = self.line.len(),
+            Action::BackwardDeleteChar => self.delete(self.prev_grapheme(), self.cursor),
+            Action::DeleteChar => self.delete(self.cursor, self.next_grapheme()),
+            Action::UnixLineDiscard => self.kill(0, self.cursor, killing),
+            Action::UnixWordRubout => self.kill(self.prev_word(), self.cursor, killing),
+            Action::KillLine => self.kill(self.cursor, self.line.len(), killing),
+            Action::KillWord => self.kill(self.cursor, self.word_end(), killing),
+            Action::Yank => self.yank(),
+            Action::YankPop => self.yank_pop(yanked),
+            Action::PreviousHistory => {
+                if let Some(entry) = self.history.previous(&self.line) {
+                    self.line = entry;
+                    self.cursor = self.line.len();
+                }
+            }
+            Action::NextHistory => {
+                if let Some(entry) = self.history.next() {
+                    self.line = entry;
+                    self.cursor = self.line.len();
+                }
+            }
+            Action::ReverseSearchHistory => {
+                self.search = Some(Search {
+                    saved: (self.line.clone(), self.cursor),
+                    ..Search::default()
+                });
+            }
+            Action::ClearScreen => self.clear_screen = true,
+            Action::Complete => self.complete(false),
+            Action::ScrollUp => self.scroll = Some(Scroll::Up),
+            Action::ScrollDown => self.scroll = Some(Scroll::Down),
+            Action::ViMovementMode => {
+                if self.keymap.mode() == EditMode::Vi && !self.command_mode {
+                    self.command_mode = true;
+                    self.cursor = self.prev_grapheme();
+                }
+            }
+            Action::ViInsertionMode => self.command_mode = false,
+            Action::ViAppendMode => {
+                self.cursor = self.next_grapheme();
+                self.command_mode = false;
+            }
+            Action::ViInsertBeg => {
+                self.cursor = 0;
+                self.command_mode = false;
+            }
+            Action::ViAppendEol => {
+                self.cursor = self.line.len();
+                self.command_mode = false;
+            }
+            Action::ViDeleteTo => self.pending = Some(Operator::Delete),
+            Action::ViChangeTo => self.pending = Some(Operator::Change),
+        }
+        None
+    }
+
+    /// Handle a key pressed while a history search is in progress.  Typing
+    /// extends the query, the key for `reverse-search-history` moves to the
+    /// next older match, and Esc or Ctrl-G cancels the search.  Any other key
+    /// ends the search, leaving the matched entry in the line, and is then
+    /// handled as usual, in which case `false` is returned.
+    fn search_key(&mut self, key: Key) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        if key.aborts_search() {
+            self.cancel_search();
+            return true;
+        }
+        if let Some(c) = key.printable() {
+            search.query.push(c);
+            // The current match may still match the longer query
+            let before = search.found.map_or(self.history.entries.len(), |i| i + 1);
+            self.search_history(before);
+            return true;
+        }
+        match self.keymap.get(key, self.command_mode) {
+            Some(Binding::Action(Action::ReverseSearchHistory)) => {
+                let before = search.found.unwrap_or(self.history.entries.len());
+                self.search_history(before);
+                true
+            }
+            Some(Binding::Action(Action::BackwardDeleteChar)) => {
+                search.query.pop();
+                self.search_history(self.history.entries.len());
+                true
+            }
+            Some(Binding::Action(Action::Interrupt)) => {
+                self.cancel_search();
+                false
+            }
+            _ => {
+                self.search = None;
+                false
+            }
+        }
+    }
+
+    /// Show the newest history entry before index `before` that contains the
+    /// search query, with the cursor at the start of the match.  If there is
+    /// no such entry, the current match stays shown, and the search is marked
+    /// as failed.
+    fn search_history(&mut self, before: usize) {
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        if search.query.is_empty() {
+            search.found = None;
+            search.failed = false;
+            (self.line, self.cursor) = search.saved.clone();
+            return;
+        }
+        match self.history.search(&search.query, before) {
+            Some((i, offset)) => {
+                search.found = Some(i);
+                search.failed = false;
+                self.line.clone_from(&self.history.entries[i]);
+                self.cursor = offset;
+            }
+            None => search.failed = true,
+        }
+    }
+
+    /// End the history search, restoring the line as it was before the
+    /// search began
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            (self.line, self.cursor) = search.saved;
+        }
+    }
+
+    /// Apply a Vi operator to the text between the cursor and the position
+    /// that the action bound to `key` would move to.  Pressing the operator's
+    /// own key again applies it to the whole line.
+    fn apply_operator(&mut self, op: Operator, key: Key) {
+        let Some(&Binding::Action(action)) = self.keymap.get(key, true) else {
+            return;
+        };
+        let target = match action {
+            a if a == op.action() => {
+                self.cursor = 0;
+                self.line.len()
+            }
+            Action::BackwardChar => self.prev_grapheme(),
+            Action::ForwardChar => self.next_grapheme(),
+            Action::BackwardWord => self.prev_word(),
+            Action::ForwardWord => self.word_end(),
+            // As in vi, "cw" changes only to the end of the word.
+            Action::ViNextWord if op == Operator::Change => self.word_end(),
+            Action::ViNextWord => self.next_word(),
+            Action::BeginningOfLine => 0,
+            Action::EndOfLine => self.line.len(),
+            _ => return,
+        };
+        self.delete(self.cursor.min(target), self.cursor.max(target));
+        match op {
+            Operator::Delete => self.clamp_cursor(),
+            Operator::Change => self.command_mode = false,
+        }
+    }
+
+    /// Complete the word before the cursor.  If there are multiple possible
+    /// completions, the word is extended to their longest common prefix; if
+    /// that makes no progress and the previous key pressed also performed
+    /// completion (`again`), the completions are listed instead.
+    fn complete(&mut self, again: bool) {
+        let before = &self.line[..self.cursor];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &before[start..];
+        let first = before[..start].trim().is_empty();
+        let candidates = self.completer.complete(
+            word,
+            first,
+            self.history.entries.iter().rev().map(String::as_str),
+        );
+        match &candidates[..] {
+            [] => (),
+            [only] => {
+                let completion = format!("{only} ");
+                self.delete(start, self.cursor);
+                self.insert(&completion);
+            }
+            _ => {
+                let prefix = common_prefix(&candidates);
+                if prefix.len() > word.len() {
+                    let prefix = prefix.to_owned();
+                    self.delete(start, self.cursor);
+                    self.insert(&prefix);
+                } else if again {
+                    self.listing = Some(format!("{}\n", candidates.join("  ")));
+                }
+                self.completing = true;
+            }
+        }
+    }
+
+    /// Clear the editing state after a line is entered or discarded
+    fn reset(&mut self) {
+        self.cursor = 0;
+        self.command_mode = false;
+        self.pending = None;
+        self.search = None;
+        self.history.reset();
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.line.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Insert pasted text at the cursor.  If the text contains any line
+    /// breaks, the completed lines (the first of which includes the line
+    /// being edited) are returned all together, and any text after the last
+    /// line break is left in the buffer.
+    fn paste(&mut self, text: &str) -> Option<EditorEvent> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let Some((body, rest)) = text.rsplit_once('\n') else {
+            self.insert(&text);
+            return None;
+        };
+        let mut lines = body.split('\n').map(String::from).collect::<Vec<_>>();
+        if let Some(first) = lines.first_mut() {
+            self.insert(first);
+            *first = std::mem::take(&mut self.line);
+        }
+        self.reset();
+        self.insert(rest);
+        Some(EditorEvent::Paste(lines))
+    }
+
+    /// Delete the text between the given byte offsets and place the cursor
+    /// where it was
+    fn delete(&mut self, start: usize, end: usize) {
+        self.line.drain(start..end);
+        self.cursor = start;
+    }
+
+    /// Delete the text between the given byte offsets and save it in the kill
+    /// ring.  If `append` is set because the previous key also killed text,
+    /// the text is joined to the most recent kill instead, so that a series
+    /// of kills can be yanked back all at once.
+    fn kill(&mut self, start: usize, end: usize, append: bool) {
+        let backward = start < self.cursor;
+        self.kill_ring
+            .push(&self.line[start..end], append, backward);
+        self.delete(start, end);
+        self.killing = true;
+    }
+
+    /// Insert the most recently killed text at the cursor
+    fn yank(&mut self) {
+        let Some(text) = self.kill_ring.yank().map(str::to_owned) else {
+            return;
+        };
+        let start = self.cursor;
+        self.insert(&text);
+        self.yanked = Some((start, self.cursor));
+    }
+
+    /// If the previous key yanked text (at the byte range `yanked`), replace
+    /// that text with the kill before it in the kill ring
+    fn yank_pop(&mut self, yanked: Option<(usize, usize)>) {
+        let Some((start, end)) = yanked else {
+            return;
+        };
+        let Some(text) = self.kill_ring.rotate().map(str::to_owned) else {
+            return;
+        };
+        self.line.replace_range(start..end, &text);
+        self.cursor = start + text.len();
+        self.yanked = Some((start, self.cursor));
+    }
+
+    /// In Vi command mode, the cursor sits on a character rather than after
+    /// the end of the line.
+    fn clamp_cursor(&mut self) {
+        if self.cursor == self.line.len() {
+            self.cursor = self.prev_grapheme();
+        }
+    }
+
+    fn prev_grapheme(&self) -> usize {
+        self.line[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_grapheme(&self) -> usize {
+        self.line[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |g| self.cursor + g.len())
+    }
+
+    /// Offset of the start of the word before the cursor, where words are
+    /// delimited by whitespace
+    fn prev_word(&self) -> usize {
+        self.line[..self.cursor]
+            .trim_end()
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+
+    /// Offset of the end of the word at or after the cursor
+    fn word_end(&self) -> usize {
+        let rest = &self.line[self.cursor..];
+        let word = rest.trim_start();
+        let skipped = rest.len() - word.len();
+        self.cursor + skipped + word.find(char::is_whitespace).unwrap_or(word.len())
+    }
+
+    /// Offset of the start of the word after the one the cursor is in
+    fn next_word(&self) -> usize {
+        let rest = &self.line[self.cursor..];
+        let after = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let gap = rest[after..].len() - rest[after..].trim_start().len();
+        self.cursor + after + gap
+    }
+}
+
+/// Previously-entered lines, navigable with the Up & Down keys
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct History {
+    entries: VecDeque<String>,
+    /// Index of the entry currently shown, if any
+    position: Option<usize>,
+    /// The line that was being edited before the user started navigating
+    /// the history
+    draft: String,
+}
+
+impl History {
+    fn add(&mut self, entry: String) {
+        self.position = None;
+        if entry.is_empty() || self.entries.back() == Some(&entry) {
+            return;
+        }
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.pop_front();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.position = None;
+    }
+
+    /// Return the entry before the one currently shown.  `current` is the
+    /// line being edited, which is restored after moving past the newest
+    /// entry.
+    fn previous(&mut self, current: &str) -> Option<String> {
+        let i = match self.position {
+            None => {
+                let i = self.entries.len().checked_sub(1)?;
+                current.clone_into(&mut self.draft);
+                i
+            }
+            Some(0) => return None,
+            Some(i) => i - 1,
+        };
+        self.position = Some(i);
+        self.entries.get(i).cloned()
+    }
+
+    /// Return the index of the newest entry before index `before` that
+    /// contains `query`, along with the byte offset of the last occurrence of
+    /// `query` in it
+    fn search(&self, query: &str, before: usize) -> Option<(usize, usize)> {
+        (0..before.min(self.entries.len()))
+            .rev()
+            .find_map(|i| self.entries[i].rfind(query).map(|offset| (i, offset)))
+    }
+
+    /// Return the entry after the one currently shown
+    fn next(&mut self) -> Option<String> {
+        let i = self.position? + 1;
+        if i < self.entries.len() {
+            self.position = Some(i);
+            self.entries.get(i).cloned()
+        } else {
+            self.position = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+}
+
+/// An incremental search backwards through the history for entries
+/// containing a query, as with Readline's `reverse-search-history`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Search {
+    query: String,
+    /// Index in the history of the entry currently shown, if any
+    found: Option<usize>,
+    /// Whether the last attempt to find a (further) match failed
+    failed: bool,
+    /// The line & cursor position from before the search began, restored if
+    /// the search is cancelled
+    saved: (String, usize),
+}
+
+/// Text removed by the kill actions, oldest first, which can be inserted
+/// back with `yank` and then cycled through with `yank-pop`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct KillRing {
+    entries: VecDeque<String>,
+    /// Index of the entry most recently yanked
+    yanked: usize,
+}
+
+impl KillRing {
+    /// Save killed text.  If `append` is set, the text is instead joined to
+    /// the most recent entry, at its start if the text was killed `backward`
+    /// from the cursor and at its end otherwise.
+    fn push(&mut self, text: &str, append: bool, backward: bool) {
+        match self.entries.back_mut() {
+            Some(last) if append => {
+                if backward {
+                    last.insert_str(0, text);
+                } else {
+                    last.push_str(text);
+                }
+            }
+            _ if text.is_empty() => (),
+            _ => {
+                self.entries.push_back(text.to_owned());
+                if self.entries.len() > MAX_KILLS {
+                    self.entries.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Return the most recent entry
+    fn yank(&mut self) -> Option<&str> {
+        self.yanked = self.entries.len().checked_sub(1)?;
+        self.entries.get(self.yanked).map(String::as_str)
+    }
+
+    /// Return the entry before the one most recently yanked, wrapping around
+    /// to the most recent entry after the oldest
+    fn rotate(&mut self) -> Option<&str> {
+        let len = self.entries.len();
+        if len == 0 {
+            return None;
+        }
+        self.yanked = (self.yanked + len - 1) % len;
+        self.entries.get(self.yanked).map(String::as_str)
+    }
+}
+
+fn to_u16(n: usize) -> u16 {
+    u16::try_from(n).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complete::TokenIndex;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn press(state: &mut LineState, keys: &[KeyEvent]) -> Option<EditorEvent> {
+        let mut term = Vec::new();
+        let mut r = None;
+        for &key in keys {
+            r = state.handle_event(Event::Key(key), &mut term).unwrap();
+        }
+        r
+    }
+
+    fn typing(s: &str) -> Vec<KeyEvent> {
+        s.chars()
+            .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+            .collect()
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn state(mode: EditMode) -> LineState {
+        LineState::new(
+            String::from("> "),
+            KeyMap::new(mode),
+            Completer::default(),
+            80,
+        )
+    }
+
+    #[test]
+    fn test_emacs_editing() {
+        let mut st = state(EditMode::Emacs);
+        press(&mut st, &typing("hello world"));
+        press(&mut st, &[ctrl('a'), ctrl('f'), key(KeyCode::Delete)]);
+        assert_eq!(st.line, "hllo world");
+        press(&mut st, &[ctrl('e'), ctrl('w')]);
+        assert_eq!(st.line, "hllo ");
+        press(&mut st, &[key(KeyCode::Left), key(KeyCode::Backspace)]);
+        assert_eq!(st.line, "hll ");
+        assert_eq!(st.cursor, 3);
+        press(&mut st, &[key(KeyCode::Left), ctrl('k')]);
+        assert_eq!(st.line, "hl");
+        assert_eq!(
+            press(&mut st, &[key(KeyCode::Enter)]),
+            Some(EditorEvent::Line(String::from("hl")))
+        );
+        assert_eq!(st.line, "");
+        assert_eq!(st.cursor, 0);
+    }
+
+    #[test]
+    fn test_kill_and_yank() {
+        let alt = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT);
+        let mut st = state(EditMode::Emacs);
+        press(&mut st, &typing("foo bar baz"));
+        // Consecutive kills are yanked back together
+        press(&mut st, &[ctrl('w'), ctrl('w')]);
+        assert_eq!(st.line, "foo ");
+        press(&mut st, &[ctrl('y')]);
+        assert_eq!(st.line, "foo bar baz");
+        press(&mut st, &[ctrl('a'), alt('d')]);
+        assert_eq!(st.line, " bar baz");
+        press(&mut st, &[ctrl('e'), ctrl('y')]);
+        assert_eq!(st.line, " bar bazfoo");
+        press(&mut st, &[alt('y')]);
+        assert_eq!(st.line, " bar bazbar baz");
+        assert_eq!(st.cursor, st.line.len());
+        press(&mut st, &[alt('y')]);
+        assert_eq!(st.line, " bar bazfoo");
+        // yank-pop does nothing unless the previous key yanked
+        press(&mut st, &[key(KeyCode::Left), alt('y')]);
+        assert_eq!(st.line, " bar bazfoo");
+        // The kill ring is kept across lines
+        press(&mut st, &[key(KeyCode::Enter), ctrl('y')]);
+        assert_eq!(st.line, "foo");
+    }
+
+    #[test]
+    fn test_words() {
+        let mut st = state(EditMode::Emacs);
+        press(&mut st, &typing("foo  bar baz"));
+        press(
+            &mut st,
+            &[KeyEvent::new(KeyCode::Char('b'), KeyModifiers::ALT)],
+        );
+        assert_eq!(st.cursor, 9);
+        press(
+            &mut st,
+            &[KeyEvent::new(KeyCode::Char('b'), KeyModifiers::ALT)],
+        );
+        assert_eq!(st.cursor, 5);
+        press(&mut st, &[ctrl('a')]);
+        press(
+            &mut st,
+            &[KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT)],
+        );
+        assert_eq!(st.cursor, 3);
+        press(
+            &mut st,
+            &[KeyEvent::new(KeyCode::Char('d'), KeyModifiers::ALT)],
+        );
+        assert_eq!(st.line, "foo baz");
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut st = state(EditMode::Emacs);
+        press(&mut st, &typing("ae\u{301}z"));
+        press(&mut st, &[key(KeyCode::Left), key(KeyCode::Backspace)]);
+        assert_eq!(st.line, "az");
+        assert_eq!(st.cursor, 1);
+    }
+
+    #[test]
+    fn test_interrupt_and_eof() {
+        let mut st = state(EditMode::Emacs);
+        press(&mut st, &typing("foo"));
+        assert_eq!(press(&mut st, &[ctrl('c')]), Some(EditorEvent::Interrupted));
+        assert_eq!(st.line, "");
+        assert_eq!(press(&mut st, &[ctrl('d')]), Some(EditorEvent::Eof));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut st = state(EditMode::Emacs);
+        st.history.add(String::from("first"));
+        st.history.add(String::from("second"));
+        press(&mut st, &typing("draft"));
+        press(&mut st, &[key(KeyCode::Up)]);
+        assert_eq!(st.line, "second");
+        press(&mut st, &[key(KeyCode::Up), key(KeyCode::Up)]);
+        assert_eq!(st.line, "first");
+        press(&mut st, &[key(KeyCode::Down)]);
+        assert_eq!(st.line, "second");
+        press(&mut st, &[key(KeyCode::Down)]);
+        assert_eq!(st.line, "draft");
+        press(&mut st, &[key(KeyCode::Down)]);
+        assert_eq!(st.line, "draft");
+    }
+
+    #[test]
+    fn test_reverse_search() {
+        let mut st = state(EditMode::Emacs);
+        st.history.add(String::from("GET /foo HTTP/1.1"));
+        st.history.add(String::from("QUIT"));
+        st.history.add(String::from("GET /bar HTTP/1.1"));
+        press(&mut st, &typing("draft"));
+        press(&mut st, &[ctrl('r')]);
+        assert_eq!(st.shown_prompt(), "(reverse-i-search)'': ");
+        press(&mut st, &typing("GET"));
+        assert_eq!(st.line, "GET /bar HTTP/1.1");
+        assert_eq!(st.cursor, 0);
+        assert_eq!(st.shown_prompt(), "(reverse-i-search)'GET': ");
+        press(&mut st, &[ctrl('r')]);
+        assert_eq!(st.line, "GET /foo HTTP/1.1");
+        press(&mut st, &[ctrl('r')]);
+        assert_eq!(st.line, "GET /foo HTTP/1.1");
+        assert_eq!(st.shown_prompt(), "(failed reverse-i-search)'GET': ");
+        press(&mut st, &[key(KeyCode::Backspace)]);
+        press(&mut st, &typing("T /f"));
+        assert_eq!(st.line, "GET /foo HTTP/1.1");
+        assert_eq!(st.shown_prompt(), "(reverse-i-search)'GET /f': ");
+        press(&mut st, &[ctrl('g')]);
+        assert_eq!(st.line, "draft");
+        assert_eq!(st.cursor, 5);
+        assert_eq!(st.shown_prompt(), "> ");
+        press(&mut st, &[ctrl('u'), ctrl('r')]);
+        press(&mut st, &typing("QU"));
+        press(&mut st, &[ctrl('e')]);
+        assert_eq!(st.shown_prompt(), "> ");
+        assert_eq!(st.cursor, 4);
+        press(&mut st, &[ctrl('r')]);
+        press(&mut st, &typing("bar"));
+        assert_eq!(
+            press(&mut st, &[key(KeyCode::Enter)]),
+            Some(EditorEvent::Line(String::from("GET /bar HTTP/1.1")))
+        );
+    }
+
+    #[test]
+    fn test_custom_bindings() {
+        let mut keymap = KeyMap::new(EditMode::Emacs);
+        keymap
+            .extend("F2 insert \"/queue drop \"\nF3 submit /stats\n")
+            .unwrap();
+        let mut st = LineState::new(String::from("> "), keymap, Completer::default(), 80);
+        press(&mut st, &typing("x"));
+        press(&mut st, &[key(KeyCode::F(2))]);
+        assert_eq!(st.line, "x/queue drop ");
+        assert_eq!(
+            press(&mut st, &[key(KeyCode::F(3))]),
+            Some(EditorEvent::Line(String::from("/stats")))
+        );
+        assert_eq!(st.line, "x/queue drop ");
+    }
+
+    #[test]
+    fn test_complete() {
+        let tokens = TokenIndex::default();
+        let mut st = LineState::new(
+            String::from("> "),
+            KeyMap::default(),
+            Completer::new(tokens.clone()),
+            80,
+        );
+        tokens.record("Created obj-1234 and obj-1299");
+        press(&mut st, &typing("/st"));
+        press(&mut st, &[key(KeyCode::Tab)]);
+        assert_eq!(st.line, "/stats ");
+        press(&mut st, &[ctrl('u')]);
+        press(&mut st, &typing("GET o"));
+        press(&mut st, &[key(KeyCode::Tab)]);
+        assert_eq!(st.line, "GET obj-12");
+        let mut term = Vec::new();
+        st.handle_event(Event::Key(key(KeyCode::Tab)), &mut term)
+            .unwrap();
+        assert!(String::from_utf8_lossy(&term).contains("obj-1299  obj-1234\n"));
+        press(&mut st, &typing("3"));
+        press(&mut st, &[key(KeyCode::Tab)]);
+        assert_eq!(st.line, "GET obj-1234 ");
+    }
+
+    #[test]
+    fn test_vi_mode() {
+        let mut st = state(EditMode::Vi);
+        press(&mut st, &typing("foo bar baz"));
+        press(&mut st, &[key(KeyCode::Esc)]);
+        assert!(st.command_mode);
+        assert_eq!(st.cursor, 10);
+        press(&mut st, &typing("0w"));
+        assert_eq!(st.cursor, 4);
+        press(&mut st, &typing("dw"));
+        assert_eq!(st.line, "foo baz");
+        press(&mut st, &typing("x"));
+        assert_eq!(st.line, "foo az");
+        press(&mut st, &typing("$x"));
+        assert_eq!(st.line, "foo a");
+        assert_eq!(st.cursor, 4);
+        press(&mut st, &typing("0cwqux"));
+        assert_eq!(st.line, "qux a");
+        assert!(!st.command_mode);
+        press(&mut st, &[key(KeyCode::Esc)]);
+        press(&mut st, &typing("Aq"));
+        assert_eq!(st.line, "qux aq");
+        press(&mut st, &[key(KeyCode::Esc)]);
+        press(&mut st, &typing("dd"));
+        assert_eq!(st.line, "");
+        assert_eq!(
+            press(&mut st, &[key(KeyCode::Enter)]),
+            Some(EditorEvent::Line(String::new()))
+        );
+        assert!(!st.command_mode);
+    }
+
+    #[test]
+    fn test_render_wrapped() {
+        let mut st = LineState::new(
+            String::from("> "),
+            KeyMap::default(),
+            Completer::default(),
+            10,
+        );
+        press(&mut st, &typing("12345678"));
+        assert_eq!(st.row, 1);
+        press(&mut st, &[ctrl('a')]);
+        assert_eq!(st.row, 0);
+        let mut term = Vec::new();
+        st.clear(&mut term).unwrap();
+        assert_eq!(term, b"\x1B[1G\x1B[J");
+    }
+
+    #[test]
+    fn test_render_wide_char_at_edge() {
+        let mut st = LineState::new(
+            String::from("> "),
+            KeyMap::default(),
+            Completer::default(),
+            10,
+        );
+        // "日" does not fit in the last column of the first row, so the
+        // terminal moves it to the second row.
+        press(&mut st, &typing("1234567日"));
+        assert_eq!(st.row, 1);
+        press(&mut st, &[ctrl('b')]);
+        assert_eq!(st.row, 1);
+        let mut term = Vec::new();
+        st.render(&mut term).unwrap();
+        assert!(term.ends_with(b"\x1B[1G"));
+        press(&mut st, &[ctrl('b')]);
+        assert_eq!(st.row, 0);
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut st = state(EditMode::Emacs);
+        let mut term = Vec::new();
+        press(&mut st, &typing("echo "));
+        let r = st.handle_event(Event::Paste(String::from("foo")), &mut term);
+        assert_eq!(r.unwrap(), None);
+        assert_eq!(st.line, "echo foo");
+        let r = st.handle_event(
+            Event::Paste(String::from(" bar\r\n/stats\r\n\r\nbaz\rqu")),
+            &mut term,
+        );
+        assert_eq!(
+            r.unwrap(),
+            Some(EditorEvent::Paste(vec![
+                String::from("echo foo bar"),
+                String::from("/stats"),
+                String::new(),
+                String::from("baz"),
+            ]))
+        );
+        assert_eq!(st.line, "qu");
+        assert_eq!(st.cursor, 2);
+        press(&mut st, &typing("ux"));
+        assert_eq!(
+            press(&mut st, &[key(KeyCode::Enter)]),
+            Some(EditorEvent::Line(String::from("quux")))
+        );
+    }
+}
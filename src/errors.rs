@@ -1,6 +1,52 @@
+use crate::codec::CodecError;
+use crate::util::DecodeError;
+use std::error::Error as StdError;
 use std::io;
 use thiserror::Error;
 
+// The exit statuses documented in the README.  Errors that occur before the
+// session starts are returned from `main()` and so exit with status 1.
+
+/// Exit status used when the session ends normally
+pub(crate) const SUCCESS_EXIT: u8 = 0;
+
+/// Exit status used when resolving or connecting to the remote host fails
+pub(crate) const CONNECT_ERROR_EXIT: u8 = 2;
+
+/// Exit status used when the TLS handshake fails
+const TLS_ERROR_EXIT: u8 = 3;
+
+/// Exit status used when a network error occurs after connecting or the
+/// server does not send what was expected
+const NETWORK_ERROR_EXIT: u8 = 4;
+
+/// Exit status used when reading from or writing to the terminal or startup
+/// script fails
+const INTERFACE_ERROR_EXIT: u8 = 5;
+
+/// Exit status used when the session is ended for exceeding a byte budget
+pub(crate) const BUDGET_EXCEEDED_EXIT: u8 = 6;
+
+/// Exit status used when the session is ended by a received line that could
+/// not be decoded under `--exit-on-encoding-error`
+const DECODE_ERROR_EXIT: u8 = 7;
+
+/// Exit status used when the lines required by `--assert-recv` are not
+/// received
+const ASSERTION_FAILED_EXIT: u8 = 8;
+
+/// Exit status used when output can no longer be written because stdout was
+/// closed, matching that of a process killed by `SIGPIPE`
+const BROKEN_PIPE_EXIT: u8 = 141;
+
+/// Exit status used by `confab transcript diff` when the transcripts differ,
+/// as with diff(1)
+pub(crate) const TRANSCRIPTS_DIFFER_EXIT: u8 = 1;
+
+/// Exit status used by `confab transcript` subcommands when an error occurs,
+/// as with diff(1)
+pub(crate) const TRANSCRIPT_ERROR_EXIT: u8 = 2;
+
 #[derive(Debug, Error)]
 pub(crate) enum IoError {
     #[error(transparent)]
@@ -11,8 +57,8 @@ pub(crate) enum IoError {
 
 #[derive(Debug, Error)]
 pub(crate) enum InterfaceError {
-    #[error("failed to initialize readline facility")]
-    Init(#[source] rustyline_async::ReadlineError),
+    #[error("failed to initialize line editor")]
+    Init(#[source] io::Error),
     #[error("error reading from startup script")]
     ReadScript(#[source] io::Error),
     #[error("error reading input from terminal")]
@@ -21,16 +67,200 @@ pub(crate) enum InterfaceError {
     Write(#[source] io::Error),
 }
 
+impl InterfaceError {
+    pub(crate) fn kind(&self) -> ErrorKind {
+        ErrorKind::Interface
+    }
+
+    /// Exit status to use when the program ends due to this error
+    pub(crate) fn exit_code(&self) -> u8 {
+        if self.is_broken_pipe() {
+            BROKEN_PIPE_EXIT
+        } else {
+            INTERFACE_ERROR_EXIT
+        }
+    }
+
+    /// Returns `true` if the error was caused by the reader of our output
+    /// going away (e.g., stdout being piped to `head`), in which case the
+    /// program should exit quietly
+    pub(crate) fn is_broken_pipe(&self) -> bool {
+        matches!(self, InterfaceError::Write(e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum InetError {
+    #[error("failed to resolve remote host")]
+    Resolve(#[source] io::Error),
     #[error("failed to connect to server")]
     Connect(#[source] io::Error),
+    #[error("failed to set socket options")]
+    SocketOptions(#[source] io::Error),
     #[error("failed to get peer address")]
     PeerAddr(#[source] io::Error),
+    #[error("failed to accept connection")]
+    Accept(#[source] io::Error),
+    #[cfg(feature = "serial")]
+    #[error("failed to open serial device")]
+    Serial(#[source] tokio_serial::Error),
+    #[error("failed to start command")]
+    Spawn(#[source] io::Error),
     #[error("failed to establish TLS connection")]
     Tls(#[from] crate::tls::TlsError),
     #[error("failed to send line to server")]
     Send(#[source] io::Error),
     #[error("failed to receive line from server")]
     Recv(#[source] io::Error),
+    #[error("received text that is not valid in the selected encoding")]
+    Decode(#[source] DecodeError),
+    #[error("failed to shut down connection for writing")]
+    Shutdown(#[source] io::Error),
+    #[error("timed out waiting for banner from server")]
+    NoBanner,
+    #[error("connection closed before the expected response was received")]
+    NoResponse,
+    #[error("timed out waiting for a line matching {0:?}")]
+    AssertTimeout(String),
+    #[error("session ended before a line matching {0:?} was received")]
+    AssertUnmet(String),
+}
+
+impl InetError {
+    pub(crate) fn kind(&self) -> ErrorKind {
+        match self {
+            InetError::Resolve(_)
+            | InetError::Connect(_)
+            | InetError::SocketOptions(_)
+            | InetError::PeerAddr(_)
+            | InetError::Accept(_) => ErrorKind::Connect,
+            #[cfg(feature = "serial")]
+            InetError::Serial(_) => ErrorKind::Connect,
+            InetError::Spawn(_) => ErrorKind::Connect,
+            InetError::Tls(_) => ErrorKind::Tls,
+            InetError::Send(_) | InetError::Shutdown(_) => ErrorKind::Send,
+            InetError::Recv(_)
+            | InetError::Decode(_)
+            | InetError::NoBanner
+            | InetError::NoResponse
+            | InetError::AssertTimeout(_)
+            | InetError::AssertUnmet(_) => ErrorKind::Recv,
+        }
+    }
+
+    /// Returns `true` if the error is a failure to resolve the remote host or
+    /// to connect to it, which may succeed if tried again later
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(self, InetError::Resolve(_) | InetError::Connect(_))
+    }
+
+    /// Exit status to use when the session ends due to this error
+    pub(crate) fn exit_code(&self) -> u8 {
+        match self {
+            InetError::Resolve(_)
+            | InetError::Connect(_)
+            | InetError::SocketOptions(_)
+            | InetError::PeerAddr(_)
+            | InetError::Accept(_) => CONNECT_ERROR_EXIT,
+            #[cfg(feature = "serial")]
+            InetError::Serial(_) => CONNECT_ERROR_EXIT,
+            InetError::Spawn(_) => CONNECT_ERROR_EXIT,
+            InetError::Tls(_) => TLS_ERROR_EXIT,
+            InetError::Send(_)
+            | InetError::Recv(_)
+            | InetError::Shutdown(_)
+            | InetError::NoBanner
+            | InetError::NoResponse => NETWORK_ERROR_EXIT,
+            InetError::Decode(_) => DECODE_ERROR_EXIT,
+            InetError::AssertTimeout(_) | InetError::AssertUnmet(_) => ASSERTION_FAILED_EXIT,
+        }
+    }
+}
+
+impl From<CodecError> for InetError {
+    fn from(e: CodecError) -> InetError {
+        match e {
+            CodecError::Io(e) => InetError::Recv(e),
+            CodecError::Decode(e) => InetError::Decode(e),
+        }
+    }
+}
+
+/// A machine-readable classification of a fatal error, recorded in the
+/// `"kind"` field of "error" events
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ErrorKind {
+    /// Resolving the remote host or connecting to it failed
+    Connect,
+    /// The TLS handshake failed
+    Tls,
+    /// Sending data to the server failed
+    Send,
+    /// Receiving data from the server failed, or the server did not send
+    /// what was expected
+    Recv,
+    /// Reading from or writing to the terminal or startup script failed
+    Interface,
+}
+
+impl ErrorKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Connect => "connect",
+            ErrorKind::Tls => "tls",
+            ErrorKind::Send => "send",
+            ErrorKind::Recv => "recv",
+            ErrorKind::Interface => "interface",
+        }
+    }
+}
+
+/// Return the OS error code of the first `io::Error` in the chain of errors
+/// starting at `e`, if any
+pub(crate) fn os_error_code(e: &(dyn StdError + 'static)) -> Option<i32> {
+    std::iter::successors(Some(e), |&e| e.source())
+        .find_map(|e| e.downcast_ref::<io::Error>())
+        .and_then(io::Error::raw_os_error)
+}
+
+/// Return any additional lines of detail provided by an error in the chain
+/// of errors starting at `e`, such as why a TLS certificate was rejected
+pub(crate) fn error_details(e: &(dyn StdError + 'static)) -> Vec<String> {
+    std::iter::successors(Some(e), |&e| e.source())
+        .find_map(|e| e.downcast_ref::<crate::tls::TlsError>())
+        .map(crate::tls::TlsError::details)
+        .unwrap_or_default()
+}
+
+/// Format an error and its chain of sources as a single line, in the same
+/// manner as `anyhow`'s alternate `Display`
+pub(crate) fn error_chain(e: &(dyn StdError + 'static)) -> String {
+    std::iter::successors(Some(e), |&e| e.source())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_error() {
+        let e = InetError::Connect(io::Error::from_raw_os_error(111));
+        assert_eq!(e.kind(), ErrorKind::Connect);
+        assert_eq!(os_error_code(&e), Some(111));
+        assert!(error_chain(&e).starts_with("failed to connect to server: "));
+    }
+
+    #[test]
+    fn test_error_without_os_code() {
+        let e = InetError::NoResponse;
+        assert_eq!(e.kind(), ErrorKind::Recv);
+        assert_eq!(os_error_code(&e), None);
+        assert_eq!(
+            error_chain(&e),
+            "connection closed before the expected response was received"
+        );
+    }
 }
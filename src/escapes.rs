@@ -0,0 +1,118 @@
+use thiserror::Error;
+
+/// Expand the escape sequences in a line of input entered with `--escapes` in
+/// effect.  The following sequences are recognized:
+///
+/// - `\0`, `\a`, `\b`, `\t`, `\n`, `\v`, `\f`, `\r`, `\e` — the corresponding
+///   control characters (`\e` being ESC)
+/// - `\xHH` — the ASCII character with the given two-digit hexadecimal code
+///   point, from `\x00` to `\x7F`.  Higher values are rejected, as they would
+///   be sent as whatever bytes the encoding in use gives the corresponding
+///   character rather than as the single byte written.
+/// - `\\` and `\^` — a literal backslash or caret
+/// - `^@` through `^_` (letters in either case) and `^?` — the control
+///   characters in caret notation, as used when displaying control characters
+///
+/// A `^` that is not followed by one of the above characters is left as-is.
+pub(crate) fn unescape(s: &str) -> Result<String, EscapeError> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let Some(esc) = chars.next() else {
+                    return Err(EscapeError::Trailing);
+                };
+                out.push(match esc {
+                    '0' => '\0',
+                    'a' => '\x07',
+                    'b' => '\x08',
+                    't' => '\t',
+                    'n' => '\n',
+                    'v' => '\x0B',
+                    'f' => '\x0C',
+                    'r' => '\r',
+                    'e' => '\x1B',
+                    '\\' | '^' => esc,
+                    'x' => {
+                        let digits = chars.by_ref().take(2).collect::<String>();
+                        // from_str_radix() accepts a leading sign, so check
+                        // the digits first.
+                        let valid =
+                            digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit());
+                        match u8::from_str_radix(&digits, 16) {
+                            Ok(b) if valid && b.is_ascii() => char::from(b),
+                            Ok(_) if valid => return Err(EscapeError::NonAscii(digits)),
+                            _ => return Err(EscapeError::Hex(digits)),
+                        }
+                    }
+                    _ => return Err(EscapeError::Unknown(esc)),
+                });
+            }
+            '^' => match chars.clone().next().and_then(caret_control) {
+                Some(ctrl) => {
+                    chars.next();
+                    out.push(ctrl);
+                }
+                None => out.push('^'),
+            },
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// Returns the control character denoted by `^` followed by `c`, if any
+fn caret_control(c: char) -> Option<char> {
+    match c.to_ascii_uppercase() {
+        '?' => Some('\x7F'),
+        c @ '@'..='_' => char::from_u32(u32::from(c) & 0x1F),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum EscapeError {
+    #[error("line ends in the middle of an escape sequence")]
+    Trailing,
+    #[error("unknown escape sequence: \\{0}")]
+    Unknown(char),
+    #[error("invalid hexadecimal escape: \\x{0}; expected two hex digits")]
+    Hex(String),
+    #[error("hexadecimal escape out of range: \\x{0}; expected \\x00 through \\x7F")]
+    NonAscii(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("plain text", "plain text")]
+    #[case(r"\e[1mbold\e[m", "\x1B[1mbold\x1B[m")]
+    #[case("^[[1mbold^[[m", "\x1B[1mbold\x1B[m")]
+    #[case(r"tab\there", "tab\there")]
+    #[case(r"CRLF\r\n", "CRLF\r\n")]
+    #[case(r"nul\0", "nul\0")]
+    #[case(r"\x01\x7f\x7F\x1b", "\x01\x7F\x7F\x1B")]
+    #[case("^A^a^@^_^?", "\x01\x01\0\x1F\x7F")]
+    #[case("x^2 + ^", "x^2 + ^")]
+    #[case(r"\\e \^A", r"\e ^A")]
+    fn test_unescape(#[case] s: &str, #[case] out: &str) {
+        assert_eq!(unescape(s).unwrap(), out);
+    }
+
+    #[rstest]
+    #[case(r"foo\", EscapeError::Trailing)]
+    #[case(r"\q", EscapeError::Unknown('q'))]
+    #[case(r"\x1", EscapeError::Hex("1".into()))]
+    #[case(r"\xzz", EscapeError::Hex("zz".into()))]
+    #[case(r"\x+1", EscapeError::Hex("+1".into()))]
+    #[case(r"\x80", EscapeError::NonAscii("80".into()))]
+    #[case(r"\xe9", EscapeError::NonAscii("e9".into()))]
+    #[case(r"\xFF", EscapeError::NonAscii("FF".into()))]
+    fn test_unescape_error(#[case] s: &str, #[case] err: EscapeError) {
+        assert_eq!(unescape(s), Err(err));
+    }
+}
@@ -1,44 +1,242 @@
-use crate::util::{chomp, display_vis, now, JsonStrMap, HMS_FMT};
-use crossterm::style::{StyledContent, Stylize};
+use crate::ansi::{display_ansi, is_escape, reset_after, AnsiMode};
+use crate::commands::Setting;
+use crate::errors::{error_chain, error_details, os_error_code, ErrorKind};
+use crate::repeat::{Latency, RepeatSummary};
+use crate::sniff::BinaryProtocol;
+use crate::stats::{Direction, StatsSnapshot, Throughput, THROUGHPUT_WINDOW};
+use crate::util::{
+    chomp, display_vis, hex_bytes, now, write_json_str, DecodeError, JsonStrMap, HMS_FMT,
+    TIMESTAMP_FMT,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::Bytes;
+use confab::transcript::FORMAT_VERSION;
+use crossterm::style::{Color, StyledContent, Stylize};
 use std::fmt;
-use std::net::SocketAddr;
-use time::format_description::well_known::Rfc3339;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 use time::OffsetDateTime;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub(crate) enum Event {
+    SessionStart {
+        timestamp: OffsetDateTime,
+        host: String,
+        port: u16,
+        /// The options in effect for the session, by long name
+        options: Vec<(String, OptionValue)>,
+    },
     ConnectStart {
         timestamp: OffsetDateTime,
         host: String,
         port: u16,
     },
+    ListenStart {
+        timestamp: OffsetDateTime,
+        /// The local address on which connections are accepted
+        addr: SocketAddr,
+    },
+    Accepted {
+        timestamp: OffsetDateTime,
+        peer: SocketAddr,
+    },
+    #[cfg(feature = "serial")]
+    SerialOpened {
+        timestamp: OffsetDateTime,
+        device: String,
+        baud: u32,
+    },
+    /// A program was started with `--exec`
+    ProcessStarted {
+        timestamp: OffsetDateTime,
+        /// The program & its arguments
+        command: Vec<String>,
+        pid: Option<u32>,
+    },
+    DnsResolved {
+        timestamp: OffsetDateTime,
+        host: String,
+        addrs: Vec<IpAddr>,
+        /// Whether the addresses came from `--resolve` instead of DNS
+        overridden: bool,
+    },
+    /// Connecting to one of the addresses from `--addr-file` is about to be
+    /// tried
+    ConnectAttempt {
+        timestamp: OffsetDateTime,
+        addr: SocketAddr,
+    },
+    /// Connecting to one of the addresses from `--addr-file` failed
+    ConnectFailed {
+        timestamp: OffsetDateTime,
+        addr: SocketAddr,
+        os_error: Option<i32>,
+        data: String,
+    },
     ConnectFinish {
         timestamp: OffsetDateTime,
         peer: SocketAddr,
+        options: SocketOptions,
     },
     TlsStart {
         timestamp: OffsetDateTime,
     },
     TlsFinish {
         timestamp: OffsetDateTime,
+        /// Whether the handshake resumed an earlier TLS session, if known
+        resumed: Option<bool>,
+    },
+    PreTlsData {
+        timestamp: OffsetDateTime,
+        data: String,
+    },
+    /// The server's certificate was recorded in the `--tofu` store on first
+    /// use
+    #[cfg(feature = "rustls")]
+    CertificateTrusted {
+        timestamp: OffsetDateTime,
+        fingerprint: String,
+    },
+    Timing {
+        timestamp: OffsetDateTime,
+        timing: ConnectTiming,
     },
     Recv {
         timestamp: OffsetDateTime,
+        /// Time since the connection was established
+        elapsed: Duration,
         data: String,
+        /// Whether the line continues a multi-line reply under `--protocol`
+        continued: bool,
+        /// Whether the line was broken off at `--max-line-length`, with its
+        /// remainder in the next `Recv`
+        partial: bool,
+        /// Whether the line is the remainder of a line that was broken off at
+        /// `--max-line-length`
+        resumed: bool,
+        /// Text to display in place of `data`, as produced by `--recv-hook`
+        shown: Option<String>,
+        /// Time since data was last sent, if any, for `--latency-colors`
+        since_send: Option<Duration>,
+        /// The line's bytes as received, before decoding, if
+        /// `--transcript-raw-bytes` was given
+        raw: Option<Bytes>,
     },
     Send {
         timestamp: OffsetDateTime,
+        /// Time since the connection was established
+        elapsed: Duration,
         data: String,
+        /// Text to display in place of `data`, as produced by `--send-hook`
+        shown: Option<String>,
+        /// The bytes put on the wire for the line, after encoding, if
+        /// `--transcript-raw-bytes` was given
+        raw: Option<Bytes>,
+    },
+    ShutdownWrite {
+        timestamp: OffsetDateTime,
+    },
+    BudgetExceeded {
+        timestamp: OffsetDateTime,
+        direction: Direction,
+        limit: u64,
+    },
+    /// A line to send was longer than `--max-send-length`
+    SendTooLong {
+        timestamp: OffsetDateTime,
+        /// Length of the line in bytes, once encoded & terminated
+        length: usize,
+        limit: usize,
+        /// Whether the line was not sent because of `--strict`
+        refused: bool,
+    },
+    LinesHidden {
+        timestamp: OffsetDateTime,
+        count: u64,
+    },
+    MemoryEvicted {
+        timestamp: OffsetDateTime,
+        count: u64,
+    },
+    /// A received line could not be decoded under `--encoding utf8-strict`
+    EncodingError {
+        timestamp: OffsetDateTime,
+        /// Position of the first invalid sequence within the line
+        offset: usize,
+        /// The first invalid sequence
+        bytes: Vec<u8>,
+    },
+    /// The first bytes received look like a binary protocol rather than
+    /// lines of text
+    ProtocolMismatch {
+        timestamp: OffsetDateTime,
+        protocol: BinaryProtocol,
+    },
+    /// Nothing was received within the `--banner-warning` window after
+    /// connecting
+    NoBanner {
+        timestamp: OffsetDateTime,
+        wait: Duration,
+    },
+    /// Transfer rates over the last `THROUGHPUT_WINDOW`, emitted
+    /// periodically under `--show-throughput`
+    Throughput {
+        timestamp: OffsetDateTime,
+        throughput: Throughput,
+    },
+    /// The results of a `/repeat` or `--flood` run
+    RepeatSummary {
+        timestamp: OffsetDateTime,
+        summary: RepeatSummary,
+    },
+    /// A codec setting was changed with `/set`
+    SettingChanged {
+        timestamp: OffsetDateTime,
+        setting: Setting,
+    },
+    /// An annotation entered with `/note`
+    Note {
+        timestamp: OffsetDateTime,
+        text: String,
+    },
+    SessionStats {
+        timestamp: OffsetDateTime,
+        stats: StatsSnapshot,
     },
     Disconnect {
         timestamp: OffsetDateTime,
+        closed_by: ClosedBy,
+        /// Time since the connection was established
+        duration: Duration,
+        lines_sent: u64,
+        lines_recv: u64,
     },
     Error {
         timestamp: OffsetDateTime,
-        data: anyhow::Error,
+        kind: ErrorKind,
+        /// The OS error code underlying the error, if any
+        os_error: Option<i32>,
+        /// The error message, including its chain of causes
+        data: String,
+        /// Additional lines describing the error in more detail
+        details: Vec<String>,
     },
 }
 
 impl Event {
+    pub(crate) fn session_start(
+        host: &str,
+        port: u16,
+        options: Vec<(String, OptionValue)>,
+    ) -> Self {
+        Event::SessionStart {
+            timestamp: now(),
+            host: String::from(host),
+            port,
+            options,
+        }
+    }
+
     pub(crate) fn connect_start(host: &str, port: u16) -> Self {
         Event::ConnectStart {
             timestamp: now(),
@@ -47,10 +245,67 @@ impl Event {
         }
     }
 
-    pub(crate) fn connect_finish(peer: SocketAddr) -> Self {
+    pub(crate) fn listen_start(addr: SocketAddr) -> Self {
+        Event::ListenStart {
+            timestamp: now(),
+            addr,
+        }
+    }
+
+    pub(crate) fn accepted(peer: SocketAddr) -> Self {
+        Event::Accepted {
+            timestamp: now(),
+            peer,
+        }
+    }
+
+    #[cfg(feature = "serial")]
+    pub(crate) fn serial_opened(device: &str, baud: u32) -> Self {
+        Event::SerialOpened {
+            timestamp: now(),
+            device: String::from(device),
+            baud,
+        }
+    }
+
+    pub(crate) fn process_started(command: &[String], pid: Option<u32>) -> Self {
+        Event::ProcessStarted {
+            timestamp: now(),
+            command: command.to_vec(),
+            pid,
+        }
+    }
+
+    pub(crate) fn dns_resolved(host: &str, addrs: Vec<IpAddr>, overridden: bool) -> Self {
+        Event::DnsResolved {
+            timestamp: now(),
+            host: String::from(host),
+            addrs,
+            overridden,
+        }
+    }
+
+    pub(crate) fn connect_attempt(addr: SocketAddr) -> Self {
+        Event::ConnectAttempt {
+            timestamp: now(),
+            addr,
+        }
+    }
+
+    pub(crate) fn connect_failed(addr: SocketAddr, e: &std::io::Error) -> Self {
+        Event::ConnectFailed {
+            timestamp: now(),
+            addr,
+            os_error: e.raw_os_error(),
+            data: e.to_string(),
+        }
+    }
+
+    pub(crate) fn connect_finish(peer: SocketAddr, options: SocketOptions) -> Self {
         Event::ConnectFinish {
             timestamp: now(),
             peer,
+            options,
         }
     }
 
@@ -58,44 +313,244 @@ impl Event {
         Event::TlsStart { timestamp: now() }
     }
 
-    pub(crate) fn tls_finish() -> Self {
-        Event::TlsFinish { timestamp: now() }
+    pub(crate) fn tls_finish(resumed: Option<bool>) -> Self {
+        Event::TlsFinish {
+            timestamp: now(),
+            resumed,
+        }
+    }
+
+    pub(crate) fn pre_tls_data(data: String) -> Self {
+        Event::PreTlsData {
+            timestamp: now(),
+            data,
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    pub(crate) fn certificate_trusted(fingerprint: String) -> Self {
+        Event::CertificateTrusted {
+            timestamp: now(),
+            fingerprint,
+        }
     }
 
-    pub(crate) fn recv(data: String) -> Self {
+    pub(crate) fn timing(timing: ConnectTiming) -> Self {
+        Event::Timing {
+            timestamp: now(),
+            timing,
+        }
+    }
+
+    pub(crate) fn recv(data: String, elapsed: Duration, continued: bool) -> Self {
         Event::Recv {
             timestamp: now(),
+            elapsed,
             data,
+            continued,
+            partial: false,
+            resumed: false,
+            shown: None,
+            since_send: None,
+            raw: None,
+        }
+    }
+
+    /// Set the time since data was last sent before a line was received
+    pub(crate) fn with_since_send(mut self, d: Option<Duration>) -> Self {
+        if let Event::Recv { since_send, .. } = &mut self {
+            *since_send = d;
+        }
+        self
+    }
+
+    /// Mark a received line as broken off at `--max-line-length` and/or as
+    /// the remainder of such a line
+    pub(crate) fn with_split(mut self, is_partial: bool, is_resumed: bool) -> Self {
+        if let Event::Recv {
+            partial, resumed, ..
+        } = &mut self
+        {
+            *partial = is_partial;
+            *resumed = is_resumed;
         }
+        self
     }
 
-    pub(crate) fn send(data: String) -> Self {
+    pub(crate) fn send(data: String, elapsed: Duration) -> Self {
         Event::Send {
             timestamp: now(),
+            elapsed,
             data,
+            shown: None,
+            raw: None,
+        }
+    }
+
+    /// Attach the exact wire bytes of a received or sent line for the
+    /// transcript
+    pub(crate) fn with_raw(mut self, bytes: Option<Bytes>) -> Self {
+        if let Event::Recv { raw, .. } | Event::Send { raw, .. } = &mut self {
+            *raw = bytes;
+        }
+        self
+    }
+
+    /// Set the text to display in place of a received or sent line's data
+    pub(crate) fn with_shown(mut self, text: Option<String>) -> Self {
+        if let Event::Recv { shown, .. } | Event::Send { shown, .. } = &mut self {
+            *shown = text;
+        }
+        self
+    }
+
+    pub(crate) fn budget_exceeded(direction: Direction, limit: u64) -> Self {
+        Event::BudgetExceeded {
+            timestamp: now(),
+            direction,
+            limit,
+        }
+    }
+
+    pub(crate) fn send_too_long(length: usize, limit: usize, refused: bool) -> Self {
+        Event::SendTooLong {
+            timestamp: now(),
+            length,
+            limit,
+            refused,
+        }
+    }
+
+    pub(crate) fn lines_hidden(count: u64) -> Self {
+        Event::LinesHidden {
+            timestamp: now(),
+            count,
+        }
+    }
+
+    pub(crate) fn memory_evicted(count: u64) -> Self {
+        Event::MemoryEvicted {
+            timestamp: now(),
+            count,
+        }
+    }
+
+    pub(crate) fn session_stats(stats: StatsSnapshot) -> Self {
+        Event::SessionStats {
+            timestamp: now(),
+            stats,
+        }
+    }
+
+    pub(crate) fn encoding_error(e: &DecodeError) -> Self {
+        Event::EncodingError {
+            timestamp: now(),
+            offset: e.offset,
+            bytes: e.bytes.clone(),
+        }
+    }
+
+    pub(crate) fn protocol_mismatch(protocol: BinaryProtocol) -> Self {
+        Event::ProtocolMismatch {
+            timestamp: now(),
+            protocol,
+        }
+    }
+
+    pub(crate) fn no_banner(wait: Duration) -> Self {
+        Event::NoBanner {
+            timestamp: now(),
+            wait,
+        }
+    }
+
+    pub(crate) fn throughput(throughput: Throughput) -> Self {
+        Event::Throughput {
+            timestamp: now(),
+            throughput,
         }
     }
 
-    pub(crate) fn disconnect() -> Self {
-        Event::Disconnect { timestamp: now() }
+    pub(crate) fn repeat_summary(summary: RepeatSummary) -> Self {
+        Event::RepeatSummary {
+            timestamp: now(),
+            summary,
+        }
+    }
+
+    pub(crate) fn setting_changed(setting: Setting) -> Self {
+        Event::SettingChanged {
+            timestamp: now(),
+            setting,
+        }
+    }
+
+    pub(crate) fn note(text: String) -> Self {
+        Event::Note {
+            timestamp: now(),
+            text,
+        }
+    }
+
+    pub(crate) fn shutdown_write() -> Self {
+        Event::ShutdownWrite { timestamp: now() }
+    }
+
+    pub(crate) fn disconnect(closed_by: ClosedBy, stats: &StatsSnapshot) -> Self {
+        Event::Disconnect {
+            timestamp: now(),
+            closed_by,
+            duration: stats.duration,
+            lines_sent: stats.sent.lines,
+            lines_recv: stats.recv.lines,
+        }
     }
 
-    pub(crate) fn error(data: anyhow::Error) -> Self {
+    pub(crate) fn error(kind: ErrorKind, e: &(dyn std::error::Error + 'static)) -> Self {
         Event::Error {
             timestamp: now(),
-            data,
+            kind,
+            os_error: os_error_code(e),
+            data: error_chain(e),
+            details: error_details(e),
         }
     }
 
     pub(crate) fn timestamp(&self) -> &OffsetDateTime {
         match self {
+            Event::SessionStart { timestamp, .. } => timestamp,
             Event::ConnectStart { timestamp, .. } => timestamp,
+            Event::ListenStart { timestamp, .. } => timestamp,
+            Event::Accepted { timestamp, .. } => timestamp,
+            #[cfg(feature = "serial")]
+            Event::SerialOpened { timestamp, .. } => timestamp,
+            Event::ProcessStarted { timestamp, .. } => timestamp,
+            Event::DnsResolved { timestamp, .. } => timestamp,
+            Event::ConnectAttempt { timestamp, .. } => timestamp,
+            Event::ConnectFailed { timestamp, .. } => timestamp,
             Event::ConnectFinish { timestamp, .. } => timestamp,
             Event::TlsStart { timestamp } => timestamp,
-            Event::TlsFinish { timestamp } => timestamp,
+            Event::TlsFinish { timestamp, .. } => timestamp,
+            Event::PreTlsData { timestamp, .. } => timestamp,
+            #[cfg(feature = "rustls")]
+            Event::CertificateTrusted { timestamp, .. } => timestamp,
+            Event::Timing { timestamp, .. } => timestamp,
             Event::Recv { timestamp, .. } => timestamp,
             Event::Send { timestamp, .. } => timestamp,
-            Event::Disconnect { timestamp } => timestamp,
+            Event::ShutdownWrite { timestamp } => timestamp,
+            Event::BudgetExceeded { timestamp, .. } => timestamp,
+            Event::SendTooLong { timestamp, .. } => timestamp,
+            Event::LinesHidden { timestamp, .. } => timestamp,
+            Event::MemoryEvicted { timestamp, .. } => timestamp,
+            Event::EncodingError { timestamp, .. } => timestamp,
+            Event::ProtocolMismatch { timestamp, .. } => timestamp,
+            Event::NoBanner { timestamp, .. } => timestamp,
+            Event::Throughput { timestamp, .. } => timestamp,
+            Event::RepeatSummary { timestamp, .. } => timestamp,
+            Event::SettingChanged { timestamp, .. } => timestamp,
+            Event::Note { timestamp, .. } => timestamp,
+            Event::SessionStats { timestamp, .. } => timestamp,
+            Event::Disconnect { timestamp, .. } => timestamp,
             Event::Error { timestamp, .. } => timestamp,
         }
     }
@@ -106,29 +561,253 @@ impl Event {
             .expect("formatting a datetime as HMS should not fail")
     }
 
+    /// Return the event's time in brackets for display at the start of its
+    /// message.  If `latency_colors` is true and the event is a line received
+    /// after something was sent, the time is colored by how long after the
+    /// send the line arrived.
+    pub(crate) fn display_stamp(&self, latency_colors: bool) -> StyledContent<String> {
+        let stamp = format!("[{}]", self.display_time());
+        match self {
+            Event::Recv {
+                since_send: Some(d),
+                ..
+            } if latency_colors => stamp.with(latency_color(*d)),
+            _ => stamp.stylize(),
+        }
+    }
+
     pub(crate) fn sigil(&self) -> char {
         match self {
             Event::Recv { .. } => '<',
             Event::Send { .. } => '>',
-            Event::Error { .. } => '!',
+            Event::ConnectFailed { .. }
+            | Event::EncodingError { .. }
+            | Event::ProtocolMismatch { .. }
+            | Event::NoBanner { .. }
+            | Event::Error { .. } => '!',
+
             _ => '*',
         }
     }
 
     pub(crate) fn to_message(&self, time: bool) -> EventDisplay<'_> {
-        EventDisplay { event: self, time }
+        EventDisplay {
+            event: self,
+            time,
+            latency_colors: false,
+            fit: None,
+            ansi: AnsiMode::Vis,
+            plain: false,
+        }
+    }
+
+    /// Return the event's message without any styling, sigil, or timestamp
+    pub(crate) fn plain_message(&self) -> String {
+        self.message_chunks(AnsiMode::Vis)
+            .iter()
+            .map(|chunk| chunk.content().as_str())
+            .collect()
     }
 
-    fn message_chunks(&self) -> Vec<StyledContent<String>> {
+    /// Return the styled chunks of the event's message.  `ansi` determines
+    /// how escape sequences in received lines are shown.
+    fn message_chunks(&self, ansi: AnsiMode) -> Vec<StyledContent<String>> {
         match self {
+            Event::SessionStart { .. } => {
+                vec![format!("Starting confab {}", env!("CARGO_PKG_VERSION")).stylize()]
+            }
             Event::ConnectStart { .. } => vec![String::from("Connecting ...").stylize()],
-            Event::ConnectFinish { peer, .. } => vec![format!("Connected to {peer}").stylize()],
+            Event::ListenStart { addr, .. } => {
+                vec![format!("Listening on {addr} ...").stylize()]
+            }
+            Event::Accepted { peer, .. } => {
+                vec![format!("Accepted connection from {peer}").stylize()]
+            }
+            #[cfg(feature = "serial")]
+            Event::SerialOpened { device, baud, .. } => {
+                vec![format!("Opened {device} at {baud} baud").stylize()]
+            }
+            Event::ProcessStarted { command, pid, .. } => {
+                let command = command.join(" ");
+                let msg = match pid {
+                    Some(pid) => format!("Started {command} (PID {pid})"),
+                    None => format!("Started {command}"),
+                };
+                vec![msg.stylize()]
+            }
+            Event::DnsResolved {
+                host,
+                addrs,
+                overridden,
+                ..
+            } => {
+                let addrs = addrs
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let source = if *overridden { " (from --resolve)" } else { "" };
+                vec![format!("Resolved {host} to {addrs}{source}").stylize()]
+            }
+            Event::ConnectAttempt { addr, .. } => vec![format!("Trying {addr} ...").stylize()],
+            Event::ConnectFailed { addr, data, .. } => {
+                vec![format!("Failed to connect to {addr}: {data}").stylize()]
+            }
+            Event::ConnectFinish { peer, options, .. } => {
+                let mut msg = format!("Connected to {peer}");
+                let opts = options.describe();
+                if !opts.is_empty() {
+                    msg.push_str(" (");
+                    msg.push_str(&opts.join(", "));
+                    msg.push(')');
+                }
+                vec![msg.stylize()]
+            }
             Event::TlsStart { .. } => vec![String::from("Initializing TLS ...").stylize()],
-            Event::TlsFinish { .. } => vec![String::from("TLS established").stylize()],
-            Event::Recv { data, .. } => display_vis(chomp(data)),
-            Event::Send { data, .. } => display_vis(chomp(data)),
-            Event::Disconnect { .. } => vec![String::from("Disconnected").stylize()],
-            Event::Error { data, .. } => vec![format!("{data:#}").stylize()],
+            Event::TlsFinish { resumed, .. } => {
+                let msg = if *resumed == Some(true) {
+                    "TLS established (resumed session)"
+                } else {
+                    "TLS established"
+                };
+                vec![String::from(msg).stylize()]
+            }
+            Event::PreTlsData { data, .. } => {
+                let mut chunks = vec![String::from("Received before TLS: ").stylize()];
+                chunks.extend(display_vis(chomp(data)));
+                chunks
+            }
+            #[cfg(feature = "rustls")]
+            Event::CertificateTrusted { fingerprint, .. } => {
+                vec![format!("Trusting server certificate on first use: {fingerprint}").stylize()]
+            }
+            Event::Timing { timing, .. } => {
+                let mut msg = format!(
+                    "Timing: DNS {}, connect {}",
+                    fmt_ms(timing.dns),
+                    fmt_ms(timing.connect)
+                );
+                if let Some(tls) = timing.tls {
+                    msg.push_str(", TLS ");
+                    msg.push_str(&fmt_ms(tls));
+                }
+                msg.push_str(", total ");
+                msg.push_str(&fmt_ms(timing.total));
+                vec![msg.stylize()]
+            }
+            Event::Recv {
+                data,
+                continued,
+                partial,
+                resumed,
+                shown,
+                ..
+            } => {
+                let mut chunks = line_chunks(data, shown.as_deref(), ansi);
+                // Mark where a line was split at `--max-line-length` so that
+                // the fragments are not mistaken for separate lines
+                if *resumed {
+                    chunks.insert(0, String::from(GAP_MARKER).stylize());
+                }
+                if *partial {
+                    chunks.push(String::from(GAP_MARKER).stylize());
+                }
+                if *continued {
+                    // Indent continuation lines so that they appear grouped
+                    // under the first line of their reply
+                    chunks.insert(0, String::from("  ").stylize());
+                }
+                chunks
+            }
+            Event::Send { data, shown, .. } => line_chunks(data, shown.as_deref(), AnsiMode::Vis),
+            Event::BudgetExceeded {
+                direction, limit, ..
+            } => {
+                let what = match direction {
+                    Direction::Send => "Send",
+                    Direction::Recv => "Receive",
+                };
+                vec![
+                    format!("{what} budget of {limit} bytes exhausted; closing connection")
+                        .stylize(),
+                ]
+            }
+            Event::SendTooLong {
+                length,
+                limit,
+                refused,
+                ..
+            } => {
+                let mut msg =
+                    format!("Line of {length} bytes exceeds maximum send length of {limit}");
+                if *refused {
+                    msg.push_str("; not sent");
+                }
+                vec![msg.stylize()]
+            }
+            Event::LinesHidden { count, .. } => {
+                vec![format!("Received lines not displayed: {count}").stylize()]
+            }
+            Event::MemoryEvicted { count, .. } => {
+                vec![format!("Items discarded to stay under memory cap: {count}").stylize()]
+            }
+            Event::EncodingError { offset, bytes, .. } => vec![format!(
+                "Received invalid UTF-8 sequence {} at byte {offset} of line",
+                hex_bytes(bytes)
+            )
+            .stylize()],
+            Event::ProtocolMismatch { protocol, .. } => {
+                vec![String::from(protocol.warning()).stylize()]
+            }
+            Event::NoBanner { wait, .. } => vec![no_banner_message(*wait).stylize()],
+            Event::Throughput { throughput, .. } => vec![format!(
+                "Throughput over the last {}s: received {}; sent {}",
+                THROUGHPUT_WINDOW.as_secs(),
+                throughput.recv,
+                throughput.sent
+            )
+            .stylize()],
+            Event::RepeatSummary { summary, .. } => vec![repeat_summary_message(
+                &summary.line,
+                summary.sent.into(),
+                summary.responses.into(),
+                summary.latency,
+            )
+            .stylize()],
+            Event::SettingChanged { setting, .. } => {
+                vec![format!("Set {} to {}", setting.name(), setting.value()).stylize()]
+            }
+            Event::Note { text, .. } => vec![format!("Note: {text}").stylize()],
+            Event::SessionStats { stats, .. } => vec![stats.to_string().stylize()],
+            Event::ShutdownWrite { .. } => vec![String::from(
+                "Shut down sending; waiting for the server to close the connection",
+            )
+            .stylize()],
+            Event::Disconnect {
+                closed_by,
+                duration,
+                lines_sent,
+                lines_recv,
+                ..
+            } => {
+                let by = match closed_by {
+                    ClosedBy::Local => "",
+                    ClosedBy::Peer => " by peer",
+                };
+                vec![format!(
+                    "Disconnected{by} after {:.1}s (sent {lines_sent} lines, received {lines_recv} lines)",
+                    duration.as_secs_f64()
+                )
+                .stylize()]
+            }
+            Event::Error { data, details, .. } => {
+                let mut chunks = vec![data.clone().stylize()];
+                for line in details {
+                    chunks.push(String::from("\n").stylize());
+                    chunks.push(format!("  {line}").stylize());
+                }
+                chunks
+            }
         }
     }
 
@@ -137,46 +816,1118 @@ impl Event {
             "timestamp",
             &self
                 .timestamp()
-                .format(&Rfc3339)
-                .expect("formatting a datetime as RFC3339 should not fail"),
+                .format(&TIMESTAMP_FMT)
+                .expect("formatting a datetime as RFC 3339 should not fail"),
         );
         match self {
+            Event::SessionStart {
+                host,
+                port,
+                options,
+                ..
+            } => {
+                let opts =
+                    options
+                        .iter()
+                        .fold(JsonStrMap::new(), |opts, (name, value)| match value {
+                            OptionValue::Single(s) => opts.field(name, s),
+                            OptionValue::Multiple(values) => {
+                                opts.raw_field(name, &json_str_array(values))
+                            }
+                        });
+                json.field("event", "session-start")
+                    .raw_field("format_version", &FORMAT_VERSION.to_string())
+                    .field("confab_version", env!("CARGO_PKG_VERSION"))
+                    .field("host", host)
+                    .raw_field("port", &port.to_string())
+                    .raw_field("options", &opts.finish())
+                    .finish()
+            }
             Event::ConnectStart { host, port, .. } => json
                 .field("event", "connection-start")
+                .raw_field("format_version", &FORMAT_VERSION.to_string())
                 .field("host", host)
                 .raw_field("port", &port.to_string())
                 .finish(),
-            Event::ConnectFinish { peer, .. } => json
-                .field("event", "connection-complete")
+            Event::ListenStart { addr, .. } => json
+                .field("event", "listen-start")
+                .field("ip", &addr.ip())
+                .raw_field("port", &addr.port().to_string())
+                .finish(),
+            Event::Accepted { peer, .. } => json
+                .field("event", "accepted")
                 .field("peer_ip", &peer.ip())
+                .raw_field("peer_port", &peer.port().to_string())
+                .finish(),
+            #[cfg(feature = "serial")]
+            Event::SerialOpened { device, baud, .. } => json
+                .field("event", "serial-opened")
+                .field("device", device)
+                .raw_field("baud", &baud.to_string())
                 .finish(),
+            Event::ProcessStarted { command, pid, .. } => {
+                let json = json
+                    .field("event", "process-started")
+                    .raw_field("command", &json_str_array(command));
+                match pid {
+                    Some(pid) => json.raw_field("pid", &pid.to_string()).finish(),
+                    None => json.finish(),
+                }
+            }
+            Event::DnsResolved {
+                host,
+                addrs,
+                overridden,
+                ..
+            } => json
+                .field("event", "dns-resolved")
+                .field("host", host)
+                .raw_field("addresses", &json_str_array(addrs))
+                .raw_field("override", &overridden.to_string())
+                .finish(),
+            Event::ConnectAttempt { addr, .. } => json
+                .field("event", "connect-attempt")
+                .field("ip", &addr.ip())
+                .raw_field("port", &addr.port().to_string())
+                .finish(),
+            Event::ConnectFailed {
+                addr,
+                os_error,
+                data,
+                ..
+            } => {
+                let json = json
+                    .field("event", "connect-failed")
+                    .field("ip", &addr.ip())
+                    .raw_field("port", &addr.port().to_string());
+                match os_error {
+                    Some(code) => json.raw_field("os_error", &code.to_string()),
+                    None => json,
+                }
+                .field("data", data)
+                .finish()
+            }
+            Event::ConnectFinish { peer, options, .. } => {
+                let mut json = json
+                    .field("event", "connection-complete")
+                    .field("peer_ip", &peer.ip())
+                    .raw_field(
+                        "tcp_nodelay",
+                        if options.nodelay { "true" } else { "false" },
+                    );
+                if let Some(keepalive) = options.keepalive {
+                    json = json.raw_field("tcp_keepalive", &keepalive.as_secs().to_string());
+                }
+                if let Some(ttl) = options.ttl {
+                    json = json.raw_field("ttl", &ttl.to_string());
+                }
+                json.finish()
+            }
             Event::TlsStart { .. } => json.field("event", "tls-start").finish(),
-            Event::TlsFinish { .. } => json.field("event", "tls-complete").finish(),
-            Event::Recv { data, .. } => json.field("event", "recv").field("data", data).finish(),
-            Event::Send { data, .. } => json.field("event", "send").field("data", data).finish(),
-            Event::Disconnect { .. } => json.field("event", "disconnect").finish(),
-            Event::Error { data, .. } => json
-                .field("event", "error")
-                .field("data", &format!("{data:#}"))
+            Event::TlsFinish { resumed, .. } => {
+                let json = json.field("event", "tls-complete");
+                match resumed {
+                    Some(resumed) => json.raw_field("resumed", &resumed.to_string()).finish(),
+                    None => json.finish(),
+                }
+            }
+            Event::PreTlsData { data, .. } => json
+                .field("event", "pre-tls-data")
+                .field("data", data)
+                .finish(),
+            #[cfg(feature = "rustls")]
+            Event::CertificateTrusted { fingerprint, .. } => json
+                .field("event", "certificate-trusted")
+                .field("fingerprint", fingerprint)
+                .finish(),
+            Event::Timing { timing, .. } => {
+                let mut json = json
+                    .field("event", "timing")
+                    .raw_field("dns_ms", &ms(timing.dns))
+                    .raw_field("connect_ms", &ms(timing.connect));
+                if let Some(tls) = timing.tls {
+                    json = json.raw_field("tls_ms", &ms(tls));
+                }
+                json.raw_field("total_ms", &ms(timing.total)).finish()
+            }
+            Event::Recv {
+                elapsed,
+                data,
+                partial,
+                raw,
+                ..
+            } => {
+                let mut json = json
+                    .field("event", "recv")
+                    .raw_field("elapsed_ms", &ms(*elapsed))
+                    .field("data", data);
+                if let Some(raw) = raw {
+                    json = json.field("data_base64", &BASE64_STANDARD.encode(raw));
+                }
+                if *partial {
+                    json.raw_field("partial", "true").finish()
+                } else {
+                    json.finish()
+                }
+            }
+            Event::Send {
+                elapsed, data, raw, ..
+            } => {
+                let mut json = json
+                    .field("event", "send")
+                    .raw_field("elapsed_ms", &ms(*elapsed))
+                    .field("data", data);
+                if let Some(raw) = raw {
+                    json = json.field("data_base64", &BASE64_STANDARD.encode(raw));
+                }
+                json.finish()
+            }
+            Event::BudgetExceeded {
+                direction, limit, ..
+            } => json
+                .field("event", "budget-exceeded")
+                .field("direction", direction.as_str())
+                .raw_field("limit", &limit.to_string())
+                .finish(),
+            Event::SendTooLong {
+                length,
+                limit,
+                refused,
+                ..
+            } => json
+                .field("event", "send-too-long")
+                .raw_field("length", &length.to_string())
+                .raw_field("limit", &limit.to_string())
+                .raw_field("refused", if *refused { "true" } else { "false" })
                 .finish(),
+            Event::LinesHidden { count, .. } => json
+                .field("event", "lines-hidden")
+                .raw_field("count", &count.to_string())
+                .finish(),
+            Event::MemoryEvicted { count, .. } => json
+                .field("event", "memory-evicted")
+                .raw_field("count", &count.to_string())
+                .finish(),
+            Event::EncodingError { offset, bytes, .. } => json
+                .field("event", "encoding-error")
+                .raw_field("offset", &offset.to_string())
+                .field("bytes", &hex_bytes(bytes))
+                .finish(),
+            Event::ProtocolMismatch { protocol, .. } => json
+                .field("event", "protocol-mismatch")
+                .field("protocol", protocol.as_str())
+                .finish(),
+            Event::NoBanner { wait, .. } => json
+                .field("event", "no-banner")
+                .raw_field("wait_ms", &ms(*wait))
+                .finish(),
+            Event::Throughput { throughput, .. } => json
+                .field("event", "throughput")
+                .raw_field("window_ms", &ms(THROUGHPUT_WINDOW))
+                .raw_field("lines_sent_per_sec", &rate(throughput.sent.lines_per_sec))
+                .raw_field("bytes_sent_per_sec", &rate(throughput.sent.bytes_per_sec))
+                .raw_field("lines_recv_per_sec", &rate(throughput.recv.lines_per_sec))
+                .raw_field("bytes_recv_per_sec", &rate(throughput.recv.bytes_per_sec))
+                .finish(),
+            Event::RepeatSummary { summary, .. } => {
+                let json = json
+                    .field("event", "repeat-summary")
+                    .field("data", &summary.line)
+                    .raw_field("sent", &summary.sent.to_string())
+                    .raw_field("responses", &summary.responses.to_string());
+                match summary.latency {
+                    Some(latency) => json
+                        .raw_field("latency_min_ms", &ms(latency.min))
+                        .raw_field("latency_avg_ms", &ms(latency.avg))
+                        .raw_field("latency_p95_ms", &ms(latency.p95))
+                        .finish(),
+                    None => json.finish(),
+                }
+            }
+            Event::SettingChanged { setting, .. } => json
+                .field("event", "setting-changed")
+                .field("setting", setting.name())
+                .field("value", &setting.value())
+                .finish(),
+            Event::Note { text, .. } => json
+                .field("event", "annotation")
+                .field("data", text)
+                .finish(),
+            Event::SessionStats { stats, .. } => {
+                let json = json
+                    .field("event", "session-stats")
+                    .raw_field("lines_sent", &stats.sent.lines.to_string())
+                    .raw_field("bytes_sent", &stats.sent.bytes.to_string())
+                    .raw_field("lines_recv", &stats.recv.lines.to_string())
+                    .raw_field("bytes_recv", &stats.recv.bytes.to_string())
+                    .raw_field("duration_ms", &ms(stats.duration));
+                match stats.idle {
+                    Some(idle) => json.raw_field("idle_ms", &ms(idle)).finish(),
+                    None => json.finish(),
+                }
+            }
+            Event::ShutdownWrite { .. } => json.field("event", "shutdown-write").finish(),
+            Event::Disconnect {
+                closed_by,
+                duration,
+                lines_sent,
+                lines_recv,
+                ..
+            } => json
+                .field("event", "disconnect")
+                .field("closed_by", closed_by.as_str())
+                .raw_field("duration_ms", &ms(*duration))
+                .raw_field("lines_sent", &lines_sent.to_string())
+                .raw_field("lines_recv", &lines_recv.to_string())
+                .finish(),
+            Event::Error {
+                kind,
+                os_error,
+                data,
+                details,
+                ..
+            } => {
+                let json = json.field("event", "error").field("kind", kind.as_str());
+                let json = match os_error {
+                    Some(code) => json.raw_field("os_error", &code.to_string()),
+                    None => json,
+                }
+                .field("data", data);
+                if details.is_empty() {
+                    json.finish()
+                } else {
+                    json.raw_field("details", &json_str_array(details)).finish()
+                }
+            }
+        }
+    }
+}
+
+/// The value of a command-line option as recorded in the "session-start"
+/// event
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum OptionValue {
+    Single(String),
+    /// The values of an option that may be given multiple times
+    Multiple(Vec<String>),
+}
+
+/// Which end of the connection ended the session
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ClosedBy {
+    /// The session was ended by the user quitting, by input running out, or
+    /// by an option like `--until` or `--max-recv-bytes`.  This includes the
+    /// case where the remote end closed the connection after we shut down
+    /// our side of it.
+    Local,
+    /// The remote end closed the connection on its own
+    Peer,
+}
+
+impl ClosedBy {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ClosedBy::Local => "local",
+            ClosedBy::Peer => "peer",
+        }
+    }
+}
+
+/// Durations of the stages of establishing a connection
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct ConnectTiming {
+    pub(crate) dns: Duration,
+    pub(crate) connect: Duration,
+    /// `None` if TLS was not used
+    pub(crate) tls: Option<Duration>,
+    pub(crate) total: Duration,
+}
+
+/// Options applied to the TCP socket after connecting
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SocketOptions {
+    /// Whether to set `TCP_NODELAY`
+    pub(crate) nodelay: bool,
+    /// If set, enable TCP keepalive probes after the connection has been idle
+    /// for this long
+    pub(crate) keepalive: Option<Duration>,
+    /// If set, the IP time-to-live to use for outgoing packets
+    pub(crate) ttl: Option<u32>,
+}
+
+impl SocketOptions {
+    /// Returns a human-readable description of each option that was set
+    fn describe(&self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if self.nodelay {
+            opts.push(String::from("TCP_NODELAY"));
+        }
+        if let Some(keepalive) = self.keepalive {
+            opts.push(format!("keepalive {}s", keepalive.as_secs()));
+        }
+        if let Some(ttl) = self.ttl {
+            opts.push(format!("TTL {ttl}"));
+        }
+        opts
+    }
+}
+
+/// Return the message for a "no-banner" event
+pub(crate) fn no_banner_message(wait: Duration) -> String {
+    format!(
+        "Nothing received within {}s of connecting; the server may be waiting for the client to speak first",
+        wait.as_secs()
+    )
+}
+
+/// Return the message for a "repeat-summary" event
+pub(crate) fn repeat_summary_message(
+    line: &str,
+    sent: u64,
+    responses: u64,
+    latency: Option<Latency>,
+) -> String {
+    match latency {
+        Some(latency) => format!(
+            "Sent {line:?} {sent} times; {responses} responses; latency min {}ms, avg {}ms, p95 {}ms",
+            ms(latency.min),
+            ms(latency.avg),
+            ms(latency.p95)
+        ),
+        None => format!("Sent {line:?} {sent} times; {responses} responses"),
+    }
+}
+
+/// Format a duration as a number of milliseconds with microsecond precision
+pub(crate) fn ms(d: Duration) -> String {
+    format!("{:.3}", d.as_secs_f64() * 1000.0)
+}
+
+/// Format a per-second rate for a transcript
+pub(crate) fn rate(r: f64) -> String {
+    format!("{r:.3}")
+}
+
+/// The color of a received line's time under `--latency-colors`, given how
+/// long after the most recent send the line arrived
+fn latency_color(d: Duration) -> Color {
+    if d < Duration::from_millis(50) {
+        Color::Green
+    } else if d < Duration::from_millis(500) {
+        Color::Yellow
+    } else {
+        Color::Red
+    }
+}
+
+pub(crate) fn fmt_ms(d: Duration) -> String {
+    format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+}
+
+/// Format a list of values as a JSON array of strings
+fn json_str_array<D: fmt::Display>(values: &[D]) -> String {
+    let mut s = String::from("[");
+    for (i, v) in values.iter().enumerate() {
+        if i > 0 {
+            s.push_str(", ");
         }
+        write_json_str(&v.to_string(), &mut s).expect("formatting a String should not fail");
     }
+    s.push(']');
+    s
+}
+
+/// Marker shown at the end of a received line that was broken off at
+/// `--max-line-length` and at the start of its remainder
+pub(crate) const GAP_MARKER: &str = "…";
+
+/// Return the chunks for displaying a received or sent line, or the text
+/// shown in its place.  If the latter spans multiple lines, the lines are
+/// separated by chunks consisting of a single newline.
+fn line_chunks(data: &str, shown: Option<&str>, ansi: AnsiMode) -> Vec<StyledContent<String>> {
+    let Some(shown) = shown else {
+        return display_ansi(chomp(data), ansi);
+    };
+    let mut chunks = Vec::new();
+    for (i, line) in shown.lines().enumerate() {
+        if i > 0 {
+            chunks.push(String::from("\n").stylize());
+        }
+        chunks.extend(display_ansi(line, ansi));
+    }
+    chunks
 }
 
 pub(crate) struct EventDisplay<'a> {
     event: &'a Event,
     time: bool,
+    latency_colors: bool,
+    fit: Option<Fit>,
+    ansi: AnsiMode,
+    plain: bool,
+}
+
+impl EventDisplay<'_> {
+    /// Fit each line of the message into the given number of columns, if any
+    pub(crate) fn fit(self, fit: Option<Fit>) -> Self {
+        EventDisplay { fit, ..self }
+    }
+
+    /// Show escape sequences in received lines according to `ansi`
+    pub(crate) fn ansi(self, ansi: AnsiMode) -> Self {
+        EventDisplay { ansi, ..self }
+    }
+
+    /// Color the times of received lines by their response latency
+    pub(crate) fn latency_colors(self, latency_colors: bool) -> Self {
+        EventDisplay {
+            latency_colors,
+            ..self
+        }
+    }
+
+    /// Write the message without any colors or text styles, e.g., for output
+    /// that isn't going to a terminal
+    pub(crate) fn plain(self, plain: bool) -> Self {
+        EventDisplay { plain, ..self }
+    }
 }
 
 impl fmt::Display for EventDisplay<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut indent = 0;
         if self.time {
-            write!(f, "[{}] ", self.event.display_time())?;
+            let stamp = self.event.display_stamp(self.latency_colors && !self.plain);
+            indent += stamp.content().width() + 1;
+            write!(f, "{stamp} ")?;
         }
         write!(f, "{} ", self.event.sigil())?;
-        for chunk in self.event.message_chunks() {
-            write!(f, "{chunk}")?;
+        indent += 2;
+        let mut chunks = self.event.message_chunks(self.ansi);
+        if self.plain {
+            chunks = chunks.into_iter().map(unstyle).collect();
+        }
+        for (i, line) in chunks.split(|c| c.content() == "\n").enumerate() {
+            if i > 0 {
+                // Line up continuation lines with the start of the message
+                write!(f, "\n{:indent$}", "")?;
+            }
+            match self.fit {
+                Some(fit) => fit.write_line(f, line, indent)?,
+                None => {
+                    for chunk in line {
+                        write!(f, "{chunk}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strip the colors & text styles from a piece of styled text
+pub(crate) fn unstyle(chunk: StyledContent<String>) -> StyledContent<String> {
+    chunk.content().clone().stylize()
+}
+
+/// How to display lines of a message that are too wide for the terminal
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Fit {
+    /// Total number of columns available, including the message's prefix
+    pub(crate) width: usize,
+    /// Whether to cut off long lines with an ellipsis instead of wrapping
+    /// them
+    pub(crate) truncate: bool,
+}
+
+impl Fit {
+    /// Marker shown at the start of each row that continues a wrapped line
+    const CONTINUATION: &'static str = "↪ ";
+
+    const ELLIPSIS: char = '…';
+
+    /// Write one line of a message, which starts after a prefix `indent`
+    /// columns wide, wrapping or truncating it as needed
+    fn write_line(
+        self,
+        f: &mut fmt::Formatter<'_>,
+        line: &[StyledContent<String>],
+        indent: usize,
+    ) -> fmt::Result {
+        let avail = self.width.saturating_sub(indent).max(1);
+        let total = line
+            .iter()
+            .filter(|c| !is_escape(c.content()))
+            .map(|c| c.content().width())
+            .sum::<usize>();
+        if total <= avail {
+            for chunk in line {
+                write!(f, "{chunk}")?;
+            }
+            return Ok(());
+        }
+        // When truncating, leave room for the ellipsis
+        let limit = if self.truncate { avail - 1 } else { avail };
+        let mut col = 0;
+        for chunk in line {
+            if is_escape(chunk.content()) {
+                write!(f, "{chunk}")?;
+                continue;
+            }
+            let mut piece = String::new();
+            for c in chunk.content().chars() {
+                let w = c.width().unwrap_or(0);
+                if col + w > limit && col > 0 {
+                    write!(f, "{}", StyledContent::new(*chunk.style(), &piece))?;
+                    if self.truncate {
+                        return write!(f, "{}{}", Fit::ELLIPSIS, reset_after(line));
+                    }
+                    piece.clear();
+                    let marker = Fit::CONTINUATION;
+                    write!(
+                        f,
+                        "\n{:pad$}{marker}",
+                        "",
+                        pad = indent.saturating_sub(marker.width())
+                    )?;
+                    col = 0;
+                }
+                piece.push(c);
+                col += w;
+            }
+            write!(f, "{}", StyledContent::new(*chunk.style(), &piece))?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{Counter, Rate};
+    use crate::util::CharEncoding;
+    use rstest::rstest;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_session_start_json() {
+        let ev = Event::SessionStart {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56 -04:00),
+            host: String::from("localhost"),
+            port: 80,
+            options: vec![
+                (
+                    String::from("crlf"),
+                    OptionValue::Single(String::from("true")),
+                ),
+                (
+                    String::from("send"),
+                    OptionValue::Multiple(vec![String::from("HELO"), String::from("QUIT")]),
+                ),
+            ],
+        };
+        assert_eq!(
+            ev.to_json(),
+            format!(
+                concat!(
+                    r#"{{"timestamp": "2024-05-01T12:34:56.000000-04:00", "event": "session-start", "#,
+                    r#""format_version": {}, "confab_version": "{}", "host": "localhost", "port": 80, "#,
+                    r#""options": {{"crlf": "true", "send": ["HELO", "QUIT"]}}}}"#,
+                ),
+                FORMAT_VERSION,
+                env!("CARGO_PKG_VERSION"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_recv_json() {
+        let ev = Event::Recv {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56.5 -04:00),
+            elapsed: Duration::from_micros(1_234_567),
+            data: String::from("Hello!\r\n"),
+            continued: true,
+            partial: false,
+            resumed: false,
+            shown: None,
+            since_send: None,
+            raw: None,
+        };
+        assert_eq!(
+            ev.to_json(),
+            r#"{"timestamp": "2024-05-01T12:34:56.500000-04:00", "event": "recv", "elapsed_ms": 1234.567, "data": "Hello!\r\n"}"#
+        );
+    }
+
+    #[test]
+    fn test_raw_bytes_json() {
+        let ev = Event::recv(String::from("Libert\u{FFFD}\n"), Duration::ZERO, false)
+            .with_raw(Some(Bytes::from_static(b"Libert\xE9\n")));
+        assert!(ev.to_json().ends_with(
+            r#""event": "recv", "elapsed_ms": 0.000, "data": "Libert\ufffd\n", "data_base64": "TGliZXJ06Qo="}"#
+        ));
+        let ev = Event::send(String::from("QUIT\r\n"), Duration::ZERO)
+            .with_raw(Some(Bytes::from_static(b"QUIT\r\n")));
+        assert!(ev.to_json().ends_with(
+            r#""event": "send", "elapsed_ms": 0.000, "data": "QUIT\r\n", "data_base64": "UVVJVA0K"}"#
+        ));
+    }
+
+    #[test]
+    fn test_recv_continued_message() {
+        let first = Event::recv(String::from("250-example.com\r\n"), Duration::ZERO, false);
+        assert_eq!(first.to_message(false).to_string(), "< 250-example.com");
+        let cont = Event::recv(String::from("250 HELP\r\n"), Duration::ZERO, true);
+        assert_eq!(cont.to_message(false).to_string(), "<   250 HELP");
+    }
+
+    #[test]
+    fn test_split_line() {
+        let first =
+            Event::recv(String::from("abcdef"), Duration::ZERO, false).with_split(true, false);
+        assert_eq!(first.to_message(false).to_string(), "< abcdef…");
+        assert_eq!(
+            first.to_json(),
+            format!(
+                r#"{{"timestamp": "{}", "event": "recv", "elapsed_ms": 0.000, "data": "abcdef", "partial": true}}"#,
+                first.timestamp().format(&TIMESTAMP_FMT).unwrap()
+            )
+        );
+        let middle =
+            Event::recv(String::from("ghijkl"), Duration::ZERO, false).with_split(true, true);
+        assert_eq!(middle.to_message(false).to_string(), "< …ghijkl…");
+        let last = Event::recv(String::from("mn\n"), Duration::ZERO, false).with_split(false, true);
+        assert_eq!(last.to_message(false).to_string(), "< …mn");
+        assert_eq!(
+            last.to_json(),
+            format!(
+                r#"{{"timestamp": "{}", "event": "recv", "elapsed_ms": 0.000, "data": "mn\n"}}"#,
+                last.timestamp().format(&TIMESTAMP_FMT).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_shown_message() {
+        let ev = Event::recv(String::from("aGk=\r\n"), Duration::ZERO, false)
+            .with_shown(Some(String::from("hi\n\tthere")));
+        assert_eq!(ev.to_message(false).to_string(), "< hi\n  \tthere");
+        assert_eq!(
+            ev.to_json(),
+            format!(
+                r#"{{"timestamp": "{}", "event": "recv", "elapsed_ms": 0.000, "data": "aGk=\r\n"}}"#,
+                ev.timestamp().format(&TIMESTAMP_FMT).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_connect_finish_options() {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 80));
+        let plain = Event::connect_finish(peer, SocketOptions::default());
+        assert_eq!(
+            plain.to_message(false).to_string(),
+            "* Connected to 127.0.0.1:80"
+        );
+        assert!(plain
+            .to_json()
+            .ends_with(r#""peer_ip": "127.0.0.1", "tcp_nodelay": false}"#));
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            ttl: Some(64),
+        };
+        let ev = Event::connect_finish(peer, options);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Connected to 127.0.0.1:80 (TCP_NODELAY, keepalive 30s, TTL 64)"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""tcp_nodelay": true, "tcp_keepalive": 30, "ttl": 64}"#));
+    }
+
+    #[test]
+    fn test_listen_events() {
+        let ev = Event::listen_start(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8080)));
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Listening on 0.0.0.0:8080 ..."
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "listen-start", "ip": "0.0.0.0", "port": 8080}"#));
+        let ev = Event::accepted(SocketAddr::from((Ipv4Addr::LOCALHOST, 54321)));
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Accepted connection from 127.0.0.1:54321"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "accepted", "peer_ip": "127.0.0.1", "peer_port": 54321}"#));
+    }
+
+    #[test]
+    fn test_connect_attempt_events() {
+        let addr = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 25));
+        let ev = Event::connect_attempt(addr);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Trying 192.0.2.1:25 ..."
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "connect-attempt", "ip": "192.0.2.1", "port": 25}"#));
+        let e = std::io::Error::from_raw_os_error(111);
+        let ev = Event::connect_failed(addr, &e);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            format!("! Failed to connect to 192.0.2.1:25: {e}")
+        );
+        assert!(ev.to_json().ends_with(&format!(
+            r#""event": "connect-failed", "ip": "192.0.2.1", "port": 25, "os_error": 111, "data": "{e}"}}"#
+        )));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let stats = StatsSnapshot {
+            sent: Counter {
+                lines: 2,
+                bytes: 12,
+            },
+            recv: Counter {
+                lines: 5,
+                bytes: 80,
+            },
+            duration: Duration::from_millis(12_345),
+            idle: None,
+        };
+        let ev = Event::disconnect(ClosedBy::Peer, &stats);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Disconnected by peer after 12.3s (sent 2 lines, received 5 lines)"
+        );
+        assert!(ev.to_json().ends_with(
+            r#""event": "disconnect", "closed_by": "peer", "duration_ms": 12345.000, "lines_sent": 2, "lines_recv": 5}"#
+        ));
+        let ev = Event::disconnect(ClosedBy::Local, &stats);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Disconnected after 12.3s (sent 2 lines, received 5 lines)"
+        );
+        assert!(ev.to_json().contains(r#""closed_by": "local""#));
+    }
+
+    #[test]
+    fn test_encoding_error() {
+        let e = CharEncoding::Utf8Strict
+            .decode(b"caf\xE9!\n".to_vec())
+            .unwrap_err();
+        let ev = Event::encoding_error(&e);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "! Received invalid UTF-8 sequence e9 at byte 3 of line"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "encoding-error", "offset": 3, "bytes": "e9"}"#));
+    }
+
+    #[rstest]
+    #[case(false, "< abcdefgh\n↪ ijklmnop\n↪ qr")]
+    #[case(true, "< abcdefg…")]
+    fn test_fit(#[case] truncate: bool, #[case] shown: &str) {
+        let ev = Event::recv(String::from("abcdefghijklmnopqr\n"), Duration::ZERO, false);
+        let fit = Fit {
+            width: 10,
+            truncate,
+        };
+        assert_eq!(ev.to_message(false).fit(Some(fit)).to_string(), shown);
+    }
+
+    #[test]
+    fn test_fit_short() {
+        let ev = Event::recv(String::from("abcdefgh\n"), Duration::ZERO, false);
+        let fit = Fit {
+            width: 10,
+            truncate: true,
+        };
+        assert_eq!(
+            ev.to_message(false).fit(Some(fit)).to_string(),
+            "< abcdefgh"
+        );
+    }
+
+    #[test]
+    fn test_fit_wide_chars() {
+        let ev = Event::recv(String::from("日本語のテキスト"), Duration::ZERO, false);
+        let fit = Fit {
+            width: 9,
+            truncate: false,
+        };
+        assert_eq!(
+            ev.to_message(false).fit(Some(fit)).to_string(),
+            "< 日本語\n↪ のテキ\n↪ スト"
+        );
+    }
+
+    #[test]
+    fn test_fit_combining_chars() {
+        let ev = Event::recv(
+            String::from("cafe\u{301} cre\u{300}me"),
+            Duration::ZERO,
+            false,
+        );
+        let fit = Fit {
+            width: 7,
+            truncate: false,
+        };
+        assert_eq!(
+            ev.to_message(false).fit(Some(fit)).to_string(),
+            "< cafe\u{301} \n↪ cre\u{300}me"
+        );
+    }
+
+    #[rstest]
+    #[case(false, "< \x1B[31mabcdefgh\n↪ ij\x1B[0m")]
+    #[case(true, "< \x1B[31mabcdefg…\x1B[0m")]
+    fn test_fit_ansi_pass(#[case] truncate: bool, #[case] shown: &str) {
+        let ev = Event::recv(String::from("\x1B[31mabcdefghij\n"), Duration::ZERO, false);
+        let fit = Fit {
+            width: 10,
+            truncate,
+        };
+        assert_eq!(
+            ev.to_message(false)
+                .ansi(AnsiMode::Pass)
+                .fit(Some(fit))
+                .to_string(),
+            shown
+        );
+    }
+
+    #[test]
+    fn test_fit_time_prefix() {
+        let ev = Event::Recv {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56 -04:00),
+            elapsed: Duration::ZERO,
+            data: String::from("abcdefghij"),
+            continued: false,
+            partial: false,
+            resumed: false,
+            shown: None,
+            since_send: None,
+            raw: None,
+        };
+        let fit = Fit {
+            width: 20,
+            truncate: false,
+        };
+        assert_eq!(
+            ev.to_message(true).fit(Some(fit)).to_string(),
+            "[12:34:56] < abcdefg\n           ↪ hij"
+        );
+    }
+
+    #[rstest]
+    #[case(Some(Duration::from_millis(12)), "[12:34:56]".green())]
+    #[case(Some(Duration::from_millis(50)), "[12:34:56]".yellow())]
+    #[case(Some(Duration::from_millis(499)), "[12:34:56]".yellow())]
+    #[case(Some(Duration::from_secs(2)), "[12:34:56]".red())]
+    #[case(None, "[12:34:56]".stylize())]
+    fn test_latency_colors(
+        #[case] since_send: Option<Duration>,
+        #[case] stamp: StyledContent<&str>,
+    ) {
+        let ev = Event::Recv {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56 -04:00),
+            elapsed: Duration::ZERO,
+            data: String::from("pong"),
+            continued: false,
+            partial: false,
+            resumed: false,
+            shown: None,
+            since_send,
+            raw: None,
+        };
+        assert_eq!(
+            ev.to_message(true).latency_colors(true).to_string(),
+            format!("{stamp} < pong")
+        );
+        assert_eq!(ev.to_message(true).to_string(), "[12:34:56] < pong");
+    }
+
+    #[test]
+    fn test_plain() {
+        let ev = Event::Recv {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56 -04:00),
+            elapsed: Duration::ZERO,
+            data: String::from("ab\x01cd\x1B[31m\r\n"),
+            continued: false,
+            partial: false,
+            resumed: false,
+            shown: None,
+            since_send: Some(Duration::from_secs(2)),
+            raw: None,
+        };
+        assert_ne!(
+            ev.to_message(true).latency_colors(true).to_string(),
+            "[12:34:56] < ab^Acd^[[31m"
+        );
+        assert_eq!(
+            ev.to_message(true)
+                .latency_colors(true)
+                .plain(true)
+                .to_string(),
+            "[12:34:56] < ab^Acd^[[31m"
+        );
+    }
+
+    #[test]
+    fn test_throughput() {
+        let ev = Event::throughput(Throughput {
+            sent: Rate::default(),
+            recv: Rate {
+                lines_per_sec: 12.0,
+                bytes_per_sec: 3072.0,
+            },
+        });
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Throughput over the last 5s: received 12.0 lines/s, 3.0 KiB/s; sent 0.0 lines/s, 0.0 KiB/s"
+        );
+        assert!(ev.to_json().ends_with(
+            r#""event": "throughput", "window_ms": 5000.000, "lines_sent_per_sec": 0.000, "bytes_sent_per_sec": 0.000, "lines_recv_per_sec": 12.000, "bytes_recv_per_sec": 3072.000}"#
+        ));
+    }
+
+    #[test]
+    fn test_repeat_summary() {
+        let ev = Event::repeat_summary(RepeatSummary {
+            line: String::from("PING"),
+            sent: 10,
+            responses: 9,
+            latency: Some(Latency {
+                min: Duration::from_micros(250),
+                avg: Duration::from_micros(1500),
+                p95: Duration::from_millis(4),
+            }),
+        });
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            r#"* Sent "PING" 10 times; 9 responses; latency min 0.250ms, avg 1.500ms, p95 4.000ms"#
+        );
+        assert!(ev.to_json().ends_with(
+            r#""event": "repeat-summary", "data": "PING", "sent": 10, "responses": 9, "latency_min_ms": 0.250, "latency_avg_ms": 1.500, "latency_p95_ms": 4.000}"#
+        ));
+    }
+
+    #[test]
+    fn test_tls_finish() {
+        let ev = Event::tls_finish(Some(true));
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* TLS established (resumed session)"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "tls-complete", "resumed": true}"#));
+        let ev = Event::tls_finish(None);
+        assert_eq!(ev.to_message(false).to_string(), "* TLS established");
+        assert!(ev.to_json().ends_with(r#""event": "tls-complete"}"#));
+    }
+
+    #[test]
+    fn test_setting_changed() {
+        let ev = Event::setting_changed(Setting::Crlf(true));
+        assert_eq!(ev.to_message(false).to_string(), "* Set crlf to on");
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "setting-changed", "setting": "crlf", "value": "on"}"#));
+    }
+
+    #[test]
+    fn test_note() {
+        let ev = Event::note(String::from("bug happens here"));
+        assert_eq!(ev.to_message(false).to_string(), "* Note: bug happens here");
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "annotation", "data": "bug happens here"}"#));
+    }
+
+    #[rstest]
+    #[case(
+        false,
+        "* Line of 12 bytes exceeds maximum send length of 10",
+        r#""event": "send-too-long", "length": 12, "limit": 10, "refused": false}"#
+    )]
+    #[case(
+        true,
+        "* Line of 12 bytes exceeds maximum send length of 10; not sent",
+        r#""event": "send-too-long", "length": 12, "limit": 10, "refused": true}"#
+    )]
+    fn test_send_too_long(#[case] refused: bool, #[case] msg: &str, #[case] json_tail: &str) {
+        let ev = Event::send_too_long(12, 10, refused);
+        assert_eq!(ev.to_message(false).to_string(), msg);
+        assert!(ev.to_json().ends_with(json_tail));
+    }
+
+    #[test]
+    fn test_protocol_mismatch() {
+        let ev = Event::protocol_mismatch(BinaryProtocol::Tls);
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "! Received data looks like TLS, not lines of text; is --tls needed?"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "protocol-mismatch", "protocol": "tls"}"#));
+    }
+
+    #[cfg(feature = "rustls")]
+    #[test]
+    fn test_certificate_trusted() {
+        let ev = Event::certificate_trusted(String::from("AB:CD"));
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "* Trusting server certificate on first use: AB:CD"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "certificate-trusted", "fingerprint": "AB:CD"}"#));
+    }
+
+    #[test]
+    fn test_no_banner() {
+        let ev = Event::no_banner(Duration::from_secs(3));
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "! Nothing received within 3s of connecting; the server may be waiting for the client to speak first"
+        );
+        assert!(ev
+            .to_json()
+            .ends_with(r#""event": "no-banner", "wait_ms": 3000.000}"#));
+    }
+
+    #[test]
+    fn test_error_details() {
+        let ev = Event::Error {
+            timestamp: now(),
+            kind: ErrorKind::Tls,
+            os_error: None,
+            data: String::from("server certificate has expired"),
+            details: vec![
+                String::from("subject: CN=example.com"),
+                String::from("expired: 2026-11-16 08:16:25 UTC"),
+            ],
+        };
+        assert_eq!(
+            ev.to_message(false).to_string(),
+            "! server certificate has expired\n    subject: CN=example.com\n    expired: 2026-11-16 08:16:25 UTC"
+        );
+        assert!(ev.to_json().ends_with(
+            r#""data": "server certificate has expired", "details": ["subject: CN=example.com", "expired: 2026-11-16 08:16:25 UTC"]}"#
+        ));
+    }
+}
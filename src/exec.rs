@@ -0,0 +1,115 @@
+use std::io;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// A local program run with `--exec`, whose standard input & output take the
+/// place of a connection to a server
+#[derive(Debug)]
+pub(crate) struct ChildPipes {
+    /// Held so that the process is killed if it is still running when the
+    /// session ends
+    child: Child,
+    /// `None` once the sending side has been shut down, which closes the
+    /// program's standard input
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl ChildPipes {
+    /// Start the given command (program followed by arguments) with its
+    /// standard input & output piped to `confab`.  Its standard error is
+    /// inherited.
+    pub(crate) fn spawn(argv: &[String]) -> io::Result<ChildPipes> {
+        let Some((program, args)) = argv.split_first() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no command given",
+            ));
+        };
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("child's stdin should be piped");
+        let stdout = child.stdout.take().expect("child's stdout should be piped");
+        Ok(ChildPipes {
+            child,
+            stdin: Some(stdin),
+            stdout,
+        })
+    }
+
+    /// Return the process ID of the program, if it is still running
+    pub(crate) fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+}
+
+impl AsyncRead for ChildPipes {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ChildPipes {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut().stdin.as_mut() {
+            Some(stdin) => Pin::new(stdin).poll_write(cx, buf),
+            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().stdin.as_mut() {
+            Some(stdin) => Pin::new(stdin).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(stdin) = this.stdin.as_mut() {
+            ready!(Pin::new(stdin).poll_flush(cx))?;
+            // Dropping the pipe is the only way to signal EOF to the program
+            this.stdin = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cat() {
+        let mut pipes = ChildPipes::spawn(&[String::from("cat")]).unwrap();
+        assert!(pipes.id().is_some());
+        pipes.write_all(b"Hello!\n").await.unwrap();
+        pipes.shutdown().await.unwrap();
+        let mut output = String::new();
+        pipes.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "Hello!\n");
+    }
+
+    #[test]
+    fn test_no_command() {
+        let e = ChildPipes::spawn(&[]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
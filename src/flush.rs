@@ -0,0 +1,131 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// How often buffered output to standard output and the transcript is
+/// flushed, as set by `--flush`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum FlushPolicy {
+    /// Flush after every write
+    Always,
+    /// Flush whenever a complete line has been written
+    Line,
+    /// Flush at most once per the given interval, batching the writes in
+    /// between
+    Interval(Duration),
+}
+
+impl FromStr for FlushPolicy {
+    type Err = ParseFlushError;
+
+    fn from_str(s: &str) -> Result<FlushPolicy, ParseFlushError> {
+        match s {
+            "always" => Ok(FlushPolicy::Always),
+            "line" => Ok(FlushPolicy::Line),
+            _ => s
+                .strip_prefix("interval:")
+                .and_then(|ms| ms.parse::<u64>().ok())
+                .map(|ms| FlushPolicy::Interval(Duration::from_millis(ms)))
+                .ok_or(ParseFlushError),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+#[error(r#"expected "always", "line", or "interval:<MS>""#)]
+pub(crate) struct ParseFlushError;
+
+/// Decides when a buffered writer should be flushed under a `FlushPolicy`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct FlushTimer {
+    policy: FlushPolicy,
+    /// When the oldest unflushed write is due to be flushed under
+    /// `FlushPolicy::Interval`
+    due: Option<Instant>,
+}
+
+impl FlushTimer {
+    pub(crate) fn new(policy: FlushPolicy) -> FlushTimer {
+        FlushTimer { policy, due: None }
+    }
+
+    /// Record a write that did (`newline = true`) or did not complete a line,
+    /// and return whether the writer should now be flushed
+    pub(crate) fn wrote(&mut self, newline: bool, now: Instant) -> bool {
+        match self.policy {
+            FlushPolicy::Always => true,
+            FlushPolicy::Line => newline,
+            FlushPolicy::Interval(period) => {
+                if now >= *self.due.get_or_insert(now + period) {
+                    self.due = None;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Record that the writer has been flushed
+    pub(crate) fn flushed(&mut self) {
+        self.due = None;
+    }
+
+    /// Returns when unflushed output is due to be flushed even if nothing
+    /// more is written, if ever
+    pub(crate) fn due(&self) -> Option<Instant> {
+        self.due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("always", FlushPolicy::Always)]
+    #[case("line", FlushPolicy::Line)]
+    #[case("interval:250", FlushPolicy::Interval(Duration::from_millis(250)))]
+    #[case("interval:0", FlushPolicy::Interval(Duration::ZERO))]
+    fn test_parse_flush(#[case] s: &str, #[case] policy: FlushPolicy) {
+        assert_eq!(s.parse::<FlushPolicy>(), Ok(policy));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("never")]
+    #[case("interval")]
+    #[case("interval:")]
+    #[case("interval:-5")]
+    #[case("interval:1s")]
+    fn test_parse_bad_flush(#[case] s: &str) {
+        assert_eq!(s.parse::<FlushPolicy>(), Err(ParseFlushError));
+    }
+
+    #[test]
+    fn test_line() {
+        let mut timer = FlushTimer::new(FlushPolicy::Line);
+        let now = Instant::now();
+        assert!(!timer.wrote(false, now));
+        assert!(timer.wrote(true, now));
+        assert_eq!(timer.due(), None);
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut timer = FlushTimer::new(FlushPolicy::Interval(Duration::from_millis(100)));
+        let start = Instant::now();
+        assert_eq!(timer.due(), None);
+        assert!(!timer.wrote(true, start));
+        assert_eq!(timer.due(), Some(start + Duration::from_millis(100)));
+        assert!(!timer.wrote(true, start + Duration::from_millis(60)));
+        assert_eq!(timer.due(), Some(start + Duration::from_millis(100)));
+        assert!(timer.wrote(false, start + Duration::from_millis(120)));
+        assert_eq!(timer.due(), None);
+        assert!(!timer.wrote(false, start + Duration::from_millis(130)));
+        assert_eq!(timer.due(), Some(start + Duration::from_millis(230)));
+        timer.flushed();
+        assert_eq!(timer.due(), None);
+    }
+}
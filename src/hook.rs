@@ -0,0 +1,344 @@
+use crate::events::Event;
+use crate::util::{chomp, TIMESTAMP_FMT};
+use std::io;
+use std::net::SocketAddr;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Maximum amount of time a hook command may take to process a single line
+/// before it is killed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An external command given with `--recv-hook` or `--send-hook`.  Each line
+/// is piped through a fresh invocation of the command, and the command's
+/// output is displayed in place of the line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Hook {
+    command: String,
+}
+
+impl Hook {
+    pub(crate) fn new(command: String) -> Hook {
+        Hook { command }
+    }
+
+    /// Run the command via the shell with `line` (terminated by a newline)
+    /// on its standard input, and return what it wrote to standard output,
+    /// minus any trailing line ending.  Returns `Ok(None)` if the command
+    /// produced no output.
+    ///
+    /// Lines are processed one at a time; while the command runs, nothing
+    /// more is read from the server, so a slow hook slows down the
+    /// connection rather than letting lines pile up in memory.
+    pub(crate) async fn run(&self, line: &str) -> Result<Option<String>, HookError> {
+        let mut child = shell(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(HookError::Spawn)?;
+        let stdin = child.stdin.take();
+        let input = format!("{}\n", chomp(line));
+        let write = async move {
+            if let Some(mut stdin) = stdin {
+                // The command is free to not read its input, so ignore
+                // errors like EPIPE
+                let _ = stdin.write_all(input.as_bytes()).await;
+            }
+        };
+        let output = match tokio::time::timeout(HOOK_TIMEOUT, async {
+            tokio::join!(write, child.wait_with_output()).1
+        })
+        .await
+        {
+            Ok(r) => r.map_err(HookError::Wait)?,
+            Err(_) => return Err(HookError::Timeout),
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(HookError::Status {
+                status: output.status,
+                stderr: stderr.lines().next().unwrap_or_default().to_owned(),
+            });
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let text = chomp(&stdout);
+        Ok((!text.is_empty()).then(|| text.to_owned()))
+    }
+}
+
+/// The commands given with `--on-connect-cmd`, `--on-disconnect-cmd`, and
+/// `--on-error-cmd`.  Each command is run in the background via the shell
+/// whenever the corresponding event is reported, with details of the event in
+/// `CONFAB_*` environment variables.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct EventCommands {
+    on_connect: Option<String>,
+    on_disconnect: Option<String>,
+    on_error: Option<String>,
+    /// The host & port of the current or most recent connection
+    target: Option<(String, u16)>,
+    /// The address of the remote end of the current or most recent
+    /// connection
+    peer: Option<SocketAddr>,
+}
+
+impl EventCommands {
+    pub(crate) fn new(
+        on_connect: Option<String>,
+        on_disconnect: Option<String>,
+        on_error: Option<String>,
+    ) -> EventCommands {
+        EventCommands {
+            on_connect,
+            on_disconnect,
+            on_error,
+            target: None,
+            peer: None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.on_connect.is_none() && self.on_disconnect.is_none() && self.on_error.is_none()
+    }
+
+    /// If `event` has a command, start the command with details of the event
+    /// in its environment.  The command is not waited for, and its output &
+    /// exit status are ignored.
+    pub(crate) fn run(&mut self, event: &Event) -> Result<(), EventCommandError> {
+        let Some(Invocation {
+            option,
+            command,
+            env,
+        }) = self.prepare(event)
+        else {
+            return Ok(());
+        };
+        // Dropping the child leaves it running; tokio reaps it once it exits.
+        shell(command)
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(drop)
+            .map_err(|source| EventCommandError { option, source })
+    }
+
+    /// Keep track of the connection described by `event` and, if `event` has
+    /// a command, return how to run it
+    fn prepare(&mut self, event: &Event) -> Option<Invocation<'_>> {
+        let (option, command, name) = match event {
+            Event::ConnectStart { host, port, .. } => {
+                self.target = Some((host.clone(), *port));
+                self.peer = None;
+                return None;
+            }
+            Event::ListenStart { .. } => {
+                self.target = None;
+                self.peer = None;
+                return None;
+            }
+            Event::ConnectFinish { peer, .. } | Event::Accepted { peer, .. } => {
+                self.peer = Some(*peer);
+                ("--on-connect-cmd", self.on_connect.as_deref()?, "connect")
+            }
+            Event::Disconnect { .. } => (
+                "--on-disconnect-cmd",
+                self.on_disconnect.as_deref()?,
+                "disconnect",
+            ),
+            Event::Error { .. } => ("--on-error-cmd", self.on_error.as_deref()?, "error"),
+            _ => return None,
+        };
+        let mut env = vec![
+            ("CONFAB_EVENT", String::from(name)),
+            (
+                "CONFAB_TIMESTAMP",
+                event
+                    .timestamp()
+                    .format(&TIMESTAMP_FMT)
+                    .expect("formatting a datetime as RFC 3339 should not fail"),
+            ),
+            ("CONFAB_MESSAGE", event.plain_message()),
+        ];
+        if let Some((host, port)) = self.target.as_ref() {
+            env.push(("CONFAB_HOST", host.clone()));
+            env.push(("CONFAB_PORT", port.to_string()));
+        }
+        if let Some(peer) = self.peer {
+            env.push(("CONFAB_PEER", peer.to_string()));
+        }
+        match event {
+            Event::Disconnect {
+                closed_by,
+                duration,
+                lines_sent,
+                lines_recv,
+                ..
+            } => {
+                env.push(("CONFAB_CLOSED_BY", String::from(closed_by.as_str())));
+                env.push(("CONFAB_DURATION_MS", duration.as_millis().to_string()));
+                env.push(("CONFAB_LINES_SENT", lines_sent.to_string()));
+                env.push(("CONFAB_LINES_RECV", lines_recv.to_string()));
+            }
+            Event::Error {
+                kind,
+                os_error,
+                data,
+                ..
+            } => {
+                env.push(("CONFAB_ERROR_KIND", String::from(kind.as_str())));
+                env.push(("CONFAB_ERROR", data.clone()));
+                if let Some(code) = os_error {
+                    env.push(("CONFAB_OS_ERROR", code.to_string()));
+                }
+            }
+            _ => (),
+        }
+        Some(Invocation {
+            option,
+            command,
+            env,
+        })
+    }
+}
+
+/// A command from [`EventCommands`] to run for an event
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Invocation<'a> {
+    /// The option with which the command was given
+    option: &'static str,
+    command: &'a str,
+    env: Vec<(&'static str, String)>,
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum HookError {
+    #[error("failed to run hook command")]
+    Spawn(#[source] io::Error),
+    #[error("error waiting for hook command")]
+    Wait(#[source] io::Error),
+    #[error("hook command timed out")]
+    Timeout,
+    #[error("hook command failed ({status}){}", fmt_detail(.stderr))]
+    Status {
+        status: ExitStatus,
+        /// The first line of the command's standard error
+        stderr: String,
+    },
+}
+
+#[derive(Debug, Error)]
+#[error("failed to run {option} command")]
+pub(crate) struct EventCommandError {
+    option: &'static str,
+    source: io::Error,
+}
+
+fn fmt_detail(detail: &str) -> String {
+    if detail.is_empty() {
+        String::new()
+    } else {
+        format!(": {detail}")
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::events::{ClosedBy, SocketOptions};
+
+    #[tokio::test]
+    async fn test_run() {
+        let hook = Hook::new(String::from("tr a-z A-Z"));
+        assert_eq!(
+            hook.run("hello\r\n").await.unwrap(),
+            Some(String::from("HELLO"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_multiline() {
+        let hook = Hook::new(String::from("cat; echo decoded"));
+        assert_eq!(
+            hook.run("aGk=").await.unwrap(),
+            Some(String::from("aGk=\ndecoded"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_no_output() {
+        let hook = Hook::new(String::from("cat >/dev/null"));
+        assert_eq!(hook.run("hello").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_failure() {
+        let hook = Hook::new(String::from("echo oops >&2; exit 3"));
+        let e = hook.run("hello").await.unwrap_err();
+        assert_eq!(e.to_string(), "hook command failed (exit status: 3): oops");
+    }
+
+    #[test]
+    fn test_event_command_env() {
+        let mut cmds = EventCommands::new(None, Some(String::from("notify")), None);
+        assert_eq!(cmds.prepare(&Event::connect_start("example.com", 80)), None);
+        let peer = "93.184.215.14:80".parse::<SocketAddr>().unwrap();
+        assert_eq!(
+            cmds.prepare(&Event::connect_finish(peer, SocketOptions::default())),
+            None
+        );
+        let event = Event::Disconnect {
+            timestamp: time::macros::datetime!(2024-05-01 12:34:56 -04:00),
+            closed_by: ClosedBy::Peer,
+            duration: Duration::from_millis(12345),
+            lines_sent: 2,
+            lines_recv: 5,
+        };
+        let inv = cmds.prepare(&event).unwrap();
+        assert_eq!(inv.option, "--on-disconnect-cmd");
+        assert_eq!(inv.command, "notify");
+        assert_eq!(
+            inv.env,
+            [
+                ("CONFAB_EVENT", String::from("disconnect")),
+                (
+                    "CONFAB_TIMESTAMP",
+                    String::from("2024-05-01T12:34:56.000000-04:00")
+                ),
+                (
+                    "CONFAB_MESSAGE",
+                    String::from(
+                        "Disconnected by peer after 12.3s (sent 2 lines, received 5 lines)"
+                    )
+                ),
+                ("CONFAB_HOST", String::from("example.com")),
+                ("CONFAB_PORT", String::from("80")),
+                ("CONFAB_PEER", String::from("93.184.215.14:80")),
+                ("CONFAB_CLOSED_BY", String::from("peer")),
+                ("CONFAB_DURATION_MS", String::from("12345")),
+                ("CONFAB_LINES_SENT", String::from("2")),
+                ("CONFAB_LINES_RECV", String::from("5")),
+            ]
+        );
+    }
+}
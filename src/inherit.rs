@@ -0,0 +1,156 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The first file descriptor passed by systemd under socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Which inherited file descriptor to use, as given with `--fd`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum FdSpec {
+    /// A specific file descriptor number
+    Fd(RawFd),
+    /// The first socket passed via systemd's `LISTEN_FDS` protocol
+    Systemd,
+}
+
+impl FdSpec {
+    /// Determine the file descriptor to use, checking the `LISTEN_PID` &
+    /// `LISTEN_FDS` environment variables for `FdSpec::Systemd`
+    fn resolve(self) -> Result<RawFd, InheritError> {
+        match self {
+            FdSpec::Fd(fd) => Ok(fd),
+            FdSpec::Systemd => {
+                let pid = std::env::var("LISTEN_PID").ok();
+                if pid.is_some_and(|pid| pid.trim() != std::process::id().to_string()) {
+                    return Err(InheritError::NotForUs);
+                }
+                match std::env::var("LISTEN_FDS")
+                    .ok()
+                    .map(|n| n.trim().parse::<u32>())
+                {
+                    Some(Ok(n)) if n > 0 => Ok(SD_LISTEN_FDS_START),
+                    _ => Err(InheritError::NoListenFds),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for FdSpec {
+    type Err = ParseFdSpecError;
+
+    fn from_str(s: &str) -> Result<FdSpec, ParseFdSpecError> {
+        if s == "systemd" {
+            Ok(FdSpec::Systemd)
+        } else {
+            match s.parse::<RawFd>() {
+                Ok(fd) if fd >= 0 => Ok(FdSpec::Fd(fd)),
+                _ => Err(ParseFdSpecError),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+#[error(r#"expected a nonnegative integer or "systemd""#)]
+pub(crate) struct ParseFdSpecError;
+
+/// A TCP socket inherited from the parent process instead of one opened by
+/// connecting to a server.  A listening socket is used by accepting a single
+/// connection on it.
+#[derive(Debug)]
+pub(crate) enum InheritedSocket {
+    Stream(TcpStream),
+    Listener(TcpListener),
+}
+
+impl InheritedSocket {
+    /// Take ownership of the TCP socket identified by `spec`
+    pub(crate) fn open(spec: FdSpec) -> Result<InheritedSocket, InheritError> {
+        let fd = spec.resolve()?;
+        let meta =
+            std::fs::metadata(format!("/dev/fd/{fd}")).map_err(|e| InheritError::BadFd(fd, e))?;
+        if !meta.file_type().is_socket() {
+            return Err(InheritError::NotSocket(fd));
+        }
+        // SAFETY: We just checked that `fd` is open, and by passing it to us
+        // with `--fd`, the user has handed it over to confab, so nothing else
+        // in this process uses it.
+        #[allow(unsafe_code)]
+        let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+        let stream = TcpStream::from(owned);
+        if stream.local_addr().is_err() {
+            return Err(InheritError::NotTcp(fd));
+        }
+        match stream.peer_addr() {
+            Ok(_) => Ok(InheritedSocket::Stream(stream)),
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(InheritedSocket::Listener(
+                TcpListener::from(OwnedFd::from(stream)),
+            )),
+            Err(e) => Err(InheritError::BadFd(fd, e)),
+        }
+    }
+
+    /// Return the address that stands in for the remote host & port: the
+    /// peer's address for a connected socket, or the local address for a
+    /// listening socket
+    pub(crate) fn addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            InheritedSocket::Stream(s) => s.peer_addr(),
+            InheritedSocket::Listener(s) => s.local_addr(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum InheritError {
+    #[error("file descriptor {0} is not usable")]
+    BadFd(RawFd, #[source] io::Error),
+    #[error("file descriptor {0} is not a socket")]
+    NotSocket(RawFd),
+    #[error("file descriptor {0} is not a TCP socket")]
+    NotTcp(RawFd),
+    #[error("LISTEN_PID does not match this process; sockets were not passed to confab")]
+    NotForUs,
+    #[error("no sockets were passed via LISTEN_FDS")]
+    NoListenFds,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn test_parse_fd_spec() {
+        assert_eq!("3".parse::<FdSpec>(), Ok(FdSpec::Fd(3)));
+        assert_eq!("systemd".parse::<FdSpec>(), Ok(FdSpec::Systemd));
+        assert_eq!("-1".parse::<FdSpec>(), Err(ParseFdSpecError));
+        assert_eq!("three".parse::<FdSpec>(), Err(ParseFdSpecError));
+    }
+
+    #[test]
+    fn test_open_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+        let socket = InheritedSocket::open(FdSpec::Fd(fd)).unwrap();
+        assert_matches!(socket, InheritedSocket::Listener(_));
+        assert_eq!(socket.addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_open_not_socket() {
+        let file = tempfile::tempfile().unwrap();
+        let fd = file.into_raw_fd();
+        assert_matches!(
+            InheritedSocket::open(FdSpec::Fd(fd)),
+            Err(InheritError::NotSocket(n)) if n == fd
+        );
+    }
+}
@@ -1,20 +1,87 @@
+use crate::commands::{parse_line, Command, CommandError};
+use crate::editor::{Editor, EditorEvent};
 use crate::errors::InterfaceError;
+use crate::memory::{MemoryBudget, RetainedQueue};
 use async_stream::stream;
-use futures_util::Stream;
+use clap::ValueEnum;
+use futures_util::{stream, Stream, StreamExt};
 use pin_project_lite::pin_project;
-use rustyline_async::{Readline, ReadlineError, ReadlineEvent};
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{ready, Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs::File as TokioFile;
 use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::sync::mpsc;
 use tokio::time::{sleep, Sleep};
 
+/// The prompt shown by the line editor
+pub(crate) const PROMPT: &str = "confab> ";
+
+/// The prompt shown by the line editor while composing lines with `/compose`
+const COMPOSE_PROMPT: &str = "compose> ";
+
+/// The prompt shown by the line editor after a line ending in a backslash,
+/// while waiting for the rest of the line
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// A line that, when entered by itself, ends a `/compose` buffer
+const COMPOSE_END: &str = ".";
+
+/// Maximum number of lines read from stdin that may be waiting to be
+/// processed
+const STDIN_BUFFER: usize = 64;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) enum Input {
     Line(String),
+    /// Multiple lines pasted into the terminal at once
+    Paste(Vec<String>),
+    /// A command entered at the prompt
+    Command(Result<Command, CommandError>),
     CtrlC,
+    /// The end of the lines entered after `/compose`, marked by a "." line or
+    /// by Ctrl-D
+    ComposeEnd,
+}
+
+/// How to handle multiple lines pasted into the terminal at once
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum PasteMode {
+    /// Ask the user for confirmation before sending the lines
+    Ask,
+    /// Send the lines immediately
+    #[default]
+    Send,
+    /// Send the lines one at a time with a delay between each one
+    Delay,
+}
+
+/// How to handle an empty line of input, e.g., from pressing Enter at an
+/// empty prompt
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum EmptyLineMode {
+    /// Send the empty line
+    #[default]
+    Send,
+    /// Do not send the empty line
+    Skip,
+    /// Ask the user whether to send the empty line
+    Confirm,
+}
+
+/// What to do when the user presses Ctrl-C at the prompt
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum CtrlCMode {
+    /// End the session
+    Quit,
+    /// Ask the user whether to end the session
+    Confirm,
+    /// Send the break sequence to the server
+    SendBreak,
+    /// Do nothing
+    #[default]
+    Ignore,
 }
 
 pin_project! {
@@ -63,20 +130,346 @@ impl Stream for StartupScript {
     }
 }
 
+/// Lines to send to the server whenever the connection has been idle for a
+/// given length of time
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct IdleScript {
+    pub(crate) lines: Vec<String>,
+    /// How long the connection must be idle before the lines are sent
+    pub(crate) after: Duration,
+    /// When the lines were last queued for sending
+    pub(crate) last_run: Option<Instant>,
+}
+
+impl IdleScript {
+    pub(crate) fn new(lines: Vec<String>, after: Duration) -> IdleScript {
+        IdleScript {
+            lines,
+            after,
+            last_run: None,
+        }
+    }
+
+    /// Return when the script should next be run, given how long the
+    /// connection has been idle, or `None` if that is too far in the future
+    /// to represent
+    pub(crate) fn deadline(&self, idle: Duration) -> Option<Instant> {
+        let due = Instant::now().checked_add(self.after.saturating_sub(idle))?;
+        match self.last_run {
+            Some(t) => Some(due.max(t.checked_add(self.after)?)),
+            None => Some(due),
+        }
+    }
+}
+
+/// Lines waiting to be sent to the server at a fixed pace
+#[derive(Debug)]
+pub(crate) struct SendQueue {
+    lines: RetainedQueue<String>,
+    delay: Duration,
+    nap: Option<Pin<Box<Sleep>>>,
+}
+
+impl SendQueue {
+    pub(crate) fn new(delay: Duration, budget: MemoryBudget) -> SendQueue {
+        SendQueue {
+            lines: RetainedQueue::new(budget),
+            delay,
+            nap: None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Add lines to the end of the queue.  If they do not all fit within the
+    /// memory budget, none of them are added and `false` is returned, as
+    /// evicting queued lines would silently keep them from being sent.
+    #[must_use]
+    pub(crate) fn extend(&mut self, lines: Vec<String>) -> bool {
+        if !self.lines.fits(lines.iter().map(String::len).sum()) {
+            return false;
+        }
+        self.lines.extend(lines);
+        true
+    }
+
+    /// Iterate over the lines in the queue in the order they will be sent
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// Remove & return the line at the given (zero-based) position in the
+    /// queue, if any
+    pub(crate) fn remove(&mut self, index: usize) -> Option<String> {
+        let line = self.lines.remove(index)?;
+        if self.lines.is_empty() {
+            self.nap = None;
+        }
+        Some(line)
+    }
+
+    /// Discard all lines in the queue and return how many there were
+    pub(crate) fn clear(&mut self) -> usize {
+        self.nap = None;
+        self.lines.clear()
+    }
+
+    /// Wait until the next line in the queue is due to be sent and then
+    /// return it.  The first line added to an empty queue is due immediately.
+    ///
+    /// This method is cancel-safe.  It never returns if the queue is empty.
+    pub(crate) async fn next(&mut self) -> String {
+        if let Some(nap) = self.nap.as_mut() {
+            nap.await;
+            self.nap = None;
+        }
+        let Some(line) = self.lines.pop_front() else {
+            return std::future::pending().await;
+        };
+        if !self.lines.is_empty() {
+            self.nap = Some(Box::pin(sleep(self.delay)));
+        }
+        line
+    }
+}
+
+/// Read lines of input from stdin without a line editor, for use when stdout
+/// is not a terminal
+pub(crate) fn stdin_stream() -> impl Stream<Item = Result<Input, InterfaceError>> + Send {
+    // tokio's stdin blocks runtime shutdown while waiting for input, so read
+    // it from a detached thread instead.
+    let (sender, mut receiver) = mpsc::channel(STDIN_BUFFER);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let failed = line.is_err();
+            if sender.blocking_send(line).is_err() || failed {
+                break;
+            }
+        }
+    });
+    stream! {
+        while let Some(r) = receiver.recv().await {
+            yield r.map(Input::Line).map_err(InterfaceError::ReadLine);
+        }
+    }
+}
+
+/// Input for `--receive-only` mode: nothing is read from the user, and the
+/// stream simply ends once Ctrl-C is pressed so that the session can be closed
+/// cleanly.  If Ctrl-C can't be listened for, the stream never ends, and
+/// Ctrl-C kills the process as usual.
+pub(crate) fn interrupt_stream() -> impl Stream<Item = Result<Input, InterfaceError>> + Send {
+    stream::once(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    })
+    .filter_map(|()| std::future::ready(None))
+}
+
+/// Read input from the line editor.  After `/compose` is entered, lines are
+/// passed through without being parsed as commands, and the prompt is changed,
+/// until a "." line, Ctrl-D, or Ctrl-C ends the composition.
+///
+/// Outside of `/compose`, a line ending in a backslash is continued on the
+/// next prompt, and the pieces are joined (without the backslashes) into a
+/// single line.  Ctrl-C discards a partially-entered line.  Unless `escapes`
+/// is set (in which case escape sequences are expanded later), a line that
+/// instead ends in an escaped backslash has the escaping backslash removed so
+/// that a line ending in a literal backslash can still be entered.
+///
+/// Each line of a paste is parsed the same way as a typed line, and runs of
+/// consecutive non-command lines are returned as a single [`Input::Paste`].
 pub(crate) fn readline_stream(
-    rl: &mut Readline,
+    rl: &mut Editor,
+    escapes: bool,
 ) -> impl Stream<Item = Result<Input, InterfaceError>> + Send + '_ {
     stream! {
+        let mut composing = false;
+        let mut continued: Option<String> = None;
         loop {
             match rl.readline().await {
-                Ok(ReadlineEvent::Line(line)) => {
+                Ok(EditorEvent::Line(mut line)) => {
                     rl.add_history_entry(line.clone());
-                    yield Ok(Input::Line(line));
+                    if composing {
+                        if line == COMPOSE_END {
+                            composing = false;
+                            if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                                yield Err(InterfaceError::ReadLine(e));
+                            }
+                            yield Ok(Input::ComposeEnd);
+                        } else {
+                            yield Ok(Input::Line(line));
+                        }
+                    } else {
+                        let was_continued = continued.is_some();
+                        if let Some(head) = continued.take() {
+                            line.insert_str(0, &head);
+                        }
+                        if let Some(head) = strip_continuation(&line) {
+                            continued = Some(head.to_owned());
+                            if !was_continued {
+                                if let Err(e) = rl.set_prompt(String::from(CONTINUATION_PROMPT)) {
+                                    yield Err(InterfaceError::ReadLine(e));
+                                }
+                            }
+                            continue;
+                        }
+                        if was_continued {
+                            if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                                yield Err(InterfaceError::ReadLine(e));
+                            }
+                        }
+                        if !escapes && line.ends_with("\\\\") {
+                            line.pop();
+                        }
+                        let input = parse_line(line);
+                        if input == Input::Command(Ok(Command::Compose)) {
+                            composing = true;
+                            if let Err(e) = rl.set_prompt(String::from(COMPOSE_PROMPT)) {
+                                yield Err(InterfaceError::ReadLine(e));
+                            }
+                        }
+                        yield Ok(input);
+                    }
+                }
+                Ok(EditorEvent::Paste(mut lines)) => {
+                    for line in &lines {
+                        rl.add_history_entry(line.clone());
+                    }
+                    if let Some(head) = continued.take() {
+                        if let Some(first) = lines.first_mut() {
+                            first.insert_str(0, &head);
+                        }
+                        if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                            yield Err(InterfaceError::ReadLine(e));
+                        }
+                    }
+                    let mut pasted = Vec::new();
+                    for line in lines {
+                        if composing {
+                            if line == COMPOSE_END {
+                                composing = false;
+                                if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                                    yield Err(InterfaceError::ReadLine(e));
+                                }
+                                yield Ok(Input::ComposeEnd);
+                            } else {
+                                yield Ok(Input::Line(line));
+                            }
+                            continue;
+                        }
+                        match parse_line(line) {
+                            Input::Line(line) => pasted.push(line),
+                            input => {
+                                if !pasted.is_empty() {
+                                    yield Ok(paste_input(std::mem::take(&mut pasted)));
+                                }
+                                if input == Input::Command(Ok(Command::Compose)) {
+                                    composing = true;
+                                    if let Err(e) = rl.set_prompt(String::from(COMPOSE_PROMPT)) {
+                                        yield Err(InterfaceError::ReadLine(e));
+                                    }
+                                }
+                                yield Ok(input);
+                            }
+                        }
+                    }
+                    if !pasted.is_empty() {
+                        yield Ok(paste_input(pasted));
+                    }
+                }
+                Ok(ev @ (EditorEvent::Eof | EditorEvent::Interrupted)) if composing => {
+                    composing = false;
+                    if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                        yield Err(InterfaceError::ReadLine(e));
+                    }
+                    if ev == EditorEvent::Eof {
+                        yield Ok(Input::ComposeEnd);
+                    } else {
+                        yield Ok(Input::CtrlC);
+                    }
+                }
+                Ok(ev @ (EditorEvent::Eof | EditorEvent::Interrupted)) if continued.is_some() => {
+                    let head = continued.take().unwrap_or_default();
+                    if let Err(e) = rl.set_prompt(String::from(PROMPT)) {
+                        yield Err(InterfaceError::ReadLine(e));
+                    }
+                    // At the end of input, the line entered so far is used
+                    // as-is.
+                    if ev == EditorEvent::Eof {
+                        yield Ok(parse_line(head));
+                        break;
+                    }
                 }
-                Ok(ReadlineEvent::Eof) | Err(ReadlineError::Closed) => break,
-                Ok(ReadlineEvent::Interrupted) => yield Ok(Input::CtrlC),
-                Err(ReadlineError::IO(e)) => yield Err(InterfaceError::ReadLine(e)),
+                Ok(EditorEvent::Eof) => break,
+                Ok(EditorEvent::Interrupted) => yield Ok(Input::CtrlC),
+                Err(e) => yield Err(InterfaceError::ReadLine(e)),
             }
         }
     }
 }
+
+/// Convert consecutive pasted lines to an `Input`; a lone line is treated the
+/// same as a typed one
+fn paste_input(mut lines: Vec<String>) -> Input {
+    if lines.len() == 1 {
+        Input::Line(lines.swap_remove(0))
+    } else {
+        Input::Paste(lines)
+    }
+}
+
+/// If `line` ends in a backslash that is not itself escaped by a preceding
+/// backslash, return the line without it
+fn strip_continuation(line: &str) -> Option<&str> {
+    let head = line.strip_suffix('\\')?;
+    let escapes = head.len() - head.trim_end_matches('\\').len();
+    (escapes % 2 == 0).then_some(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("HELO", None)]
+    #[case("", None)]
+    #[case("RCPT TO:<a@example.com> \\", Some("RCPT TO:<a@example.com> "))]
+    #[case("\\", Some(""))]
+    #[case("C:\\\\", None)]
+    #[case("odd\\\\\\", Some("odd\\\\"))]
+    #[case("back\\slash", None)]
+    fn test_strip_continuation(#[case] line: &str, #[case] head: Option<&str>) {
+        assert_eq!(strip_continuation(line), head);
+    }
+
+    #[test]
+    fn test_idle_deadline_overflow() {
+        let mut script = IdleScript::new(vec![String::from("PING")], Duration::MAX);
+        assert_eq!(script.deadline(Duration::ZERO), None);
+        script.after = Duration::from_secs(60);
+        let now = Instant::now();
+        assert!(script
+            .deadline(Duration::ZERO)
+            .is_some_and(|t| t >= now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_send_queue_over_budget() {
+        let budget = MemoryBudget::new(Some(8));
+        let mut queue = SendQueue::new(Duration::ZERO, budget.clone());
+        assert!(queue.extend(vec![String::from("foo"), String::from("bar")]));
+        assert!(!queue.extend(vec![String::from("x"), String::from("yz")]));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), ["foo", "bar"]);
+        assert_eq!(budget.used(), 6);
+        assert_eq!(budget.evictions(), 0);
+    }
+}
@@ -0,0 +1,632 @@
+use crate::escapes::{unescape, EscapeError};
+use clap::ValueEnum;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Which set of default key bindings the line editor uses
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, ValueEnum)]
+pub(crate) enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+/// A key press, possibly with modifiers
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Construct a `Key`, normalizing it so that key events from the terminal
+    /// compare equal to the corresponding keys parsed from a key map
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        let mut modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        let code = match code {
+            // Terminals report Shift as part of the character, and they can't
+            // distinguish between Ctrl-A and Ctrl-Shift-A.
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            KeyCode::Tab | KeyCode::BackTab if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        Key { code, modifiers }
+    }
+
+    fn plain(code: KeyCode) -> Key {
+        Key::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> Key {
+        Key::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(c: char) -> Key {
+        Key::new(KeyCode::Char(c), KeyModifiers::ALT)
+    }
+
+    /// Returns `true` if pressing this key cancels an incremental history
+    /// search, as Esc & Ctrl-G do in Readline
+    pub(crate) fn aborts_search(self) -> bool {
+        self == Key::plain(KeyCode::Esc) || self == Key::ctrl('g')
+    }
+
+    /// If pressing this key should simply insert a character into the line,
+    /// return the character
+    pub(crate) fn printable(self) -> Option<char> {
+        match self.code {
+            // Control+Alt is used to type certain characters on a number of
+            // international keyboard layouts.
+            KeyCode::Char(c)
+                if self.modifiers.is_empty()
+                    || self.modifiers == KeyModifiers::CONTROL | KeyModifiers::ALT =>
+            {
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Key {
+        Key::new(event.code, event.modifiers)
+    }
+}
+
+impl FromStr for Key {
+    type Err = BindingError;
+
+    /// Parse a key name like `F2`, `C-a`, `M-Left`, or `x`
+    fn from_str(s: &str) -> Result<Key, BindingError> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut name = s;
+        loop {
+            if let Some(rest) = name.strip_prefix("C-").filter(|r| !r.is_empty()) {
+                modifiers |= KeyModifiers::CONTROL;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("M-").filter(|r| !r.is_empty()) {
+                modifiers |= KeyModifiers::ALT;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("S-").filter(|r| !r.is_empty()) {
+                modifiers |= KeyModifiers::SHIFT;
+                name = rest;
+            } else {
+                break;
+            }
+        }
+        let mut chars = name.chars();
+        let code = if let (Some(c), None) = (chars.next(), chars.next()) {
+            KeyCode::Char(c)
+        } else {
+            match &*name.to_ascii_lowercase() {
+                "backspace" => KeyCode::Backspace,
+                "backtab" => KeyCode::BackTab,
+                "delete" | "del" => KeyCode::Delete,
+                "down" => KeyCode::Down,
+                "end" => KeyCode::End,
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "home" => KeyCode::Home,
+                "insert" => KeyCode::Insert,
+                "left" => KeyCode::Left,
+                "pagedown" => KeyCode::PageDown,
+                "pageup" => KeyCode::PageUp,
+                "right" => KeyCode::Right,
+                "space" => KeyCode::Char(' '),
+                "tab" => KeyCode::Tab,
+                "up" => KeyCode::Up,
+                s => match s.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=24) => KeyCode::F(n),
+                    _ => return Err(BindingError::Key(s.to_owned())),
+                },
+            }
+        };
+        Ok(Key::new(code, modifiers))
+    }
+}
+
+/// An editing function that can be bound to a key
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Action {
+    AcceptLine,
+    Interrupt,
+    EndOfFile,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardDeleteChar,
+    DeleteChar,
+    UnixLineDiscard,
+    UnixWordRubout,
+    KillLine,
+    KillWord,
+    Yank,
+    YankPop,
+    PreviousHistory,
+    NextHistory,
+    ReverseSearchHistory,
+    ClearScreen,
+    Complete,
+    ScrollUp,
+    ScrollDown,
+    ViMovementMode,
+    ViInsertionMode,
+    ViAppendMode,
+    ViInsertBeg,
+    ViAppendEol,
+    ViNextWord,
+    ViDeleteTo,
+    ViChangeTo,
+}
+
+/// The names by which actions are referred to in key map files, following
+/// GNU Readline where possible
+static ACTION_NAMES: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("interrupt", Action::Interrupt),
+    ("end-of-file", Action::EndOfFile),
+    ("backward-char", Action::BackwardChar),
+    ("forward-char", Action::ForwardChar),
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("delete-char", Action::DeleteChar),
+    ("unix-line-discard", Action::UnixLineDiscard),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("kill-line", Action::KillLine),
+    ("kill-word", Action::KillWord),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("previous-history", Action::PreviousHistory),
+    ("next-history", Action::NextHistory),
+    ("reverse-search-history", Action::ReverseSearchHistory),
+    ("clear-screen", Action::ClearScreen),
+    ("complete", Action::Complete),
+    ("scroll-up", Action::ScrollUp),
+    ("scroll-down", Action::ScrollDown),
+    ("vi-movement-mode", Action::ViMovementMode),
+    ("vi-insertion-mode", Action::ViInsertionMode),
+    ("vi-append-mode", Action::ViAppendMode),
+    ("vi-insert-beg", Action::ViInsertBeg),
+    ("vi-append-eol", Action::ViAppendEol),
+    ("vi-next-word", Action::ViNextWord),
+    ("vi-delete-to", Action::ViDeleteTo),
+    ("vi-change-to", Action::ViChangeTo),
+];
+
+impl FromStr for Action {
+    type Err = BindingError;
+
+    fn from_str(s: &str) -> Result<Action, BindingError> {
+        ACTION_NAMES
+            .iter()
+            .find(|&&(name, _)| name == s)
+            .map(|&(_, action)| action)
+            .ok_or_else(|| BindingError::Action(s.to_owned()))
+    }
+}
+
+/// What happens when a key is pressed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Binding {
+    /// Perform an editing function
+    Action(Action),
+    /// Insert text into the line at the cursor
+    Insert(String),
+    /// Enter the given text as though it had been typed at an empty prompt,
+    /// leaving the line being edited as-is
+    Submit(String),
+}
+
+/// The key bindings used by the line editor
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct KeyMap {
+    mode: EditMode,
+    /// Bindings in effect in Emacs mode and in Vi insert mode
+    insert: HashMap<Key, Binding>,
+    /// Bindings in effect in Vi command mode
+    command: HashMap<Key, Binding>,
+}
+
+impl KeyMap {
+    /// Return the default key bindings for the given mode
+    pub(crate) fn new(mode: EditMode) -> KeyMap {
+        let insert = match mode {
+            EditMode::Emacs => emacs_bindings(),
+            EditMode::Vi => vi_insert_bindings(),
+        };
+        let command = match mode {
+            EditMode::Emacs => Vec::new(),
+            EditMode::Vi => vi_command_bindings(),
+        };
+        let table = |bindings: Vec<(Key, Action)>| {
+            bindings
+                .into_iter()
+                .map(|(key, action)| (key, Binding::Action(action)))
+                .collect::<HashMap<_, _>>()
+        };
+        KeyMap {
+            mode,
+            insert: table(insert),
+            command: table(command),
+        }
+    }
+
+    pub(crate) fn mode(&self) -> EditMode {
+        self.mode
+    }
+
+    /// Look up the binding for a key in the given mode
+    pub(crate) fn get(&self, key: Key, command_mode: bool) -> Option<&Binding> {
+        if command_mode {
+            self.command.get(&key)
+        } else {
+            self.insert.get(&key)
+        }
+    }
+
+    /// Add the bindings in the key map file at `path`, overriding any
+    /// existing bindings for the same keys
+    pub(crate) fn load(&mut self, path: &Path) -> Result<(), KeyMapError> {
+        let src = std::fs::read_to_string(path).map_err(KeyMapError::Read)?;
+        self.extend(&src)
+    }
+
+    /// Add the bindings in the given key map file contents.
+    ///
+    /// Each non-blank line of a key map file that does not start with `#`
+    /// consists of a key name followed by either the name of an action or by
+    /// `insert` or `submit` and the text to insert or submit.  The text may be
+    /// enclosed in double quotes in order to preserve leading or trailing
+    /// whitespace, and it may contain the escape sequences recognized by
+    /// `--escapes`.  A binding applies in every editing mode.
+    pub(crate) fn extend(&mut self, src: &str) -> Result<(), KeyMapError> {
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, binding) = parse_binding(line).map_err(|source| KeyMapError::Parse {
+                lineno: i + 1,
+                source,
+            })?;
+            self.insert.insert(key, binding.clone());
+            if self.mode == EditMode::Vi {
+                self.command.insert(key, binding);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::new(EditMode::default())
+    }
+}
+
+fn parse_binding(line: &str) -> Result<(Key, Binding), BindingError> {
+    let (key, rest) = split_word(line);
+    let key = key.parse::<Key>()?;
+    let (name, arg) = split_word(rest);
+    let binding = match name {
+        "" => return Err(BindingError::NoAction),
+        "insert" | "submit" => {
+            if arg.is_empty() {
+                return Err(BindingError::NoText(name.to_owned()));
+            }
+            let text = arg
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .unwrap_or(arg);
+            let text = unescape(text)?;
+            if name == "insert" {
+                Binding::Insert(text)
+            } else {
+                Binding::Submit(text)
+            }
+        }
+        name => {
+            let action = name.parse::<Action>()?;
+            if !arg.is_empty() {
+                return Err(BindingError::Argument(name.to_owned()));
+            }
+            Binding::Action(action)
+        }
+    };
+    Ok((key, binding))
+}
+
+/// Split off the first whitespace-delimited word of `s`, returning it and the
+/// rest of the string with leading & trailing whitespace removed
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+fn emacs_bindings() -> Vec<(Key, Action)> {
+    vec![
+        (Key::plain(KeyCode::Enter), Action::AcceptLine),
+        (Key::ctrl('c'), Action::Interrupt),
+        (Key::ctrl('d'), Action::EndOfFile),
+        (Key::plain(KeyCode::Left), Action::BackwardChar),
+        (Key::ctrl('b'), Action::BackwardChar),
+        (Key::plain(KeyCode::Right), Action::ForwardChar),
+        (Key::ctrl('f'), Action::ForwardChar),
+        (
+            Key::new(KeyCode::Left, KeyModifiers::CONTROL),
+            Action::BackwardWord,
+        ),
+        (Key::alt('b'), Action::BackwardWord),
+        (
+            Key::new(KeyCode::Right, KeyModifiers::CONTROL),
+            Action::ForwardWord,
+        ),
+        (Key::alt('f'), Action::ForwardWord),
+        (Key::plain(KeyCode::Home), Action::BeginningOfLine),
+        (Key::ctrl('a'), Action::BeginningOfLine),
+        (Key::plain(KeyCode::End), Action::EndOfLine),
+        (Key::ctrl('e'), Action::EndOfLine),
+        (Key::plain(KeyCode::Backspace), Action::BackwardDeleteChar),
+        (Key::ctrl('h'), Action::BackwardDeleteChar),
+        (Key::plain(KeyCode::Delete), Action::DeleteChar),
+        (Key::ctrl('u'), Action::UnixLineDiscard),
+        (Key::ctrl('w'), Action::UnixWordRubout),
+        (Key::ctrl('k'), Action::KillLine),
+        (Key::alt('d'), Action::KillWord),
+        (Key::ctrl('y'), Action::Yank),
+        (Key::alt('y'), Action::YankPop),
+        (Key::plain(KeyCode::Up), Action::PreviousHistory),
+        (Key::ctrl('p'), Action::PreviousHistory),
+        (Key::plain(KeyCode::Down), Action::NextHistory),
+        (Key::ctrl('n'), Action::NextHistory),
+        (Key::ctrl('r'), Action::ReverseSearchHistory),
+        (Key::ctrl('l'), Action::ClearScreen),
+        (Key::plain(KeyCode::Tab), Action::Complete),
+        (Key::plain(KeyCode::PageUp), Action::ScrollUp),
+        (Key::plain(KeyCode::PageDown), Action::ScrollDown),
+    ]
+}
+
+fn vi_insert_bindings() -> Vec<(Key, Action)> {
+    vec![
+        (Key::plain(KeyCode::Enter), Action::AcceptLine),
+        (Key::ctrl('c'), Action::Interrupt),
+        (Key::ctrl('d'), Action::EndOfFile),
+        (Key::plain(KeyCode::Esc), Action::ViMovementMode),
+        (Key::plain(KeyCode::Left), Action::BackwardChar),
+        (Key::plain(KeyCode::Right), Action::ForwardChar),
+        (
+            Key::new(KeyCode::Left, KeyModifiers::CONTROL),
+            Action::BackwardWord,
+        ),
+        (
+            Key::new(KeyCode::Right, KeyModifiers::CONTROL),
+            Action::ForwardWord,
+        ),
+        (Key::plain(KeyCode::Home), Action::BeginningOfLine),
+        (Key::plain(KeyCode::End), Action::EndOfLine),
+        (Key::plain(KeyCode::Backspace), Action::BackwardDeleteChar),
+        (Key::ctrl('h'), Action::BackwardDeleteChar),
+        (Key::plain(KeyCode::Delete), Action::DeleteChar),
+        (Key::ctrl('u'), Action::UnixLineDiscard),
+        (Key::ctrl('w'), Action::UnixWordRubout),
+        (Key::ctrl('y'), Action::Yank),
+        (Key::plain(KeyCode::Up), Action::PreviousHistory),
+        (Key::plain(KeyCode::Down), Action::NextHistory),
+        (Key::ctrl('r'), Action::ReverseSearchHistory),
+        (Key::ctrl('l'), Action::ClearScreen),
+        (Key::plain(KeyCode::Tab), Action::Complete),
+        (Key::plain(KeyCode::PageUp), Action::ScrollUp),
+        (Key::plain(KeyCode::PageDown), Action::ScrollDown),
+    ]
+}
+
+fn vi_command_bindings() -> Vec<(Key, Action)> {
+    let mut bindings = vec![
+        (Key::plain(KeyCode::Enter), Action::AcceptLine),
+        (Key::ctrl('c'), Action::Interrupt),
+        (Key::ctrl('d'), Action::EndOfFile),
+        (Key::ctrl('l'), Action::ClearScreen),
+        (Key::plain(KeyCode::Left), Action::BackwardChar),
+        (Key::plain(KeyCode::Backspace), Action::BackwardChar),
+        (Key::plain(KeyCode::Right), Action::ForwardChar),
+        (Key::plain(KeyCode::Home), Action::BeginningOfLine),
+        (Key::plain(KeyCode::End), Action::EndOfLine),
+        (Key::plain(KeyCode::Delete), Action::DeleteChar),
+        (Key::plain(KeyCode::Up), Action::PreviousHistory),
+        (Key::plain(KeyCode::Down), Action::NextHistory),
+        (Key::ctrl('r'), Action::ReverseSearchHistory),
+        (Key::plain(KeyCode::PageUp), Action::ScrollUp),
+        (Key::plain(KeyCode::PageDown), Action::ScrollDown),
+    ];
+    for (c, action) in [
+        ('h', Action::BackwardChar),
+        ('l', Action::ForwardChar),
+        (' ', Action::ForwardChar),
+        ('b', Action::BackwardWord),
+        ('e', Action::ForwardWord),
+        ('w', Action::ViNextWord),
+        ('0', Action::BeginningOfLine),
+        ('^', Action::BeginningOfLine),
+        ('$', Action::EndOfLine),
+        ('x', Action::DeleteChar),
+        ('X', Action::BackwardDeleteChar),
+        ('D', Action::KillLine),
+        ('k', Action::PreviousHistory),
+        ('-', Action::PreviousHistory),
+        ('j', Action::NextHistory),
+        ('+', Action::NextHistory),
+        ('i', Action::ViInsertionMode),
+        ('a', Action::ViAppendMode),
+        ('I', Action::ViInsertBeg),
+        ('A', Action::ViAppendEol),
+        ('d', Action::ViDeleteTo),
+        ('c', Action::ViChangeTo),
+    ] {
+        bindings.push((Key::plain(KeyCode::Char(c)), action));
+    }
+    bindings
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum KeyMapError {
+    #[error("failed to read file")]
+    Read(#[source] io::Error),
+    #[error("invalid binding on line {lineno}")]
+    Parse { lineno: usize, source: BindingError },
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum BindingError {
+    #[error("unknown key: {0:?}")]
+    Key(String),
+    #[error("unknown action: {0:?}")]
+    Action(String),
+    #[error("no action given")]
+    NoAction,
+    #[error("{0} requires text")]
+    NoText(String),
+    #[error("{0} does not take an argument")]
+    Argument(String),
+    #[error("invalid text")]
+    Escape(#[from] EscapeError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("F2", Key::plain(KeyCode::F(2)))]
+    #[case("f12", Key::plain(KeyCode::F(12)))]
+    #[case("C-a", Key::ctrl('a'))]
+    #[case("C-A", Key::ctrl('a'))]
+    #[case("M-b", Key::alt('b'))]
+    #[case("S-a", Key::plain(KeyCode::Char('A')))]
+    #[case("C-Left", Key::new(KeyCode::Left, KeyModifiers::CONTROL))]
+    #[case("Space", Key::plain(KeyCode::Char(' ')))]
+    #[case("-", Key::plain(KeyCode::Char('-')))]
+    #[case("C--", Key::ctrl('-'))]
+    #[case("S-Tab", Key::plain(KeyCode::BackTab))]
+    fn test_parse_key(#[case] s: &str, #[case] key: Key) {
+        assert_eq!(s.parse::<Key>().unwrap(), key);
+    }
+
+    #[rstest]
+    #[case("F25")]
+    #[case("C-")]
+    #[case("Hyper-x")]
+    #[case("")]
+    fn test_parse_bad_key(#[case] s: &str) {
+        assert!(s.parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn test_key_event() {
+        let event = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(Key::from(event), Key::plain(KeyCode::Char('A')));
+        assert_eq!(Key::from(event).printable(), Some('A'));
+        let event = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(Key::from(event), Key::ctrl('w'));
+        assert_eq!(Key::from(event).printable(), None);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut keymap = KeyMap::new(EditMode::Emacs);
+        keymap
+            .extend(concat!(
+                "# Comment\n",
+                "\n",
+                "F2 insert \"/queue drop \"\n",
+                "F3   submit   /stats\n",
+                "C-b end-of-line\n",
+                "F4 insert \\e[A\n",
+            ))
+            .unwrap();
+        assert_eq!(
+            keymap.get(Key::plain(KeyCode::F(2)), false),
+            Some(&Binding::Insert(String::from("/queue drop ")))
+        );
+        assert_eq!(
+            keymap.get(Key::plain(KeyCode::F(3)), false),
+            Some(&Binding::Submit(String::from("/stats")))
+        );
+        assert_eq!(
+            keymap.get(Key::ctrl('b'), false),
+            Some(&Binding::Action(Action::EndOfLine))
+        );
+        assert_eq!(
+            keymap.get(Key::plain(KeyCode::F(4)), false),
+            Some(&Binding::Insert(String::from("\x1B[A")))
+        );
+        assert_eq!(
+            keymap.get(Key::ctrl('a'), false),
+            Some(&Binding::Action(Action::BeginningOfLine))
+        );
+    }
+
+    #[test]
+    fn test_extend_vi() {
+        let mut keymap = KeyMap::new(EditMode::Vi);
+        keymap.extend("F2 submit /stats\n").unwrap();
+        for command_mode in [false, true] {
+            assert_eq!(
+                keymap.get(Key::plain(KeyCode::F(2)), command_mode),
+                Some(&Binding::Submit(String::from("/stats")))
+            );
+        }
+        assert_eq!(
+            keymap.get(Key::plain(KeyCode::Char('x')), true),
+            Some(&Binding::Action(Action::DeleteChar))
+        );
+        assert_eq!(keymap.get(Key::plain(KeyCode::Char('x')), false), None);
+    }
+
+    #[rstest]
+    #[case("F2", BindingError::NoAction)]
+    #[case("F2 insert", BindingError::NoText(String::from("insert")))]
+    #[case(
+        "F2 launch-missiles",
+        BindingError::Action(String::from("launch-missiles"))
+    )]
+    #[case("F2 kill-line now", BindingError::Argument(String::from("kill-line")))]
+    #[case("Hyper-x kill-line", BindingError::Key(String::from("hyper-x")))]
+    #[case("F2 insert foo\\", BindingError::Escape(EscapeError::Trailing))]
+    fn test_bad_binding(#[case] line: &str, #[case] err: BindingError) {
+        let mut keymap = KeyMap::default();
+        match keymap.extend(&format!("# Comment\n{line}\n")) {
+            Err(KeyMapError::Parse { lineno, source }) => {
+                assert_eq!(lineno, 2);
+                assert_eq!(source, err);
+            }
+            r => panic!("expected parse error, got {r:?}"),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Library interface to `confab`
+//!
+//! `confab` is primarily a command-line program; see
+//! <https://github.com/jwodder/confab> for information on using it.  This
+//! library exposes functionality for working with the files that `confab`
+//! produces, currently consisting of a parser for session transcripts.
+pub mod transcript;
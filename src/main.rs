@@ -1,17 +1,117 @@
+mod analysis;
+mod ansi;
+mod assertion;
+mod capture;
+mod cast;
 mod codec;
+mod commands;
+mod complete;
+mod display;
+mod doctor;
+mod editor;
 mod errors;
+mod escapes;
 mod events;
+mod exec;
+mod flush;
+mod hook;
+#[cfg(unix)]
+mod inherit;
 mod input;
+mod keymap;
+mod memory;
+mod metrics;
+#[cfg(feature = "notifications")]
+mod notification;
+#[cfg(feature = "otel")]
+mod otel;
+mod pretty;
+mod protocol;
+mod recorder;
+mod render;
+mod repeat;
+mod resolve;
+mod rewrite;
 mod runner;
+#[cfg(feature = "scripting")]
+mod script;
+mod scrollback;
+#[cfg(feature = "serial")]
+mod serial;
+mod session;
+mod sniff;
+mod stats;
+mod status;
+mod summary;
+mod syslog;
+mod target;
+mod template;
+mod term;
+mod title;
 mod tls;
+#[cfg(feature = "rustls")]
+mod tofu;
+mod transport;
+mod tui;
 mod util;
-use crate::input::StartupScript;
-use crate::runner::{Connector, Reporter, Runner};
+use crate::analysis::Operation;
+use crate::ansi::AnsiMode;
+use crate::assertion::Assertions;
+use crate::capture::{Capture, Variables};
+use crate::cast::CastFile;
+use crate::codec::{Framing, LineEnding, SendEnding};
+use crate::display::{BurstQuiet, RecvPolicy, RecvSampler, SampleRate};
+use crate::errors::{InterfaceError, TRANSCRIPTS_DIFFER_EXIT, TRANSCRIPT_ERROR_EXIT};
+use crate::escapes::unescape;
+use crate::events::{OptionValue, SocketOptions};
+use crate::flush::FlushPolicy;
+use crate::hook::{EventCommands, Hook};
+#[cfg(unix)]
+use crate::inherit::{FdSpec, InheritedSocket};
+use crate::input::{CtrlCMode, EmptyLineMode, IdleScript, PasteMode, SendQueue, StartupScript};
+use crate::keymap::{EditMode, KeyMap};
+use crate::memory::MemoryBudget;
+use crate::metrics::{Metrics, MetricsTarget};
+#[cfg(feature = "notifications")]
+use crate::notification::Notifier;
+#[cfg(feature = "otel")]
+use crate::otel::{OtelEndpoint, TraceRecorder};
+use crate::protocol::{Protocol, ProtocolLayer};
+use crate::recorder::{open_transcript, TranscriptSeries};
+use crate::render::{ConsoleRenderer, OutputFormat, Wrap};
+use crate::repeat::Repeat;
+use crate::resolve::{read_addr_file, ResolveOverride};
+use crate::rewrite::Rewrite;
+use crate::runner::{BannerWait, Connector, Reporter, Request, ResponseEnd, Runner};
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+use crate::scrollback::Scrollback;
+#[cfg(feature = "serial")]
+use crate::serial::SerialSpec;
+use crate::session::{parse_session_name, Session};
+use crate::sniff::Sniffer;
+use crate::stats::{Budget, SessionStats, ThroughputMeter};
+use crate::summary::Summary;
+use crate::syslog::{local_hostname, Facility, Severity, SyslogFormat, SyslogWrapper};
+use crate::target::{EndpointError, Target};
+use crate::template::Templates;
+use crate::term::TermWriter;
+use crate::title::WindowTitle;
+#[cfg(feature = "rustls")]
+use crate::tofu::TofuStore;
 use crate::util::CharEncoding;
 use anyhow::Context;
-use clap::Parser;
+use clap::builder::FalseyValueParser;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
+use regex::Regex;
+use std::ffi::OsStr;
 use std::fs::OpenOptions;
-use std::num::NonZeroUsize;
+use std::io::IsTerminal;
+use std::net::{AddrParseError, Ipv4Addr, SocketAddr, TcpListener as StdTcpListener};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 use std::process::ExitCode;
 use std::time::Duration;
@@ -24,29 +124,317 @@ mod build {
 /// Asynchronous line-oriented interactive TCP client
 ///
 /// See <https://github.com/jwodder/confab> for more information
-#[derive(Clone, Debug, Eq, Parser, PartialEq)]
-#[command(version)]
+// `Eq` & `PartialEq` can't be derived, as `regex::Regex` (used by
+// `expect_banner`, `assert_recv`, `capture`, and `rewrite`) implements neither.
+#[derive(Clone, Debug, Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true
+)]
 struct Arguments {
+    /// Connect to the addresses listed in the given file, trying each in
+    /// order until one succeeds, instead of resolving HOST
+    ///
+    /// The file must contain one IP:PORT per line, with IPv6 addresses
+    /// enclosed in brackets; blank lines and lines starting with '#' are
+    /// ignored.  HOST and PORT must still be given; they are used for the TLS
+    /// server name and in messages.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["transport", "resolve"])]
+    addr_file: Option<PathBuf>,
+
+    /// Set how to display ANSI escape sequences in received lines
+    ///
+    /// "pass" lets colors & text styles take effect on the terminal while
+    /// showing other escape sequences visibly, "strip" removes escape
+    /// sequences, and "vis" shows them visibly.
+    #[arg(long, default_value = "vis", value_name = "pass|strip|vis")]
+    ansi: AnsiMode,
+
+    /// Fail unless a received line matches the given regex
+    ///
+    /// This option can be given multiple times to require a series of lines
+    /// matching the regexes in order.  If they are not all received within
+    /// the time set by `--assert-timeout`, or if the session ends first,
+    /// confab exits with an error.  With `--send`, confab waits for them
+    /// before disconnecting.
+    #[arg(long, value_name = "REGEX")]
+    assert_recv: Vec<Regex>,
+
+    /// Time to wait in seconds after connecting for the lines required by
+    /// `--assert-recv`
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_name = "SECS",
+        requires = "assert_recv"
+    )]
+    assert_timeout: u64,
+
+    /// Time to wait in milliseconds for the server's banner when
+    /// `--expect-banner` is given
+    #[arg(long, default_value_t = 10000, value_name = "INT")]
+    banner_timeout_ms: u64,
+
+    /// Warn if nothing is received within this many seconds of connecting,
+    /// which suggests that the server is waiting for the client to speak
+    /// first
+    #[arg(long, value_name = "SECS")]
+    banner_warning: Option<NonZeroU64>,
+
+    /// Text to send when Ctrl-C is pressed with `--ctrl-c send-break` or
+    /// when the `/break` command is entered
+    ///
+    /// Escape sequences as for `--escapes` are recognized, and no line ending
+    /// is appended.
+    #[arg(long, default_value = "^C", value_name = "TEXT", value_parser = unescape)]
+    break_sequence: String,
+
     /// Display a summary of build information & dependencies and exit
     #[arg(long, exclusive = true)]
     build_info: bool,
 
-    /// Terminate sent lines with CR LF instead of just LF
-    #[arg(long)]
+    /// When lines are received faster than the given number per second, hold
+    /// them back from display until the rate falls to half that, then display
+    /// how many were received
+    ///
+    /// The lines held back during the most recent burst can be displayed
+    /// with the `/show-burst` command.  They are still written to the
+    /// transcript.
+    #[arg(long, value_name = "LINES/SEC")]
+    burst_quiet: Option<NonZeroU32>,
+
+    /// Store the text matched by the first capture group of the given regex in
+    /// received lines in the variable NAME, which can then be used in sent
+    /// lines as `${NAME}`
+    ///
+    /// This option can be given multiple times.
+    #[arg(long, value_name = "NAME=REGEX")]
+    capture: Vec<Capture>,
+
+    /// Record the session's terminal output to the given file in asciinema's
+    /// asciicast v2 format
+    #[arg(long, visible_alias = "record-cast", value_name = "FILE")]
+    cast: Option<PathBuf>,
+
+    /// With `--flood`, send the line this many times
+    #[arg(long, value_name = "N", default_value = "10", requires = "flood")]
+    count: NonZeroU32,
+
+    /// Terminate sent lines with CR LF instead of just LF.  Equivalent to
+    /// `--send-ending crlf`.
+    #[arg(long, conflicts_with = "send_ending")]
     crlf: bool,
 
+    /// Set what to do when Ctrl-C is pressed at the prompt
+    #[arg(
+        long,
+        default_value = "ignore",
+        value_name = "quit|confirm|send-break|ignore"
+    )]
+    ctrl_c: CtrlCMode,
+
+    /// Set how to handle empty lines of input, e.g., from pressing Enter at an
+    /// empty prompt
+    #[arg(long, default_value = "send", value_name = "send|skip|confirm")]
+    empty_line_mode: EmptyLineMode,
+
     /// Set text encoding
     ///
     /// "utf8" converts invalid byte sequences to the replacement character.
-    /// "utf8-latin1" handles invalid byte sequences by decoding the entire
-    /// line as Latin-1.
+    /// "utf8-strict" also uses replacement characters but additionally
+    /// reports each line containing invalid byte sequences.  "utf8-latin1"
+    /// handles invalid byte sequences by decoding the entire line as Latin-1.
+    /// Any other ASCII-compatible encoding can be specified by its WHATWG
+    /// label (e.g., "gbk", "koi8-r", "windows-1252").
+    #[arg(short = 'E', long, default_value = "utf8", value_name = "ENCODING")]
+    encoding: CharEncoding,
+
+    /// When the end of input is reached, shut down the write side of the
+    /// connection and wait for the server to close it instead of
+    /// disconnecting
+    #[arg(long)]
+    eof_on_stdin_close: bool,
+
+    /// With `--encoding utf8-strict`, end the session with exit status 7 when
+    /// a received line is not valid UTF-8 instead of displaying it with
+    /// replacement characters
+    #[arg(long)]
+    exit_on_encoding_error: bool,
+
+    /// Use the given set of default key bindings for the line editor
+    #[arg(long, default_value = "emacs", value_name = "emacs|vi")]
+    keybind: EditMode,
+
+    /// Read additional key bindings for the line editor from the given file
+    ///
+    /// See the README for the file format.
+    #[arg(long, value_name = "FILE")]
+    keymap: Option<PathBuf>,
+
+    /// Append the secrets of TLS sessions to the given file in the NSS key
+    /// log format so that captures of the session can be decrypted with
+    /// tools like Wireshark
+    ///
+    /// If not given, the `SSLKEYLOGFILE` environment variable is honored.
+    #[cfg(feature = "rustls")]
+    #[arg(long, value_name = "FILE")]
+    keylog: Option<PathBuf>,
+
+    /// Expand escape sequences like `\e`, `\x1B`, and `^[` in lines of input
+    /// in order to send control characters
+    #[arg(long)]
+    escapes: bool,
+
+    /// Expand templates like `%{now}`, `%{seq}`, and `%{rand:hex8}` in lines
+    /// before sending them
+    ///
+    /// `%%` is sent as a single `%`.
+    #[arg(long)]
+    expand_sends: bool,
+
+    /// After connecting, wait for the server to send a line (optionally one
+    /// matching the given regex) before sending anything
+    ///
+    /// If no such line is received within the time set by
+    /// `--banner-timeout-ms`, confab exits with an error.
+    #[arg(long, value_name = "REGEX", require_equals = true, num_args = 0..=1)]
+    expect_banner: Option<Option<Regex>>,
+
+    /// With `--send`, wait for the server to send N lines after connecting
+    /// and then disconnect
+    #[arg(long, value_name = "N", requires = "send", conflicts_with = "until")]
+    expect_lines: Option<NonZeroU64>,
+
+    /// Split received data into lines ("lines") or into binary frames, each
+    /// preceded by its length as an N-byte big-endian integer
+    /// ("length-prefixed:<N>")
+    ///
+    /// Binary frames are displayed as hex dumps, and sent lines are framed
+    /// the same way.
     #[arg(
-        short = 'E',
         long,
-        default_value = "utf8",
-        value_name = "utf8|utf8-latin1|latin1"
+        default_value = "lines",
+        value_name = "lines|length-prefixed:<N>"
     )]
-    encoding: CharEncoding,
+    framing: Framing,
+
+    /// Instead of connecting to a server, run the given program and carry
+    /// out the session over its standard input & output
+    ///
+    /// All remaining command-line arguments are taken as the program's
+    /// arguments, so this must be the last option.  HOST and PORT cannot be
+    /// given; the command is used in place of the host.
+    #[arg(
+        long,
+        value_name = "CMD",
+        num_args = 1..,
+        allow_hyphen_values = true,
+        group = "transport",
+        conflicts_with_all = ["host", "port", "tls"]
+    )]
+    exec: Option<Vec<String>>,
+
+    /// Use the given already-open socket inherited from the parent process
+    /// instead of connecting to a server
+    ///
+    /// "systemd" uses the first socket passed via systemd socket activation
+    /// (`LISTEN_FDS`).  If the socket is listening, a single connection is
+    /// accepted on it.  HOST and PORT cannot be given; the peer's address is
+    /// used in their place.
+    #[cfg(unix)]
+    #[arg(
+        long,
+        value_name = "N|systemd",
+        group = "transport",
+        conflicts_with_all = ["host", "port"]
+    )]
+    fd: Option<FdSpec>,
+
+    /// Only display received lines that match the given regex
+    ///
+    /// All received lines are still written to the transcript.
+    #[arg(long, value_name = "REGEX")]
+    filter: Option<Regex>,
+
+    /// Send the given line to the server repeatedly, then disconnect and
+    /// display a summary of how long the server took to respond
+    ///
+    /// Each send waits for a line to be received in response to the previous
+    /// one (or for 5 seconds to pass without one) and for `--interval-ms`
+    /// milliseconds to pass since the previous send.
+    #[arg(
+        long,
+        value_name = "LINE",
+        conflicts_with_all = ["send", "receive_only", "stdin_pipe", "tui"]
+    )]
+    flood: Option<String>,
+
+    /// How often to flush standard output and the transcript
+    ///
+    /// "always" flushes after every write, "line" flushes whenever a
+    /// complete line has been written, and "interval:<MS>" flushes at most
+    /// once every MS milliseconds, batching the writes in between.  By
+    /// default, standard output is flushed after every write, and the
+    /// transcript is flushed whenever confab has caught up with writing it.
+    #[arg(long, value_name = "always|line|interval:<MS>")]
+    flush: Option<FlushPolicy>,
+
+    /// Only display the first N lines received from the server
+    ///
+    /// All received lines are still written to the transcript unless
+    /// `--transcript-sample` is also given.
+    #[arg(long, value_name = "N", conflicts_with = "sample")]
+    head: Option<u64>,
+
+    /// Send the lines of the given file to the server whenever the
+    /// connection has been idle for the number of seconds given by
+    /// `--on-idle`
+    ///
+    /// The file is in the same format as a startup script.  Its lines are
+    /// sent one at a time, waiting `--paste-delay-ms` milliseconds between
+    /// each one.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "on_idle",
+        conflicts_with = "send"
+    )]
+    idle_script: Option<PathBuf>,
+
+    /// With `--flood`, wait at least this many milliseconds between sends
+    #[arg(long, value_name = "MS", default_value = "0", requires = "flood")]
+    interval_ms: u64,
+
+    /// With `--show-times`, color the time of each received line by how long
+    /// after the most recent send it arrived: green if under 50ms, yellow if
+    /// under 500ms, red otherwise
+    #[arg(long, requires = "show_times")]
+    latency_colors: bool,
+
+    /// Set how lines received from the remote server are terminated
+    ///
+    /// "lf" splits lines at LF (including CR LF).  "crlf" splits lines only
+    /// at CR LF.  "cr" splits lines at CR.  "any" splits lines at LF, CR LF,
+    /// or a bare CR.
+    #[arg(long, default_value = "lf", value_name = "lf|crlf|cr|any")]
+    line_ending: LineEnding,
+
+    /// Instead of connecting to a server, listen on the given port and wait
+    /// for a single client to connect
+    ///
+    /// If only a port is given, the port is bound on all IPv4 interfaces.
+    /// HOST and PORT cannot be given; the listening address is used in their
+    /// place.
+    #[arg(
+        long,
+        value_name = "[ADDR:]PORT",
+        value_parser = parse_listen_addr,
+        group = "transport",
+        conflicts_with_all = ["host", "port", "tls"]
+    )]
+    listen: Option<SocketAddr>,
 
     /// Set maximum length in bytes of lines read from remote server
     ///
@@ -57,16 +445,356 @@ struct Arguments {
     #[arg(long, default_value = "65535", value_name = "LIMIT")]
     max_line_length: NonZeroUsize,
 
+    /// End the session once the server has sent more than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    max_recv_bytes: Option<u64>,
+
+    /// End the session before sending a line that would take the total
+    /// number of bytes sent over this limit
+    #[arg(long, value_name = "BYTES")]
+    max_send_bytes: Option<u64>,
+
+    /// Warn before sending a line that is longer than this many bytes once
+    /// encoded and terminated
+    ///
+    /// With `--strict`, such lines are not sent.
+    #[arg(long, value_name = "BYTES")]
+    max_send_length: Option<NonZeroUsize>,
+
+    /// Limit the memory used for buffered output & queued lines to the given
+    /// number of mebibytes, discarding the oldest items once the limit is
+    /// reached
+    #[arg(long, value_name = "MB")]
+    memory_cap: Option<u64>,
+
+    /// Send counters of lines & bytes sent and received, connections, and
+    /// errors to the given statsd server over UDP
+    #[arg(long, value_name = "statsd://HOST:PORT")]
+    metrics: Option<MetricsTarget>,
+
+    /// Do not echo sent lines
+    #[arg(long)]
+    no_echo: bool,
+
+    /// Do not send the server name via SNI when connecting with `--tls`
+    ///
+    /// The server's certificate is still validated against the server name.
+    #[arg(long)]
+    no_sni: bool,
+
+    /// Show a desktop notification whenever a received line matches the given
+    /// regex
+    ///
+    /// This option can be given multiple times to notify on lines matching
+    /// any of several regexes.
+    #[cfg(feature = "notifications")]
+    #[arg(long, value_name = "REGEX")]
+    notify_on: Vec<Regex>,
+
+    /// Run the given shell command in the background whenever a connection is
+    /// established
+    ///
+    /// Details of the event are passed to the command in `CONFAB_*`
+    /// environment variables.
+    #[arg(long, value_name = "CMD")]
+    on_connect_cmd: Option<String>,
+
+    /// Run the given shell command in the background whenever a connection is
+    /// closed
+    ///
+    /// Details of the event are passed to the command in `CONFAB_*`
+    /// environment variables.
+    #[arg(long, value_name = "CMD")]
+    on_disconnect_cmd: Option<String>,
+
+    /// Run the given shell command in the background whenever an error is
+    /// reported
+    ///
+    /// Details of the event are passed to the command in `CONFAB_*`
+    /// environment variables.
+    #[arg(long, value_name = "CMD")]
+    on_error_cmd: Option<String>,
+
+    /// Run the `--idle-script` whenever nothing has been sent or received for
+    /// this many seconds
+    #[arg(long, value_name = "SECS", requires = "idle_script")]
+    on_idle: Option<NonZeroU64>,
+
+    /// Export the session as an OpenTelemetry trace to the OTLP/HTTP collector
+    /// at the given URL when the session ends
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    otel_endpoint: Option<OtelEndpoint>,
+
+    /// Append the raw bytes of all lines received from the server to the
+    /// given file
+    ///
+    /// Nothing else is written to the file: no sent lines, timestamps, or
+    /// other events.
+    #[arg(short = 'o', long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// How to present events on standard output
+    ///
+    /// "console" shows human-readable messages.  "json" shows each event as a
+    /// line of JSON, as in transcripts.  "raw" shows only the lines received
+    /// from the server, unaltered.  Under "json" and "raw", other messages are
+    /// written to standard error.
+    #[arg(long, default_value = "console", value_name = "console|json|raw")]
+    output_format: OutputFormat,
+
+    /// Time to wait in milliseconds between sending lines of a paste when
+    /// `--paste-mode delay` is in effect
+    #[arg(long, default_value_t = 500, value_name = "INT")]
+    paste_delay_ms: u64,
+
+    /// Set how to handle multiple lines pasted into the terminal at once
+    #[arg(long, default_value = "send", value_name = "ask|send|delay")]
+    paste_mode: PasteMode,
+
+    /// Pretty-print and syntax-color received lines that are JSON objects or
+    /// arrays
+    ///
+    /// The transcript still records the lines as received.  This only has an
+    /// effect with `--output-format console`.
+    #[arg(long)]
+    pretty_json: bool,
+
+    /// Apply conventions of the given protocol to the session
+    ///
+    /// Under both "smtp" and "ftp", the continuation lines of numeric
+    /// multi-line replies are indented under the first line.  Under "smtp",
+    /// lines of a message body sent after the server accepts a DATA command
+    /// are dot-stuffed.
+    #[arg(long, value_name = "smtp|ftp")]
+    protocol: Option<Protocol>,
+
+    /// Only receive: never read input from the user, so nothing typed can be
+    /// sent to the server.  Press Ctrl-C to disconnect.
+    #[arg(
+        long,
+        conflicts_with_all = ["send", "startup_script", "stdin_pipe", "eof_on_stdin_close", "tui"]
+    )]
+    receive_only: bool,
+
+    /// Pipe each received line through the given shell command and display
+    /// the command's output in its place
+    ///
+    /// The command is run once per line with the line on standard input.  If
+    /// it outputs nothing, the line is displayed as-is.  The transcript still
+    /// records the lines as received.
+    #[arg(long, value_name = "CMD")]
+    recv_hook: Option<String>,
+
+    /// Connect to the given IP addresses instead of looking up HOST when
+    /// connecting to HOST on PORT.
+    ///
+    /// Multiple comma-separated addresses may be given; IPv6 addresses may be
+    /// enclosed in brackets.  This option can be given multiple times.
+    #[arg(long, value_name = "HOST:PORT:ADDR")]
+    resolve: Vec<ResolveOverride>,
+
+    /// If resolving the remote host or connecting to it fails, try again up
+    /// to this many more times before giving up
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "N",
+        conflicts_with = "transport"
+    )]
+    retry: u32,
+
+    /// Wait this many seconds between connection attempts under `--retry`
+    #[arg(long, default_value_t = 1, value_name = "SECS", requires = "retry")]
+    retry_wait: u64,
+
+    /// Apply a sed-style substitution to each received line before display
+    ///
+    /// The flag `g` replaces every match instead of just the first, and `i`
+    /// makes the match case-insensitive.  `$1`, `${name}`, etc. in the
+    /// replacement refer to capture groups.  This option can be given
+    /// multiple times; the substitutions are applied in order, after any
+    /// `--recv-hook`.  The transcript still records the lines as received.
+    #[arg(long, value_name = "s/REGEX/REPLACEMENT/[FLAGS]")]
+    rewrite: Vec<Rewrite>,
+
+    /// Do not display informational messages, such as those about the
+    /// connection starting & ending; only display sent & received lines and
+    /// errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Only display every Nth line received from the server, starting with
+    /// the first
+    ///
+    /// All received lines are still written to the transcript unless
+    /// `--transcript-sample` is also given.
+    #[arg(long, value_name = "1/N")]
+    sample: Option<SampleRate>,
+
+    /// Keep the last N displayed messages in memory so that they can be shown
+    /// again with the `/search` and `/last` commands.  0 disables the
+    /// scrollback.
+    #[arg(long, default_value_t = 1000, value_name = "N")]
+    scrollback: usize,
+
+    /// Automate the session with the given Rhai script
+    ///
+    /// The script can define `on_connect()` and `on_recv(line)` functions,
+    /// which are called when the connection is established and for each
+    /// line received, and can call `send(line)`, `log(msg)`, `after(ms,
+    /// "func")`, `every(ms, "func")`, and `cancel(id)`.
+    #[cfg(feature = "scripting")]
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    /// Send the given line to the server and then disconnect, without
+    /// reading any input
+    ///
+    /// This option can be given multiple times to send multiple lines.  Use
+    /// `--expect-lines` or `--until` to wait for a response before
+    /// disconnecting.
+    #[arg(long, value_name = "LINE", conflicts_with = "startup_script")]
+    send: Vec<String>,
+
+    /// Set the line ending appended to each line sent to the server
+    ///
+    /// "none" sends lines without any terminator.
+    #[arg(long, default_value = "lf", value_name = "lf|crlf|cr|none")]
+    send_ending: SendEnding,
+
+    /// Pipe each sent line through the given shell command and display the
+    /// command's output in its place
+    ///
+    /// This works the same way as `--recv-hook`.  The line is sent to the
+    /// server unchanged.
+    #[arg(long, value_name = "CMD")]
+    send_hook: Option<String>,
+
+    /// Instead of connecting to a server, carry out the session over the
+    /// given serial device, at the given baud rate [default baud: 9600]
+    ///
+    /// HOST and PORT cannot be given; the device path is used in place of
+    /// the host.
+    #[cfg(feature = "serial")]
+    #[arg(
+        long,
+        value_name = "DEVICE[,BAUD]",
+        group = "transport",
+        conflicts_with_all = ["host", "port", "tls"]
+    )]
+    serial: Option<SerialSpec>,
+
     /// Use the given domain name for SNI and certificate hostname validation
     /// [default: the remote host name]
     #[arg(long, value_name = "DOMAIN")]
     servername: Option<String>,
 
+    /// Save the input history, captured variables, endpoint, and settings
+    /// changed with `/set` under the given name when the session ends, and
+    /// restore them when a session with the same name is started
+    ///
+    /// If HOST and PORT are not given, the endpoint of the previous session
+    /// with the name is used.
+    #[arg(long, value_name = "NAME", value_parser = parse_session_name)]
+    session: Option<String>,
+
+    /// Keep the terminal's window title set to the host, port, and state of
+    /// the session (connecting, connected, reconnecting, or closed)
+    ///
+    /// This has no effect when standard output is not a terminal.
+    #[arg(long)]
+    set_title: bool,
+
+    /// Warn if writing to the terminal is blocked for longer than this many
+    /// milliseconds
+    #[arg(long, default_value_t = 5000, value_name = "INT")]
+    stall_warning_ms: u64,
+
     /// Time to wait in milliseconds before sending each line of the startup
     /// script
     #[arg(long, default_value_t = 500, value_name = "INT")]
     startup_wait_ms: u64,
 
+    /// Measure the rates at which lines & bytes are sent & received over the
+    /// last five seconds.  The rate of receipt is added to the status line,
+    /// and the rates are recorded every five seconds as "throughput" events
+    /// in the transcript (and displayed under `--verbose`).
+    #[arg(long)]
+    show_throughput: bool,
+
+    /// Check up to this many of the first bytes received for signs that the
+    /// server is speaking a binary protocol (TLS, gzip, or HTTP/2) instead of
+    /// sending lines of text, and warn if so.  Set to 0 to disable the check.
+    ///
+    /// The check is skipped when TLS is in use or `--framing` is not "lines".
+    #[arg(long, default_value_t = 16, value_name = "BYTES")]
+    sniff_bytes: usize,
+
+    /// Display traffic statistics for the session when it ends
+    #[arg(long)]
+    stats_on_exit: bool,
+
+    /// Show a status line below the prompt with the state of the connection,
+    /// the number of bytes sent & received, and the time since the last
+    /// activity
+    #[arg(long)]
+    status_line: bool,
+
+    /// Read lines to send from standard input a line at a time, without a
+    /// prompt or line editor, even if standard output is a terminal
+    #[arg(long)]
+    stdin_pipe: bool,
+
+    /// With `--max-send-length`, refuse to send lines that are too long
+    /// instead of only warning about them
+    #[arg(long, requires = "max_send_length")]
+    strict: bool,
+
+    /// At exit, write a JSON document of aggregate statistics for the run
+    /// (durations, line & byte counts, errors, and why it ended) to the given
+    /// file
+    #[arg(long, value_name = "FILE")]
+    summary_file: Option<PathBuf>,
+
+    /// Wrap each line sent in a syslog header of the given format, for
+    /// testing syslog receivers
+    #[arg(long, value_name = "rfc5424|rfc3164")]
+    syslog_format: Option<SyslogFormat>,
+
+    /// With `--syslog-format`, the facility to give in syslog headers
+    #[arg(
+        long,
+        default_value = "user",
+        value_name = "NAME",
+        requires = "syslog_format"
+    )]
+    syslog_facility: Facility,
+
+    /// With `--syslog-format`, the severity to give in syslog headers
+    #[arg(
+        long,
+        default_value = "notice",
+        value_name = "NAME",
+        requires = "syslog_format"
+    )]
+    syslog_severity: Severity,
+
+    /// With `--syslog-format`, the host name to give in syslog headers
+    /// [default: the local host name]
+    #[arg(long, value_name = "NAME", requires = "syslog_format")]
+    syslog_hostname: Option<String>,
+
+    /// With `--syslog-format`, the application name to give in syslog
+    /// headers
+    #[arg(
+        long,
+        default_value = "confab",
+        value_name = "NAME",
+        requires = "syslog_format"
+    )]
+    syslog_app_name: String,
+
     /// On startup, read lines from the given file and send them to the server
     /// one at a time.
     ///
@@ -79,37 +807,404 @@ struct Arguments {
     #[arg(short = 't', long)]
     show_times: bool,
 
+    /// Enable TCP keepalive probes, starting after the connection has been
+    /// idle for the given number of seconds
+    #[arg(long, value_name = "SECS")]
+    tcp_keepalive: Option<NonZeroU64>,
+
+    /// Set the `TCP_NODELAY` option on the connection, disabling Nagle's
+    /// algorithm
+    #[arg(long)]
+    tcp_nodelay: bool,
+
     /// Connect using SSL/TLS
     #[arg(long)]
     tls: bool,
 
+    /// With `--listen`, act as a TLS server using the certificate chain in
+    /// the given PEM file
+    #[arg(long, value_name = "FILE", requires_all = ["listen", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// With `--listen`, act as a TLS server using the private key in the
+    /// given PEM file
+    #[arg(long, value_name = "FILE", requires_all = ["listen", "tls_cert"])]
+    tls_key: Option<PathBuf>,
+
+    /// With TLS, accept server certificates that are self-signed or otherwise
+    /// untrusted by recording each server's certificate fingerprint the first
+    /// time it is seen and refusing to connect if it later changes
+    ///
+    /// Fingerprints are kept in the given file, keyed by host & port
+    /// [default: `confab/known_certs` in the user's data directory].
+    #[cfg(feature = "rustls")]
+    #[arg(long, value_name = "STORE", require_equals = true, num_args = 0..=1)]
+    tofu: Option<Option<PathBuf>>,
+
     /// Append a transcript of events to the given file
+    ///
+    /// The path may contain the strftime-style placeholders %Y, %y, %m, %d,
+    /// %H, %M, %S, %j, %s, and %%, which are replaced with the current local
+    /// time, {host} and {port}, which are replaced with the remote host &
+    /// port, and {conn}, which is replaced with the number of the connection
+    /// under `--transcript-per-connection` (otherwise 1).  Any missing parent
+    /// directories are created.
     #[arg(short = 'T', long, value_name = "FILE")]
     transcript: Option<PathBuf>,
 
+    /// Write the events of each connection made during the session (e.g.,
+    /// with `/connect`) to a separate transcript
+    ///
+    /// The `--transcript` path is expanded anew as each connection starts,
+    /// and its {conn} placeholder is replaced with the number of the
+    /// connection, starting at 1.  Each transcript begins with the
+    /// session's "session-start" event.
+    #[arg(long, requires = "transcript")]
+    transcript_per_connection: bool,
+
+    /// Record the exact bytes of each line sent & received in the transcript
+    ///
+    /// The bytes are given, base64-encoded, in a `data_base64` field of each
+    /// "send" and "recv" event: as sent after encoding and as received
+    /// before decoding, respectively.  Unlike `data`, this is not altered by
+    /// `--encoding` replacing undecodable bytes.
+    #[arg(long, requires = "transcript")]
+    transcript_raw_bytes: bool,
+
+    /// Apply `--head` or `--sample` to the transcript as well
+    #[arg(long, requires = "transcript")]
+    transcript_sample: bool,
+
+    /// With `--wrap`, cut off received lines that are too wide with an
+    /// ellipsis instead of wrapping them
+    #[arg(long)]
+    truncate_lines: bool,
+
+    /// Set the IP time-to-live for packets sent on the connection
+    #[arg(long, value_name = "N")]
+    ttl: Option<u32>,
+
+    /// Use a full-screen interface with a scrollable pane of output above a
+    /// separate input line instead of printing output above the prompt
+    #[arg(long, conflicts_with_all = ["status_line", "stdin_pipe"])]
+    tui: bool,
+
+    /// With `--send`, wait for the server to send a line matching the given
+    /// regex and then disconnect
+    #[arg(long, value_name = "REGEX", requires = "send")]
+    until: Option<Regex>,
+
+    /// Also display events that are normally only recorded in the transcript,
+    /// such as connection timing
+    #[arg(short = 'v', long)]
+    verbose: bool,
+
+    /// Hard-wrap received lines that are too wide for the terminal ("terminal")
+    /// or the given number of columns, marking each continuation with "↪"
+    ///
+    /// "none" leaves wrapping to the terminal.  This only has an effect with
+    /// `--output-format console`.
+    #[arg(long, default_value = "none", value_name = "none|terminal|N")]
+    wrap: Wrap,
+
     /// Remote host (domain name or IP address) to which to connect
-    #[arg(default_value = "localhost", required = true)]
+    ///
+    /// The port can be included as HOST:PORT (with IPv6 addresses enclosed in
+    /// brackets) instead of being given separately.  The host can also be
+    /// given as a URL of the form tcp://HOST:PORT or tls://HOST:PORT, the
+    /// latter implying `--tls`.
+    #[arg(
+        default_value = "localhost",
+        required_unless_present_any = ["transport", "session"]
+    )]
     // The dummy default value is just there so that `--build-info` can be made
     // exclusive.
-    host: String,
+    host: Target,
 
-    /// Remote port (integer) to which to connect
-    #[arg(default_value_t = 80, required = true)]
-    // The dummy default value is just there so that `--build-info` can be made
-    // exclusive.
-    port: u16,
+    /// Remote port (integer) to which to connect, if not included in HOST
+    port: Option<u16>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Check each step of connecting to a server — DNS lookup, TCP
+    /// connection, and (with `--tls`) the TLS handshake — and report how long
+    /// each took and whether it succeeded, without starting a session
+    Doctor(DoctorArguments),
+    /// Summarize, search, or display a transcript written by `--transcript`
+    Transcript(TranscriptArguments),
+}
+
+#[derive(Args, Clone, Debug)]
+struct DoctorArguments {
+    /// Do not send the server name via SNI when checking the TLS handshake
+    #[arg(long, requires = "tls")]
+    no_sni: bool,
+
+    /// Connect to the given IP addresses instead of looking up HOST when
+    /// connecting to HOST on PORT.
+    ///
+    /// This option can be given multiple times.
+    #[arg(long, value_name = "HOST:PORT:ADDR")]
+    resolve: Vec<ResolveOverride>,
+
+    /// Use the given domain name for SNI and certificate hostname validation
+    /// [default: the remote host name]
+    #[arg(long, value_name = "DOMAIN", requires = "tls")]
+    servername: Option<String>,
+
+    /// Also check the SSL/TLS handshake
+    #[arg(long)]
+    tls: bool,
+
+    /// Remote host (domain name or IP address) to check, optionally as
+    /// HOST:PORT or as a tcp:// or tls:// URL
+    host: Target,
+
+    /// Remote port (integer) to check, if not included in HOST
+    port: Option<u16>,
+}
+
+#[derive(Args, Clone, Debug)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new("operation").required(true).args(["stats", "grep", "to_text"])),
+)]
+struct TranscriptArguments {
+    /// Show only the events whose messages match the given regular
+    /// expression, as displayed by `--to-text`
+    #[arg(long, value_name = "REGEX")]
+    grep: Option<Regex>,
+
+    /// Prepend timestamps to the displayed events
+    #[arg(short = 't', long, conflicts_with = "stats")]
+    show_times: bool,
+
+    /// Show the numbers of lines & bytes sent and received, the duration of
+    /// the session, and a histogram of the times between sending lines and
+    /// receiving replies
+    #[arg(long)]
+    stats: bool,
+
+    /// Display every event in the transcript as `confab --verbose` would
+    /// have displayed it
+    #[arg(long)]
+    to_text: bool,
+
+    /// The transcript file to read
+    #[arg(required = true)]
+    file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<TranscriptCommand>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum TranscriptCommand {
+    /// Compare the lines sent & received in two transcripts, showing where
+    /// they diverge
+    Diff {
+        /// The transcript to compare against
+        old: PathBuf,
+
+        /// The transcript to compare
+        new: PathBuf,
+    },
+}
+
+impl TranscriptArguments {
+    fn run(&self) -> anyhow::Result<ExitCode> {
+        let out = std::io::stdout().lock();
+        if let Some(TranscriptCommand::Diff { old, new }) = &self.command {
+            if analysis::diff(old, new, out)? {
+                Ok(ExitCode::from(TRANSCRIPTS_DIFFER_EXIT))
+            } else {
+                Ok(ExitCode::SUCCESS)
+            }
+        } else {
+            let file = self
+                .file
+                .as_deref()
+                .expect("clap should require a file when there is no subcommand");
+            analysis::run(file, &self.operation(), out)?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+
+    fn operation(&self) -> Operation {
+        if self.stats {
+            Operation::Stats
+        } else if let Some(regex) = self.grep.clone() {
+            Operation::Grep {
+                regex,
+                show_times: self.show_times,
+            }
+        } else {
+            Operation::ToText {
+                show_times: self.show_times,
+            }
+        }
+    }
+}
+
+impl DoctorArguments {
+    fn connector(self) -> Connector {
+        let (host, port) = endpoint_or_exit(&self.host, self.port);
+        Connector {
+            tls: self.tls || self.host.tls,
+            host,
+            port,
+            servername: self.servername,
+            sni: !self.no_sni,
+            tls_context: tls::ClientContext::new(),
+            #[cfg(feature = "rustls")]
+            tofu: None,
+            socket: SocketOptions::default(),
+            resolve: self.resolve,
+            addr_list: Vec::new(),
+            encoding: CharEncoding::Utf8,
+            max_line_length: NonZeroUsize::MAX,
+            send_ending: SendEnding::Lf,
+            line_ending: LineEnding::Lf,
+            framing: Framing::Lines,
+            fatal_decode_errors: false,
+            retries: 0,
+            retry_wait: Duration::ZERO,
+            #[cfg(unix)]
+            inherited: None,
+            listener: None,
+            identity: None,
+            #[cfg(feature = "serial")]
+            serial: None,
+            exec: None,
+        }
+    }
 }
 
 impl Arguments {
-    async fn open(self) -> anyhow::Result<Runner> {
-        let transcript = self
-            .transcript
+    async fn open(
+        self,
+        options: Vec<(String, OptionValue)>,
+        session: Option<Session>,
+    ) -> anyhow::Result<Runner> {
+        let tls = self.tls || self.host.tls;
+        // The endpoint has already been validated by `main()` if no other
+        // transport was given; otherwise, it's replaced below.
+        let (mut host, mut port) = self.host.endpoint(self.port).unwrap_or_default();
+        #[cfg(unix)]
+        let inherited = self
+            .fd
+            .map(InheritedSocket::open)
+            .transpose()
+            .context("failed to use inherited socket")?;
+        #[cfg(unix)]
+        if let Some(socket) = inherited.as_ref() {
+            let addr = socket
+                .addr()
+                .context("failed to get address of inherited socket")?;
+            host = addr.ip().to_string();
+            port = addr.port();
+        }
+        let listener = self
+            .listen
+            .map(|addr| {
+                StdTcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}"))
+            })
+            .transpose()?;
+        if let Some(listener) = listener.as_ref() {
+            let addr = listener
+                .local_addr()
+                .context("failed to get address of listening socket")?;
+            host = addr.ip().to_string();
+            port = addr.port();
+        }
+        #[cfg(feature = "serial")]
+        if let Some(spec) = self.serial.as_ref() {
+            host.clone_from(&spec.device);
+            port = 0;
+        }
+        if let Some(argv) = self.exec.as_ref() {
+            host = argv.join(" ");
+            port = 0;
+        }
+        let addr_list = self
+            .addr_file
+            .as_deref()
+            .map(read_addr_file)
+            .transpose()
+            .context("failed to load address file")?
+            .unwrap_or_default();
+        let identity = match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => Some(
+                tls::ServerIdentity::load(&cert, &key)
+                    .context("failed to load TLS certificate & key")?,
+            ),
+            _ => None,
+        };
+        let tls_context = tls::ClientContext::new();
+        #[cfg(feature = "rustls")]
+        let tls_context = match self.keylog.or_else(|| {
+            std::env::var_os("SSLKEYLOGFILE")
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+        }) {
+            Some(p) => tls_context
+                .keylog(tls::KeyLogFile::open(&p).context("failed to open TLS key log file")?),
+            None => tls_context,
+        };
+        #[cfg(feature = "rustls")]
+        let tofu = self
+            .tofu
+            .map(|store| {
+                store
+                    .or_else(TofuStore::default_path)
+                    .map(TofuStore::new)
+                    .context(
+                    "could not determine where to keep trusted certificates; give a path to --tofu",
+                )
+            })
+            .transpose()?;
+        #[cfg(feature = "rustls")]
+        let tls_context = if tofu.is_some() {
+            tls_context.tofu()
+        } else {
+            tls_context
+        };
+        let (transcript, transcript_series) = match self.transcript {
+            Some(template) if self.transcript_per_connection => {
+                (None, Some(TranscriptSeries::new(template, self.flush)))
+            }
+            Some(template) => (
+                Some(open_transcript(&template, &host, port, 1, self.flush)?),
+                None,
+            ),
+            None => (None, None),
+        };
+        let cast = self
+            .cast
+            .map(|p| CastFile::create(p).context("failed to create cast file"))
+            .transpose()?;
+        let metrics = self
+            .metrics
+            .as_ref()
+            .map(|target| Metrics::connect(target).context("failed to set up metrics socket"))
+            .transpose()?;
+        let summary = self
+            .summary_file
+            .map(|p| Summary::create(p).context("failed to create summary file"))
+            .transpose()?;
+        let output = self
+            .output
             .map(|p| {
                 OpenOptions::new()
                     .append(true)
                     .create(true)
                     .open(p)
-                    .context("failed to open transcript file")
+                    .context("failed to open output file")
             })
             .transpose()?;
         let startup_script = if let Some(path) = self.startup_script {
@@ -125,21 +1220,217 @@ impl Arguments {
         } else {
             None
         };
+        let idle_script = if let (Some(path), Some(secs)) = (self.idle_script, self.on_idle) {
+            let content = std::fs::read_to_string(path).context("failed to read idle script")?;
+            Some(IdleScript::new(
+                content.lines().map(String::from).collect(),
+                Duration::from_secs(secs.get()),
+            ))
+        } else {
+            None
+        };
+        let mut keymap = KeyMap::new(self.keybind);
+        if let Some(path) = self.keymap {
+            keymap.load(&path).context("failed to load key map")?;
+        }
+        #[cfg(feature = "scripting")]
+        let script = self
+            .script
+            .as_deref()
+            .map(Script::load)
+            .transpose()
+            .context("failed to load script")?;
+        let policy = if let Some(n) = self.head {
+            RecvPolicy::Head(n)
+        } else if let Some(SampleRate(n)) = self.sample {
+            RecvPolicy::Sample(n)
+        } else {
+            RecvPolicy::All
+        };
+        let banner = self.expect_banner.map(|pattern| BannerWait {
+            pattern,
+            timeout: Duration::from_millis(self.banner_timeout_ms),
+        });
+        let request = (!self.send.is_empty()).then(|| Request {
+            lines: self.send,
+            response: if let Some(n) = self.expect_lines {
+                ResponseEnd::Lines(n)
+            } else if let Some(rgx) = self.until {
+                ResponseEnd::Match(rgx)
+            } else {
+                ResponseEnd::Immediate
+            },
+        });
+        let memory = MemoryBudget::new(
+            self.memory_cap
+                .map(|mb| usize::try_from(mb.saturating_mul(1 << 20)).unwrap_or(usize::MAX)),
+        );
+        let console = ConsoleRenderer {
+            show_times: self.show_times,
+            latency_colors: self.latency_colors,
+            pretty_json: self.pretty_json,
+            // The terminal's width is irrelevant if output isn't going to it
+            wrap: if self.wrap == Wrap::Terminal && !std::io::stdout().is_terminal() {
+                Wrap::None
+            } else {
+                self.wrap
+            },
+            truncate: self.truncate_lines,
+            ansi: self.ansi,
+            // Keep logs written to files free of styling sequences
+            plain: !std::io::stdout().is_terminal(),
+        };
         Ok(Runner {
             startup_script,
+            idle_script,
+            banner,
+            request,
+            flood: self.flood.map(|line| {
+                Repeat::new(
+                    line,
+                    self.count,
+                    Duration::from_millis(self.interval_ms),
+                    true,
+                )
+            }),
+            repeat: None,
+            batch: self.stdin_pipe || !std::io::stdout().is_terminal(),
+            receive_only: self.receive_only,
+            budget: Budget {
+                max_send_bytes: self.max_send_bytes,
+                max_recv_bytes: self.max_recv_bytes,
+            },
+            max_send_length: self.max_send_length,
+            strict_send_length: self.strict,
+            raw_bytes: self.transcript_raw_bytes,
+            stats: SessionStats::default(),
+            sniffer: NonZeroUsize::new(self.sniff_bytes)
+                .filter(|_| !tls && identity.is_none() && self.framing == Framing::Lines)
+                .map(Sniffer::new),
+            banner_warning: self
+                .banner_warning
+                .map(|secs| Duration::from_secs(secs.get())),
+            assertions: (!self.assert_recv.is_empty()).then(|| {
+                Assertions::new(self.assert_recv, Duration::from_secs(self.assert_timeout))
+            }),
+            memory: memory.clone(),
+            paste_mode: self.paste_mode,
+            empty_line_mode: self.empty_line_mode,
+            keymap,
+            escapes: self.escapes,
+            protocol: self.protocol.map(ProtocolLayer::new),
+            syslog: self.syslog_format.map(|format| SyslogWrapper {
+                format,
+                facility: self.syslog_facility,
+                severity: self.syslog_severity,
+                hostname: self.syslog_hostname.or_else(local_hostname),
+                app_name: self.syslog_app_name,
+                pid: std::process::id(),
+            }),
+            variables: {
+                let mut variables = Variables::new(self.capture);
+                if let Some(session) = session.as_ref() {
+                    variables.restore(session.state.variables.clone());
+                }
+                variables
+            },
+            session,
+            templates: self.expand_sends.then(Templates::default),
+            #[cfg(feature = "scripting")]
+            script,
+            #[cfg(feature = "notifications")]
+            notifier: (!self.notify_on.is_empty())
+                .then(|| Notifier::new(self.notify_on, format!("{host}:{port}"))),
+            eof_on_stdin_close: self.eof_on_stdin_close,
+            write_closed: false,
+            status_line: self.status_line && self.output_format == OutputFormat::Console,
+            status: None,
+            title: (self.set_title && std::io::stdout().is_terminal())
+                .then(|| WindowTitle::new(format!("{host}:{port}"))),
+            compose: None,
+            throughput: self.show_throughput.then(ThroughputMeter::default),
+            tui: self.tui,
+            ctrl_c: self.ctrl_c,
+            break_sequence: self.break_sequence,
+            queue: SendQueue::new(Duration::from_millis(self.paste_delay_ms), memory.clone()),
+            options,
+            ending_before_crlf: None,
             reporter: Reporter {
-                writer: Box::new(std::io::stdout()),
+                writer: TermWriter::new(
+                    Box::new(tokio::io::stdout()),
+                    Duration::from_millis(self.stall_warning_ms),
+                    self.output_format.renderer(console),
+                    memory.clone(),
+                    self.flush.unwrap_or(FlushPolicy::Always),
+                ),
                 transcript,
-                show_times: self.show_times,
+                transcript_series,
+                cast,
+                output,
+                renderer: self.output_format.renderer(console),
+                verbose: self.verbose,
+                quiet: self.quiet,
+                echo: !self.no_echo,
+                filter: self.filter,
+                stats_on_exit: self.stats_on_exit,
+                sampler: RecvSampler::new(policy),
+                sample_transcript: self.transcript_sample,
+                burst: self
+                    .burst_quiet
+                    .map(|rate| BurstQuiet::new(rate, memory.clone())),
+                tokens: None,
+                scrollback: Scrollback::new(self.scrollback, memory),
+                recv_hook: self.recv_hook.map(Hook::new),
+                send_hook: self.send_hook.map(Hook::new),
+                event_commands: Some(EventCommands::new(
+                    self.on_connect_cmd,
+                    self.on_disconnect_cmd,
+                    self.on_error_cmd,
+                ))
+                .filter(|cmds| !cmds.is_empty()),
+                rewrites: self.rewrite,
+                metrics,
+                summary,
+                #[cfg(feature = "otel")]
+                trace: self.otel_endpoint.map(TraceRecorder::new),
             },
             connector: Connector {
-                tls: self.tls,
-                host: self.host,
-                port: self.port,
+                tls,
+                host,
+                port,
                 servername: self.servername,
+                sni: !self.no_sni,
+                tls_context,
+                #[cfg(feature = "rustls")]
+                tofu,
+                socket: SocketOptions {
+                    nodelay: self.tcp_nodelay,
+                    keepalive: self
+                        .tcp_keepalive
+                        .map(|secs| Duration::from_secs(secs.get())),
+                    ttl: self.ttl,
+                },
+                resolve: self.resolve,
+                addr_list,
                 encoding: self.encoding,
                 max_line_length: self.max_line_length,
-                crlf: self.crlf,
+                send_ending: if self.crlf {
+                    SendEnding::Crlf
+                } else {
+                    self.send_ending
+                },
+                line_ending: self.line_ending,
+                framing: self.framing,
+                fatal_decode_errors: self.exit_on_encoding_error,
+                retries: self.retry,
+                retry_wait: Duration::from_secs(self.retry_wait),
+                #[cfg(unix)]
+                inherited,
+                listener,
+                identity,
+                #[cfg(feature = "serial")]
+                serial: self.serial,
+                exec: self.exec,
             },
         })
     }
@@ -147,15 +1438,166 @@ impl Arguments {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<ExitCode> {
-    let args = Arguments::parse();
+    let matches = command().get_matches();
+    let args = Arguments::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     if args.build_info {
         build_info();
         Ok(ExitCode::SUCCESS)
+    } else if let Some(Command::Doctor(dargs)) = args.command {
+        let diagnosis = doctor::diagnose(&dargs.connector()).await;
+        print!("{diagnosis}");
+        Ok(ExitCode::from(diagnosis.exit_code))
+    } else if let Some(Command::Transcript(targs)) = args.command {
+        match targs.run() {
+            Ok(code) => Ok(code),
+            Err(e) => match e.downcast_ref::<InterfaceError>() {
+                Some(ie) if ie.is_broken_pipe() => Ok(ExitCode::from(ie.exit_code())),
+                // `diff` uses status 1 to mean that the transcripts differ
+                _ if targs.command.is_some() => {
+                    eprintln!("Error: {e:?}");
+                    Ok(ExitCode::from(TRANSCRIPT_ERROR_EXIT))
+                }
+                _ => Err(e),
+            },
+        }
     } else {
-        Ok(args.open().await?.run().await?)
+        let mut args = args;
+        let mut session = args
+            .session
+            .as_deref()
+            .map(Session::open)
+            .transpose()
+            .context("failed to load session")?;
+        if let Some(session) = session.as_mut() {
+            session.save_target = !matches.contains_id("transport");
+        }
+        if !matches.contains_id("transport") {
+            if matches.value_source("host") == Some(ValueSource::DefaultValue) {
+                if let Some(session) = session.as_ref() {
+                    let Some(saved) = session.state.target.clone() else {
+                        command()
+                            .error(
+                                clap::error::ErrorKind::MissingRequiredArgument,
+                                "the session has no saved endpoint; HOST and PORT must be given",
+                            )
+                            .exit()
+                    };
+                    args.host = Target {
+                        host: saved.host,
+                        port: Some(saved.port),
+                        tls: saved.tls,
+                    };
+                }
+            }
+            endpoint_or_exit(&args.host, args.port);
+        }
+        match args
+            .open(resolved_options(&matches), session)
+            .await?
+            .run()
+            .await
+        {
+            Ok(code) => Ok(code),
+            Err(e) => {
+                let code = e.exit_code();
+                if !e.is_broken_pipe() {
+                    eprintln!("Error: {:?}", anyhow::Error::new(e));
+                }
+                Ok(ExitCode::from(code))
+            }
+        }
     }
 }
 
+/// Combine the HOST & PORT arguments into the endpoint to connect to, exiting
+/// with a usage error if the port is missing or given twice
+fn endpoint_or_exit(target: &Target, port: Option<u16>) -> (String, u16) {
+    target.endpoint(port).unwrap_or_else(|e| {
+        let kind = match e {
+            EndpointError::NoPort => clap::error::ErrorKind::MissingRequiredArgument,
+            EndpointError::DoublePort => clap::error::ErrorKind::ArgumentConflict,
+        };
+        command().error(kind, e).exit()
+    })
+}
+
+/// Parse the argument to `--listen`, which is either a bare port number or a
+/// socket address
+fn parse_listen_addr(s: &str) -> Result<SocketAddr, AddrParseError> {
+    match s.parse::<u16>() {
+        Ok(port) => Ok(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))),
+        Err(_) => s.parse::<SocketAddr>(),
+    }
+}
+
+/// Return the command-line parser, in which each option of the main command
+/// can also be set via an environment variable named after its long name,
+/// e.g., `CONFAB_MAX_LINE_LENGTH` for `--max-line-length`.  Options given on
+/// the command line take precedence.
+fn command() -> clap::Command {
+    Arguments::command().mut_args(|arg| match arg.get_long() {
+        // `--build-info` is exclusive with everything else, so setting it in
+        // the environment would make all other invocations fail
+        Some(long) if long != "build-info" => {
+            let var = env_var_name(long);
+            if !matches!(arg.get_action(), ArgAction::SetTrue) {
+                arg.env(var)
+            } else if std::env::var_os(&var).is_some_and(|v| is_falsey(&v)) {
+                // Treat a flag set to a false value as not given at all so
+                // that, e.g., `CONFAB_QUIET=0` doesn't conflict with
+                // `--verbose`
+                arg
+            } else {
+                // Accept `CONFAB_TLS=1` and the like, not just "true"
+                arg.env(var).value_parser(FalseyValueParser::new())
+            }
+        }
+        _ => arg,
+    })
+}
+
+/// Returns `true` if an environment variable value is one that clap's
+/// `FalseyValueParser` treats as false
+fn is_falsey(value: &OsStr) -> bool {
+    value.to_str().is_some_and(|s| {
+        ["", "n", "no", "f", "false", "off", "0"]
+            .iter()
+            .any(|f| s.eq_ignore_ascii_case(f))
+    })
+}
+
+/// Return the name of the environment variable for setting the option with
+/// the given long name
+fn env_var_name(long: &str) -> String {
+    format!("CONFAB_{}", long.to_ascii_uppercase().replace('-', "_"))
+}
+
+/// Collect the values of the options in effect, whether given on the command
+/// line, set in the environment, or defaulted, keyed by long name.  The host
+/// & port are omitted, as are options that were not given and have no
+/// default.
+fn resolved_options(matches: &ArgMatches) -> Vec<(String, OptionValue)> {
+    let mut options = Vec::new();
+    for arg in Arguments::command().get_arguments() {
+        let Some(name) = arg.get_long() else {
+            continue;
+        };
+        let Some(raw) = matches.get_raw(arg.get_id().as_str()) else {
+            continue;
+        };
+        let values = raw
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let value = if matches!(arg.get_action(), ArgAction::Append) {
+            OptionValue::Multiple(values)
+        } else {
+            OptionValue::Single(values.join(" "))
+        };
+        options.push((name.to_owned(), value));
+    }
+    options
+}
+
 #[allow(clippy::const_is_empty)] // Shut clippy up about FEATURES.is_empty()
 fn build_info() {
     use build::*;
@@ -189,10 +1631,31 @@ mod tests {
     use super::*;
     use clap::error::ErrorKind;
     use clap::CommandFactory;
+    use rstest::rstest;
+    use std::path::Path;
 
     #[test]
     fn validate_cli() {
         Arguments::command().debug_assert();
+        command().debug_assert();
+    }
+
+    #[rstest]
+    #[case("tls", "CONFAB_TLS")]
+    #[case("max-line-length", "CONFAB_MAX_LINE_LENGTH")]
+    fn test_env_var_name(#[case] long: &str, #[case] var: &str) {
+        assert_eq!(env_var_name(long), var);
+    }
+
+    #[rstest]
+    #[case("", true)]
+    #[case("0", true)]
+    #[case("Off", true)]
+    #[case("no", true)]
+    #[case("1", false)]
+    #[case("yes", false)]
+    fn test_is_falsey(#[case] value: &str, #[case] falsey: bool) {
+        assert_eq!(is_falsey(OsStr::new(value)), falsey);
     }
 
     #[test]
@@ -208,6 +1671,265 @@ mod tests {
         assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
 
+    #[test]
+    fn doctor() {
+        let args =
+            Arguments::try_parse_from(["confab", "doctor", "--tls", "example.com", "443"]).unwrap();
+        let Some(Command::Doctor(dargs)) = args.command else {
+            panic!("doctor subcommand not parsed");
+        };
+        assert!(dargs.tls);
+        assert_eq!(
+            dargs.host.endpoint(dargs.port),
+            Ok((String::from("example.com"), 443))
+        );
+    }
+
+    #[test]
+    fn host_port_target() {
+        let args = Arguments::try_parse_from(["confab", "[::1]:8080"]).unwrap();
+        assert!(!args.host.tls);
+        assert_eq!(
+            args.host.endpoint(args.port),
+            Ok((String::from("::1"), 8080))
+        );
+    }
+
+    #[test]
+    fn tls_url_target() {
+        let args = Arguments::try_parse_from(["confab", "tls://example.com:443"]).unwrap();
+        assert!(args.host.tls);
+        assert_eq!(
+            args.host.endpoint(args.port),
+            Ok((String::from("example.com"), 443))
+        );
+    }
+
+    #[test]
+    fn bad_target() {
+        let args = Arguments::try_parse_from(["confab", "ws://example.com/chat"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn doctor_and_session_args() {
+        let args =
+            Arguments::try_parse_from(["confab", "--send=HELO", "doctor", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn transcript() {
+        let args =
+            Arguments::try_parse_from(["confab", "transcript", "--grep", "^OK", "session.jsonl"])
+                .unwrap();
+        let Some(Command::Transcript(targs)) = args.command else {
+            panic!("transcript subcommand not parsed");
+        };
+        assert_eq!(targs.file.as_deref(), Some(Path::new("session.jsonl")));
+        assert!(
+            matches!(targs.operation(), Operation::Grep { regex, show_times: false } if regex.as_str() == "^OK")
+        );
+    }
+
+    #[test]
+    fn transcript_diff() {
+        let args =
+            Arguments::try_parse_from(["confab", "transcript", "diff", "old.jsonl", "new.jsonl"])
+                .unwrap();
+        let Some(Command::Transcript(targs)) = args.command else {
+            panic!("transcript subcommand not parsed");
+        };
+        let Some(TranscriptCommand::Diff { old, new }) = targs.command else {
+            panic!("transcript diff subcommand not parsed");
+        };
+        assert_eq!(old, Path::new("old.jsonl"));
+        assert_eq!(new, Path::new("new.jsonl"));
+    }
+
+    #[test]
+    fn transcript_diff_and_operation() {
+        let args = Arguments::try_parse_from([
+            "confab",
+            "transcript",
+            "--stats",
+            "diff",
+            "old.jsonl",
+            "new.jsonl",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn transcript_multiple_operations() {
+        let args = Arguments::try_parse_from([
+            "confab",
+            "transcript",
+            "--stats",
+            "--to-text",
+            "session.jsonl",
+        ]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn transcript_no_operation() {
+        let args = Arguments::try_parse_from(["confab", "transcript", "session.jsonl"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn quiet_and_verbose() {
+        let args = Arguments::try_parse_from(["confab", "-q", "-v", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn until_without_send() {
+        let args = Arguments::try_parse_from(["confab", "--until=^OK", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn assert_timeout_without_assert_recv() {
+        let args = Arguments::try_parse_from(["confab", "--assert-timeout=5", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn expect_lines_and_until() {
+        let args = Arguments::try_parse_from([
+            "confab",
+            "--send=HELO",
+            "--expect-lines=1",
+            "--until=^OK",
+            "localhost",
+            "80",
+        ]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn session_without_host() {
+        let args = Arguments::try_parse_from(["confab", "--session=demo"]).unwrap();
+        assert_eq!(args.session.as_deref(), Some("demo"));
+        let args = Arguments::try_parse_from(["confab", "--session=../demo"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn latency_colors_without_show_times() {
+        let args = Arguments::try_parse_from(["confab", "--latency-colors", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[rstest]
+    #[case("--count=5")]
+    #[case("--interval-ms=100")]
+    fn flood_options_without_flood(#[case] opt: &str) {
+        let args = Arguments::try_parse_from(["confab", opt, "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn crlf_and_send_ending() {
+        let args =
+            Arguments::try_parse_from(["confab", "--crlf", "--send-ending=cr", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[rstest]
+    #[case("--send=HELO")]
+    #[case("--stdin-pipe")]
+    #[case("--tui")]
+    fn receive_only_and_input(#[case] opt: &str) {
+        let args = Arguments::try_parse_from(["confab", "--receive-only", opt, "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn retry_wait_without_retry() {
+        let args = Arguments::try_parse_from(["confab", "--retry-wait", "5", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+        let args = Arguments::try_parse_from([
+            "confab",
+            "--retry",
+            "3",
+            "--retry-wait",
+            "5",
+            "localhost",
+            "80",
+        ])
+        .unwrap();
+        assert_eq!(args.retry, 3);
+        assert_eq!(args.retry_wait, 5);
+    }
+
+    #[test]
+    fn retry_and_listen() {
+        let args = Arguments::try_parse_from(["confab", "--retry", "3", "--listen", "8080"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fd_without_host() {
+        let args = Arguments::try_parse_from(["confab", "--fd", "3"]).unwrap();
+        assert_eq!(args.fd, Some(FdSpec::Fd(3)));
+        let args = Arguments::try_parse_from(["confab", "--fd", "3", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn listen_without_host() {
+        let args = Arguments::try_parse_from(["confab", "--listen", "8080"]).unwrap();
+        assert_eq!(args.listen, Some(SocketAddr::from(([0, 0, 0, 0], 8080))));
+        let args = Arguments::try_parse_from(["confab", "--listen", "[::1]:8080"]).unwrap();
+        assert_eq!(
+            args.listen,
+            Some(SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 8080)))
+        );
+        let args = Arguments::try_parse_from(["confab", "--listen", "8080", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        let args =
+            Arguments::try_parse_from(["confab", "--tls-cert", "cert.pem", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn head_and_sample() {
+        let args =
+            Arguments::try_parse_from(["confab", "--head=5", "--sample=1/3", "localhost", "80"]);
+        assert!(args.is_err());
+        assert_eq!(args.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn record_cast_alias() {
+        let args =
+            Arguments::try_parse_from(["confab", "--record-cast", "s.cast", "localhost", "80"])
+                .unwrap();
+        assert_eq!(args.cast, Some(PathBuf::from("s.cast")));
+    }
+
     #[test]
     fn no_args() {
         let args = Arguments::try_parse_from(["confab"]);
@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A global limit on the memory used by everything that `confab` holds on to
+/// over the course of a session (queued lines, buffered output, etc.),
+/// shared between all such retention subsystems.
+///
+/// Usage is tracked approximately, by the sizes of the retained items'
+/// contents.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MemoryBudget(Arc<BudgetInner>);
+
+#[derive(Debug, Default)]
+struct BudgetInner {
+    cap: Option<usize>,
+    used: AtomicUsize,
+    evictions: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(cap: Option<usize>) -> MemoryBudget {
+        MemoryBudget(Arc::new(BudgetInner {
+            cap,
+            used: AtomicUsize::new(0),
+            evictions: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns the number of bytes currently retained
+    pub(crate) fn used(&self) -> usize {
+        self.0.used.load(Ordering::Acquire)
+    }
+
+    /// Returns the total number of items evicted so far in order to stay
+    /// under the cap
+    pub(crate) fn evictions(&self) -> u64 {
+        self.0.evictions.load(Ordering::Acquire)
+    }
+
+    /// Returns whether `bytes` more bytes can be retained without exceeding
+    /// the cap
+    pub(crate) fn fits(&self, bytes: usize) -> bool {
+        !self
+            .0
+            .cap
+            .is_some_and(|cap| self.used().saturating_add(bytes) > cap)
+    }
+
+    fn exceeded(&self) -> bool {
+        self.0.cap.is_some_and(|cap| self.used() > cap)
+    }
+
+    fn acquire(&self, bytes: usize) {
+        self.0.used.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    fn release(&self, bytes: usize) {
+        self.0.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    fn record_eviction(&self) {
+        self.0.evictions.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Trait for values stored in a `RetainedQueue`
+pub(crate) trait Retained {
+    /// The number of bytes that the value counts for against the memory
+    /// budget.  Values of size zero are never evicted.
+    fn mem_size(&self) -> usize;
+}
+
+impl Retained for String {
+    fn mem_size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A FIFO queue whose contents count against a `MemoryBudget`.  Whenever the
+/// budget is exceeded after adding an item, the oldest items in the queue
+/// are discarded until usage is back under the cap or there is nothing left
+/// to discard.
+#[derive(Debug)]
+pub(crate) struct RetainedQueue<T: Retained> {
+    items: VecDeque<T>,
+    budget: MemoryBudget,
+}
+
+impl<T: Retained> RetainedQueue<T> {
+    pub(crate) fn new(budget: MemoryBudget) -> RetainedQueue<T> {
+        RetainedQueue {
+            items: VecDeque::new(),
+            budget,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Iterate over the items in the queue from oldest to newest
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    /// Returns whether items totalling `bytes` bytes can be added without
+    /// any evictions
+    pub(crate) fn fits(&self, bytes: usize) -> bool {
+        self.budget.fits(bytes)
+    }
+
+    /// Add an item to the end of the queue and return the number of items
+    /// evicted to make room for it
+    pub(crate) fn push_back(&mut self, item: T) -> usize {
+        let mut evicted = 0;
+        self.budget.acquire(item.mem_size());
+        self.items.push_back(item);
+        while self.budget.exceeded() {
+            // Never evict the item that was just added
+            let oldest = self
+                .items
+                .iter()
+                .take(self.items.len() - 1)
+                .position(|it| it.mem_size() > 0);
+            let Some(i) = oldest else {
+                break;
+            };
+            if let Some(it) = self.items.remove(i) {
+                self.budget.release(it.mem_size());
+                self.budget.record_eviction();
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<T> {
+        let item = self.items.pop_front()?;
+        self.budget.release(item.mem_size());
+        Some(item)
+    }
+
+    /// Remove & return the item at the given (zero-based) index, if any
+    pub(crate) fn remove(&mut self, index: usize) -> Option<T> {
+        let item = self.items.remove(index)?;
+        self.budget.release(item.mem_size());
+        Some(item)
+    }
+
+    /// Discard all items in the queue and return how many there were
+    pub(crate) fn clear(&mut self) -> usize {
+        let size = self.items.iter().map(Retained::mem_size).sum();
+        self.budget.release(size);
+        let count = self.items.len();
+        self.items.clear();
+        count
+    }
+}
+
+impl<T: Retained> Extend<T> for RetainedQueue<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push_back(item);
+        }
+    }
+}
+
+impl<T: Retained> Drop for RetainedQueue<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let budget = MemoryBudget::default();
+        let mut queue = RetainedQueue::new(budget.clone());
+        queue.extend(["foo", "bar", "baz"].map(String::from));
+        assert_eq!(budget.used(), 9);
+        assert_eq!(queue.pop_front().as_deref(), Some("foo"));
+        assert_eq!(budget.used(), 6);
+        drop(queue);
+        assert_eq!(budget.used(), 0);
+        assert_eq!(budget.evictions(), 0);
+    }
+
+    #[test]
+    fn test_remove_and_clear() {
+        let budget = MemoryBudget::default();
+        let mut queue = RetainedQueue::new(budget.clone());
+        queue.extend(["foo", "quux", "bar"].map(String::from));
+        assert_eq!(queue.remove(1).as_deref(), Some("quux"));
+        assert_eq!(queue.remove(5), None);
+        assert_eq!(budget.used(), 6);
+        assert_eq!(
+            queue.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["foo", "bar"]
+        );
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.is_empty());
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_evict_oldest() {
+        let budget = MemoryBudget::new(Some(8));
+        let mut queue = RetainedQueue::new(budget.clone());
+        queue.extend(["foo", "bar", "baz"].map(String::from));
+        assert_eq!(budget.used(), 6);
+        assert_eq!(budget.evictions(), 1);
+        assert_eq!(queue.pop_front().as_deref(), Some("bar"));
+        assert_eq!(queue.pop_front().as_deref(), Some("baz"));
+        assert_eq!(queue.pop_front(), None);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_shared_budget() {
+        let budget = MemoryBudget::new(Some(8));
+        let mut queue1 = RetainedQueue::new(budget.clone());
+        let mut queue2 = RetainedQueue::new(budget.clone());
+        queue1.push_back(String::from("foobar"));
+        queue2.push_back(String::from("quux"));
+        // queue2 has nothing else to evict, so usage stays over the cap
+        assert_eq!(budget.used(), 10);
+        assert_eq!(budget.evictions(), 0);
+        queue1.push_back(String::from("x"));
+        assert_eq!(budget.used(), 5);
+        assert_eq!(budget.evictions(), 1);
+    }
+
+    #[test]
+    fn test_fits() {
+        let mut queue = RetainedQueue::new(MemoryBudget::new(Some(8)));
+        queue.push_back(String::from("foobar"));
+        assert!(queue.fits(2));
+        assert!(!queue.fits(3));
+        assert!(MemoryBudget::default().fits(usize::MAX));
+    }
+
+    #[test]
+    fn test_oversized_item() {
+        let budget = MemoryBudget::new(Some(4));
+        let mut queue = RetainedQueue::new(budget.clone());
+        queue.push_back(String::from("foo"));
+        queue.push_back(String::from("toolong"));
+        assert_eq!(budget.used(), 7);
+        assert_eq!(budget.evictions(), 1);
+        assert_eq!(queue.pop_front().as_deref(), Some("toolong"));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::events::Event;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Prefix prepended to the name of every metric
+const PREFIX: &str = "confab";
+
+/// A `statsd://HOST:PORT` address of a statsd server to which to send metrics
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct MetricsTarget {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+impl FromStr for MetricsTarget {
+    type Err = MetricsTargetError;
+
+    fn from_str(s: &str) -> Result<MetricsTarget, MetricsTargetError> {
+        let Some(addr) = s.strip_prefix("statsd://") else {
+            return Err(MetricsTargetError::Scheme);
+        };
+        let Some((host, port)) = addr.rsplit_once(':') else {
+            return Err(MetricsTargetError::Syntax);
+        };
+        // IPv6 addresses may be given in brackets, as in URLs
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        if host.is_empty() {
+            return Err(MetricsTargetError::Syntax);
+        }
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| MetricsTargetError::Port(port.to_owned()))?;
+        Ok(MetricsTarget {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum MetricsTargetError {
+    #[error(r#"only "statsd://" metrics targets are supported"#)]
+    Scheme,
+    #[error(r#"expected "statsd://HOST:PORT""#)]
+    Syntax,
+    #[error("invalid port: {0:?}")]
+    Port(String),
+}
+
+/// Counters of session activity, sent to a statsd server over UDP as events
+/// are reported
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    socket: UdpSocket,
+}
+
+impl Metrics {
+    /// Resolve the target's address and create a socket for sending metrics
+    /// to it
+    pub(crate) fn connect(target: &MetricsTarget) -> io::Result<Metrics> {
+        let addr = (target.host.as_str(), target.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no addresses"))?;
+        let local = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Metrics { socket })
+    }
+
+    /// Update the counters affected by an event.  Metrics are sent on a
+    /// best-effort basis, and failures to send them are ignored.
+    pub(crate) fn observe(&self, event: &Event) {
+        if let Some(packet) = packet(event) {
+            let _ = self.socket.send(packet.as_bytes());
+        }
+    }
+}
+
+/// Return the statsd packet (if any) incrementing the counters affected by an
+/// event
+fn packet(event: &Event) -> Option<String> {
+    let counts: &[(&str, usize)] = match event {
+        Event::ConnectFinish { .. } | Event::Accepted { .. } => &[("connections", 1)],
+        Event::Send { data, .. } => &[("lines_sent", 1), ("bytes_sent", data.len())],
+        Event::Recv { data, .. } => &[("lines_received", 1), ("bytes_received", data.len())],
+        Event::Error { .. } => &[("errors", 1)],
+        _ => return None,
+    };
+    Some(
+        counts
+            .iter()
+            .map(|(name, n)| format!("{PREFIX}.{name}:{n}|c"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case("statsd://localhost:8125", "localhost", 8125)]
+    #[case("statsd://127.0.0.1:9125", "127.0.0.1", 9125)]
+    #[case("statsd://[::1]:8125", "::1", 8125)]
+    fn test_parse_target(#[case] s: &str, #[case] host: &str, #[case] port: u16) {
+        assert_eq!(
+            s.parse::<MetricsTarget>(),
+            Ok(MetricsTarget {
+                host: host.into(),
+                port
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("localhost:8125", MetricsTargetError::Scheme)]
+    #[case("udp://localhost:8125", MetricsTargetError::Scheme)]
+    #[case("statsd://localhost", MetricsTargetError::Syntax)]
+    #[case("statsd://:8125", MetricsTargetError::Syntax)]
+    #[case("statsd://localhost:statsd", MetricsTargetError::Port("statsd".into()))]
+    fn test_parse_target_bad(#[case] s: &str, #[case] err: MetricsTargetError) {
+        assert_eq!(s.parse::<MetricsTarget>(), Err(err));
+    }
+
+    #[test]
+    fn test_packet() {
+        let ev = Event::recv(String::from("Hello!\r\n"), Duration::ZERO, false);
+        assert_eq!(
+            packet(&ev).as_deref(),
+            Some("confab.lines_received:1|c\nconfab.bytes_received:8|c")
+        );
+        let ev = Event::error(ErrorKind::Recv, &io::Error::from(io::ErrorKind::Other));
+        assert_eq!(packet(&ev).as_deref(), Some("confab.errors:1|c"));
+        assert_eq!(packet(&Event::connect_start("localhost", 80)), None);
+    }
+
+    #[test]
+    fn test_observe() {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let target = MetricsTarget {
+            host: String::from("127.0.0.1"),
+            port: server.local_addr().unwrap().port(),
+        };
+        let metrics = Metrics::connect(&target).unwrap();
+        metrics.observe(&Event::send(String::from("QUIT\n"), Duration::ZERO));
+        let mut buf = [0u8; 512];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"confab.lines_sent:1|c\nconfab.bytes_sent:5|c");
+    }
+}
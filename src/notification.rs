@@ -0,0 +1,69 @@
+use notify_rust::Notification;
+use regex::Regex;
+use thiserror::Error;
+
+/// Shows a desktop notification whenever a received line matches one of the
+/// patterns given with `--notify-on`
+#[derive(Clone, Debug)]
+pub(crate) struct Notifier {
+    patterns: Vec<Regex>,
+    /// The host & port, shown in the notification's title
+    endpoint: String,
+}
+
+impl Notifier {
+    pub(crate) fn new(patterns: Vec<Regex>, endpoint: String) -> Notifier {
+        Notifier { patterns, endpoint }
+    }
+
+    /// Change the host & port shown in notifications, as after `/connect`
+    pub(crate) fn set_endpoint(&mut self, endpoint: String) {
+        self.endpoint = endpoint;
+    }
+
+    /// Returns `true` if `line` matches any of the patterns
+    pub(crate) fn matches(&self, line: &str) -> bool {
+        self.patterns.iter().any(|rgx| rgx.is_match(line))
+    }
+
+    /// Show a notification containing `line`
+    pub(crate) async fn show(&self, line: &str) -> Result<(), NotifyError> {
+        let mut notification = Notification::new();
+        notification
+            .appname("confab")
+            .summary(&format!("confab {}", self.endpoint))
+            .body(line);
+        // Showing a notification blocks while talking to the notification
+        // service.
+        tokio::task::spawn_blocking(move || notification.show().map(drop)).await??;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum NotifyError {
+    #[error(transparent)]
+    Show(#[from] notify_rust::error::Error),
+    #[error("notification task failed")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let notifier = Notifier::new(
+            vec![
+                Regex::new(r"^BUILD (FAILED|PASSED)\b").unwrap(),
+                Regex::new(r"(?i)\berror\b").unwrap(),
+            ],
+            String::from("ci.example.com:6667"),
+        );
+        assert!(notifier.matches("BUILD PASSED in 3m12s"));
+        assert!(notifier.matches("fatal ERROR: out of disk"));
+        assert!(!notifier.matches("BUILD STARTED"));
+        assert!(!notifier.matches("no errors"));
+    }
+}
@@ -0,0 +1,559 @@
+//! Export of sessions as OpenTelemetry traces, sent to a collector as OTLP
+//! over HTTP with JSON encoding
+use crate::events::Event;
+use crate::util::{chomp, now};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Path to which traces are posted if the `--otel-endpoint` URL has none
+const DEFAULT_PATH: &str = "/v1/traces";
+
+/// Maximum amount of time to spend sending a trace to the collector
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `SPAN_KIND_CLIENT`
+const KIND_CLIENT: u8 = 3;
+
+/// `STATUS_CODE_ERROR`
+const STATUS_ERROR: u8 = 2;
+
+/// An `http://HOST[:PORT][/PATH]` URL of an OTLP/HTTP collector
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct OtelEndpoint {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+}
+
+impl FromStr for OtelEndpoint {
+    type Err = OtelEndpointError;
+
+    fn from_str(s: &str) -> Result<OtelEndpoint, OtelEndpointError> {
+        let Some(rest) = s.strip_prefix("http://") else {
+            return Err(OtelEndpointError::Scheme);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        // IPv6 addresses are given in brackets, which are not part of the
+        // host to connect to
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let (host, after) = rest.split_once(']').ok_or(OtelEndpointError::Syntax)?;
+            match after {
+                "" => (host, None),
+                _ => match after.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(OtelEndpointError::Syntax),
+                },
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) if !host.ends_with(':') => (host, Some(port)),
+                _ => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| OtelEndpointError::Port(port.to_owned()))?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(OtelEndpointError::Syntax);
+        }
+        let path = if path.is_empty() || path == "/" {
+            DEFAULT_PATH
+        } else {
+            path
+        };
+        Ok(OtelEndpoint {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum OtelEndpointError {
+    #[error(r#"only "http://" endpoints are supported"#)]
+    Scheme,
+    #[error(r#"expected "http://HOST[:PORT][/PATH]""#)]
+    Syntax,
+    #[error("invalid port: {0:?}")]
+    Port(String),
+}
+
+/// A span under construction or finished
+#[derive(Clone, Debug, PartialEq)]
+struct Span {
+    id: [u8; 8],
+    parent: Option<[u8; 8]>,
+    name: String,
+    start: OffsetDateTime,
+    end: Option<OffsetDateTime>,
+    attributes: Vec<(&'static str, String)>,
+    events: Vec<SpanEvent>,
+    /// Error message, if the span ended in failure
+    error: Option<String>,
+}
+
+impl Span {
+    fn new(name: String, start: OffsetDateTime, parent: Option<[u8; 8]>) -> Span {
+        Span {
+            id: random_id(),
+            parent,
+            name,
+            start,
+            end: None,
+            attributes: Vec::new(),
+            events: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn to_json(&self, trace_id: &[u8; 16]) -> Value {
+        let mut span = json!({
+            "traceId": hex(trace_id),
+            "spanId": hex(&self.id),
+            "name": self.name,
+            "kind": KIND_CLIENT,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end.unwrap_or(self.start)),
+            "attributes": attributes(&self.attributes),
+            "events": self.events.iter().map(SpanEvent::to_json).collect::<Vec<_>>(),
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = Value::from(hex(&parent));
+        }
+        if let Some(msg) = self.error.as_ref() {
+            span["status"] = json!({"code": STATUS_ERROR, "message": msg});
+        }
+        span
+    }
+}
+
+/// A timestamped occurrence within a span
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SpanEvent {
+    name: &'static str,
+    time: OffsetDateTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl SpanEvent {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "timeUnixNano": unix_nanos(self.time),
+            "attributes": attributes(&self.attributes),
+        })
+    }
+}
+
+/// Builds an OpenTelemetry trace of the session from its events.  Each
+/// connection is a root span, with the TLS handshake as a child span and
+/// each sent & received line as an event on the connection's span.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TraceRecorder {
+    endpoint: OtelEndpoint,
+    trace_id: [u8; 16],
+    /// Spans that have ended
+    finished: Vec<Span>,
+    /// The span for the current connection, if any
+    root: Option<Span>,
+    /// The span for a TLS handshake in progress, if any
+    tls: Option<Span>,
+}
+
+impl TraceRecorder {
+    pub(crate) fn new(endpoint: OtelEndpoint) -> TraceRecorder {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_id());
+        trace_id[8..].copy_from_slice(&random_id());
+        TraceRecorder {
+            endpoint,
+            trace_id,
+            finished: Vec::new(),
+            root: None,
+            tls: None,
+        }
+    }
+
+    /// Update the trace with an event of the session
+    pub(crate) fn observe(&mut self, event: &Event) {
+        let timestamp = *event.timestamp();
+        match event {
+            Event::ConnectStart { host, port, .. } => {
+                let root = self.start_root(format!("connect {host}:{port}"), timestamp);
+                root.attributes.push(("server.address", host.clone()));
+                root.attributes.push(("server.port", port.to_string()));
+            }
+            Event::ListenStart { addr, .. } => {
+                let root = self.start_root(format!("listen {addr}"), timestamp);
+                root.attributes.push(("server.address", addr.to_string()));
+            }
+            #[cfg(feature = "serial")]
+            Event::SerialOpened { device, baud, .. } => {
+                let root = self.start_root(format!("serial {device}"), timestamp);
+                root.attributes.push(("serial.baud", baud.to_string()));
+            }
+            Event::ProcessStarted { command, pid, .. } => {
+                let name = command.first().map_or("", String::as_str);
+                let root = self.start_root(format!("exec {name}"), timestamp);
+                root.attributes
+                    .push(("process.command_line", command.join(" ")));
+                if let Some(pid) = pid {
+                    root.attributes.push(("process.pid", pid.to_string()));
+                }
+            }
+            Event::ConnectFinish { peer, .. } | Event::Accepted { peer, .. } => {
+                if let Some(root) = self.root.as_mut() {
+                    root.attributes
+                        .push(("network.peer.address", peer.ip().to_string()));
+                    root.attributes
+                        .push(("network.peer.port", peer.port().to_string()));
+                }
+            }
+            Event::TlsStart { .. } => {
+                let parent = self.root.as_ref().map(|root| root.id);
+                self.tls = Some(Span::new(String::from("tls handshake"), timestamp, parent));
+            }
+            Event::TlsFinish { resumed, .. } => {
+                if let Some(mut span) = self.tls.take() {
+                    if let Some(resumed) = resumed {
+                        span.attributes.push(("tls.resumed", resumed.to_string()));
+                    }
+                    span.end = Some(timestamp);
+                    self.finished.push(span);
+                }
+            }
+            Event::Send { data, .. } | Event::Recv { data, .. } => {
+                if let Some(root) = self.root.as_mut() {
+                    let name = if matches!(event, Event::Send { .. }) {
+                        "send"
+                    } else {
+                        "recv"
+                    };
+                    root.events.push(SpanEvent {
+                        name,
+                        time: timestamp,
+                        attributes: vec![
+                            ("data", String::from(chomp(data))),
+                            ("bytes", data.len().to_string()),
+                        ],
+                    });
+                }
+            }
+            Event::Disconnect {
+                closed_by,
+                lines_sent,
+                lines_recv,
+                ..
+            } => {
+                if let Some(mut root) = self.root.take() {
+                    root.attributes
+                        .push(("confab.closed_by", String::from(closed_by.as_str())));
+                    root.attributes
+                        .push(("confab.lines_sent", lines_sent.to_string()));
+                    root.attributes
+                        .push(("confab.lines_recv", lines_recv.to_string()));
+                    root.end = Some(timestamp);
+                    self.finished.push(root);
+                }
+            }
+            Event::Error { data, .. } => {
+                if let Some(mut span) = self.tls.take() {
+                    span.error = Some(data.clone());
+                    span.end = Some(timestamp);
+                    self.finished.push(span);
+                }
+                if let Some(mut root) = self.root.take() {
+                    root.error = Some(data.clone());
+                    root.end = Some(timestamp);
+                    self.finished.push(root);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn start_root(&mut self, name: String, timestamp: OffsetDateTime) -> &mut Span {
+        self.finish_open(timestamp);
+        self.root.insert(Span::new(name, timestamp, None))
+    }
+
+    /// End any spans that are still open at the given time
+    fn finish_open(&mut self, timestamp: OffsetDateTime) {
+        for mut span in [self.tls.take(), self.root.take()].into_iter().flatten() {
+            span.end = Some(timestamp);
+            self.finished.push(span);
+        }
+    }
+
+    /// Return the OTLP/JSON request body for the trace, ending any spans that
+    /// are still open
+    fn body(&mut self) -> Value {
+        self.finish_open(now());
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": attributes(&[("service.name", String::from("confab"))]),
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "confab", "version": env!("CARGO_PKG_VERSION")},
+                    "spans": self
+                        .finished
+                        .iter()
+                        .map(|span| span.to_json(&self.trace_id))
+                        .collect::<Vec<_>>(),
+                }],
+            }],
+        })
+    }
+
+    /// Send the trace to the collector.  Does nothing if the session never
+    /// started a connection.
+    pub(crate) async fn export(&mut self) -> Result<(), ExportError> {
+        if self.finished.is_empty() && self.root.is_none() {
+            return Ok(());
+        }
+        let body = self.body().to_string();
+        tokio::time::timeout(EXPORT_TIMEOUT, post(&self.endpoint, &body))
+            .await
+            .map_err(|_| ExportError::Timeout)?
+    }
+}
+
+/// Send a JSON document to the collector in an HTTP/1.1 POST request
+async fn post(endpoint: &OtelEndpoint, body: &str) -> Result<(), ExportError> {
+    let mut conn = TcpStream::connect((endpoint.host.as_str(), endpoint.port))
+        .await
+        .map_err(ExportError::Connect)?;
+    let host = if endpoint.host.contains(':') {
+        format!("[{}]", endpoint.host)
+    } else {
+        endpoint.host.clone()
+    };
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {host}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        endpoint.path,
+        endpoint.port,
+        body.len(),
+    );
+    conn.write_all(request.as_bytes())
+        .await
+        .map_err(ExportError::Io)?;
+    let mut response = Vec::new();
+    conn.read_to_end(&mut response)
+        .await
+        .map_err(ExportError::Io)?;
+    let status_line = String::from_utf8_lossy(&response)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(ExportError::Status(status_line)),
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ExportError {
+    #[error("failed to connect to collector")]
+    Connect(#[source] io::Error),
+    #[error("failed to communicate with collector")]
+    Io(#[source] io::Error),
+    #[error("collector did not respond in time")]
+    Timeout,
+    #[error("collector rejected trace: {0:?}")]
+    Status(String),
+}
+
+fn attributes(attrs: &[(&'static str, String)]) -> Vec<Value> {
+    attrs
+        .iter()
+        .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+        .collect()
+}
+
+/// Format a timestamp as nanoseconds since the Unix epoch, which OTLP/JSON
+/// encodes as a string
+fn unix_nanos(t: OffsetDateTime) -> String {
+    t.unix_timestamp_nanos().to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Return a random nonzero span ID (or half of a trace ID)
+fn random_id() -> [u8; 8] {
+    loop {
+        let n = RandomState::new().build_hasher().finish();
+        if n != 0 {
+            return n.to_be_bytes();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ClosedBy;
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    #[rstest]
+    #[case("http://localhost:4318", "localhost", 4318, "/v1/traces")]
+    #[case("http://localhost:4318/", "localhost", 4318, "/v1/traces")]
+    #[case("http://collector/otlp/v1/traces", "collector", 80, "/otlp/v1/traces")]
+    #[case("http://[::1]:4318", "::1", 4318, "/v1/traces")]
+    #[case("http://[::1]/otlp", "::1", 80, "/otlp")]
+    fn test_parse_endpoint(
+        #[case] s: &str,
+        #[case] host: &str,
+        #[case] port: u16,
+        #[case] path: &str,
+    ) {
+        assert_eq!(
+            s.parse::<OtelEndpoint>(),
+            Ok(OtelEndpoint {
+                host: host.into(),
+                port,
+                path: path.into(),
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("https://localhost:4318", OtelEndpointError::Scheme)]
+    #[case("localhost:4318", OtelEndpointError::Scheme)]
+    #[case("http://:4318", OtelEndpointError::Syntax)]
+    #[case("http://[::1:4318", OtelEndpointError::Syntax)]
+    #[case("http://[::1]4318", OtelEndpointError::Syntax)]
+    #[case("http://localhost:otlp", OtelEndpointError::Port("otlp".into()))]
+    fn test_parse_endpoint_bad(#[case] s: &str, #[case] err: OtelEndpointError) {
+        assert_eq!(s.parse::<OtelEndpoint>(), Err(err));
+    }
+
+    fn recorder() -> TraceRecorder {
+        TraceRecorder::new("http://localhost:4318".parse().unwrap())
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut trace = recorder();
+        trace.observe(&Event::connect_start("example.com", 443));
+        trace.observe(&Event::tls_start());
+        trace.observe(&Event::tls_finish(Some(false)));
+        trace.observe(&Event::send(String::from("HELO\r\n"), Duration::ZERO));
+        trace.observe(&Event::recv(
+            String::from("250 Hello\r\n"),
+            Duration::ZERO,
+            false,
+        ));
+        let stats = crate::stats::SessionStats::default().snapshot();
+        trace.observe(&Event::disconnect(ClosedBy::Peer, &stats));
+        let body = trace.body();
+        let spans = &body["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans.as_array().map(Vec::len), Some(2));
+        let (tls, root) = (&spans[0], &spans[1]);
+        assert_eq!(tls["name"], "tls handshake");
+        assert_eq!(tls["parentSpanId"], root["spanId"]);
+        assert_eq!(tls["traceId"], root["traceId"]);
+        assert_eq!(root["name"], "connect example.com:443");
+        assert!(root.get("parentSpanId").is_none());
+        assert!(root.get("status").is_none());
+        let events = root["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "send");
+        assert_eq!(
+            events[0]["attributes"][0],
+            json!({"key": "data", "value": {"stringValue": "HELO"}})
+        );
+        assert_eq!(events[1]["name"], "recv");
+    }
+
+    #[test]
+    fn test_trace_error() {
+        let mut trace = recorder();
+        trace.observe(&Event::connect_start("example.com", 443));
+        trace.observe(&Event::tls_start());
+        trace.observe(&Event::error(
+            crate::errors::ErrorKind::Tls,
+            &io::Error::other("handshake failed"),
+        ));
+        let body = trace.body();
+        let spans = &body["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans.as_array().map(Vec::len), Some(2));
+        for span in spans.as_array().unwrap() {
+            assert_eq!(
+                span["status"],
+                json!({"code": STATUS_ERROR, "message": "handshake failed"})
+            );
+        }
+    }
+
+    /// Accept a single export at `listener` and return the request
+    async fn collect(listener: TcpListener) -> String {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        // The request ends with the JSON body's closing brace
+        while !request.ends_with(b"}") {
+            let n = conn.read(&mut buf).await.unwrap();
+            assert!(n > 0, "request ended early");
+            request.extend_from_slice(&buf[..n]);
+        }
+        conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = tokio::spawn(collect(listener));
+        let mut trace = TraceRecorder::new(format!("http://127.0.0.1:{port}").parse().unwrap());
+        trace.observe(&Event::connect_start("example.com", 80));
+        trace.export().await.unwrap();
+        let request = collector.await.unwrap();
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body = serde_json::from_str::<Value>(body).unwrap();
+        assert_eq!(
+            body["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["name"],
+            "connect example.com:80"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_ipv6() {
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = tokio::spawn(collect(listener));
+        let mut trace = TraceRecorder::new(format!("http://[::1]:{port}/otlp").parse().unwrap());
+        trace.observe(&Event::connect_start("example.com", 80));
+        trace.export().await.unwrap();
+        let request = collector.await.unwrap();
+        assert!(request.starts_with(&format!("POST /otlp HTTP/1.1\r\nHost: [::1]:{port}\r\n")));
+    }
+
+    #[test]
+    fn test_random_id() {
+        assert_ne!(random_id(), random_id());
+    }
+}
@@ -0,0 +1,193 @@
+use crossterm::style::{StyledContent, Stylize};
+use serde::de::IgnoredAny;
+
+/// Number of spaces by which each level of nesting is indented
+const INDENT: usize = 2;
+
+/// A lexical token of a JSON document
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Token<'a> {
+    Open(char),
+    Close(char),
+    Colon,
+    Comma,
+    /// A string literal, including its quotes
+    Str(&'a str),
+    /// A number, `true`, `false`, or `null`
+    Scalar(&'a str),
+}
+
+/// If `s` is a JSON object or array, return it pretty-printed with syntax
+/// coloring, one element per line.  Object keys are kept in their original
+/// order, and numbers & strings are kept exactly as written.
+pub(crate) fn pretty_json(s: &str) -> Option<Vec<Vec<StyledContent<String>>>> {
+    let s = s.trim();
+    if !(s.starts_with('{') || s.starts_with('[')) {
+        return None;
+    }
+    serde_json::from_str::<IgnoredAny>(s).ok()?;
+    let tokens = tokenize(s);
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut depth = 0;
+    let mut iter = tokens.iter().peekable();
+    while let Some(&tok) = iter.next() {
+        let next = iter.peek().copied().copied();
+        match tok {
+            Token::Open(c) => {
+                line.push(c.to_string().stylize());
+                if let Some(Token::Close(d)) = next {
+                    // Keep empty objects & arrays on one line
+                    line.push(d.to_string().stylize());
+                    iter.next();
+                } else {
+                    depth += 1;
+                    newline(&mut lines, &mut line, depth);
+                }
+            }
+            Token::Close(c) => {
+                depth -= 1;
+                newline(&mut lines, &mut line, depth);
+                line.push(c.to_string().stylize());
+            }
+            Token::Colon => line.push(String::from(": ").stylize()),
+            Token::Comma => {
+                line.push(String::from(",").stylize());
+                newline(&mut lines, &mut line, depth);
+            }
+            Token::Str(text) if next == Some(Token::Colon) => {
+                line.push(text.to_owned().blue().bold());
+            }
+            Token::Str(text) => line.push(text.to_owned().green()),
+            Token::Scalar(text @ ("true" | "false" | "null")) => {
+                line.push(text.to_owned().magenta());
+            }
+            Token::Scalar(text) => line.push(text.to_owned().cyan()),
+        }
+    }
+    lines.push(line);
+    Some(lines)
+}
+
+/// End the current line and start a new one indented to the given depth
+fn newline(
+    lines: &mut Vec<Vec<StyledContent<String>>>,
+    line: &mut Vec<StyledContent<String>>,
+    depth: usize,
+) {
+    lines.push(std::mem::take(line));
+    if depth > 0 {
+        line.push(" ".repeat(depth * INDENT).stylize());
+    }
+}
+
+/// Split a valid JSON document into tokens
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '{' | '[' => {
+                tokens.push(Token::Open(c));
+                1
+            }
+            '}' | ']' => {
+                tokens.push(Token::Close(c));
+                1
+            }
+            ':' => {
+                tokens.push(Token::Colon);
+                1
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                1
+            }
+            '"' => {
+                let mut escaped = false;
+                let end = rest[1..]
+                    .char_indices()
+                    .find(|&(_, ch)| {
+                        let done = ch == '"' && !escaped;
+                        escaped = ch == '\\' && !escaped;
+                        done
+                    })
+                    .map_or(rest.len(), |(i, _)| i + 2);
+                tokens.push(Token::Str(&rest[..end]));
+                end
+            }
+            c if c.is_ascii_whitespace() => 1,
+            _ => {
+                let end = rest
+                    .find(|ch: char| ch.is_ascii_whitespace() || ",:]}".contains(ch))
+                    .unwrap_or(rest.len());
+                tokens.push(Token::Scalar(&rest[..end]));
+                end
+            }
+        };
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(s: &str) -> Option<Vec<String>> {
+        pretty_json(s).map(|lines| {
+            lines
+                .into_iter()
+                .map(|line| line.iter().map(|c| c.content().as_str()).collect())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_pretty_object() {
+        assert_eq!(
+            plain(r#"{"zeta": 1.50, "alpha": [true, null, "a\"b,c"], "empty": {}}"#).unwrap(),
+            [
+                "{",
+                r#"  "zeta": 1.50,"#,
+                r#"  "alpha": ["#,
+                "    true,",
+                "    null,",
+                r#"    "a\"b,c""#,
+                "  ],",
+                r#"  "empty": {}"#,
+                "}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pretty_empty_array() {
+        assert_eq!(plain(" [] ").unwrap(), ["[]"]);
+    }
+
+    #[test]
+    fn test_not_pretty() {
+        assert_eq!(plain("42"), None);
+        assert_eq!(plain(r#""hello""#), None);
+        assert_eq!(plain("{not json}"), None);
+        assert_eq!(plain("[1, 2"), None);
+        assert_eq!(plain("Hello, world"), None);
+    }
+
+    #[test]
+    fn test_key_style() {
+        let lines = pretty_json(r#"{"a": "b"}"#).unwrap();
+        let key = &lines[1][1];
+        let value = &lines[1][3];
+        assert_eq!(key.content(), r#""a""#);
+        assert_eq!(
+            key.style().foreground_color,
+            Some(crossterm::style::Color::Blue)
+        );
+        assert_eq!(
+            value.style().foreground_color,
+            Some(crossterm::style::Color::Green)
+        );
+    }
+}
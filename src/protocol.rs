@@ -0,0 +1,157 @@
+use crate::util::chomp;
+use clap::ValueEnum;
+
+/// An application protocol whose conventions `confab` can help with
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum Protocol {
+    Smtp,
+    Ftp,
+}
+
+/// Protocol-aware processing of lines on their way to & from the codec.
+///
+/// For both SMTP and FTP, numeric multi-line replies (`250-...` continued
+/// until a `250 ...` line) are tracked so that their continuation lines can
+/// be grouped under the first line when displayed.  For SMTP, lines of a
+/// message body sent after a `DATA` command is accepted are dot-stuffed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ProtocolLayer {
+    protocol: Protocol,
+    /// The code of the multi-line reply currently being received, if any
+    reply: Option<String>,
+    data: DataState,
+}
+
+/// Progress through an SMTP `DATA` transaction
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum DataState {
+    #[default]
+    Idle,
+    /// `DATA` was sent, and the server's reply is awaited
+    Requested,
+    /// The server accepted `DATA`, so lines sent are part of the message
+    /// body
+    Body,
+}
+
+impl ProtocolLayer {
+    pub(crate) fn new(protocol: Protocol) -> ProtocolLayer {
+        ProtocolLayer {
+            protocol,
+            reply: None,
+            data: DataState::Idle,
+        }
+    }
+
+    /// Process a line received from the server.  Returns `true` if the line
+    /// is a continuation of a multi-line reply.
+    pub(crate) fn incoming(&mut self, line: &str) -> bool {
+        let line = chomp(line);
+        let code = line
+            .get(..3)
+            .filter(|c| c.bytes().all(|b| b.is_ascii_digit()));
+        let continues = line.as_bytes().get(3) == Some(&b'-');
+        if let Some(open) = self.reply.take() {
+            if code != Some(&*open) || continues {
+                self.reply = Some(open);
+            } else {
+                self.reply_complete(&open);
+            }
+            return true;
+        }
+        if let Some(code) = code {
+            if continues {
+                self.reply = Some(code.to_owned());
+            } else {
+                self.reply_complete(code);
+            }
+        }
+        false
+    }
+
+    /// Process a line (without line ending) about to be sent to the server,
+    /// returning the line to actually send
+    pub(crate) fn outgoing(&mut self, line: String) -> String {
+        if self.protocol != Protocol::Smtp {
+            return line;
+        }
+        match self.data {
+            DataState::Body if line == "." => {
+                self.data = DataState::Idle;
+                line
+            }
+            DataState::Body if line.starts_with('.') => format!(".{line}"),
+            DataState::Body => line,
+            DataState::Idle | DataState::Requested => {
+                if line.trim().eq_ignore_ascii_case("DATA") {
+                    self.data = DataState::Requested;
+                }
+                line
+            }
+        }
+    }
+
+    fn reply_complete(&mut self, code: &str) {
+        if self.data == DataState::Requested {
+            self.data = if code == "354" {
+                DataState::Body
+            } else {
+                DataState::Idle
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiline_reply() {
+        let mut layer = ProtocolLayer::new(Protocol::Smtp);
+        assert!(!layer.incoming("220 mail.example.com ESMTP\r\n"));
+        assert!(!layer.incoming("250-mail.example.com\r\n"));
+        assert!(layer.incoming("250-SIZE 1000000\r\n"));
+        assert!(layer.incoming("250 HELP\r\n"));
+        assert!(!layer.incoming("250 OK\r\n"));
+    }
+
+    #[test]
+    fn test_ftp_multiline_reply() {
+        let mut layer = ProtocolLayer::new(Protocol::Ftp);
+        assert!(!layer.incoming("211-Features:\r\n"));
+        assert!(layer.incoming(" MDTM\r\n"));
+        assert!(layer.incoming("200 not the end\r\n"));
+        assert!(layer.incoming("211 End\r\n"));
+        assert!(!layer.incoming("Not a reply\r\n"));
+    }
+
+    #[test]
+    fn test_dot_stuffing() {
+        let mut layer = ProtocolLayer::new(Protocol::Smtp);
+        assert_eq!(layer.outgoing(String::from(".early")), ".early");
+        assert_eq!(layer.outgoing(String::from("data")), "data");
+        layer.incoming("354 Start mail input\r\n");
+        assert_eq!(layer.outgoing(String::from("Hello")), "Hello");
+        assert_eq!(layer.outgoing(String::from(".hidden")), "..hidden");
+        assert_eq!(layer.outgoing(String::from("..")), "...");
+        assert_eq!(layer.outgoing(String::from(".")), ".");
+        assert_eq!(layer.outgoing(String::from(".after")), ".after");
+    }
+
+    #[test]
+    fn test_data_rejected() {
+        let mut layer = ProtocolLayer::new(Protocol::Smtp);
+        layer.outgoing(String::from("DATA"));
+        layer.incoming("503 Bad sequence of commands\r\n");
+        assert_eq!(layer.outgoing(String::from(".x")), ".x");
+    }
+
+    #[test]
+    fn test_ftp_no_dot_stuffing() {
+        let mut layer = ProtocolLayer::new(Protocol::Ftp);
+        layer.outgoing(String::from("DATA"));
+        layer.incoming("354 Go ahead\r\n");
+        assert_eq!(layer.outgoing(String::from(".x")), ".x");
+    }
+}
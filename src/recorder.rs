@@ -0,0 +1,421 @@
+use crate::flush::{FlushPolicy, FlushTimer};
+use crate::util::now;
+use futures_util::FutureExt; // now_or_never()
+use std::fs::{create_dir_all, OpenOptions};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use thiserror::Error;
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tokio::time::sleep_until;
+
+/// Maximum number of events waiting to be written to the transcript.  Events
+/// reported while the queue is full are dropped rather than making the
+/// session wait for the disk.
+const QUEUE_SIZE: usize = 4096;
+
+/// A handle for appending lines to the session transcript via a background
+/// task, so that a slow disk or network filesystem never holds up receiving
+/// from the server
+#[derive(Debug)]
+pub(crate) struct TranscriptWriter {
+    sender: Option<mpsc::Sender<String>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+    /// Number of lines dropped because the queue was full
+    dropped: u64,
+}
+
+impl TranscriptWriter {
+    /// Start writing to `fp`, flushing according to `flush` or, if it is
+    /// `None`, whenever the writer has caught up with the lines queued
+    pub(crate) fn new(fp: std::fs::File, flush: Option<FlushPolicy>) -> TranscriptWriter {
+        TranscriptWriter::with_capacity(fp, QUEUE_SIZE, flush)
+    }
+
+    fn with_capacity(
+        fp: std::fs::File,
+        capacity: usize,
+        flush: Option<FlushPolicy>,
+    ) -> TranscriptWriter {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let handle = tokio::spawn(write_lines(
+            BufWriter::new(File::from_std(fp)),
+            receiver,
+            flush,
+        ));
+        TranscriptWriter {
+            sender: Some(sender),
+            handle: Some(handle),
+            dropped: 0,
+        }
+    }
+
+    /// Queue a line (without trailing newline) to be written.  If the queue
+    /// is full, the line is dropped.  If the background task has stopped due
+    /// to an error, that error is returned.
+    pub(crate) fn write(&mut self, line: String) -> io::Result<()> {
+        let Some(sender) = self.sender.as_ref() else {
+            return Err(self.stopped());
+        };
+        match sender.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.sender = None;
+                Err(self.stopped())
+            }
+        }
+    }
+
+    /// Returns the number of lines dropped because the queue was full
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Wait for all queued lines to be written & flushed and shut down the
+    /// background task
+    pub(crate) async fn close(&mut self) -> io::Result<()> {
+        self.sender = None;
+        match self.handle.take() {
+            Some(handle) => handle.await.unwrap_or_else(|e| Err(io::Error::other(e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the error that caused the task to exit
+    fn stopped(&mut self) -> io::Error {
+        match self.handle.take().and_then(FutureExt::now_or_never) {
+            Some(Ok(Err(e))) => e,
+            _ => io::Error::other("transcript writer has stopped"),
+        }
+    }
+}
+
+/// Write each line received on `receiver` to `fp`, flushing according to
+/// `flush` or, if it is `None`, whenever the queue has been emptied
+async fn write_lines(
+    mut fp: BufWriter<File>,
+    mut receiver: mpsc::Receiver<String>,
+    flush: Option<FlushPolicy>,
+) -> io::Result<()> {
+    let mut timer = flush.map(FlushTimer::new);
+    loop {
+        let flush_at = timer.as_ref().and_then(FlushTimer::due);
+        let line = tokio::select! {
+            line = receiver.recv() => line,
+            () = sleep_until(flush_at.unwrap_or_else(Instant::now).into()), if flush_at.is_some() => {
+                fp.flush().await?;
+                if let Some(t) = timer.as_mut() {
+                    t.flushed();
+                }
+                continue;
+            }
+        };
+        let Some(line) = line else {
+            break;
+        };
+        write_line(&mut fp, &line, timer.as_mut()).await?;
+        while let Ok(line) = receiver.try_recv() {
+            write_line(&mut fp, &line, timer.as_mut()).await?;
+        }
+        if timer.is_none() {
+            fp.flush().await?;
+        }
+    }
+    fp.flush().await
+}
+
+async fn write_line(
+    fp: &mut BufWriter<File>,
+    line: &str,
+    timer: Option<&mut FlushTimer>,
+) -> io::Result<()> {
+    fp.write_all(line.as_bytes()).await?;
+    fp.write_all(b"\n").await?;
+    if timer.is_some_and(|t| t.wrote(true, Instant::now())) {
+        fp.flush().await?;
+    }
+    Ok(())
+}
+
+/// A series of transcripts under `--transcript-per-connection`, one for each
+/// connection made during the session, with paths expanded from the
+/// `--transcript` template as each connection starts
+#[derive(Debug)]
+pub(crate) struct TranscriptSeries {
+    template: PathBuf,
+    flush: Option<FlushPolicy>,
+    /// Number of connections for which a transcript has been started
+    connections: u64,
+    /// The "session-start" event, which is written at the start of each
+    /// transcript
+    header: Option<String>,
+    /// The transcripts of earlier connections, which are closed at the end
+    /// of the session
+    finished: Vec<TranscriptWriter>,
+}
+
+impl TranscriptSeries {
+    pub(crate) fn new(template: PathBuf, flush: Option<FlushPolicy>) -> TranscriptSeries {
+        TranscriptSeries {
+            template,
+            flush,
+            connections: 0,
+            header: None,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Record the session's "session-start" event for writing at the start of
+    /// each transcript
+    pub(crate) fn set_header(&mut self, line: String) {
+        self.header = Some(line);
+    }
+
+    /// Open the transcript for a new connection to `host` & `port`.
+    /// `previous` is the transcript of the preceding connection, if any,
+    /// which is kept open until [`TranscriptSeries::finish()`] is called.
+    pub(crate) fn next(
+        &mut self,
+        host: &str,
+        port: u16,
+        previous: Option<TranscriptWriter>,
+    ) -> Result<TranscriptWriter, TranscriptOpenError> {
+        self.finished.extend(previous);
+        self.connections += 1;
+        let mut w = open_transcript(&self.template, host, port, self.connections, self.flush)?;
+        if let Some(header) = self.header.clone() {
+            // The file was just opened, so the writer can't have stopped yet
+            let _ = w.write(header);
+        }
+        Ok(w)
+    }
+
+    /// Return the transcripts of the earlier connections so that they can be
+    /// closed
+    pub(crate) fn finish(&mut self) -> Vec<TranscriptWriter> {
+        mem::take(&mut self.finished)
+    }
+}
+
+/// Open the transcript at the path given by expanding `template` for the
+/// current time and connection number `conn` to `host` & `port`, creating
+/// any missing parent directories
+pub(crate) fn open_transcript(
+    template: &Path,
+    host: &str,
+    port: u16,
+    conn: u64,
+    flush: Option<FlushPolicy>,
+) -> Result<TranscriptWriter, TranscriptOpenError> {
+    let p = expand_path_template(template, now(), host, port, conn)?;
+    if let Some(parent) = p.parent().filter(|d| !d.as_os_str().is_empty()) {
+        create_dir_all(parent).map_err(TranscriptOpenError::Mkdir)?;
+    }
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(p)
+        .map(|fp| TranscriptWriter::new(fp, flush))
+        .map_err(TranscriptOpenError::Open)
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum TranscriptOpenError {
+    #[error("invalid transcript path")]
+    Template(#[from] PathTemplateError),
+    #[error("failed to create transcript directory")]
+    Mkdir(#[source] io::Error),
+    #[error("failed to open transcript file")]
+    Open(#[source] io::Error),
+}
+
+/// Expand placeholders in a path given on the command line.  The following
+/// placeholders are recognized:
+///
+/// - `%Y`, `%y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%j` — the components of the
+///   given time, as for `strftime()`
+/// - `%s` — the given time as a Unix timestamp
+/// - `%%` — a literal `%`
+/// - `{host}`, `{port}` — the remote host & port, with any characters in the
+///   host that can't appear in file names (such as the colons in IPv6
+///   addresses) replaced by underscores
+/// - `{conn}` — the number of the connection within the session, starting at
+///   1
+///
+/// Paths that are not valid UTF-8 are returned unchanged.
+pub(crate) fn expand_path_template(
+    template: &Path,
+    when: OffsetDateTime,
+    host: &str,
+    port: u16,
+    conn: u64,
+) -> Result<PathBuf, PathTemplateError> {
+    let Some(template) = template.to_str() else {
+        return Ok(template.to_path_buf());
+    };
+    let mut path = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let Some(d) = chars.next() else {
+                    return Err(PathTemplateError::Trailing);
+                };
+                let expansion = match d {
+                    'Y' => format!("{:04}", when.year()),
+                    'y' => format!("{:02}", when.year().rem_euclid(100)),
+                    'm' => format!("{:02}", u8::from(when.month())),
+                    'd' => format!("{:02}", when.day()),
+                    'H' => format!("{:02}", when.hour()),
+                    'M' => format!("{:02}", when.minute()),
+                    'S' => format!("{:02}", when.second()),
+                    'j' => format!("{:03}", when.ordinal()),
+                    's' => when.unix_timestamp().to_string(),
+                    '%' => String::from("%"),
+                    d => return Err(PathTemplateError::Directive(d)),
+                };
+                path.push_str(&expansion);
+            }
+            '{' => {
+                let rest = chars.as_str();
+                if let Some(r) = rest.strip_prefix("host}") {
+                    path.extend(host.chars().map(|c| {
+                        if c.is_control()
+                            || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+                        {
+                            '_'
+                        } else {
+                            c
+                        }
+                    }));
+                    chars = r.chars();
+                } else if let Some(r) = rest.strip_prefix("port}") {
+                    path.push_str(&port.to_string());
+                    chars = r.chars();
+                } else if let Some(r) = rest.strip_prefix("conn}") {
+                    path.push_str(&conn.to_string());
+                    chars = r.chars();
+                } else {
+                    path.push('{');
+                }
+            }
+            c => path.push(c),
+        }
+    }
+    Ok(PathBuf::from(path))
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub(crate) enum PathTemplateError {
+    #[error("unsupported placeholder \"%{0}\"")]
+    Directive(char),
+    #[error("path ends with incomplete placeholder \"%\"")]
+    Trailing,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+    use time::macros::datetime;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_write() {
+        let tmpfile = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = TranscriptWriter::new(tmpfile.reopen().unwrap(), None);
+        writer.write(String::from(r#"{"foo": 1}"#)).unwrap();
+        writer.write(String::from(r#"{"bar": 2}"#)).unwrap();
+        writer.close().await.unwrap();
+        assert_eq!(writer.dropped(), 0);
+        let content = std::fs::read_to_string(tmpfile.path()).unwrap();
+        assert_eq!(content, "{\"foo\": 1}\n{\"bar\": 2}\n");
+    }
+
+    #[tokio::test]
+    async fn test_flush_interval() {
+        let tmpfile = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = TranscriptWriter::new(
+            tmpfile.reopen().unwrap(),
+            Some(FlushPolicy::Interval(Duration::from_millis(200))),
+        );
+        writer.write(String::from(r#"{"foo": 1}"#)).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(std::fs::read_to_string(tmpfile.path()).unwrap(), "");
+        sleep(Duration::from_millis(350)).await;
+        assert_eq!(
+            std::fs::read_to_string(tmpfile.path()).unwrap(),
+            "{\"foo\": 1}\n"
+        );
+        writer.close().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_full_queue() {
+        let tmpfile = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = TranscriptWriter::with_capacity(tmpfile.reopen().unwrap(), 2, None);
+        // The background task doesn't get to run until we yield, so the
+        // third line doesn't fit in the queue.
+        for i in 0..3 {
+            writer.write(format!("{i}")).unwrap();
+        }
+        writer.close().await.unwrap();
+        assert_eq!(writer.dropped(), 1);
+        let content = std::fs::read_to_string(tmpfile.path()).unwrap();
+        assert_eq!(content, "0\n1\n");
+    }
+
+    #[rstest]
+    #[case("transcript.jsonl", "transcript.jsonl")]
+    #[case(
+        "logs/%Y-%m-%d/%H%M%S-{host}.jsonl",
+        "logs/2024-03-07/090502-example.com.jsonl"
+    )]
+    #[case(
+        "%y%j-{host}:{port}-%s.jsonl",
+        "24067-example.com:8080-1709802302.jsonl"
+    )]
+    #[case("100%%-{hostname}-{port", "100%-{hostname}-{port")]
+    #[case("{host}-{conn}.jsonl", "example.com-1.jsonl")]
+    fn test_expand_path_template(#[case] template: &str, #[case] path: &str) {
+        let when = datetime!(2024-03-07 09:05:02 UTC);
+        assert_eq!(
+            expand_path_template(Path::new(template), when, "example.com", 8080, 1),
+            Ok(PathBuf::from(path))
+        );
+    }
+
+    #[rstest]
+    #[case("example.com", "logs/example.com-8080.jsonl")]
+    #[case("::1", "logs/__1-8080.jsonl")]
+    #[case("fe80::1%eth0", "logs/fe80__1%eth0-8080.jsonl")]
+    #[case("../etc/passwd", "logs/.._etc_passwd-8080.jsonl")]
+    fn test_expand_path_template_host(#[case] host: &str, #[case] path: &str) {
+        let when = datetime!(2024-03-07 09:05:02 UTC);
+        assert_eq!(
+            expand_path_template(Path::new("logs/{host}-{port}.jsonl"), when, host, 8080, 1),
+            Ok(PathBuf::from(path))
+        );
+    }
+
+    #[rstest]
+    #[case("%Q.jsonl", PathTemplateError::Directive('Q'))]
+    #[case("transcript-%", PathTemplateError::Trailing)]
+    fn test_expand_bad_path_template(#[case] template: &str, #[case] err: PathTemplateError) {
+        let when = datetime!(2024-03-07 09:05:02 UTC);
+        assert_eq!(
+            expand_path_template(Path::new(template), when, "example.com", 8080, 1),
+            Err(err)
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use crate::ansi::AnsiMode;
+use crate::events::{unstyle, Event, Fit};
+use crate::pretty::pretty_json;
+use crate::util::{chomp, now_hms};
+use clap::ValueEnum;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use thiserror::Error;
+use unicode_width::UnicodeWidthStr;
+
+/// How events are presented on standard output
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human-readable messages
+    #[default]
+    Console,
+    /// One JSON object per event, as in transcripts
+    Json,
+    /// Only the lines received from the server, as-is
+    Raw,
+}
+
+impl OutputFormat {
+    /// Return a renderer for the format.  `console` gives the display
+    /// options, which are only used by `OutputFormat::Console`.
+    pub(crate) fn renderer(self, console: ConsoleRenderer) -> Box<dyn Renderer> {
+        match self {
+            OutputFormat::Console => Box::new(console),
+            OutputFormat::Json => Box::new(JsonRenderer),
+            OutputFormat::Raw => Box::new(RawRenderer),
+        }
+    }
+}
+
+/// Where rendered text should be written
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Rendered {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Converts events & notes into text for output
+pub(crate) trait Renderer: fmt::Debug + Send {
+    /// Render an event.  `shown` is whether the user's display options
+    /// (`--verbose`, `--quiet`, `--filter`, etc.) call for the event to be
+    /// shown.  Returns `None` if nothing should be output.
+    fn event(&self, event: &Event, shown: bool) -> Option<Rendered>;
+
+    /// Render a message that is not an event, such as a notice that a command
+    /// failed
+    fn note(&self, sigil: char, msg: &str) -> Rendered {
+        Rendered::Stderr(format!("{sigil} {msg}\n"))
+    }
+
+    /// Text to output when the user presses Ctrl-C, if any
+    fn ctrl_c(&self) -> Option<String> {
+        None
+    }
+}
+
+/// The width at which long received lines are wrapped (or truncated), as set
+/// by `--wrap`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Wrap {
+    /// Leave long lines to the terminal
+    #[default]
+    None,
+    /// Use the width of the terminal, checked anew for each line
+    Terminal,
+    /// Use the given number of columns
+    Columns(NonZeroUsize),
+}
+
+impl Wrap {
+    fn width(self) -> Option<usize> {
+        match self {
+            Wrap::None => None,
+            Wrap::Terminal => crossterm::terminal::size()
+                .ok()
+                .map(|(cols, _)| usize::from(cols)),
+            Wrap::Columns(n) => Some(n.get()),
+        }
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = ParseWrapError;
+
+    fn from_str(s: &str) -> Result<Wrap, ParseWrapError> {
+        match s {
+            "none" => Ok(Wrap::None),
+            "terminal" => Ok(Wrap::Terminal),
+            _ => s
+                .parse::<NonZeroUsize>()
+                .map(Wrap::Columns)
+                .map_err(|_| ParseWrapError),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+#[error(r#"expected "none", "terminal", or a positive integer"#)]
+pub(crate) struct ParseWrapError;
+
+/// The default renderer, which shows events as human-readable messages
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ConsoleRenderer {
+    pub(crate) show_times: bool,
+    /// Whether to color the times of received lines by how long after the
+    /// most recent send they arrived
+    pub(crate) latency_colors: bool,
+    /// Whether to pretty-print received lines that are JSON objects or arrays
+    pub(crate) pretty_json: bool,
+    /// The width to fit received lines into
+    pub(crate) wrap: Wrap,
+    /// Whether to truncate received lines that are too wide instead of
+    /// wrapping them
+    pub(crate) truncate: bool,
+    /// How to show escape sequences in received lines
+    pub(crate) ansi: AnsiMode,
+    /// Whether to output events without any colors or text styles, as is
+    /// done when standard output is not a terminal
+    pub(crate) plain: bool,
+}
+
+impl ConsoleRenderer {
+    /// If `--pretty-json` is in effect and `event` is a received line
+    /// containing a JSON object or array, render it pretty-printed over
+    /// multiple lines, with the lines after the first indented to line up
+    /// with it
+    fn render_json(&self, event: &Event) -> Option<String> {
+        let Event::Recv {
+            data, shown: None, ..
+        } = event
+        else {
+            return None;
+        };
+        if !self.pretty_json {
+            return None;
+        }
+        let lines = pretty_json(chomp(data))?;
+        let mut prefix = String::new();
+        let mut width = 2;
+        if self.show_times {
+            let stamp = event.display_stamp(self.latency_colors && !self.plain);
+            width += stamp.content().width() + 1;
+            prefix = format!("{stamp} ");
+        }
+        prefix.push(event.sigil());
+        prefix.push(' ');
+        let indent = " ".repeat(width);
+        let mut out = String::new();
+        for (i, line) in lines.into_iter().enumerate() {
+            out.push_str(if i == 0 { &prefix } else { &indent });
+            for chunk in line {
+                let chunk = if self.plain { unstyle(chunk) } else { chunk };
+                out.push_str(&chunk.to_string());
+            }
+            out.push('\n');
+        }
+        Some(out)
+    }
+}
+
+impl Renderer for ConsoleRenderer {
+    fn event(&self, event: &Event, shown: bool) -> Option<Rendered> {
+        if !shown {
+            return None;
+        }
+        let text = self.render_json(event).unwrap_or_else(|| {
+            let fit = match event {
+                Event::Recv { .. } => self.wrap.width().map(|width| Fit {
+                    width,
+                    truncate: self.truncate,
+                }),
+                _ => None,
+            };
+            format!(
+                "{}\n",
+                event
+                    .to_message(self.show_times)
+                    .latency_colors(self.latency_colors)
+                    .ansi(self.ansi)
+                    .plain(self.plain)
+                    .fit(fit)
+            )
+        });
+        Some(Rendered::Stdout(text))
+    }
+
+    fn note(&self, sigil: char, msg: &str) -> Rendered {
+        if self.show_times {
+            Rendered::Stdout(format!("[{}] {sigil} {msg}\n", now_hms()))
+        } else {
+            Rendered::Stdout(format!("{sigil} {msg}\n"))
+        }
+    }
+
+    fn ctrl_c(&self) -> Option<String> {
+        Some(String::from("^C\n"))
+    }
+}
+
+/// A renderer that outputs every event as a line of JSON.  Received lines
+/// are still subject to `--filter`, `--head`, and `--sample`, but the other
+/// display options are ignored.  Notes are written to standard error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn event(&self, event: &Event, shown: bool) -> Option<Rendered> {
+        if matches!(event, Event::Recv { .. }) && !shown {
+            return None;
+        }
+        Some(Rendered::Stdout(format!("{}\n", event.to_json())))
+    }
+}
+
+/// A renderer that outputs only the text of received lines, unaltered.
+/// Errors and notes are written to standard error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct RawRenderer;
+
+impl Renderer for RawRenderer {
+    fn event(&self, event: &Event, shown: bool) -> Option<Rendered> {
+        match event {
+            Event::Recv { data, .. } => shown.then(|| Rendered::Stdout(data.clone())),
+            Event::Error { .. } => Some(Rendered::Stderr(format!("{}\n", event.to_message(false)))),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ErrorKind, InetError};
+    use crate::events::ClosedBy;
+    use crate::stats::SessionStats;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    fn recv(data: &str) -> Event {
+        Event::recv(String::from(data), Duration::ZERO, false)
+    }
+
+    fn disconnect() -> Event {
+        Event::disconnect(ClosedBy::Peer, &SessionStats::default().snapshot())
+    }
+
+    #[test]
+    fn test_console() {
+        let r = OutputFormat::Console.renderer(ConsoleRenderer::default());
+        assert_eq!(
+            r.event(&recv("Hello\r\n"), true),
+            Some(Rendered::Stdout(String::from("< Hello\n")))
+        );
+        assert_eq!(r.event(&recv("Hello\r\n"), false), None);
+        assert_eq!(
+            r.note('*', "Paste discarded"),
+            Rendered::Stdout(String::from("* Paste discarded\n"))
+        );
+        assert_eq!(r.ctrl_c(), Some(String::from("^C\n")));
+    }
+
+    #[test]
+    fn test_console_wrap() {
+        let r = OutputFormat::Console.renderer(ConsoleRenderer {
+            wrap: Wrap::Columns(NonZeroUsize::new(6).unwrap()),
+            ..ConsoleRenderer::default()
+        });
+        assert_eq!(
+            r.event(&recv("Hello, world\r\n"), true),
+            Some(Rendered::Stdout(String::from("< Hell\n↪ o, w\n↪ orld\n")))
+        );
+        // Only received lines are wrapped
+        let Some(Rendered::Stdout(s)) = r.event(&disconnect(), true) else {
+            panic!("disconnect event not rendered to stdout");
+        };
+        assert!(!s.contains('↪'));
+    }
+
+    #[rstest]
+    #[case("none", Wrap::None)]
+    #[case("terminal", Wrap::Terminal)]
+    #[case("80", Wrap::Columns(NonZeroUsize::new(80).unwrap()))]
+    fn test_parse_wrap(#[case] s: &str, #[case] wrap: Wrap) {
+        assert_eq!(s.parse::<Wrap>(), Ok(wrap));
+    }
+
+    #[rstest]
+    #[case("0")]
+    #[case("auto")]
+    #[case("-1")]
+    fn test_parse_bad_wrap(#[case] s: &str) {
+        assert_eq!(s.parse::<Wrap>(), Err(ParseWrapError));
+    }
+
+    #[test]
+    fn test_console_pretty_json() {
+        let r = OutputFormat::Console.renderer(ConsoleRenderer {
+            pretty_json: true,
+            ..ConsoleRenderer::default()
+        });
+        let Some(Rendered::Stdout(s)) = r.event(&recv("{\"id\": [1]}\n"), true) else {
+            panic!("recv event not rendered to stdout");
+        };
+        let plain = regex::Regex::new("\x1B\\[[0-9;]*m")
+            .unwrap()
+            .replace_all(&s, "");
+        assert_eq!(plain, "< {\n    \"id\": [\n      1\n    ]\n  }\n");
+        assert_eq!(
+            r.event(&recv("Hello\r\n"), true),
+            Some(Rendered::Stdout(String::from("< Hello\n")))
+        );
+    }
+
+    #[test]
+    fn test_console_plain() {
+        let r = OutputFormat::Console.renderer(ConsoleRenderer {
+            pretty_json: true,
+            plain: true,
+            ..ConsoleRenderer::default()
+        });
+        assert_eq!(
+            r.event(&recv("{\"id\": [1]}\n"), true),
+            Some(Rendered::Stdout(String::from(
+                "< {\n    \"id\": [\n      1\n    ]\n  }\n"
+            )))
+        );
+        assert_eq!(
+            r.event(&recv("ding\x07\r\n"), true),
+            Some(Rendered::Stdout(String::from("< ding^G\n")))
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let r = OutputFormat::Json.renderer(ConsoleRenderer::default());
+        let Some(Rendered::Stdout(s)) = r.event(&recv("Hello\r\n"), true) else {
+            panic!("recv event not rendered to stdout");
+        };
+        assert!(s.ends_with(concat!(
+            r#""event": "recv", "elapsed_ms": 0.000, "data": "Hello\r\n"}"#,
+            "\n"
+        )));
+        assert_eq!(r.event(&recv("Hello\r\n"), false), None);
+        assert!(r.event(&disconnect(), false).is_some());
+        assert_eq!(
+            r.note('*', "Paste discarded"),
+            Rendered::Stderr(String::from("* Paste discarded\n"))
+        );
+        assert_eq!(r.ctrl_c(), None);
+    }
+
+    #[test]
+    fn test_raw() {
+        let r = OutputFormat::Raw.renderer(ConsoleRenderer::default());
+        assert_eq!(
+            r.event(&recv("Hello\r\n"), true),
+            Some(Rendered::Stdout(String::from("Hello\r\n")))
+        );
+        assert_eq!(r.event(&recv("Hello\r\n"), false), None);
+        assert_eq!(r.event(&disconnect(), true), None);
+        assert_eq!(
+            r.event(&Event::error(ErrorKind::Recv, &InetError::NoBanner), true),
+            Some(Rendered::Stderr(String::from(
+                "! timed out waiting for banner from server\n"
+            )))
+        );
+    }
+}
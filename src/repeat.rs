@@ -0,0 +1,213 @@
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a response to a line sent by `/repeat` or `--flood`
+/// before counting it as unanswered and moving on
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A line being sent repeatedly by `/repeat` or `--flood`, along with the
+/// time it took the server to respond to each send.  Each send waits for the
+/// response to the previous one (up to `RESPONSE_TIMEOUT`) and for `interval`
+/// to pass since the previous send.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Repeat {
+    line: String,
+    count: NonZeroU32,
+    interval: Duration,
+    /// Whether to end the session once the repetition is over, as is done
+    /// for `--flood`
+    pub(crate) exit_when_done: bool,
+    /// Number of times the line has been sent so far
+    sent: u32,
+    /// When the line was last sent
+    last_sent: Option<Instant>,
+    /// Whether the response to the last send is still awaited
+    awaiting: bool,
+    /// The time between each send and the next received line
+    latencies: Vec<Duration>,
+}
+
+impl Repeat {
+    pub(crate) fn new(
+        line: String,
+        count: NonZeroU32,
+        interval: Duration,
+        exit_when_done: bool,
+    ) -> Repeat {
+        Repeat {
+            line,
+            count,
+            interval,
+            exit_when_done,
+            sent: 0,
+            last_sent: None,
+            awaiting: false,
+            latencies: Vec::new(),
+        }
+    }
+
+    /// Return the time at which [`Repeat::step()`] should next be called
+    pub(crate) fn deadline(&self) -> Instant {
+        match self.last_sent {
+            Some(sent) if self.awaiting => sent + RESPONSE_TIMEOUT.max(self.interval),
+            Some(sent) if self.sent < self.count.get() => sent + self.interval,
+            _ => Instant::now(),
+        }
+    }
+
+    /// Give up on the response to the last send if it's overdue, and return
+    /// the line to send next, if it's time for one
+    pub(crate) fn step(&mut self, now: Instant) -> Option<String> {
+        if self.awaiting
+            && self
+                .last_sent
+                .is_some_and(|sent| now >= sent + RESPONSE_TIMEOUT.max(self.interval))
+        {
+            self.awaiting = false;
+        }
+        if self.awaiting
+            || self.sent >= self.count.get()
+            || self
+                .last_sent
+                .is_some_and(|sent| now < sent + self.interval)
+        {
+            return None;
+        }
+        self.sent += 1;
+        self.last_sent = Some(now);
+        self.awaiting = true;
+        Some(self.line.clone())
+    }
+
+    /// Record the receipt of a line from the server at `now`
+    pub(crate) fn observe_recv(&mut self, now: Instant) {
+        if let Some(sent) = self.last_sent.filter(|_| self.awaiting) {
+            self.latencies.push(now - sent);
+            self.awaiting = false;
+        }
+    }
+
+    /// Returns `true` if the line has been sent the requested number of
+    /// times and the last send has been answered or given up on
+    pub(crate) fn is_done(&self) -> bool {
+        self.sent >= self.count.get() && !self.awaiting
+    }
+
+    /// Summarize the sends made so far
+    pub(crate) fn summary(&self) -> RepeatSummary {
+        RepeatSummary {
+            line: self.line.clone(),
+            sent: self.sent,
+            responses: u32::try_from(self.latencies.len()).unwrap_or(u32::MAX),
+            latency: Latency::from_samples(&self.latencies),
+        }
+    }
+}
+
+/// The results of a `/repeat` or `--flood` run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct RepeatSummary {
+    pub(crate) line: String,
+    pub(crate) sent: u32,
+    /// Number of sends that were followed by a received line before the next
+    /// send
+    pub(crate) responses: u32,
+    /// `None` if there were no responses
+    pub(crate) latency: Option<Latency>,
+}
+
+/// Statistics on the response times measured by `/repeat` or `--flood`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Latency {
+    pub(crate) min: Duration,
+    pub(crate) avg: Duration,
+    /// 95th percentile, by the nearest-rank method
+    pub(crate) p95: Duration,
+}
+
+impl Latency {
+    fn from_samples(samples: &[Duration]) -> Option<Latency> {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let min = *sorted.first()?;
+        let n = sorted.len();
+        let avg = sorted.iter().sum::<Duration>() / u32::try_from(n).unwrap_or(u32::MAX);
+        let p95 = sorted[(n * 95).div_ceil(100) - 1];
+        Some(Latency { min, avg, p95 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_latency() {
+        let samples = (1..=20).rev().map(ms).collect::<Vec<_>>();
+        assert_eq!(
+            Latency::from_samples(&samples),
+            Some(Latency {
+                min: ms(1),
+                avg: Duration::from_micros(10500),
+                p95: ms(19),
+            })
+        );
+        assert_eq!(
+            Latency::from_samples(&[ms(7)]),
+            Some(Latency {
+                min: ms(7),
+                avg: ms(7),
+                p95: ms(7),
+            })
+        );
+        assert_eq!(Latency::from_samples(&[]), None);
+    }
+
+    #[test]
+    fn test_repeat() {
+        let start = Instant::now();
+        let mut repeat = Repeat::new(
+            String::from("PING"),
+            NonZeroU32::new(3).unwrap(),
+            ms(100),
+            false,
+        );
+        assert_eq!(repeat.step(start), Some(String::from("PING")));
+        // Waiting for a response
+        assert_eq!(repeat.deadline(), start + RESPONSE_TIMEOUT);
+        assert_eq!(repeat.step(start + ms(50)), None);
+        repeat.observe_recv(start + ms(20));
+        // A second line before the next send is not a response
+        repeat.observe_recv(start + ms(30));
+        // Waiting for the interval
+        assert_eq!(repeat.deadline(), start + ms(100));
+        assert_eq!(repeat.step(start + ms(50)), None);
+        assert_eq!(repeat.step(start + ms(100)), Some(String::from("PING")));
+        // No response
+        assert_eq!(
+            repeat.step(start + ms(100) + RESPONSE_TIMEOUT),
+            Some(String::from("PING"))
+        );
+        assert!(!repeat.is_done());
+        repeat.observe_recv(start + ms(140) + RESPONSE_TIMEOUT);
+        assert!(repeat.is_done());
+        assert_eq!(repeat.step(start + ms(1000) + RESPONSE_TIMEOUT), None);
+        assert_eq!(
+            repeat.summary(),
+            RepeatSummary {
+                line: String::from("PING"),
+                sent: 3,
+                responses: 2,
+                latency: Some(Latency {
+                    min: ms(20),
+                    avg: ms(30),
+                    p95: ms(40),
+                }),
+            }
+        );
+    }
+}
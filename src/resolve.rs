@@ -0,0 +1,191 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A curl-style `HOST:PORT:ADDR[,ADDR...]` override of the addresses that a
+/// host & port resolve to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ResolveOverride {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) addrs: Vec<IpAddr>,
+}
+
+impl ResolveOverride {
+    /// Returns `true` if the override applies to connections to the given
+    /// host & port
+    pub(crate) fn matches(&self, host: &str, port: u16) -> bool {
+        self.port == port && self.host.eq_ignore_ascii_case(host)
+    }
+}
+
+impl FromStr for ResolveOverride {
+    type Err = ResolveOverrideError;
+
+    fn from_str(s: &str) -> Result<ResolveOverride, ResolveOverrideError> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ResolveOverrideError::Syntax);
+        };
+        if host.is_empty() {
+            return Err(ResolveOverrideError::Syntax);
+        }
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| ResolveOverrideError::Port(port.to_owned()))?;
+        let addrs = addrs
+            .split(',')
+            .map(|a| {
+                // IPv6 addresses may be given in brackets, as in URLs
+                let bare = a
+                    .strip_prefix('[')
+                    .and_then(|a| a.strip_suffix(']'))
+                    .unwrap_or(a);
+                bare.parse::<IpAddr>()
+                    .map_err(|_| ResolveOverrideError::Address(a.to_owned()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ResolveOverride {
+            host: host.to_owned(),
+            port,
+            addrs,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum ResolveOverrideError {
+    #[error(r#"expected "HOST:PORT:ADDR[,ADDR...]""#)]
+    Syntax,
+    #[error("invalid port: {0:?}")]
+    Port(String),
+    #[error("invalid IP address: {0:?}")]
+    Address(String),
+}
+
+/// Read the list of candidate addresses given with `--addr-file`: one
+/// `IP:PORT` per line (with IPv6 addresses in brackets), to be tried in
+/// order.  Blank lines and lines starting with `#` are ignored.
+pub(crate) fn read_addr_file(path: &Path) -> Result<Vec<SocketAddr>, AddrFileError> {
+    parse_addr_list(&std::fs::read_to_string(path).map_err(AddrFileError::Read)?)
+}
+
+fn parse_addr_list(s: &str) -> Result<Vec<SocketAddr>, AddrFileError> {
+    let addrs = s
+        .lines()
+        .enumerate()
+        .map(|(i, ln)| (i + 1, ln.trim()))
+        .filter(|&(_, ln)| !ln.is_empty() && !ln.starts_with('#'))
+        .map(|(lineno, ln)| {
+            ln.parse::<SocketAddr>()
+                .map_err(|_| AddrFileError::Address {
+                    lineno,
+                    text: ln.to_owned(),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if addrs.is_empty() {
+        Err(AddrFileError::Empty)
+    } else {
+        Ok(addrs)
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum AddrFileError {
+    #[error("failed to read file")]
+    Read(#[source] io::Error),
+    #[error("invalid address on line {lineno}: {text:?}; expected IP:PORT")]
+    Address { lineno: usize, text: String },
+    #[error("file does not contain any addresses")]
+    Empty,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[rstest]
+    #[case("example.com:80:127.0.0.1", "example.com", 80, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])]
+    #[case(
+        "example.com:443:[::1],10.0.0.1",
+        "example.com",
+        443,
+        vec![IpAddr::V6(Ipv6Addr::LOCALHOST), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))],
+    )]
+    #[case("localhost:25:::1", "localhost", 25, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)])]
+    fn test_parse(
+        #[case] s: &str,
+        #[case] host: &str,
+        #[case] port: u16,
+        #[case] addrs: Vec<IpAddr>,
+    ) {
+        assert_eq!(
+            s.parse::<ResolveOverride>(),
+            Ok(ResolveOverride {
+                host: host.into(),
+                port,
+                addrs
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("example.com", ResolveOverrideError::Syntax)]
+    #[case("example.com:80", ResolveOverrideError::Syntax)]
+    #[case(":80:127.0.0.1", ResolveOverrideError::Syntax)]
+    #[case("example.com:http:127.0.0.1", ResolveOverrideError::Port("http".into()))]
+    #[case("example.com:80:localhost", ResolveOverrideError::Address("localhost".into()))]
+    #[case(
+        "example.com:80:127.0.0.1,",
+        ResolveOverrideError::Address(String::new())
+    )]
+    fn test_parse_bad(#[case] s: &str, #[case] err: ResolveOverrideError) {
+        assert_eq!(s.parse::<ResolveOverride>(), Err(err));
+    }
+
+    #[test]
+    fn test_parse_addr_list() {
+        let addrs = parse_addr_list(
+            "# primary\n10.0.0.1:5432\n\n  [2001:db8::1]:5432\n# standby\n10.0.0.2:5433\n",
+        )
+        .unwrap();
+        assert_eq!(
+            addrs,
+            [
+                SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 5432)),
+                SocketAddr::from(("2001:db8::1".parse::<Ipv6Addr>().unwrap(), 5432)),
+                SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 5433)),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "10.0.0.1:5432\ndb.example.com:5432\n",
+        "invalid address on line 2: \"db.example.com:5432\"; expected IP:PORT"
+    )]
+    #[case(
+        "10.0.0.1\n",
+        "invalid address on line 1: \"10.0.0.1\"; expected IP:PORT"
+    )]
+    #[case("# nothing here\n\n", "file does not contain any addresses")]
+    fn test_parse_bad_addr_list(#[case] s: &str, #[case] msg: &str) {
+        assert_eq!(parse_addr_list(s).unwrap_err().to_string(), msg);
+    }
+
+    #[test]
+    fn test_matches() {
+        let ovr = "Example.COM:80:127.0.0.1"
+            .parse::<ResolveOverride>()
+            .unwrap();
+        assert!(ovr.matches("example.com", 80));
+        assert!(!ovr.matches("example.com", 443));
+        assert!(!ovr.matches("example.org", 80));
+    }
+}
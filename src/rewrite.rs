@@ -0,0 +1,163 @@
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A substitution applied to received lines before display, given with
+/// `--rewrite s/REGEX/REPLACEMENT/[FLAGS]`
+#[derive(Clone, Debug)]
+pub(crate) struct Rewrite {
+    regex: Regex,
+    /// Replacement text, in which `$1`, `${name}`, etc. refer to capture
+    /// groups
+    replacement: String,
+    /// Whether to replace every match rather than just the first
+    global: bool,
+}
+
+impl Rewrite {
+    /// Apply the substitution to `text`.  Returns `Cow::Borrowed` if nothing
+    /// matched.
+    pub(crate) fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.global {
+            self.regex.replace_all(text, &self.replacement)
+        } else {
+            self.regex.replace(text, &self.replacement)
+        }
+    }
+}
+
+/// Apply each of a sequence of substitutions in turn.  Returns
+/// `Cow::Borrowed` if none of them matched.
+pub(crate) fn apply_all<'a>(rewrites: &[Rewrite], text: &'a str) -> Cow<'a, str> {
+    let mut out = Cow::Borrowed(text);
+    for rw in rewrites {
+        if let Cow::Owned(s) = rw.apply(&out) {
+            out = Cow::Owned(s);
+        }
+    }
+    out
+}
+
+impl FromStr for Rewrite {
+    type Err = ParseRewriteError;
+
+    fn from_str(s: &str) -> Result<Rewrite, ParseRewriteError> {
+        // As in sed, any character may be used as the delimiter
+        let mut chars = s.chars();
+        let (Some('s'), Some(delim)) = (chars.next(), chars.next()) else {
+            return Err(ParseRewriteError::Syntax);
+        };
+        if delim == '\\' || delim.is_alphanumeric() || delim.is_whitespace() {
+            return Err(ParseRewriteError::Syntax);
+        }
+        let [pattern, replacement, flags] =
+            <[String; 3]>::try_from(split_unescaped(chars.as_str(), delim))
+                .map_err(|_| ParseRewriteError::Syntax)?;
+        let mut global = false;
+        let mut case_insensitive = false;
+        for c in flags.chars() {
+            match c {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                c => return Err(ParseRewriteError::Flag(c)),
+            }
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()?;
+        Ok(Rewrite {
+            regex,
+            replacement,
+            global,
+        })
+    }
+}
+
+/// Split `s` at each occurrence of `delim` that is not preceded by a
+/// backslash.  Backslash-escaped delimiters become literal delimiters; all
+/// other backslashes are kept for the regex engine.
+fn split_unescaped(s: &str, delim: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts should always be nonempty");
+        if c == '\\' {
+            match chars.next() {
+                Some(d) if d == delim => part.push(d),
+                Some(d) => {
+                    part.push('\\');
+                    part.push(d);
+                }
+                None => part.push('\\'),
+            }
+        } else if c == delim {
+            parts.push(String::new());
+        } else {
+            part.push(c);
+        }
+    }
+    parts
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub(crate) enum ParseRewriteError {
+    #[error("expected s/REGEX/REPLACEMENT/[FLAGS]")]
+    Syntax,
+    #[error("unknown flag {0:?}; expected 'g' or 'i'")]
+    Flag(char),
+    #[error("invalid regex")]
+    Regex(#[from] regex::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("s/foo/bar/", "foo foo", "bar foo")]
+    #[case("s/foo/bar/g", "foo foo", "bar bar")]
+    #[case("s/FOO/bar/gi", "foo Foo", "bar bar")]
+    #[case(r"s/\x1B\[[0-9;]*m//g", "\x1B[1;31mError\x1B[0m", "Error")]
+    #[case(r"s/token=(\w+)/token=<${1}>/", "token=abc123", "token=<abc123>")]
+    #[case(r"s/a\/b/a|b/", "a/b", "a|b")]
+    #[case("s|/usr/local|~|", "/usr/local/bin", "~/bin")]
+    #[case("s/x/y/", "nothing here", "nothing here")]
+    fn test_rewrite(#[case] spec: &str, #[case] text: &str, #[case] out: &str) {
+        let rw = spec.parse::<Rewrite>().unwrap();
+        assert_eq!(rw.apply(text), out);
+    }
+
+    #[rstest]
+    #[case("foo", ParseRewriteError::Syntax)]
+    #[case("s/foo/bar", ParseRewriteError::Syntax)]
+    #[case("s/foo/bar/g/", ParseRewriteError::Syntax)]
+    #[case("sxfooxbarx", ParseRewriteError::Syntax)]
+    #[case("s/foo/bar/q", ParseRewriteError::Flag('q'))]
+    fn test_bad_rewrite(#[case] spec: &str, #[case] err: ParseRewriteError) {
+        assert_eq!(spec.parse::<Rewrite>().unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_bad_regex() {
+        assert!(matches!(
+            "s/(foo/bar/".parse::<Rewrite>(),
+            Err(ParseRewriteError::Regex(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_all() {
+        let rewrites = ["s/secret/******/g", "s/^/[redacted] /"]
+            .into_iter()
+            .map(|s| s.parse::<Rewrite>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            apply_all(&rewrites, "secret is secret"),
+            "[redacted] ****** is ******"
+        );
+        let rewrites = &rewrites[..1];
+        assert!(matches!(apply_all(rewrites, "public"), Cow::Borrowed(_)));
+    }
+}
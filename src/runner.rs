@@ -1,84 +1,1508 @@
-use crate::codec::ConfabCodec;
-use crate::errors::{InetError, InterfaceError, IoError};
-use crate::events::Event;
-use crate::input::{readline_stream, Input, StartupScript};
+use crate::assertion::Assertions;
+use crate::capture::Variables;
+use crate::cast::CastFile;
+use crate::codec::{ConfabCodec, Framing, LineEnding, RecvLine, SendEnding};
+use crate::commands::{Command, SearchPattern, Setting};
+use crate::complete::{Completer, TokenIndex};
+use crate::display::{burst_message, BurstQuiet, BurstState, RecvSampler};
+use crate::editor::{Editor, SharedWriter};
+use crate::errors::{
+    error_chain, InetError, InterfaceError, IoError, BUDGET_EXCEEDED_EXIT, SUCCESS_EXIT,
+};
+use crate::escapes::unescape;
+use crate::events::{ClosedBy, ConnectTiming, Event, OptionValue, SocketOptions};
+use crate::exec::ChildPipes;
+use crate::hook::{EventCommands, Hook};
+#[cfg(unix)]
+use crate::inherit::InheritedSocket;
+use crate::input::{
+    interrupt_stream, readline_stream, stdin_stream, CtrlCMode, EmptyLineMode, IdleScript, Input,
+    PasteMode, SendQueue, StartupScript, PROMPT,
+};
+use crate::keymap::KeyMap;
+use crate::memory::MemoryBudget;
+use crate::metrics::Metrics;
+#[cfg(feature = "notifications")]
+use crate::notification::Notifier;
+#[cfg(feature = "otel")]
+use crate::otel::TraceRecorder;
+use crate::protocol::ProtocolLayer;
+use crate::recorder::{TranscriptSeries, TranscriptWriter};
+use crate::render::{Rendered, Renderer};
+use crate::repeat::Repeat;
+use crate::resolve::ResolveOverride;
+use crate::rewrite::{apply_all, Rewrite};
+#[cfg(feature = "scripting")]
+use crate::script::{Action, Script, ScriptError};
+use crate::scrollback::Scrollback;
+#[cfg(feature = "serial")]
+use crate::serial::SerialSpec;
+use crate::session::{SavedTarget, Session, SessionError};
+use crate::sniff::Sniffer;
+use crate::stats::{Budget, Direction, SessionStats, ThroughputMeter, THROUGHPUT_WINDOW};
+use crate::status::{StatusLine, STATUS_INTERVAL};
+use crate::summary::{ExitReason, Summary};
+use crate::syslog::SyslogWrapper;
+use crate::template::Templates;
+use crate::term::{Sink, TermWriter};
+use crate::title::{SessionState, WindowTitle};
 use crate::tls;
-use crate::util::{now_hms, CharEncoding};
-use futures_util::{SinkExt, Stream, StreamExt};
-use rustyline_async::{Readline, SharedWriter};
+#[cfg(feature = "rustls")]
+use crate::tofu::{TofuStore, Trust};
+use crate::transport::{boxed, BoxedTransport};
+use crate::util::{chomp, now, CharEncoding};
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use regex::Regex;
+use socket2::{SockRef, TcpKeepalive};
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::{self, Write};
-use std::num::NonZeroUsize;
+use std::mem;
+use std::net::SocketAddr;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::process::ExitCode;
-use tokio::net::TcpStream;
-use tokio_util::{codec::Framed, either::Either};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::time::{interval, interval_at, sleep, sleep_until, timeout, MissedTickBehavior};
+use tokio_util::codec::Framed;
 
-type Connection = Framed<Either<TcpStream, tls::TlsStream>, ConfabCodec>;
+type Connection = Framed<BoxedTransport, ConfabCodec>;
+
+/// How often to flush the line editor while waiting for the terminal writer to
+/// finish at the end of a session
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Message shown when input that needs a connection is entered after
+/// `/disconnect`
+const NOT_CONNECTED: &str = "Not connected";
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum ConnectState {
     Open,
+    /// The remote end closed the connection
     Closed,
+    /// The session's byte budget for the given direction was exhausted
+    OverBudget(Direction),
+    /// The user closed the connection with `/disconnect`
+    Disconnected,
+    /// The user asked to connect again with `/connect`
+    Reconnect,
+    /// A `--flood` run sent all of its lines
+    Done,
 }
 
 pub(crate) struct Runner {
     pub(crate) startup_script: Option<StartupScript>,
+    /// Lines to send whenever the connection has been idle for a while
+    pub(crate) idle_script: Option<IdleScript>,
+    pub(crate) banner: Option<BannerWait>,
+    /// Lines to send in place of an interactive session, given via `--send`
+    pub(crate) request: Option<Request>,
+    /// Line to send repeatedly in place of an interactive session, given via
+    /// `--flood`
+    pub(crate) flood: Option<Repeat>,
+    /// The `/repeat` or `--flood` run in progress, if any
+    pub(crate) repeat: Option<Repeat>,
+    /// Whether to read input from stdin a line at a time without a line
+    /// editor, as is done when stdout is not a terminal or `--stdin-pipe` is
+    /// given
+    pub(crate) batch: bool,
+    /// Whether to read no input at all and only display what is received, as
+    /// is done under `--receive-only`
+    pub(crate) receive_only: bool,
+    pub(crate) budget: Budget,
+    /// Length in bytes (once encoded & terminated) of the longest line that
+    /// can be sent without a warning, if `--max-send-length` was given
+    pub(crate) max_send_length: Option<NonZeroUsize>,
+    /// Whether to refuse to send lines longer than `max_send_length` instead
+    /// of only warning about them
+    pub(crate) strict_send_length: bool,
+    /// Whether to record the exact bytes of each line sent & received in the
+    /// transcript, as is done under `--transcript-raw-bytes`
+    pub(crate) raw_bytes: bool,
+    pub(crate) stats: SessionStats,
+    /// Checks the first bytes received for signs of a binary protocol, unless
+    /// TLS or binary framing is in use or `--sniff-bytes 0` was given
+    pub(crate) sniffer: Option<Sniffer>,
+    /// How long to wait after connecting for the server to send something
+    /// before warning that it hasn't, if `--banner-warning` was given.  This
+    /// is cleared once anything is received or the warning is shown.
+    pub(crate) banner_warning: Option<Duration>,
+    /// Patterns that received lines must match, if `--assert-recv` was given
+    pub(crate) assertions: Option<Assertions>,
+    /// Memory budget shared by `queue` and the reporter's terminal writer
+    pub(crate) memory: MemoryBudget,
+    pub(crate) paste_mode: PasteMode,
+    pub(crate) empty_line_mode: EmptyLineMode,
+    /// Key bindings for the line editor
+    pub(crate) keymap: KeyMap,
+    /// Whether to expand escape sequences in lines of input
+    pub(crate) escapes: bool,
+    /// Protocol-specific handling of sent & received lines, if `--protocol`
+    /// was given
+    pub(crate) protocol: Option<ProtocolLayer>,
+    /// Syslog headers to wrap sent lines in, if `--syslog-format` was given
+    pub(crate) syslog: Option<SyslogWrapper>,
+    /// Values captured from received lines by `--capture`, for expanding in
+    /// sent lines
+    pub(crate) variables: Variables,
+    /// Expander of `%{...}` templates in sent lines, if `--expand-sends` was
+    /// given
+    pub(crate) templates: Option<Templates>,
+    /// The named session whose state is restored & saved, if `--session` was
+    /// given
+    pub(crate) session: Option<Session>,
+    /// Automation script given with `--script`
+    #[cfg(feature = "scripting")]
+    pub(crate) script: Option<Script>,
+    /// Desktop notifications for received lines, if `--notify-on` was given
+    #[cfg(feature = "notifications")]
+    pub(crate) notifier: Option<Notifier>,
+    pub(crate) ctrl_c: CtrlCMode,
+    /// Text sent to the server when Ctrl-C is pressed under
+    /// `--ctrl-c send-break` or when `/break` is entered
+    pub(crate) break_sequence: String,
+    /// Lines waiting to be sent after a delay
+    pub(crate) queue: SendQueue,
+    /// Whether to shut down the write side of the connection instead of
+    /// disconnecting when the end of input is reached
+    pub(crate) eof_on_stdin_close: bool,
+    /// Whether the write side of the connection has been shut down
+    pub(crate) write_closed: bool,
+    /// Whether to show a status line below the prompt in interactive mode
+    pub(crate) status_line: bool,
+    /// The status line, while it is being shown
+    pub(crate) status: Option<StatusLine>,
+    /// The terminal's window title, if `--set-title` was given and stdout is
+    /// a terminal
+    pub(crate) title: Option<WindowTitle>,
+    /// Lines entered since `/compose`, while composing
+    pub(crate) compose: Option<Vec<String>>,
+    /// Recent traffic readings for computing transfer rates, if
+    /// `--show-throughput` was given
+    pub(crate) throughput: Option<ThroughputMeter>,
+    /// Whether to use the full-screen interface in interactive mode
+    pub(crate) tui: bool,
+    /// The options in effect, as recorded in the "session-start" event
+    pub(crate) options: Vec<(String, OptionValue)>,
+    /// The line ending that was in effect before `/set crlf on`, to restore
+    /// on `/set crlf off`
+    pub(crate) ending_before_crlf: Option<SendEnding>,
     pub(crate) reporter: Reporter,
     pub(crate) connector: Connector,
 }
 
 impl Runner {
     pub(crate) async fn run(mut self) -> Result<ExitCode, InterfaceError> {
-        match self.try_run().await {
-            Ok(()) => Ok(ExitCode::SUCCESS),
-            Err(IoError::Interface(e)) => Err(e),
+        self.restore_settings();
+        let r = self.run_inner().await;
+        self.set_title(SessionState::Closed)?;
+        self.save_session()?;
+        self.reporter.close_transcript().await?;
+        #[cfg(feature = "otel")]
+        self.reporter.export_trace().await?;
+        let code = match r {
+            Ok(code) => code,
+            Err(ref e) => e.exit_code(),
+        };
+        self.reporter.write_summary(code)?;
+        self.reporter
+            .writer
+            .close()
+            .await
+            .map_err(InterfaceError::Write)?;
+        r.map(ExitCode::from)
+    }
+
+    /// Run the session and return the exit status to use
+    async fn run_inner(&mut self) -> Result<u8, InterfaceError> {
+        let r = self.try_run().await;
+        match r.and_then(|cs| self.check_assertions(cs)) {
+            Ok(ConnectState::OverBudget(_)) => Ok(BUDGET_EXCEEDED_EXIT),
+            Ok(_) => Ok(SUCCESS_EXIT),
+            Err(IoError::Interface(e)) => {
+                let event = Event::error(e.kind(), &e);
+                if let Some(summary) = self.reporter.summary.as_mut() {
+                    summary.observe(&event);
+                }
+                if !e.is_broken_pipe() {
+                    // The terminal may be unusable, so only record the error
+                    // in the transcript; it is displayed by `main()`.
+                    self.reporter.record(&event);
+                }
+                Err(e)
+            }
             Err(IoError::Inet(e)) => {
-                self.reporter.report(Event::error(anyhow::Error::new(e)))?;
-                Ok(ExitCode::FAILURE)
+                if let InetError::Decode(ref de) = e {
+                    self.reporter.report(Event::encoding_error(de))?;
+                }
+                self.reporter.report(Event::error(e.kind(), &e))?;
+                Ok(e.exit_code())
             }
         }
     }
 
-    async fn try_run(&mut self) -> Result<(), IoError> {
-        let mut frame = self.connector.connect(&mut self.reporter).await?;
+    async fn try_run(&mut self) -> Result<ConnectState, IoError> {
+        self.reporter.report(Event::session_start(
+            &self.connector.host,
+            self.connector.port,
+            mem::take(&mut self.options),
+        ))?;
+        self.set_title(SessionState::Connecting)?;
+        self.reporter
+            .start_transcript(&self.connector.host, self.connector.port)?;
+        let mut frame = self
+            .connector
+            .connect_with_retries(&mut self.reporter)
+            .await?;
+        self.set_title(SessionState::Connected)?;
+        self.stats.started = Some(Instant::now());
+        if let Some(meter) = self.throughput.as_mut() {
+            *meter = ThroughputMeter::default();
+            meter.sample(Instant::now(), &self.stats);
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = self.script.as_mut() {
+            let r = script.on_connect();
+            self.report_script_error(r)?;
+        }
+        if let Some(banner) = self.banner.take() {
+            let cs = self.wait_for_banner(&mut frame, &banner).await?;
+            if cs != ConnectState::Open {
+                return self.end_session(cs);
+            }
+        }
+        if let Some(request) = self.request.take() {
+            let cs = self.single_shot(&mut frame, request).await?;
+            return self.end_session(cs);
+        }
         if let Some(script) = self.startup_script.take() {
-            let cs = ioloop(&mut frame, script, &mut self.reporter).await?;
-            if cs == ConnectState::Closed {
-                self.reporter.report(Event::disconnect())?;
-                return Ok(());
+            let cs = self.ioloop(&mut frame, script).await?;
+            if cs != ConnectState::Open {
+                return self.end_session(cs);
+            }
+        }
+        if let Some(flood) = self.flood.take() {
+            self.repeat = Some(flood);
+            let cs = self.ioloop(&mut frame, interrupt_stream()).await?;
+            return self.end_session(cs);
+        }
+        if self.receive_only {
+            let cs = self.ioloop(&mut frame, interrupt_stream()).await?;
+            return self.end_session(cs);
+        }
+        if self.batch {
+            let cs = self.ioloop(&mut frame, stdin_stream()).await?;
+            // There's no more input to read, so just wait for the server.
+            let cs = self.half_close(&mut frame, cs, stream::pending()).await?;
+            return self.end_session(cs);
+        }
+        // Make sure everything reported so far has been printed before the
+        // terminal is put into raw mode.
+        self.reporter
+            .writer
+            .sync()
+            .await
+            .map_err(InterfaceError::Write)?;
+        if self.status_line {
+            let endpoint = format!("{}:{}", self.connector.host, self.connector.port);
+            match StatusLine::install(endpoint) {
+                Ok(status) => self.status = Some(status),
+                Err(e) => self.reporter.warn_status(&e)?,
+            }
+        }
+        let tokens = TokenIndex::default();
+        self.reporter.tokens = Some(tokens.clone());
+        let (mut rl, shared) = init_editor(self.keymap.clone(), Completer::new(tokens), self.tui)?;
+        if let Some(session) = self.session.as_ref() {
+            for entry in &session.state.history {
+                rl.add_history_entry(entry.clone());
             }
         }
-        let (mut rl, shared) = init_readline()?;
         // Lines written to the SharedWriter are only output when
-        // Readline::readline() or Readline::flush() is called, so anything
+        // Editor::readline() or Editor::flush() is called, so anything
         // written before we start getting input from the user should be
         // written directly to stdout instead.
-        self.reporter.set_writer(Box::new(shared));
-        let r = ioloop(&mut frame, readline_stream(&mut rl), &mut self.reporter)
-            .await
-            .map_err(IoError::from)
-            .and_then(|_| {
-                self.reporter
-                    .report(Event::disconnect())
-                    .map_err(IoError::from)
-            });
-        let _ = rl.flush();
+        self.reporter.set_writer(Box::new(shared))?;
+        // `/disconnect` & `/connect` close and replace the connection while
+        // the line editor, scrollback, and transcript carry on.
+        let mut current = Some(frame);
+        let r = loop {
+            let r = match current.as_mut() {
+                Some(frame) => match self
+                    .ioloop(frame, readline_stream(&mut rl, self.escapes))
+                    .await
+                {
+                    Ok(cs) => {
+                        self.half_close(frame, cs, readline_stream(&mut rl, self.escapes))
+                            .await
+                    }
+                    Err(e) => Err(e),
+                }
+                .and_then(|cs| self.end_session(cs)),
+                None => self.offline(readline_stream(&mut rl, self.escapes)).await,
+            };
+            match r {
+                Ok(ConnectState::Disconnected) => current = None,
+                Ok(ConnectState::Reconnect) => {
+                    current = None;
+                    match self.reconnect().await {
+                        Ok(frame) => current = Some(frame),
+                        Err(IoError::Inet(e)) => {
+                            self.set_title(SessionState::Closed)?;
+                            self.reporter.report(Event::error(e.kind(), &e))?;
+                        }
+                        Err(e) => break Err(e),
+                    }
+                }
+                r => break r,
+            }
+        };
         // Set the writer back to stdout so that errors reported by run() will
         // show up without having to call rl.flush().
-        self.reporter.set_writer(Box::new(io::stdout()));
+        self.reporter.set_writer(Box::new(tokio::io::stdout()))?;
+        // Keep flushing the Editor until everything written to the
+        // SharedWriter has been printed.
+        while self.reporter.writer.pending() {
+            let _ = rl.flush();
+            sleep(DRAIN_INTERVAL).await;
+        }
+        let _ = rl.flush();
+        if let Some(status) = self.status.take() {
+            let _ = status.remove();
+        }
+        if let Some(session) = self.session.as_mut() {
+            session.state.history = rl.history();
+        }
         r
     }
+
+    /// Connect to the connector's current target after `/connect`, starting
+    /// the session's statistics afresh
+    async fn reconnect(&mut self) -> Result<Connection, IoError> {
+        let endpoint = format!("{}:{}", self.connector.host, self.connector.port);
+        if let Some(title) = self.title.as_mut() {
+            title.set_endpoint(endpoint.clone());
+        }
+        self.set_title(SessionState::Reconnecting)?;
+        self.reporter
+            .start_transcript(&self.connector.host, self.connector.port)?;
+        let frame = self
+            .connector
+            .connect_with_retries(&mut self.reporter)
+            .await?;
+        self.set_title(SessionState::Connected)?;
+        self.stats = SessionStats {
+            started: Some(Instant::now()),
+            ..SessionStats::default()
+        };
+        self.write_closed = false;
+        if let Some(meter) = self.throughput.as_mut() {
+            *meter = ThroughputMeter::default();
+            meter.sample(Instant::now(), &self.stats);
+        }
+        #[cfg(feature = "notifications")]
+        if let Some(notifier) = self.notifier.as_mut() {
+            notifier.set_endpoint(endpoint.clone());
+        }
+        if let Some(status) = self.status.as_mut() {
+            status.set_endpoint(endpoint);
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = self.script.as_mut() {
+            let r = script.on_connect();
+            self.report_script_error(r)?;
+        }
+        Ok(frame)
+    }
+
+    /// Process input while there is no connection after `/disconnect` or a
+    /// failed `/connect`.  Only commands can be run; lines entered are not
+    /// sent anywhere, and any lines already in the send queue stay there
+    /// until the next connection.  Returns `ConnectState::Reconnect` if the
+    /// user entered `/connect` or `ConnectState::Closed` if the user quit.
+    async fn offline<S>(&mut self, input: S) -> Result<ConnectState, IoError>
+    where
+        S: Stream<Item = Result<Input, InterfaceError>> + Send,
+    {
+        tokio::pin!(input);
+        loop {
+            let cs = tokio::select! {
+                r = input.next() => match r {
+                    Some(Ok(input)) if self.compose.is_some() => {
+                        if self.compose_input(input)?.is_some() {
+                            self.reporter
+                                .notify(&format!("{NOT_CONNECTED}; composed lines not sent"))?;
+                        }
+                        ConnectState::Disconnected
+                    }
+                    Some(Ok(Input::Line(_) | Input::Paste(_) | Input::ComposeEnd)) => {
+                        self.reporter
+                            .notify(&format!("{NOT_CONNECTED}; use /connect to connect again"))?;
+                        ConnectState::Disconnected
+                    }
+                    Some(Ok(Input::Command(Ok(cmd)))) => self.run_command(None, cmd).await?,
+                    Some(Ok(Input::Command(Err(e)))) => {
+                        self.reporter.notify(&e.to_string())?;
+                        ConnectState::Disconnected
+                    }
+                    Some(Ok(Input::CtrlC)) => {
+                        self.reporter.echo_ctrlc()?;
+                        return Ok(ConnectState::Closed);
+                    }
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Ok(ConnectState::Closed),
+                },
+                e = self.reporter.writer.failed() => {
+                    return Err(IoError::Interface(InterfaceError::Write(e)));
+                }
+            };
+            if cs == ConnectState::Reconnect {
+                return Ok(cs);
+            }
+        }
+    }
+
+    fn end_session(&mut self, cs: ConnectState) -> Result<ConnectState, IoError> {
+        self.set_title(SessionState::Closed)?;
+        if let ConnectState::OverBudget(direction) = cs {
+            let limit = self.budget.limit(direction).unwrap_or_default();
+            self.reporter
+                .report(Event::budget_exceeded(direction, limit))?;
+        }
+        self.reporter.end_burst(None)?;
+        let hidden = self.reporter.sampler.hidden();
+        if hidden > 0 {
+            self.reporter.report(Event::lines_hidden(hidden))?;
+        }
+        let evicted = self.memory.evictions();
+        if evicted > 0 {
+            self.reporter.report(Event::memory_evicted(evicted))?;
+        }
+        if let Some(repeat) = self.repeat.take() {
+            self.reporter
+                .report(Event::repeat_summary(repeat.summary()))?;
+        }
+        let snapshot = self.stats.snapshot();
+        self.reporter.report(Event::session_stats(snapshot))?;
+        let closed_by = if cs == ConnectState::Closed && !self.write_closed {
+            ClosedBy::Peer
+        } else {
+            ClosedBy::Local
+        };
+        self.reporter
+            .report(Event::disconnect(closed_by, &snapshot))?;
+        Ok(cs)
+    }
+
+    async fn wait_for_banner(
+        &mut self,
+        frame: &mut Connection,
+        banner: &BannerWait,
+    ) -> Result<ConnectState, IoError> {
+        let r = timeout(banner.timeout, async {
+            while let Some(r) = frame.next().await {
+                let line = r.map_err(InetError::from)?;
+                let matched = banner
+                    .pattern
+                    .as_ref()
+                    .map_or(true, |rgx| rgx.is_match(chomp(&line.text)));
+                let cs = self.recv(line).await?;
+                if cs != ConnectState::Open || matched {
+                    return Ok(cs);
+                }
+            }
+            Ok(ConnectState::Closed)
+        })
+        .await;
+        r.unwrap_or(Err(IoError::Inet(InetError::NoBanner)))
+    }
+
+    /// Send the lines of `request` and then wait for the response it
+    /// describes
+    async fn single_shot(
+        &mut self,
+        frame: &mut Connection,
+        request: Request,
+    ) -> Result<ConnectState, IoError> {
+        for line in request.lines {
+            let cs = self.send_line(frame, line).await?;
+            if cs != ConnectState::Open {
+                return Ok(cs);
+            }
+        }
+        let (pattern, mut remaining) = match request.response {
+            ResponseEnd::Immediate => (None, 0),
+            ResponseEnd::Lines(n) => (None, n.get()),
+            ResponseEnd::Match(rgx) => (Some(rgx), 0),
+        };
+        let mut done = pattern.is_none() && remaining == 0;
+        // Under `--assert-recv`, keep reading after the response is complete
+        // until all of the assertions have passed.
+        while !done || self.assertion_deadline().is_some() {
+            let assert_at = self.assertion_deadline();
+            let r = tokio::select! {
+                r = frame.next() => r,
+                () = sleep_until(assert_at.unwrap_or_else(Instant::now).into()), if assert_at.is_some() => {
+                    return Err(self.assertion_timeout());
+                }
+            };
+            let Some(r) = r else {
+                // If any assertions are still unmet, that is reported once
+                // the session is over.
+                return if done {
+                    Ok(ConnectState::Closed)
+                } else {
+                    Err(IoError::Inet(InetError::NoResponse))
+                };
+            };
+            let line = r.map_err(InetError::from)?;
+            if !done {
+                done = if let Some(rgx) = pattern.as_ref() {
+                    rgx.is_match(chomp(&line.text))
+                } else {
+                    remaining -= 1;
+                    remaining == 0
+                };
+            }
+            let cs = self.recv(line).await?;
+            if cs != ConnectState::Open {
+                return Ok(cs);
+            }
+        }
+        Ok(ConnectState::Open)
+    }
+
+    /// Return the time by which the remaining `--assert-recv` patterns must
+    /// be matched, if any remain
+    fn assertion_deadline(&self) -> Option<Instant> {
+        self.assertions
+            .as_ref()
+            .filter(|asserts| !asserts.is_satisfied())
+            .zip(self.stats.started)
+            .map(|(asserts, started)| started + asserts.timeout)
+    }
+
+    /// Return the error for the next `--assert-recv` pattern not being
+    /// matched before the deadline
+    fn assertion_timeout(&self) -> IoError {
+        let pattern = self
+            .assertions
+            .as_ref()
+            .and_then(Assertions::next_pattern)
+            .unwrap_or_default();
+        IoError::Inet(InetError::AssertTimeout(pattern.to_owned()))
+    }
+
+    /// Once the session is over, fail if any `--assert-recv` patterns were
+    /// never matched
+    fn check_assertions(&self, cs: ConnectState) -> Result<ConnectState, IoError> {
+        match self.assertions.as_ref().and_then(Assertions::next_pattern) {
+            Some(pattern) => Err(IoError::Inet(InetError::AssertUnmet(pattern.to_owned()))),
+            None => Ok(cs),
+        }
+    }
+
+    /// If `--eof-on-stdin-close` was given and the end of input was reached
+    /// (indicated by `cs` being `ConnectState::Open`), shut down the write
+    /// side of the connection and continue processing `input` until the
+    /// server closes the connection or `input` ends again.
+    async fn half_close<S>(
+        &mut self,
+        frame: &mut Connection,
+        cs: ConnectState,
+        input: S,
+    ) -> Result<ConnectState, IoError>
+    where
+        S: Stream<Item = Result<Input, InterfaceError>> + Send,
+    {
+        if cs != ConnectState::Open || !self.eof_on_stdin_close || self.write_closed {
+            return Ok(cs);
+        }
+        self.shutdown_write(frame).await?;
+        self.ioloop(frame, input).await
+    }
+
+    /// Shut down the write side of the connection, sending a FIN (or, for
+    /// TLS, a `close_notify` alert followed by a FIN) to the server
+    async fn shutdown_write(&mut self, frame: &mut Connection) -> Result<(), IoError> {
+        if self.write_closed {
+            self.reporter
+                .notify("Connection is already shut down for writing")?;
+            return Ok(());
+        }
+        frame
+            .get_mut()
+            .shutdown()
+            .await
+            .map_err(InetError::Shutdown)?;
+        self.write_closed = true;
+        self.reporter.report(Event::shutdown_write())?;
+        Ok(())
+    }
+
+    async fn ioloop<S>(&mut self, frame: &mut Connection, input: S) -> Result<ConnectState, IoError>
+    where
+        S: Stream<Item = Result<Input, InterfaceError>> + Send,
+    {
+        tokio::pin!(input);
+        let mut unconfirmed: Option<Unconfirmed> = None;
+        let mut ticker = interval(STATUS_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut throughput_ticker = interval_at(
+            (Instant::now() + THROUGHPUT_WINDOW).into(),
+            THROUGHPUT_WINDOW,
+        );
+        throughput_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            #[cfg(feature = "scripting")]
+            {
+                let cs = self.perform_script_actions(frame).await?;
+                if cs != ConnectState::Open {
+                    return Ok(cs);
+                }
+            }
+            let script_at = self.script_deadline();
+            // The idle script isn't run again while lines from it (or
+            // anything else) are still waiting to be sent.
+            let idle_at = self
+                .idle_script
+                .as_ref()
+                .filter(|_| self.queue.is_empty() && !self.write_closed)
+                .and_then(|script| script.deadline(self.stats.idle()));
+            let banner_at = self
+                .banner_warning
+                .zip(self.stats.started)
+                .map(|(wait, started)| started + wait);
+            let assert_at = self.assertion_deadline();
+            let repeat_at = self.repeat.as_ref().map(Repeat::deadline);
+            let burst_at = self.reporter.burst.as_ref().and_then(BurstQuiet::end_at);
+            let cs = tokio::select! {
+                r = frame.next() => match r {
+                    Some(Ok(line)) => self.recv(line).await?,
+                    Some(Err(e)) => return Err(IoError::Inet(e.into())),
+                    None => return Ok(ConnectState::Closed),
+                },
+                line = self.queue.next(), if !self.queue.is_empty() => {
+                    self.send_line(frame, line).await?
+                }
+                r = input.next() => match r {
+                    Some(Ok(input)) if self.compose.is_some() => match self.compose_input(input)? {
+                        Some(lines) => self.submit(frame, lines).await?,
+                        None => ConnectState::Open,
+                    },
+                    Some(Ok(Input::Line(line))) => {
+                        if let Some(action) = unconfirmed.take() {
+                            if line.trim().eq_ignore_ascii_case("y")
+                                || line.trim().eq_ignore_ascii_case("yes")
+                            {
+                                match action {
+                                    Unconfirmed::Paste(lines) => self.submit(frame, lines).await?,
+                                    Unconfirmed::EmptyLine => self.submit(frame, vec![String::new()]).await?,
+                                    Unconfirmed::Quit => return Ok(ConnectState::Open),
+                                }
+                            } else {
+                                self.reporter.notify(action.declined())?;
+                                ConnectState::Open
+                            }
+                        } else if line.is_empty() && self.empty_line_mode != EmptyLineMode::Send {
+                            if self.empty_line_mode == EmptyLineMode::Confirm {
+                                self.reporter.notify("Send empty line? [y/N]")?;
+                                unconfirmed = Some(Unconfirmed::EmptyLine);
+                            }
+                            ConnectState::Open
+                        } else if let Some(lines) = self.unescape(vec![line])? {
+                            self.submit(frame, lines).await?
+                        } else {
+                            ConnectState::Open
+                        }
+                    }
+                    Some(Ok(Input::Paste(lines))) => {
+                        if let Some(action) = unconfirmed.take() {
+                            self.reporter.notify(action.declined())?;
+                        }
+                        let Some(lines) = self.unescape(lines)? else {
+                            continue;
+                        };
+                        match self.paste_mode {
+                            PasteMode::Ask => {
+                                self.reporter
+                                    .notify(&format!("Send {} lines? [y/N]", lines.len()))?;
+                                unconfirmed = Some(Unconfirmed::Paste(lines));
+                                ConnectState::Open
+                            }
+                            PasteMode::Send => self.submit(frame, lines).await?,
+                            PasteMode::Delay => {
+                                self.enqueue(lines)?;
+                                ConnectState::Open
+                            }
+                        }
+                    }
+                    Some(Ok(Input::Command(Ok(cmd)))) => self.run_command(Some(frame), cmd).await?,
+                    // Only produced while composing
+                    Some(Ok(Input::ComposeEnd)) => ConnectState::Open,
+                    Some(Ok(Input::Command(Err(e)))) => {
+                        self.reporter.notify(&e.to_string())?;
+                        ConnectState::Open
+                    }
+                    Some(Ok(Input::CtrlC)) => {
+                        self.reporter.echo_ctrlc()?;
+                        match unconfirmed.take() {
+                            // Pressing Ctrl-C again confirms quitting
+                            Some(Unconfirmed::Quit) => return Ok(ConnectState::Open),
+                            // Ctrl-C cancels any other pending question
+                            Some(action) => {
+                                self.reporter.notify(action.declined())?;
+                                ConnectState::Open
+                            }
+                            None => match self.ctrl_c {
+                                CtrlCMode::Quit => return Ok(ConnectState::Open),
+                                CtrlCMode::Confirm => {
+                                    self.reporter.notify("Quit? [y/N]")?;
+                                    unconfirmed = Some(Unconfirmed::Quit);
+                                    ConnectState::Open
+                                }
+                                CtrlCMode::SendBreak => {
+                                    let seq = self.break_sequence.clone();
+                                    self.send_data(frame, seq).await?
+                                }
+                                CtrlCMode::Ignore => ConnectState::Open,
+                            },
+                        }
+                    }
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Ok(ConnectState::Open),
+                },
+                () = sleep_until(idle_at.unwrap_or_else(Instant::now).into()), if idle_at.is_some() => {
+                    self.run_idle_script()?;
+                    ConnectState::Open
+                }
+                () = sleep_until(banner_at.unwrap_or_else(Instant::now).into()), if banner_at.is_some() => {
+                    if let Some(wait) = self.banner_warning.take() {
+                        self.reporter.report(Event::no_banner(wait))?;
+                    }
+                    ConnectState::Open
+                }
+                () = sleep_until(assert_at.unwrap_or_else(Instant::now).into()), if assert_at.is_some() => {
+                    return Err(self.assertion_timeout());
+                }
+                () = sleep_until(repeat_at.unwrap_or_else(Instant::now).into()), if repeat_at.is_some() => {
+                    self.repeat_step(frame).await?
+                }
+                () = sleep_until(burst_at.unwrap_or_else(Instant::now).into()), if burst_at.is_some() => {
+                    self.reporter.end_burst(Some(Instant::now()))?;
+                    ConnectState::Open
+                }
+                () = sleep_until(script_at.unwrap_or_else(Instant::now).into()), if script_at.is_some() => {
+                    #[cfg(feature = "scripting")]
+                    if let Some(script) = self.script.as_mut() {
+                        let r = script.fire_timers(Instant::now());
+                        self.report_script_error(r)?;
+                    }
+                    ConnectState::Open
+                }
+                _ = ticker.tick(), if self.status.is_some() || self.throughput.is_some() => {
+                    self.draw_status()?;
+                    ConnectState::Open
+                }
+                _ = throughput_ticker.tick(), if self.throughput.is_some() => {
+                    if let Some(meter) = self.throughput.as_mut() {
+                        let throughput = meter.sample(Instant::now(), &self.stats);
+                        self.reporter.report(Event::throughput(throughput))?;
+                    }
+                    ConnectState::Open
+                }
+                // Stop as soon as output can't be written (e.g., because
+                // stdout was closed) rather than waiting for the next event
+                // to fail
+                e = self.reporter.writer.failed() => {
+                    return Err(IoError::Interface(InterfaceError::Write(e)));
+                }
+            };
+            if cs != ConnectState::Open {
+                return Ok(cs);
+            }
+        }
+    }
+
+    /// Handle input entered after `/compose`: lines are added to the buffer,
+    /// and Ctrl-C discards it.  Once the composition is ended, the lines are
+    /// returned for sending, with escape sequences expanded if `--escapes`
+    /// is in effect.
+    fn compose_input(&mut self, input: Input) -> Result<Option<Vec<String>>, InterfaceError> {
+        let Some(buffer) = self.compose.as_mut() else {
+            return Ok(None);
+        };
+        match input {
+            Input::Line(line) => buffer.push(line),
+            Input::Paste(lines) => buffer.extend(lines),
+            Input::ComposeEnd => {
+                let lines = self.compose.take().unwrap_or_default();
+                return self.unescape(lines);
+            }
+            Input::CtrlC => {
+                self.compose = None;
+                self.reporter.echo_ctrlc()?;
+                self.reporter.notify("Composed lines discarded")?;
+            }
+            // Commands are not parsed while composing
+            Input::Command(_) => (),
+        }
+        Ok(None)
+    }
+
+    /// Return the time at which the next of the `--script` script's timers
+    /// fires, if any
+    fn script_deadline(&self) -> Option<Instant> {
+        #[cfg(feature = "scripting")]
+        {
+            self.script.as_ref().and_then(Script::next_deadline)
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            None
+        }
+    }
+
+    /// Send the lines & display the messages that the `--script` script has
+    /// asked for since the last call
+    #[cfg(feature = "scripting")]
+    async fn perform_script_actions(
+        &mut self,
+        frame: &mut Connection,
+    ) -> Result<ConnectState, IoError> {
+        let actions = self
+            .script
+            .as_mut()
+            .map(Script::take_actions)
+            .unwrap_or_default();
+        for action in actions {
+            match action {
+                Action::Send(line) => {
+                    let cs = self.send_line(frame, line).await?;
+                    if cs != ConnectState::Open {
+                        return Ok(cs);
+                    }
+                }
+                Action::Log(msg) => self.reporter.notify(&msg)?,
+            }
+        }
+        Ok(ConnectState::Open)
+    }
+
+    /// Report an error raised by the `--script` script.  The session
+    /// continues, and the script's callbacks keep being called.
+    #[cfg(feature = "scripting")]
+    fn report_script_error(&mut self, r: Result<(), ScriptError>) -> Result<(), InterfaceError> {
+        if let Err(e) = r {
+            self.reporter
+                .write_note('!', &format!("Script error: {e}"))
+                .map_err(InterfaceError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Queue the lines of the idle script to be sent
+    fn run_idle_script(&mut self) -> Result<(), InterfaceError> {
+        let Some(script) = self.idle_script.as_mut() else {
+            return Ok(());
+        };
+        script.last_run = Some(Instant::now());
+        let lines = script.lines.clone();
+        if let Some(lines) = self.unescape(lines)? {
+            self.enqueue(lines)?;
+        }
+        Ok(())
+    }
+
+    /// Add lines to the send queue, or show an error if they would exceed
+    /// `--memory-cap`
+    fn enqueue(&mut self, lines: Vec<String>) -> Result<(), InterfaceError> {
+        let count = lines.len();
+        if !self.queue.extend(lines) {
+            self.reporter
+                .write_note(
+                    '!',
+                    &format!("Not sending {count} lines: send queue would exceed --memory-cap"),
+                )
+                .map_err(InterfaceError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Show the given state of the session in the window title under
+    /// `--set-title`.  If setting the title fails, a warning is shown and the
+    /// title is no longer updated.
+    fn set_title(&mut self, state: SessionState) -> Result<(), InterfaceError> {
+        if let Some(title) = self.title.as_ref() {
+            if let Err(e) = title.set(state) {
+                self.title = None;
+                self.reporter.warn_title(&e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a reading for `--show-throughput`, if given, and redraw the
+    /// status line, if any.  If drawing fails, the status line is dropped and
+    /// the error reported.
+    fn draw_status(&mut self) -> Result<(), InterfaceError> {
+        let rate = self
+            .throughput
+            .as_mut()
+            .map(|meter| meter.sample(Instant::now(), &self.stats).recv);
+        if let Some(status) = self.status.as_mut() {
+            if let Err(e) = status.draw(&self.stats, self.write_closed, rate) {
+                self.status = None;
+                self.reporter.warn_status(&e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// If `--escapes` is in effect, expand the escape sequences in the given
+    /// lines of input.  If any line contains an invalid escape sequence, the
+    /// error is reported, and `None` is returned so that none of the lines
+    /// are sent.
+    fn unescape(&mut self, lines: Vec<String>) -> Result<Option<Vec<String>>, InterfaceError> {
+        if !self.escapes {
+            return Ok(Some(lines));
+        }
+        match lines.iter().map(|ln| unescape(ln)).collect() {
+            Ok(lines) => Ok(Some(lines)),
+            Err(e) => {
+                self.reporter.notify(&format!("Line not sent: {e}"))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Run a command entered at the prompt.  `frame` is `None` if there is
+    /// currently no connection, in which case commands that act on the
+    /// connection only report that it is closed.
+    async fn run_command(
+        &mut self,
+        frame: Option<&mut Connection>,
+        cmd: Command,
+    ) -> Result<ConnectState, IoError> {
+        let open = if frame.is_some() {
+            ConnectState::Open
+        } else {
+            ConnectState::Disconnected
+        };
+        let r = match cmd {
+            Command::Stats => self.reporter.notify(&self.stats.snapshot().to_string()),
+            Command::Queue => {
+                if self.queue.is_empty() {
+                    self.reporter.notify("Send queue is empty")?;
+                    return Ok(open);
+                }
+                self.reporter
+                    .notify(&format!("Lines waiting to be sent: {}", self.queue.len()))?;
+                for (i, line) in self.queue.iter().enumerate() {
+                    self.reporter.notify(&format!("{:>4}. {line}", i + 1))?;
+                }
+                Ok(())
+            }
+            Command::QueueDrop(n) => match self.queue.remove(n.get() - 1) {
+                Some(line) => self
+                    .reporter
+                    .notify(&format!("Removed line {n} from send queue: {line}")),
+                None => self.reporter.notify(&format!("No line {n} in send queue")),
+            },
+            Command::QueueClear => {
+                let count = self.queue.clear();
+                self.reporter
+                    .notify(&format!("Removed {count} lines from send queue"))
+            }
+            Command::ShutdownWrite => match frame {
+                Some(frame) => {
+                    self.shutdown_write(frame).await?;
+                    return Ok(open);
+                }
+                None => self.reporter.notify(NOT_CONNECTED),
+            },
+            Command::Break => match frame {
+                Some(frame) => {
+                    let seq = self.break_sequence.clone();
+                    return self.send_data(frame, seq).await;
+                }
+                None => self.reporter.notify(NOT_CONNECTED),
+            },
+            Command::Disconnect if frame.is_some() => return Ok(ConnectState::Disconnected),
+            Command::Disconnect => self.reporter.notify(NOT_CONNECTED),
+            Command::Connect(target) => {
+                if let Some((host, port)) = target {
+                    self.connector.host = host;
+                    self.connector.port = port;
+                }
+                return Ok(ConnectState::Reconnect);
+            }
+            Command::Search(SearchPattern(rgx)) => {
+                let found = self.reporter.scrollback.search(&rgx);
+                if found.is_empty() {
+                    self.reporter.notify("No matching lines in scrollback")?;
+                    return Ok(open);
+                }
+                self.reporter
+                    .notify(&format!("Matching lines in scrollback: {}", found.len()))?;
+                self.reporter.redisplay(found)
+            }
+            Command::Set(setting) => {
+                self.apply_setting(frame, setting);
+                if let Some(session) = self.session.as_mut() {
+                    session
+                        .state
+                        .settings
+                        .insert(setting.name().to_owned(), setting.value());
+                }
+                self.reporter.report(Event::setting_changed(setting))
+            }
+            Command::Note(text) => self.reporter.report(Event::note(text)),
+            Command::Repeat(..) if frame.is_none() => self.reporter.notify(NOT_CONNECTED),
+            Command::Repeat(..) if self.repeat.is_some() => self
+                .reporter
+                .notify("A /repeat is already in progress; use \"/repeat stop\" to end it"),
+            Command::Repeat(count, line) => {
+                if let Some(line) = self
+                    .unescape(vec![line])?
+                    .and_then(|lines| lines.into_iter().next())
+                {
+                    self.repeat = Some(Repeat::new(line, count, Duration::ZERO, false));
+                }
+                Ok(())
+            }
+            Command::RepeatStop => match self.repeat.take() {
+                Some(repeat) => self
+                    .reporter
+                    .report(Event::repeat_summary(repeat.summary())),
+                None => self.reporter.notify("No /repeat is in progress"),
+            },
+            Command::Compose => {
+                self.compose = Some(Vec::new());
+                self.reporter.notify(
+                    "Composing lines to send; end with \".\" on a line by itself or Ctrl-D, or press Ctrl-C to discard",
+                )
+            }
+            Command::ShowBurst => {
+                let (held, count) = self
+                    .reporter
+                    .burst
+                    .as_ref()
+                    .map_or_else(|| (Vec::new(), 0), BurstQuiet::held);
+                if count == 0 {
+                    self.reporter.notify("No lines have been held back")?;
+                    return Ok(open);
+                }
+                let kept = held.len();
+                self.reporter.redisplay(held)?;
+                let dropped = count.saturating_sub(u64::try_from(kept).unwrap_or(u64::MAX));
+                if dropped > 0 {
+                    self.reporter
+                        .notify(&format!("{dropped} more lines were not kept"))?;
+                }
+                Ok(())
+            }
+            Command::Last(n) => {
+                let found = self.reporter.scrollback.last(n.get());
+                if found.is_empty() {
+                    self.reporter.notify("Scrollback is empty")?;
+                    return Ok(open);
+                }
+                self.reporter.redisplay(found)
+            }
+        };
+        r?;
+        Ok(open)
+    }
+
+    /// Change a setting of the live codec, if there is a connection.  The
+    /// connector is updated as well so that the setting also applies to any
+    /// new connection it makes.
+    fn apply_setting(&mut self, frame: Option<&mut Connection>, setting: Setting) {
+        match setting {
+            Setting::Encoding(encoding) => self.connector.encoding = encoding,
+            Setting::Crlf(true) => {
+                let prev = mem::replace(&mut self.connector.send_ending, SendEnding::Crlf);
+                if prev != SendEnding::Crlf {
+                    self.ending_before_crlf = Some(prev);
+                }
+            }
+            Setting::Crlf(false) => {
+                if self.connector.send_ending == SendEnding::Crlf {
+                    self.connector.send_ending =
+                        self.ending_before_crlf.take().unwrap_or(SendEnding::Lf);
+                }
+            }
+            Setting::MaxLineLength(n) => self.connector.max_line_length = n,
+        }
+        if let Some(frame) = frame {
+            let codec = frame.codec_mut();
+            *codec = match setting {
+                Setting::Encoding(encoding) => mem::take(codec).encoding(encoding),
+                Setting::Crlf(_) => mem::take(codec).send_ending(self.connector.send_ending),
+                Setting::MaxLineLength(n) => mem::take(codec).max_length(n.get()),
+            };
+        }
+    }
+
+    /// Apply the settings changed with `/set` during the previous run of the
+    /// `--session`, if any.  Saved settings that are no longer valid are
+    /// ignored.
+    fn restore_settings(&mut self) {
+        let settings = self
+            .session
+            .as_ref()
+            .map(|session| {
+                session
+                    .state
+                    .settings
+                    .iter()
+                    .filter_map(|(name, value)| Setting::parse(name, value).ok())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for setting in settings {
+            self.apply_setting(None, setting);
+        }
+    }
+
+    /// Save the state of the `--session`, if any.  If this fails, a warning
+    /// is shown.
+    fn save_session(&mut self) -> Result<(), InterfaceError> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        session.state.variables = self
+            .variables
+            .values()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        if session.save_target {
+            session.state.target = Some(SavedTarget {
+                host: self.connector.host.clone(),
+                port: self.connector.port,
+                tls: self.connector.tls,
+            });
+        }
+        if let Err(e) = session.save() {
+            self.reporter.warn_session(&e)?;
+        }
+        Ok(())
+    }
+
+    /// Send the given lines to the server now, or add them to the queue if
+    /// there are already lines waiting to be sent
+    async fn submit(
+        &mut self,
+        frame: &mut Connection,
+        lines: Vec<String>,
+    ) -> Result<ConnectState, IoError> {
+        if !self.queue.is_empty() {
+            self.enqueue(lines)?;
+            return Ok(ConnectState::Open);
+        }
+        for line in lines {
+            let cs = self.send_line(frame, line).await?;
+            if cs != ConnectState::Open {
+                return Ok(cs);
+            }
+        }
+        Ok(ConnectState::Open)
+    }
+
+    /// Send & report a line of input.  If sending the line would exceed the
+    /// send budget, the line is discarded and `ConnectState::OverBudget` is
+    /// returned.  A line longer than `--max-send-length` is reported and,
+    /// under `--strict`, discarded.
+    async fn send_line(
+        &mut self,
+        frame: &mut Connection,
+        line: String,
+    ) -> Result<ConnectState, IoError> {
+        let line = match self.variables.expand(line) {
+            Ok(line) => line,
+            Err(e) => {
+                self.reporter.notify(&format!("Line not sent: {e}"))?;
+                return Ok(ConnectState::Open);
+            }
+        };
+        let expanded = match self.templates.as_mut() {
+            Some(templates) => templates.expand(line),
+            None => Ok(line),
+        };
+        let line = match expanded {
+            Ok(line) => line,
+            Err(e) => {
+                self.reporter.notify(&format!("Line not sent: {e}"))?;
+                return Ok(ConnectState::Open);
+            }
+        };
+        let line = match self.protocol.as_mut() {
+            Some(layer) => layer.outgoing(line),
+            None => line,
+        };
+        let line = match self.syslog.as_ref() {
+            Some(wrapper) => wrapper.wrap(&line, now()),
+            None => line,
+        };
+        let line = frame.codec().prepare_line(line);
+        if let Some(limit) = self.max_send_length {
+            let length = frame.codec().line_len(&line);
+            if length > limit.get() {
+                let refused = self.strict_send_length;
+                self.reporter
+                    .report(Event::send_too_long(length, limit.get(), refused))?;
+                if refused {
+                    return Ok(ConnectState::Open);
+                }
+            }
+        }
+        self.send_data(frame, line).await
+    }
+
+    /// Send & report text as-is, without appending a line ending.  If sending
+    /// the text would exceed the send budget, it is discarded and
+    /// `ConnectState::OverBudget` is returned.
+    async fn send_data(
+        &mut self,
+        frame: &mut Connection,
+        data: String,
+    ) -> Result<ConnectState, IoError> {
+        if self.write_closed {
+            self.reporter
+                .notify("Not sent: connection has been shut down for writing")?;
+            return Ok(ConnectState::Open);
+        }
+        let len = frame.codec().encoded_len(&data);
+        if !self.budget.allows(&self.stats, Direction::Send, len) {
+            return Ok(ConnectState::OverBudget(Direction::Send));
+        }
+        let raw = self
+            .raw_bytes
+            .then(|| frame.codec().wire_bytes(&data).ok())
+            .flatten();
+        frame.send(&data).await.map_err(InetError::Send)?;
+        self.stats.record_send(len);
+        self.reporter
+            .report_line(Event::send(data, self.stats.elapsed()).with_raw(raw))
+            .await?;
+        Ok(ConnectState::Open)
+    }
+
+    /// Record & report a line received from the server.  If accepting the
+    /// line would exceed the receive budget, the line is discarded and
+    /// `ConnectState::OverBudget` is returned.
+    async fn recv(&mut self, mut line: RecvLine) -> Result<ConnectState, InterfaceError> {
+        if !self
+            .budget
+            .allows(&self.stats, Direction::Recv, line.wire_len())
+        {
+            return Ok(ConnectState::OverBudget(Direction::Recv));
+        }
+        if let Some(repeat) = self.repeat.as_mut() {
+            repeat.observe_recv(Instant::now());
+        }
+        self.stats.record_recv(line.wire_len());
+        self.banner_warning = None;
+        if let Some(proto) = self.sniffer.as_mut().and_then(|s| s.feed(&line.raw)) {
+            self.reporter.report(Event::protocol_mismatch(proto))?;
+        }
+        if let Some(e) = line.invalid.take() {
+            self.reporter.report(Event::encoding_error(&e))?;
+        }
+        self.reporter.write_output(&line.raw)?;
+        self.variables.capture(chomp(&line.text));
+        if let Some(asserts) = self.assertions.as_mut() {
+            asserts.observe(chomp(&line.text));
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = self.script.as_mut() {
+            let r = script.on_recv(chomp(&line.text));
+            self.report_script_error(r)?;
+        }
+        #[cfg(feature = "notifications")]
+        let notify = self
+            .notifier
+            .as_ref()
+            .is_some_and(|notifier| notifier.matches(chomp(&line.text)))
+            .then(|| chomp(&line.text).to_owned());
+        let continued = self
+            .protocol
+            .as_mut()
+            .is_some_and(|layer| layer.incoming(&line.text));
+        self.reporter
+            .report_line(
+                Event::recv(line.text, self.stats.elapsed(), continued)
+                    .with_split(line.partial, line.resumed)
+                    .with_raw(self.raw_bytes.then_some(line.raw))
+                    .with_since_send(self.stats.last_send.map(|t| t.elapsed())),
+            )
+            .await?;
+        #[cfg(feature = "notifications")]
+        if let Some(text) = notify {
+            self.show_notification(&text).await?;
+        }
+        self.finish_repeat()
+    }
+
+    /// Send the next line of the `/repeat` or `--flood` run if it's time to,
+    /// or report the results if the run is over
+    async fn repeat_step(&mut self, frame: &mut Connection) -> Result<ConnectState, IoError> {
+        match self
+            .repeat
+            .as_mut()
+            .and_then(|repeat| repeat.step(Instant::now()))
+        {
+            Some(line) => self.send_line(frame, line).await,
+            None => Ok(self.finish_repeat()?),
+        }
+    }
+
+    /// If the `/repeat` or `--flood` run has sent all of its lines and
+    /// received (or given up on) the last response, report the results.
+    /// Returns `ConnectState::Done` if this ends a `--flood` run.
+    fn finish_repeat(&mut self) -> Result<ConnectState, InterfaceError> {
+        if !self.repeat.as_ref().is_some_and(Repeat::is_done) {
+            return Ok(ConnectState::Open);
+        }
+        let Some(repeat) = self.repeat.take() else {
+            return Ok(ConnectState::Open);
+        };
+        self.reporter
+            .report(Event::repeat_summary(repeat.summary()))?;
+        if repeat.exit_when_done {
+            Ok(ConnectState::Done)
+        } else {
+            Ok(ConnectState::Open)
+        }
+    }
+
+    /// Show a desktop notification of a received line that matched
+    /// `--notify-on`.  If this fails, a warning is shown and no further
+    /// notifications are attempted.
+    #[cfg(feature = "notifications")]
+    async fn show_notification(&mut self, line: &str) -> Result<(), InterfaceError> {
+        if let Some(notifier) = self.notifier.as_ref() {
+            if let Err(e) = notifier.show(line).await {
+                self.notifier = None;
+                self.reporter
+                    .write_note('!', &format!("Error showing desktop notification: {e}"))
+                    .map_err(InterfaceError::Write)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An action awaiting confirmation from the user
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Unconfirmed {
+    /// Lines pasted under `--paste-mode ask`
+    Paste(Vec<String>),
+    /// An empty line entered under `--empty-line-mode confirm`
+    EmptyLine,
+    /// Ending the session under `--ctrl-c confirm`
+    Quit,
+}
+
+impl Unconfirmed {
+    /// Message to show when the user declines the action
+    fn declined(&self) -> &'static str {
+        match self {
+            Unconfirmed::Paste(_) => "Paste discarded",
+            Unconfirmed::EmptyLine => "Empty line not sent",
+            Unconfirmed::Quit => "Not quitting",
+        }
+    }
+}
+
+/// Configuration for waiting for the server's greeting before sending
+/// anything
+#[derive(Clone, Debug)]
+pub(crate) struct BannerWait {
+    /// If set, keep waiting until a received line matches this pattern
+    pub(crate) pattern: Option<Regex>,
+    pub(crate) timeout: Duration,
+}
+
+/// Lines to send non-interactively, followed by a description of when the
+/// response to them is complete
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub(crate) lines: Vec<String>,
+    pub(crate) response: ResponseEnd,
+}
+
+/// When to consider the response to a `Request` complete
+#[derive(Clone, Debug)]
+pub(crate) enum ResponseEnd {
+    /// Disconnect as soon as the lines have been sent
+    Immediate,
+    /// Wait for this many lines to be received
+    Lines(NonZeroU64),
+    /// Wait for a received line to match this pattern
+    Match(Regex),
 }
 
 pub(crate) struct Reporter {
-    pub(crate) writer: Box<dyn Write + Send>,
-    pub(crate) transcript: Option<File>,
-    pub(crate) show_times: bool,
+    pub(crate) writer: TermWriter,
+    pub(crate) transcript: Option<TranscriptWriter>,
+    /// The transcripts to switch between as new connections are made, if
+    /// `--transcript-per-connection` was given
+    pub(crate) transcript_series: Option<TranscriptSeries>,
+    /// Recording of everything written to `writer`
+    pub(crate) cast: Option<CastFile>,
+    /// File to which the raw bytes of received lines are appended
+    pub(crate) output: Option<File>,
+    /// Decides how events & notes are presented
+    pub(crate) renderer: Box<dyn Renderer>,
+    /// Whether to display events that are normally only recorded in the
+    /// transcript
+    pub(crate) verbose: bool,
+    /// Whether to hide informational events
+    pub(crate) quiet: bool,
+    /// Whether to display sent lines
+    pub(crate) echo: bool,
+    /// If set, only received lines matching this pattern are displayed
+    pub(crate) filter: Option<Regex>,
+    /// Whether to display the session statistics when the session ends
+    pub(crate) stats_on_exit: bool,
+    /// Decides which received lines are displayed
+    pub(crate) sampler: RecvSampler,
+    /// Whether lines hidden by `sampler` should also be omitted from the
+    /// transcript
+    pub(crate) sample_transcript: bool,
+    /// Holds back received lines from display during bursts, if
+    /// `--burst-quiet` was given
+    pub(crate) burst: Option<BurstQuiet>,
+    /// Index of the words in displayed received lines, for tab completion in
+    /// the line editor
+    pub(crate) tokens: Option<TokenIndex>,
+    /// The most recent events displayed on standard output, for `/search`
+    /// and `/last`
+    pub(crate) scrollback: Scrollback,
+    /// Command through which received lines are piped before display
+    pub(crate) recv_hook: Option<Hook>,
+    /// Command through which sent lines are piped before display
+    pub(crate) send_hook: Option<Hook>,
+    /// Commands run when connecting, disconnecting, or reporting an error
+    pub(crate) event_commands: Option<EventCommands>,
+    /// Substitutions applied, in order, to received lines before display
+    pub(crate) rewrites: Vec<Rewrite>,
+    /// Where to send counters of session activity, if `--metrics` was given
+    pub(crate) metrics: Option<Metrics>,
+    /// Aggregate statistics to write at exit, if `--summary-file` was given
+    pub(crate) summary: Option<Summary>,
+    /// The session's OpenTelemetry trace, if `--otel-endpoint` was given
+    #[cfg(feature = "otel")]
+    pub(crate) trace: Option<TraceRecorder>,
 }
 
 impl Reporter {
-    fn set_writer(&mut self, writer: Box<dyn Write + Send>) {
-        self.writer = writer;
+    fn set_writer(&mut self, sink: Sink) -> Result<(), InterfaceError> {
+        self.writer.set_sink(sink).map_err(InterfaceError::Write)
     }
 
     fn report(&mut self, event: Event) -> Result<(), InterfaceError> {
@@ -86,96 +1510,643 @@ impl Reporter {
     }
 
     fn report_inner(&mut self, event: Event) -> Result<(), io::Error> {
-        writeln!(self.writer, "{}", event.to_message(self.show_times))?;
-        if let Some(fp) = self.transcript.as_mut() {
-            if let Err(e) = writeln!(fp, "{}", event.to_json()) {
-                let _ = self.transcript.take();
-                if self.show_times {
-                    write!(self.writer, "[{}] ", now_hms())?;
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.observe(&event);
+        }
+        if let Some(summary) = self.summary.as_mut() {
+            summary.observe(&event);
+        }
+        #[cfg(feature = "otel")]
+        if let Some(trace) = self.trace.as_mut() {
+            trace.observe(&event);
+        }
+        let display = match event {
+            Event::Recv { ref data, .. } => {
+                if self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|rgx| !rgx.is_match(chomp(data)))
+                {
+                    false
+                } else {
+                    let admit = self.sampler.admit();
+                    if !admit && self.sample_transcript {
+                        return Ok(());
+                    }
+                    admit
                 }
-                writeln!(self.writer, "! Error writing to transcript: {e}")?;
+            }
+            Event::Send { .. } => self.echo,
+            Event::EncodingError { .. }
+            | Event::Error { .. }
+            | Event::Note { .. }
+            | Event::ProtocolMismatch { .. }
+            | Event::NoBanner { .. }
+            | Event::RepeatSummary { .. }
+            | Event::SendTooLong { .. } => true,
+            Event::SessionStart { .. }
+            | Event::DnsResolved { .. }
+            | Event::Timing { .. }
+            | Event::Throughput { .. } => self.verbose,
+            Event::SessionStats { .. } => self.verbose || self.stats_on_exit,
+            _ => !self.quiet,
+        };
+        if let (Event::Recv { data, .. }, Some(tokens)) = (&event, &self.tokens) {
+            if display {
+                tokens.record(data);
+            }
+        }
+        let rendered = self.renderer.event(&event, display);
+        let held = match (&event, &rendered) {
+            (Event::Recv { .. }, Some(Rendered::Stdout(text))) => self.hold_in_burst(text)?,
+            _ => false,
+        };
+        if let Some(r) = rendered.filter(|_| !held) {
+            if let Rendered::Stdout(ref text) = r {
+                self.scrollback.push(event.plain_message(), text.clone());
+            }
+            self.output(r)?;
+        }
+        if let (Event::SessionStart { .. }, Some(series)) =
+            (&event, self.transcript_series.as_mut())
+        {
+            series.set_header(event.to_json());
+        }
+        if let Some(w) = self.transcript.as_mut() {
+            if let Err(e) = w.write(event.to_json()) {
+                let _ = self.transcript.take();
+                self.write_note('!', &format!("Error writing to transcript: {e}"))?;
+            }
+        }
+        if let Some(cmds) = self.event_commands.as_mut() {
+            if let Err(e) = cmds.run(&event) {
+                self.write_note('!', &error_chain(&e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Under `--burst-quiet`, register the display of a received line and
+    /// return whether it should be held back as part of a burst
+    fn hold_in_burst(&mut self, text: &str) -> Result<bool, io::Error> {
+        let Some(burst) = self.burst.as_mut() else {
+            return Ok(false);
+        };
+        let now = Instant::now();
+        let ended = burst.end(now);
+        let state = burst.observe(now);
+        if state != BurstState::Calm {
+            burst.hold(text.to_owned());
+        }
+        if let Some(count) = ended {
+            self.write_note('*', &burst_message(count))?;
+        }
+        if state == BurstState::Started {
+            self.write_note(
+                '*',
+                "Receiving a burst of lines; holding them back until it passes",
+            )?;
+        }
+        Ok(state != BurstState::Calm)
+    }
+
+    /// Report the end of the burst in progress, if any.  If `now` is
+    /// `None`, the burst is ended regardless of whether it has died down.
+    fn end_burst(&mut self, now: Option<Instant>) -> Result<(), InterfaceError> {
+        let ended = self.burst.as_mut().and_then(|burst| match now {
+            Some(now) => burst.end(now),
+            None => burst.finish(),
+        });
+        match ended {
+            Some(count) => self.notify(&burst_message(count)),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a received or sent line, first piping it through
+    /// `--recv-hook` or `--send-hook` if one is set and the line is to be
+    /// displayed.  If the hook fails, the line is displayed as-is after a
+    /// note about the failure.
+    async fn report_line(&mut self, event: Event) -> Result<(), InterfaceError> {
+        let hooked = match &event {
+            Event::Recv { data, .. }
+                if !self
+                    .filter
+                    .as_ref()
+                    .is_some_and(|rgx| !rgx.is_match(chomp(data))) =>
+            {
+                self.recv_hook.as_ref().map(|hook| (hook, data))
+            }
+            Event::Send { data, .. } if self.echo => {
+                self.send_hook.as_ref().map(|hook| (hook, data))
+            }
+            _ => None,
+        };
+        let Some((hook, data)) = hooked else {
+            let event = self.rewrite(event);
+            return self.report(event);
+        };
+        let event = match hook.run(data).await {
+            Ok(shown) => event.with_shown(shown),
+            Err(e) => {
+                self.write_note('!', &error_chain(&e))
+                    .map_err(InterfaceError::Write)?;
+                event
+            }
+        };
+        let event = self.rewrite(event);
+        self.report(event)
+    }
+
+    /// Apply the `--rewrite` substitutions to the displayed text of a
+    /// received line.  The line's original data is left untouched for the
+    /// transcript.
+    fn rewrite(&self, event: Event) -> Event {
+        let Event::Recv { data, shown, .. } = &event else {
+            return event;
+        };
+        if self.rewrites.is_empty() {
+            return event;
+        }
+        let text = shown.as_deref().unwrap_or_else(|| chomp(data));
+        match apply_all(&self.rewrites, text) {
+            Cow::Borrowed(_) => event,
+            Cow::Owned(s) => event.with_shown(Some(s)),
+        }
+    }
+
+    /// Write an event to the transcript only, ignoring any failure
+    fn record(&mut self, event: &Event) {
+        if let Some(w) = self.transcript.as_mut() {
+            if w.write(event.to_json()).is_err() {
+                let _ = self.transcript.take();
+            }
+        }
+    }
+
+    /// Wait for everything reported so far to be written to the transcript,
+    /// and report any events that had to be dropped
+    async fn close_transcript(&mut self) -> Result<(), InterfaceError> {
+        let mut writers = self
+            .transcript_series
+            .as_mut()
+            .map(TranscriptSeries::finish)
+            .unwrap_or_default();
+        writers.extend(self.transcript.take());
+        for mut w in writers {
+            let r = w.close().await;
+            let dropped = w.dropped();
+            if dropped > 0 {
+                self.write_note(
+                    '!',
+                    &format!("Transcript could not keep up; {dropped} events were not recorded"),
+                )
+                .map_err(InterfaceError::Write)?;
+            }
+            if let Err(e) = r {
+                self.write_note('!', &format!("Error writing to transcript: {e}"))
+                    .map_err(InterfaceError::Write)?;
             }
         }
         Ok(())
     }
 
+    /// Under `--transcript-per-connection`, start a new transcript for a
+    /// connection to `host` & `port`.  If the transcript can't be opened, a
+    /// warning is shown, and the connection's events are not recorded.
+    fn start_transcript(&mut self, host: &str, port: u16) -> Result<(), InterfaceError> {
+        let Some(series) = self.transcript_series.as_mut() else {
+            return Ok(());
+        };
+        match series.next(host, port, self.transcript.take()) {
+            Ok(w) => self.transcript = Some(w),
+            Err(e) => self
+                .write_note(
+                    '!',
+                    &format!("Error opening transcript: {}", error_chain(&e)),
+                )
+                .map_err(InterfaceError::Write)?,
+        }
+        Ok(())
+    }
+
+    /// Write the `--summary-file` summary, if any, for a run ending with the
+    /// given exit status
+    fn write_summary(&mut self, exit_code: u8) -> Result<(), InterfaceError> {
+        let Some(summary) = self.summary.take() else {
+            return Ok(());
+        };
+        let reason = match exit_code {
+            SUCCESS_EXIT => ExitReason::Closed,
+            BUDGET_EXCEEDED_EXIT => ExitReason::BudgetExceeded,
+            _ => ExitReason::Error,
+        };
+        if let Err(e) = summary.write(exit_code, reason) {
+            self.write_note('!', &format!("Error writing summary file: {e}"))
+                .map_err(InterfaceError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Send the session's trace to the `--otel-endpoint` collector, if any
+    #[cfg(feature = "otel")]
+    async fn export_trace(&mut self) -> Result<(), InterfaceError> {
+        let Some(mut trace) = self.trace.take() else {
+            return Ok(());
+        };
+        if let Err(e) = trace.export().await {
+            self.write_note('!', &format!("Error exporting trace: {}", error_chain(&e)))
+                .map_err(InterfaceError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Append the raw bytes of a received line to the output file, if any
+    fn write_output(&mut self, data: &[u8]) -> Result<(), InterfaceError> {
+        if let Some(fp) = self.output.as_mut() {
+            if let Err(e) = fp.write_all(data) {
+                let _ = self.output.take();
+                self.write_note('!', &format!("Error writing to output file: {e}"))
+                    .map_err(InterfaceError::Write)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Display text from the scrollback again without recording it anywhere
+    fn redisplay(&mut self, lines: Vec<String>) -> Result<(), InterfaceError> {
+        for text in lines {
+            self.display(&text).map_err(InterfaceError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Display a message to the user without recording it in the transcript
+    fn notify(&mut self, msg: &str) -> Result<(), InterfaceError> {
+        self.write_note('*', msg).map_err(InterfaceError::Write)
+    }
+
+    fn write_note(&mut self, sigil: char, msg: &str) -> Result<(), io::Error> {
+        let r = self.renderer.note(sigil, msg);
+        self.output(r)
+    }
+
+    fn warn_status(&mut self, e: &io::Error) -> Result<(), InterfaceError> {
+        self.write_note('!', &format!("Error drawing status line: {e}"))
+            .map_err(InterfaceError::Write)
+    }
+
+    fn warn_session(&mut self, e: &SessionError) -> Result<(), InterfaceError> {
+        self.write_note('!', &format!("Error saving session: {}", error_chain(e)))
+            .map_err(InterfaceError::Write)
+    }
+
+    fn warn_title(&mut self, e: &io::Error) -> Result<(), InterfaceError> {
+        self.write_note('!', &format!("Error setting window title: {e}"))
+            .map_err(InterfaceError::Write)
+    }
+
     fn echo_ctrlc(&mut self) -> Result<(), InterfaceError> {
-        writeln!(self.writer, "^C").map_err(InterfaceError::Write)
+        match self.renderer.ctrl_c() {
+            Some(text) => self.display(&text).map_err(InterfaceError::Write),
+            None => Ok(()),
+        }
+    }
+
+    /// Write rendered text to wherever the renderer directed it
+    fn output(&mut self, r: Rendered) -> Result<(), io::Error> {
+        match r {
+            Rendered::Stdout(text) => self.display(&text),
+            Rendered::Stderr(text) => io::stderr().write_all(text.as_bytes()),
+        }
+    }
+
+    /// Write text to the terminal and to the cast file, if any
+    fn display(&mut self, text: &str) -> Result<(), io::Error> {
+        self.writer.write(text.to_owned())?;
+        if let Some(cast) = self.cast.as_mut() {
+            if let Err(e) = cast.write_output(text) {
+                let _ = self.cast.take();
+                self.write_note('!', &format!("Error writing to cast file: {e}"))?;
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Debug)]
 pub(crate) struct Connector {
     pub(crate) tls: bool,
     pub(crate) host: String,
     pub(crate) port: u16,
     pub(crate) servername: Option<String>,
+    /// Whether to send the server name via SNI during the TLS handshake
+    pub(crate) sni: bool,
+    /// TLS client state kept across connections for session resumption
+    pub(crate) tls_context: tls::ClientContext,
+    /// Store of certificate fingerprints against which to check the server's
+    /// certificate instead of validating it, if `--tofu` was given
+    #[cfg(feature = "rustls")]
+    pub(crate) tofu: Option<TofuStore>,
+    pub(crate) socket: SocketOptions,
+    /// Addresses to use for certain host & port combinations instead of
+    /// looking them up in DNS
+    pub(crate) resolve: Vec<ResolveOverride>,
+    /// Addresses from `--addr-file` to try in order instead of resolving
+    /// `host`
+    pub(crate) addr_list: Vec<SocketAddr>,
     pub(crate) encoding: CharEncoding,
     pub(crate) max_line_length: NonZeroUsize,
-    pub(crate) crlf: bool,
+    pub(crate) send_ending: SendEnding,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) framing: Framing,
+    /// Whether a received line that cannot be decoded ends the session
+    pub(crate) fatal_decode_errors: bool,
+    /// How many more times to try connecting after a failure to resolve or
+    /// connect, as set by `--retry`
+    pub(crate) retries: u32,
+    /// How long to wait between connection attempts
+    pub(crate) retry_wait: Duration,
+    /// Socket passed down by the parent process via `--fd`, to be used
+    /// instead of connecting to `host` & `port`
+    #[cfg(unix)]
+    pub(crate) inherited: Option<InheritedSocket>,
+    /// Socket bound by `--listen`, on which to wait for a client to connect
+    /// instead of connecting to `host` & `port`
+    pub(crate) listener: Option<std::net::TcpListener>,
+    /// Certificate & key with which to act as a TLS server under `--listen`
+    pub(crate) identity: Option<tls::ServerIdentity>,
+    /// Serial device given with `--serial`, to be used instead of connecting
+    /// to `host` & `port`
+    #[cfg(feature = "serial")]
+    pub(crate) serial: Option<SerialSpec>,
+    /// Program & arguments given with `--exec`, to be run and talked to
+    /// instead of connecting to `host` & `port`
+    pub(crate) exec: Option<Vec<String>>,
 }
 
 impl Connector {
-    async fn connect(&self, reporter: &mut Reporter) -> Result<Connection, IoError> {
+    /// Connect to the server, trying again up to `retries` times if resolving
+    /// the host or connecting fails.  Each failed attempt is reported as an
+    /// error; the error from the final attempt is returned.
+    async fn connect_with_retries(
+        &mut self,
+        reporter: &mut Reporter,
+    ) -> Result<Connection, IoError> {
+        let mut retries = self.retries;
+        loop {
+            match self.connect(reporter).await {
+                Err(IoError::Inet(e)) if retries > 0 && e.is_retryable() => {
+                    reporter.report(Event::error(e.kind(), &e))?;
+                    reporter.notify(&format!(
+                        "Retrying in {}s ({retries} attempts left) ...",
+                        self.retry_wait.as_secs()
+                    ))?;
+                    retries -= 1;
+                    sleep(self.retry_wait).await;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    async fn connect(&mut self, reporter: &mut Reporter) -> Result<Connection, IoError> {
+        #[cfg(unix)]
+        if let Some(socket) = self.inherited.take() {
+            return self.adopt(socket, reporter).await;
+        }
+        if let Some(listener) = self.listener.take() {
+            return self.listen(listener, reporter).await;
+        }
+        #[cfg(feature = "serial")]
+        if let Some(spec) = self.serial.take() {
+            let port = spec.open().map_err(InetError::Serial)?;
+            reporter.report(Event::serial_opened(&spec.device, spec.baud))?;
+            return Ok(self.frame(boxed(port)));
+        }
+        if let Some(argv) = self.exec.take() {
+            let child = ChildPipes::spawn(&argv).map_err(InetError::Spawn)?;
+            reporter.report(Event::process_started(&argv, child.id()))?;
+            return Ok(self.frame(boxed(child)));
+        }
         reporter.report(Event::connect_start(&self.host, self.port))?;
-        let conn = TcpStream::connect((&*self.host, self.port))
-            .await
+        let start = Instant::now();
+        let (conn, resolved) = if self.addr_list.is_empty() {
+            let addrs = self.resolve().await?;
+            let resolved = Instant::now();
+            reporter.report(Event::dns_resolved(
+                &self.host,
+                addrs.iter().map(SocketAddr::ip).collect(),
+                self.override_for().is_some(),
+            ))?;
+            let conn = TcpStream::connect(&*addrs)
+                .await
+                .map_err(InetError::Connect)?;
+            (conn, resolved)
+        } else {
+            // Nothing is looked up, so no time is spent on DNS.
+            (self.connect_in_order(reporter).await?, start)
+        };
+        let connected = Instant::now();
+        self.apply_socket_options(&conn)
+            .map_err(InetError::SocketOptions)?;
+        reporter.report(Event::connect_finish(
+            conn.peer_addr().map_err(InetError::PeerAddr)?,
+            self.socket,
+        ))?;
+        let (conn, tls_time) = self.start_tls(conn, reporter).await?;
+        reporter.report(Event::timing(ConnectTiming {
+            dns: resolved.duration_since(start),
+            connect: connected.duration_since(resolved),
+            tls: tls_time,
+            total: start.elapsed(),
+        }))?;
+        Ok(self.frame(conn))
+    }
+
+    /// Try connecting to each of the addresses from `--addr-file` in turn,
+    /// reporting each attempt & failure, until one succeeds.  If none do, the
+    /// error from the last one is returned.
+    async fn connect_in_order(&self, reporter: &mut Reporter) -> Result<TcpStream, IoError> {
+        let mut last_error = None;
+        for &addr in &self.addr_list {
+            reporter.report(Event::connect_attempt(addr))?;
+            match TcpStream::connect(addr).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    reporter.report(Event::connect_failed(addr, &e))?;
+                    last_error = Some(e);
+                }
+            }
+        }
+        let e = last_error.unwrap_or_else(|| io::Error::other("no addresses to connect to"));
+        Err(IoError::Inet(InetError::Connect(e)))
+    }
+
+    /// Set up a session over a socket inherited from the parent process,
+    /// first waiting for a connection if the socket is listening
+    #[cfg(unix)]
+    async fn adopt(
+        &self,
+        socket: InheritedSocket,
+        reporter: &mut Reporter,
+    ) -> Result<Connection, IoError> {
+        let conn = match socket {
+            InheritedSocket::Stream(conn) => conn,
+            InheritedSocket::Listener(listener) => return self.listen(listener, reporter).await,
+        };
+        let conn = conn
+            .set_nonblocking(true)
+            .and_then(|()| TcpStream::from_std(conn))
             .map_err(InetError::Connect)?;
+        self.apply_socket_options(&conn)
+            .map_err(InetError::SocketOptions)?;
         reporter.report(Event::connect_finish(
             conn.peer_addr().map_err(InetError::PeerAddr)?,
+            self.socket,
+        ))?;
+        let (conn, _) = self.start_tls(conn, reporter).await?;
+        Ok(self.frame(conn))
+    }
+
+    /// Wait for a client to connect to `listener` and set up a session with
+    /// it.  If a certificate & key were given, the server side of a TLS
+    /// handshake is then performed.
+    async fn listen(
+        &self,
+        listener: std::net::TcpListener,
+        reporter: &mut Reporter,
+    ) -> Result<Connection, IoError> {
+        let listener = listener
+            .set_nonblocking(true)
+            .and_then(|()| TcpListener::from_std(listener))
+            .map_err(InetError::Accept)?;
+        reporter.report(Event::listen_start(
+            listener.local_addr().map_err(InetError::Accept)?,
         ))?;
-        let conn = if self.tls {
+        let (conn, peer) = listener.accept().await.map_err(InetError::Accept)?;
+        // Stop accepting further connections
+        drop(listener);
+        self.apply_socket_options(&conn)
+            .map_err(InetError::SocketOptions)?;
+        reporter.report(Event::accepted(peer))?;
+        let conn = if let Some(identity) = self.identity.as_ref() {
             reporter.report(Event::tls_start())?;
-            let conn = tls::connect(conn, self.servername.as_ref().unwrap_or(&self.host))
-                .await
-                .map_err(InetError::Tls)?;
-            reporter.report(Event::tls_finish())?;
-            Either::Right(conn)
+            let (conn, _) = tls::CleartextGuard::new(conn);
+            let conn = tls::accept(conn, identity).await.map_err(InetError::Tls)?;
+            reporter.report(Event::tls_finish(None))?;
+            boxed(conn)
         } else {
-            Either::Left(conn)
+            self.start_tls(conn, reporter).await?.0
         };
-        Ok(Framed::new(conn, self.codec()))
-    }
-
-    fn codec(&self) -> ConfabCodec {
-        ConfabCodec::new_with_max_length(self.max_line_length.get())
-            .encoding(self.encoding)
-            .crlf(self.crlf)
+        Ok(self.frame(conn))
     }
-}
 
-async fn ioloop<S>(
-    frame: &mut Connection,
-    input: S,
-    reporter: &mut Reporter,
-) -> Result<ConnectState, IoError>
-where
-    S: Stream<Item = Result<Input, InterfaceError>> + Send,
-{
-    tokio::pin!(input);
-    loop {
-        tokio::select! {
-            r = frame.next() => match r {
-                Some(Ok(msg)) => reporter.report(Event::recv(msg))?,
-                Some(Err(e)) => return Err(IoError::Inet(InetError::Recv(e))),
-                None => return Ok(ConnectState::Closed),
-            },
-            r = input.next() => match r {
-                Some(Ok(Input::Line(line))) => {
-                    let line = frame.codec().prepare_line(line);
-                    frame.send(&line).await.map_err(InetError::Send)?;
-                    reporter.report(Event::send(line))?;
+    /// If `--tls` was given, perform a TLS handshake over `conn`.  Returns
+    /// the resulting stream and how long the handshake took.
+    async fn start_tls(
+        &self,
+        conn: TcpStream,
+        reporter: &mut Reporter,
+    ) -> Result<(BoxedTransport, Option<Duration>), IoError> {
+        if self.tls {
+            reporter.report(Event::tls_start())?;
+            let tls_begin = Instant::now();
+            let (conn, cleartext) = tls::CleartextGuard::new(conn);
+            let servername = self.servername.as_ref().unwrap_or(&self.host);
+            let r = tls::connect(conn, servername, self.sni, &self.tls_context).await;
+            let tls_time = tls_begin.elapsed();
+            // Report anything the server sent before starting the handshake
+            // even if the handshake failed, as it may explain why.
+            for line in cleartext.take().split_inclusive(|&b| b == b'\n') {
+                reporter.report(Event::pre_tls_data(
+                    self.encoding.decode_lossy(line.to_vec()),
+                ))?;
+            }
+            let conn = r.map_err(InetError::Tls)?;
+            // Don't report the handshake as finished until the certificate
+            // has passed the `--tofu` check
+            #[cfg(feature = "rustls")]
+            let trusted = match self.tofu.as_ref() {
+                Some(store) => {
+                    let fingerprint = tls::peer_fingerprint(&conn).map_err(InetError::Tls)?;
+                    let endpoint = if self.host.contains(':') {
+                        format!("[{}]:{}", self.host, self.port)
+                    } else {
+                        format!("{}:{}", self.host, self.port)
+                    };
+                    let trust = store
+                        .check(&endpoint, &fingerprint)
+                        .map_err(|e| InetError::Tls(e.into()))?;
+                    (trust == Trust::New).then_some(fingerprint)
                 }
-                Some(Ok(Input::CtrlC)) => reporter.echo_ctrlc()?,
-                Some(Err(e)) => return Err(e.into()),
-                None => return Ok(ConnectState::Open),
+                None => None,
+            };
+            reporter.report(Event::tls_finish(tls::resumed(&conn)))?;
+            #[cfg(feature = "rustls")]
+            if let Some(fingerprint) = trusted {
+                reporter.report(Event::certificate_trusted(fingerprint))?;
             }
+            Ok((boxed(conn), Some(tls_time)))
+        } else {
+            Ok((boxed(conn), None))
+        }
+    }
+
+    /// Determine the addresses to connect to, either from a matching
+    /// `--resolve` override or by looking up the host
+    pub(crate) async fn resolve(&self) -> Result<Vec<SocketAddr>, InetError> {
+        if let Some(ovr) = self.override_for() {
+            return Ok(ovr
+                .addrs
+                .iter()
+                .map(|&ip| SocketAddr::new(ip, self.port))
+                .collect());
+        }
+        Ok(lookup_host((&*self.host, self.port))
+            .await
+            .map_err(InetError::Resolve)?
+            .collect())
+    }
+
+    pub(crate) fn apply_socket_options(&self, conn: &TcpStream) -> io::Result<()> {
+        if self.socket.nodelay {
+            conn.set_nodelay(true)?;
         }
+        if let Some(time) = self.socket.keepalive {
+            SockRef::from(conn).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        if let Some(ttl) = self.socket.ttl {
+            conn.set_ttl(ttl)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn override_for(&self) -> Option<&ResolveOverride> {
+        self.resolve
+            .iter()
+            .find(|ovr| ovr.matches(&self.host, self.port))
+    }
+
+    /// Wrap an established transport in the line codec
+    fn frame(&self, conn: BoxedTransport) -> Connection {
+        Framed::new(conn, self.codec())
+    }
+
+    fn codec(&self) -> ConfabCodec {
+        ConfabCodec::new_with_max_length(self.max_line_length.get())
+            .encoding(self.encoding)
+            .send_ending(self.send_ending)
+            .line_ending(self.line_ending)
+            .framing(self.framing)
+            .fatal_decode_errors(self.fatal_decode_errors)
     }
 }
 
-fn init_readline() -> Result<(Readline, SharedWriter), InterfaceError> {
-    let (mut rl, shared) = Readline::new(String::from("confab> ")).map_err(InterfaceError::Init)?;
-    rl.should_print_line_on(false, false);
-    Ok((rl, shared))
+fn init_editor(
+    keymap: KeyMap,
+    completer: Completer,
+    tui: bool,
+) -> Result<(Editor, SharedWriter), InterfaceError> {
+    Editor::new(String::from(PROMPT), keymap, completer, tui).map_err(InterfaceError::Init)
 }
@@ -0,0 +1,270 @@
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST, INT};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Something a script asked to be done during a callback, to be carried out
+/// once the callback returns
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Action {
+    /// Send a line to the server
+    Send(String),
+    /// Display a message
+    Log(String),
+}
+
+/// A timer set by a script with `after()` or `every()`
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Timer {
+    id: INT,
+    deadline: Instant,
+    /// For timers set with `every()`, the interval at which the timer repeats
+    period: Option<Duration>,
+    /// Name of the script function to call when the timer fires
+    callback: String,
+}
+
+/// State shared between a `Script` and the functions it registers with its
+/// engine
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Shared {
+    actions: Vec<Action>,
+    timers: Vec<Timer>,
+    next_id: INT,
+}
+
+impl Shared {
+    fn add_timer(&mut self, ms: INT, repeat: bool, callback: &str) -> INT {
+        let delay = Duration::from_millis(u64::try_from(ms).unwrap_or_default());
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id: self.next_id,
+            deadline: Instant::now() + delay,
+            // A zero period would make the timer fire continuously
+            period: repeat.then(|| delay.max(Duration::from_millis(1))),
+            callback: callback.to_owned(),
+        });
+        self.next_id
+    }
+}
+
+/// A Rhai script given with `--script`, which automates the session by
+/// defining callback functions:
+///
+/// - `on_connect()` — called once the connection is established
+/// - `on_recv(line)` — called with each line received from the server,
+///   without its line ending
+///
+/// Scripts can call `send(line)` to send a line to the server, `log(msg)` to
+/// display a message, `after(ms, "name")` & `every(ms, "name")` to call the
+/// function `name` once after a delay or repeatedly at an interval, and
+/// `cancel(id)` to cancel a timer using the ID returned by `after()` or
+/// `every()`.  Callbacks are called with `this` bound to an object map that
+/// persists across calls, for storing state.
+#[derive(Debug)]
+pub(crate) struct Script {
+    engine: Engine,
+    ast: AST,
+    /// The object bound to `this` in callbacks
+    state: Dynamic,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Script {
+    /// Compile the script at `path` and run its top-level statements
+    pub(crate) fn load(path: &Path) -> Result<Script, ScriptError> {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let mut engine = Engine::new();
+        let sh = Arc::clone(&shared);
+        engine.register_fn("send", move |line: &str| {
+            lock(&sh).actions.push(Action::Send(line.to_owned()));
+        });
+        let sh = Arc::clone(&shared);
+        engine.register_fn("log", move |msg: &str| {
+            lock(&sh).actions.push(Action::Log(msg.to_owned()));
+        });
+        // Also capture the output of `print()` & `debug()`, which would
+        // otherwise go straight to stdout
+        let sh = Arc::clone(&shared);
+        engine.on_print(move |msg| lock(&sh).actions.push(Action::Log(msg.to_owned())));
+        let sh = Arc::clone(&shared);
+        engine.on_debug(move |msg, _, _| lock(&sh).actions.push(Action::Log(msg.to_owned())));
+        let sh = Arc::clone(&shared);
+        engine.register_fn("after", move |ms: INT, callback: &str| {
+            lock(&sh).add_timer(ms, false, callback)
+        });
+        let sh = Arc::clone(&shared);
+        engine.register_fn("every", move |ms: INT, callback: &str| {
+            lock(&sh).add_timer(ms, true, callback)
+        });
+        let sh = Arc::clone(&shared);
+        engine.register_fn("cancel", move |id: INT| {
+            lock(&sh).timers.retain(|t| t.id != id);
+        });
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| ScriptError(e.to_string()))?;
+        engine
+            .run_ast(&ast)
+            .map_err(|e| ScriptError(e.to_string()))?;
+        Ok(Script {
+            engine,
+            ast,
+            state: Map::new().into(),
+            shared,
+        })
+    }
+
+    pub(crate) fn on_connect(&mut self) -> Result<(), ScriptError> {
+        self.call("on_connect", ())
+    }
+
+    pub(crate) fn on_recv(&mut self, line: &str) -> Result<(), ScriptError> {
+        self.call("on_recv", (line.to_owned(),))
+    }
+
+    /// Return the time at which the next timer fires, if any
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        lock(&self.shared).timers.iter().map(|t| t.deadline).min()
+    }
+
+    /// Call the callbacks of all timers that are due as of `now`.  If a
+    /// callback fails, the remaining due timers are left for the next call.
+    pub(crate) fn fire_timers(&mut self, now: Instant) -> Result<(), ScriptError> {
+        let due = lock(&self.shared)
+            .timers
+            .iter()
+            .filter(|t| t.deadline <= now)
+            .map(|t| t.id)
+            .collect::<Vec<_>>();
+        for id in due {
+            let callback = {
+                let mut shared = lock(&self.shared);
+                // The timer may have been cancelled by an earlier callback
+                let Some(i) = shared.timers.iter().position(|t| t.id == id) else {
+                    continue;
+                };
+                match shared.timers[i].period {
+                    Some(period) => {
+                        let timer = &mut shared.timers[i];
+                        timer.deadline = (timer.deadline + period).max(now);
+                        timer.callback.clone()
+                    }
+                    None => shared.timers.remove(i).callback,
+                }
+            };
+            self.call(&callback, ())?;
+        }
+        Ok(())
+    }
+
+    /// Return the actions requested by the script since the last call
+    pub(crate) fn take_actions(&mut self) -> Vec<Action> {
+        std::mem::take(&mut lock(&self.shared).actions)
+    }
+
+    /// Call the script function `name` with the given arguments, if the
+    /// script defines such a function
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> Result<(), ScriptError> {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(());
+        }
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args)
+            .map(|_| ())
+            .map_err(|e| ScriptError(format!("{name}(): {e}")))
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    // The lock is never held while running script code, so poisoning can
+    // only come from a panic in our own functions, after which the state is
+    // still usable.
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("{0}")]
+pub(crate) struct ScriptError(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn load(src: &str) -> Script {
+        let mut tmp = NamedTempFile::new().unwrap();
+        tmp.write_all(src.as_bytes()).unwrap();
+        Script::load(tmp.path()).unwrap()
+    }
+
+    #[test]
+    fn test_on_recv() {
+        let mut script = load(concat!(
+            "fn on_connect() { this.greeted = false; log(\"hello\"); }\n",
+            "fn on_recv(line) {\n",
+            "    if line.starts_with(\"220 \") && !this.greeted {\n",
+            "        this.greeted = true;\n",
+            "        send(\"EHLO example.com\");\n",
+            "    }\n",
+            "}\n",
+        ));
+        script.on_connect().unwrap();
+        assert_eq!(script.take_actions(), [Action::Log(String::from("hello"))]);
+        script.on_recv("220 mail.example.com ESMTP").unwrap();
+        script.on_recv("220 mail.example.com ESMTP").unwrap();
+        assert_eq!(
+            script.take_actions(),
+            [Action::Send(String::from("EHLO example.com"))]
+        );
+        assert!(script.take_actions().is_empty());
+    }
+
+    #[test]
+    fn test_missing_callbacks() {
+        let mut script = load("print(\"loaded\");");
+        script.on_connect().unwrap();
+        script.on_recv("foo").unwrap();
+        assert_eq!(script.take_actions(), [Action::Log(String::from("loaded"))]);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut script = load(concat!(
+            "fn on_connect() { this.n = 0; this.id = every(0, \"tick\"); after(0, \"once\"); }\n",
+            "fn tick() { this.n += 1; send(`tick ${this.n}`); if this.n == 2 { cancel(this.id); } }\n",
+            "fn once() { send(\"once\"); }\n",
+        ));
+        assert_eq!(script.next_deadline(), None);
+        script.on_connect().unwrap();
+        let now = Instant::now();
+        assert!(script.next_deadline().is_some_and(|t| t <= now));
+        script.fire_timers(now).unwrap();
+        assert_eq!(
+            script.take_actions(),
+            [
+                Action::Send(String::from("tick 1")),
+                Action::Send(String::from("once")),
+            ]
+        );
+        let later = now + Duration::from_millis(5);
+        script.fire_timers(later).unwrap();
+        assert_eq!(
+            script.take_actions(),
+            [Action::Send(String::from("tick 2"))]
+        );
+        assert_eq!(script.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let mut script = load("fn on_recv(line) { line.no_such_method(); }");
+        let e = script.on_recv("foo").unwrap_err();
+        assert!(e.to_string().starts_with("on_recv(): "), "{e}");
+    }
+}
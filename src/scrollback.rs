@@ -0,0 +1,121 @@
+use crate::memory::{MemoryBudget, Retained, RetainedQueue};
+use regex::Regex;
+
+/// An in-memory record of the most recent events displayed on standard
+/// output, so that they can be shown again with `/search` and `/last`.
+/// Entries count against the session's memory budget, and the oldest entries
+/// are discarded if the budget is exceeded.
+#[derive(Debug)]
+pub(crate) struct Scrollback {
+    /// Maximum number of entries to keep; 0 disables the scrollback
+    capacity: usize,
+    entries: RetainedQueue<Entry>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    /// The event's message without styling, sigil, or timestamp, for
+    /// matching against search patterns
+    text: String,
+    /// The text that was written to standard output for the event
+    rendered: String,
+}
+
+impl Retained for Entry {
+    fn mem_size(&self) -> usize {
+        self.text.len() + self.rendered.len()
+    }
+}
+
+impl Scrollback {
+    pub(crate) fn new(capacity: usize, budget: MemoryBudget) -> Scrollback {
+        Scrollback {
+            capacity,
+            entries: RetainedQueue::new(budget),
+        }
+    }
+
+    /// Record an event that was displayed, discarding the oldest entry if
+    /// the scrollback is full
+    pub(crate) fn push(&mut self, text: String, rendered: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry { text, rendered });
+    }
+
+    /// Return the rendered text of the last `n` entries, oldest first
+    pub(crate) fn last(&self, n: usize) -> Vec<String> {
+        let skip = self.entries.len().saturating_sub(n);
+        self.entries
+            .iter()
+            .skip(skip)
+            .map(|e| e.rendered.clone())
+            .collect()
+    }
+
+    /// Return the rendered text of all entries whose message matches `rgx`,
+    /// oldest first
+    pub(crate) fn search(&self, rgx: &Regex) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|e| rgx.is_match(&e.text))
+            .map(|e| e.rendered.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(capacity: usize, count: usize) -> Scrollback {
+        let mut sb = Scrollback::new(capacity, MemoryBudget::default());
+        for i in 0..count {
+            sb.push(format!("line {i}"), format!("< line {i}\n"));
+        }
+        sb
+    }
+
+    #[test]
+    fn test_last() {
+        let sb = filled(10, 4);
+        assert_eq!(sb.last(2), ["< line 2\n", "< line 3\n"]);
+        assert_eq!(sb.last(10).len(), 4);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let sb = filled(3, 5);
+        assert_eq!(sb.last(5), ["< line 2\n", "< line 3\n", "< line 4\n"]);
+        assert!(filled(0, 5).last(5).is_empty());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = MemoryBudget::new(Some(40));
+        let mut sb = Scrollback::new(10, budget.clone());
+        for i in 0..4 {
+            sb.push(format!("line {i}"), format!("< line {i}\n"));
+        }
+        // Each entry takes up 15 bytes, so only two fit
+        assert_eq!(sb.last(5), ["< line 2\n", "< line 3\n"]);
+        assert_eq!(budget.used(), 30);
+        assert_eq!(budget.evictions(), 2);
+    }
+
+    #[test]
+    fn test_search() {
+        let sb = filled(20, 12);
+        let rgx = Regex::new(r"^line 1\d?$").unwrap();
+        assert_eq!(
+            sb.search(&rgx),
+            ["< line 1\n", "< line 10\n", "< line 11\n"]
+        );
+        let rgx = Regex::new(r"^< line \d").unwrap();
+        assert!(sb.search(&rgx).is_empty());
+    }
+}
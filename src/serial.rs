@@ -0,0 +1,85 @@
+use std::str::FromStr;
+use thiserror::Error;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// The baud rate used when `--serial` is given without one
+pub(crate) const DEFAULT_BAUD: u32 = 9600;
+
+/// A serial device and baud rate, as given with `--serial <DEVICE>[,BAUD]`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct SerialSpec {
+    pub(crate) device: String,
+    pub(crate) baud: u32,
+}
+
+impl SerialSpec {
+    /// Open the device for asynchronous use.  The port is configured for
+    /// 8 data bits, no parity, one stop bit, and no flow control.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub(crate) fn open(&self) -> Result<SerialStream, tokio_serial::Error> {
+        tokio_serial::new(&self.device, self.baud).open_native_async()
+    }
+}
+
+impl FromStr for SerialSpec {
+    type Err = ParseSerialSpecError;
+
+    fn from_str(s: &str) -> Result<SerialSpec, ParseSerialSpecError> {
+        let (device, baud) = match s.rsplit_once(',') {
+            Some((device, baud)) => {
+                let baud = baud
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&b| b > 0)
+                    .ok_or(ParseSerialSpecError::Baud)?;
+                (device, baud)
+            }
+            None => (s, DEFAULT_BAUD),
+        };
+        if device.is_empty() {
+            return Err(ParseSerialSpecError::NoDevice);
+        }
+        Ok(SerialSpec {
+            device: device.to_owned(),
+            baud,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub(crate) enum ParseSerialSpecError {
+    #[error("no device given")]
+    NoDevice,
+    #[error("baud rate must be a positive integer")]
+    Baud,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("/dev/ttyUSB0", "/dev/ttyUSB0", DEFAULT_BAUD)]
+    #[case("/dev/ttyUSB0,115200", "/dev/ttyUSB0", 115_200)]
+    #[case("COM3,9600", "COM3", 9600)]
+    fn test_parse_serial_spec(#[case] s: &str, #[case] device: &str, #[case] baud: u32) {
+        assert_eq!(
+            s.parse::<SerialSpec>(),
+            Ok(SerialSpec {
+                device: device.to_owned(),
+                baud
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("", ParseSerialSpecError::NoDevice)]
+    #[case(",9600", ParseSerialSpecError::NoDevice)]
+    #[case("/dev/ttyS0,fast", ParseSerialSpecError::Baud)]
+    #[case("/dev/ttyS0,0", ParseSerialSpecError::Baud)]
+    fn test_parse_bad_serial_spec(#[case] s: &str, #[case] err: ParseSerialSpecError) {
+        assert_eq!(s.parse::<SerialSpec>(), Err(err));
+    }
+}
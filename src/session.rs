@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, rename, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Name of the directory within confab's state directory in which session
+/// state files are kept
+const SESSIONS_DIR: &str = "sessions";
+
+/// State carried over from one run of `confab --session NAME` to the next
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub(crate) struct SessionState {
+    /// The endpoint that the session last connected to
+    pub(crate) target: Option<SavedTarget>,
+    /// Lines entered at the prompt, oldest first
+    pub(crate) history: Vec<String>,
+    /// Values of the variables captured by `--capture` rules
+    pub(crate) variables: BTreeMap<String, String>,
+    /// Settings changed with `/set`, as values given to `/set` keyed by
+    /// setting name
+    pub(crate) settings: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct SavedTarget {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) tls: bool,
+}
+
+/// A named session whose state is loaded from & saved to a JSON file in
+/// confab's state directory
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Session {
+    path: PathBuf,
+    pub(crate) state: SessionState,
+    /// Whether to record the endpoint connected to in the state.  This is
+    /// false when the connection was made by some means other than the HOST
+    /// and PORT arguments, such as `--listen`.
+    pub(crate) save_target: bool,
+}
+
+impl Session {
+    /// Load the state of the session with the given name, starting with an
+    /// empty state if the session has not been saved before
+    pub(crate) fn open(name: &str) -> Result<Session, SessionError> {
+        let path = Session::default_dir()
+            .ok_or(SessionError::NoStateDir)?
+            .join(format!("{name}.json"));
+        Session::load(path)
+    }
+
+    fn load(path: PathBuf) -> Result<Session, SessionError> {
+        let state = match read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).map_err(|source| SessionError::Parse {
+                path: path.clone(),
+                source,
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SessionState::default(),
+            Err(source) => return Err(SessionError::Read { path, source }),
+        };
+        Ok(Session {
+            path,
+            state,
+            save_target: true,
+        })
+    }
+
+    /// Return the directory in which session state files are kept:
+    /// `confab/sessions` in the user's state directory (`$XDG_STATE_HOME`,
+    /// `~/.local/state`, or `%LOCALAPPDATA%`)
+    fn default_dir() -> Option<PathBuf> {
+        let nonempty = |var| std::env::var_os(var).filter(|v| !v.is_empty());
+        let statedir = nonempty("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| nonempty("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+            .or_else(|| nonempty("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(statedir.join("confab").join(SESSIONS_DIR))
+    }
+
+    /// Write the session's state to its file, replacing the previous state
+    pub(crate) fn save(&self) -> Result<(), SessionError> {
+        let data = serde_json::to_string_pretty(&self.state)
+            .expect("serializing session state should not fail");
+        self.write(&data).map_err(|source| SessionError::Write {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    fn write(&self, data: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so that the previous state isn't
+        // lost if writing fails partway through
+        let tmp = self.path.with_extension("json.tmp");
+        write(&tmp, data)?;
+        rename(&tmp, &self.path)
+    }
+}
+
+/// Check that a session name given to `--session` is nonempty and can be
+/// used as a filename
+pub(crate) fn parse_session_name(s: &str) -> Result<String, InvalidSessionName> {
+    if s.is_empty() || s.starts_with('.') || s.contains(['/', '\\']) {
+        Err(InvalidSessionName)
+    } else {
+        Ok(s.to_owned())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+#[error(r#"must be nonempty and must not start with "." or contain "/" or "\""#)]
+pub(crate) struct InvalidSessionName;
+
+#[derive(Debug, Error)]
+pub(crate) enum SessionError {
+    #[error("could not determine where to keep session state; set $XDG_STATE_HOME")]
+    NoStateDir,
+    #[error("failed to read session state from {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to parse session state in {}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("failed to save session state to {}", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_load() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("sessions").join("demo.json");
+        let mut session = Session::load(path.clone()).unwrap();
+        assert_eq!(session.state, SessionState::default());
+        session.state.target = Some(SavedTarget {
+            host: String::from("example.com"),
+            port: 25,
+            tls: false,
+        });
+        session.state.history = vec![String::from("EHLO me"), String::from("QUIT")];
+        session
+            .state
+            .variables
+            .insert(String::from("TOKEN"), String::from("abc123"));
+        session
+            .state
+            .settings
+            .insert(String::from("crlf"), String::from("on"));
+        session.save().unwrap();
+        assert_eq!(Session::load(path).unwrap(), session);
+    }
+
+    #[test]
+    fn test_load_partial() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("demo.json");
+        write(&path, r#"{"history": ["HELP"], "future": 42}"#).unwrap();
+        let session = Session::load(path).unwrap();
+        assert_eq!(
+            session.state,
+            SessionState {
+                history: vec![String::from("HELP")],
+                ..SessionState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("demo.json");
+        write(&path, "not json").unwrap();
+        assert_matches!(Session::load(path), Err(SessionError::Parse { .. }));
+    }
+
+    #[rstest]
+    #[case("smtp-test", true)]
+    #[case("prod.db", true)]
+    #[case("", false)]
+    #[case(".hidden", false)]
+    #[case("a/b", false)]
+    #[case(r"a\b", false)]
+    fn test_parse_session_name(#[case] name: &str, #[case] ok: bool) {
+        assert_eq!(parse_session_name(name).is_ok(), ok);
+    }
+}
@@ -0,0 +1,188 @@
+use std::num::NonZeroUsize;
+
+/// A binary protocol that can be recognized from the first bytes received on
+/// a connection that is expected to carry lines of text
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum BinaryProtocol {
+    /// A TLS record, as sent by a server that expects a TLS handshake or by a
+    /// client starting one
+    Tls,
+    /// A gzip stream
+    Gzip,
+    /// The HTTP/2 connection preface or an HTTP/2 SETTINGS frame
+    Http2,
+}
+
+impl BinaryProtocol {
+    const ALL: [BinaryProtocol; 3] = [
+        BinaryProtocol::Tls,
+        BinaryProtocol::Gzip,
+        BinaryProtocol::Http2,
+    ];
+
+    /// The name of the protocol as used in the transcript
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            BinaryProtocol::Tls => "tls",
+            BinaryProtocol::Gzip => "gzip",
+            BinaryProtocol::Http2 => "http2",
+        }
+    }
+
+    /// Look up a protocol by the name returned by `as_str()`
+    pub(crate) fn from_name(name: &str) -> Option<BinaryProtocol> {
+        BinaryProtocol::ALL.into_iter().find(|p| p.as_str() == name)
+    }
+
+    /// The warning shown when the protocol is detected
+    pub(crate) fn warning(self) -> &'static str {
+        match self {
+            BinaryProtocol::Tls => {
+                "Received data looks like TLS, not lines of text; is --tls needed?"
+            }
+            BinaryProtocol::Gzip => {
+                "Received data looks like gzip-compressed data, not lines of text"
+            }
+            BinaryProtocol::Http2 => "Received data looks like HTTP/2, not lines of text",
+        }
+    }
+
+    /// The number of bytes needed to check for the protocol
+    fn signature_len(self) -> usize {
+        match self {
+            BinaryProtocol::Tls => 3,
+            BinaryProtocol::Gzip => 2,
+            BinaryProtocol::Http2 => HTTP2_PREFACE.len(),
+        }
+    }
+
+    /// Returns true if `data` starts with the protocol's signature
+    fn matches(self, data: &[u8]) -> bool {
+        match self {
+            // Content type (change cipher spec, alert, handshake, or
+            // application data), followed by a protocol version from SSL 3.0
+            // through TLS 1.3
+            BinaryProtocol::Tls => {
+                matches!(data, [0x14..=0x17, 0x03, 0x00..=0x04, ..])
+            }
+            BinaryProtocol::Gzip => data.starts_with(b"\x1F\x8B"),
+            BinaryProtocol::Http2 => data.starts_with(HTTP2_PREFACE) || is_settings_frame(data),
+        }
+    }
+}
+
+/// The start of the connection preface sent by HTTP/2 clients
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0";
+
+/// Returns true if `data` starts with the header of an HTTP/2 SETTINGS frame
+/// on stream 0, which is the first thing an HTTP/2 server sends
+fn is_settings_frame(data: &[u8]) -> bool {
+    let &[l1, l2, l3, 0x04, 0x00..=0x01, 0, 0, 0, 0, ..] = data else {
+        return false;
+    };
+    u32::from_be_bytes([0, l1, l2, l3]) % 6 == 0
+}
+
+/// Examines the first bytes received on a connection for signs that the
+/// server is speaking a binary protocol rather than sending lines of text
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Sniffer {
+    /// The bytes received so far, up to `limit`
+    buf: Vec<u8>,
+    /// How many bytes to examine before giving up
+    limit: usize,
+    /// Whether a protocol has been detected or ruled out
+    done: bool,
+}
+
+impl Sniffer {
+    pub(crate) fn new(limit: NonZeroUsize) -> Sniffer {
+        Sniffer {
+            buf: Vec::new(),
+            limit: limit.get(),
+            done: false,
+        }
+    }
+
+    /// Examine the next bytes received from the server.  Returns the protocol
+    /// the first time that the data received so far is recognized; once a
+    /// protocol has been recognized or ruled out, all further data is
+    /// ignored.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Option<BinaryProtocol> {
+        if self.done {
+            return None;
+        }
+        let take = data.len().min(self.limit - self.buf.len());
+        self.buf.extend_from_slice(&data[..take]);
+        let mut undecided = false;
+        for proto in BinaryProtocol::ALL {
+            if self.buf.len() < proto.signature_len() {
+                undecided = true;
+            } else if proto.matches(&self.buf) {
+                self.finish();
+                return Some(proto);
+            }
+        }
+        if !undecided || self.buf.len() >= self.limit {
+            self.finish();
+        }
+        None
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.buf = Vec::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(b"\x15\x03\x03\x00\x02\x02\x46", Some(BinaryProtocol::Tls))]
+    #[case(
+        b"\x16\x03\x01\x02\x00\x01\x00\x01\xFC\x03\x03",
+        Some(BinaryProtocol::Tls)
+    )]
+    #[case(b"\x1F\x8B\x08\x00\x00\x00\x00\x00", Some(BinaryProtocol::Gzip))]
+    #[case(
+        b"\x00\x00\x12\x04\x00\x00\x00\x00\x00\x00\x03\x00\x00\x00\x64",
+        Some(BinaryProtocol::Http2)
+    )]
+    #[case(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n", Some(BinaryProtocol::Http2))]
+    #[case(b"220 smtp.example.com ESMTP\r\n", None)]
+    #[case(b"\x16\x04\x01 not TLS after all\n", None)]
+    #[case(b"\x00\x00\x12\x04\x00\x00\x00\x00\x01\x00\x03\x00\x00\x00\x64", None)]
+    fn test_sniff(#[case] data: &[u8], #[case] proto: Option<BinaryProtocol>) {
+        let mut sniffer = Sniffer::new(NonZeroUsize::new(16).unwrap());
+        assert_eq!(sniffer.feed(data), proto);
+        assert!(sniffer.done);
+    }
+
+    #[test]
+    fn test_sniff_split() {
+        let mut sniffer = Sniffer::new(NonZeroUsize::new(16).unwrap());
+        assert_eq!(sniffer.feed(b"\x1F"), None);
+        assert!(!sniffer.done);
+        assert_eq!(sniffer.feed(b"\x8B\x08"), Some(BinaryProtocol::Gzip));
+        assert_eq!(sniffer.feed(b"\x1F\x8B"), None);
+    }
+
+    #[test]
+    fn test_sniff_limit() {
+        let mut sniffer = Sniffer::new(NonZeroUsize::new(4).unwrap());
+        assert_eq!(sniffer.feed(b"PRI * HTTP/2.0\r\n"), None);
+        assert!(sniffer.done);
+        assert_eq!(sniffer.feed(b"\x16\x03\x03"), None);
+    }
+
+    #[test]
+    fn test_protocol_names() {
+        for proto in BinaryProtocol::ALL {
+            assert_eq!(BinaryProtocol::from_name(proto.as_str()), Some(proto));
+        }
+        assert_eq!(BinaryProtocol::from_name("ssh"), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Running totals of the lines & bytes transferred in one direction
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Counter {
+    pub(crate) lines: u64,
+    pub(crate) bytes: u64,
+}
+
+impl Counter {
+    pub(crate) fn record(&mut self, bytes: usize) {
+        self.lines += 1;
+        self.bytes = self.bytes.saturating_add(to_u64(bytes));
+    }
+}
+
+/// Traffic totals for a session
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SessionStats {
+    pub(crate) sent: Counter,
+    pub(crate) recv: Counter,
+    /// When the connection was established
+    pub(crate) started: Option<Instant>,
+    /// When a line was last received
+    pub(crate) last_recv: Option<Instant>,
+    /// When data was last sent
+    pub(crate) last_send: Option<Instant>,
+}
+
+impl SessionStats {
+    /// Record the receipt of a line of `bytes` bytes
+    pub(crate) fn record_recv(&mut self, bytes: usize) {
+        self.recv.record(bytes);
+        self.last_recv = Some(Instant::now());
+    }
+
+    /// Record the sending of `bytes` bytes
+    pub(crate) fn record_send(&mut self, bytes: usize) {
+        self.sent.record(bytes);
+        self.last_send = Some(Instant::now());
+    }
+
+    /// Time since data was last sent or received, or since the connection
+    /// was established if nothing has been transferred yet
+    pub(crate) fn idle(&self) -> Duration {
+        self.last_recv
+            .max(self.last_send)
+            .or(self.started)
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Time since the connection was established
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started.map(|t| t.elapsed()).unwrap_or_default()
+    }
+
+    /// Capture the statistics as of the current moment
+    pub(crate) fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            sent: self.sent,
+            recv: self.recv,
+            duration: self.elapsed(),
+            idle: self.last_recv.map(|t| t.elapsed()),
+        }
+    }
+
+    pub(crate) fn counter(&self, direction: Direction) -> &Counter {
+        match direction {
+            Direction::Send => &self.sent,
+            Direction::Recv => &self.recv,
+        }
+    }
+}
+
+/// Statistics for a session as of a given moment
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct StatsSnapshot {
+    pub(crate) sent: Counter,
+    pub(crate) recv: Counter,
+    /// Time since the connection was established
+    pub(crate) duration: Duration,
+    /// Time since a line was last received, if any
+    pub(crate) idle: Option<Duration>,
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Sent {} lines ({} bytes), received {} lines ({} bytes) over {:.1}s; ",
+            self.sent.lines,
+            self.sent.bytes,
+            self.recv.lines,
+            self.recv.bytes,
+            self.duration.as_secs_f64(),
+        )?;
+        match self.idle {
+            Some(idle) => write!(f, "last received {:.1}s ago", idle.as_secs_f64()),
+            None => write!(f, "nothing received"),
+        }
+    }
+}
+
+/// Period over which `--show-throughput` transfer rates are measured
+pub(crate) const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Recent readings of a session's traffic totals, from which transfer rates
+/// over the last `THROUGHPUT_WINDOW` are computed
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ThroughputMeter {
+    /// (time, sent, received) readings, oldest first
+    samples: VecDeque<(Instant, Counter, Counter)>,
+}
+
+impl ThroughputMeter {
+    /// Record the session's totals as of `now` and return the transfer rates
+    /// since the latest reading at least `THROUGHPUT_WINDOW` old (or since
+    /// the first reading, if none are that old)
+    pub(crate) fn sample(&mut self, now: Instant, stats: &SessionStats) -> Throughput {
+        if let Some(cutoff) = now.checked_sub(THROUGHPUT_WINDOW) {
+            while self.samples.get(1).is_some_and(|&(t, _, _)| t <= cutoff) {
+                self.samples.pop_front();
+            }
+        }
+        let throughput = match self.samples.front() {
+            Some(&(then, sent, recv)) => {
+                let secs = now.saturating_duration_since(then).as_secs_f64();
+                Throughput {
+                    sent: Rate::between(sent, stats.sent, secs),
+                    recv: Rate::between(recv, stats.recv, secs),
+                }
+            }
+            None => Throughput::default(),
+        };
+        self.samples.push_back((now, stats.sent, stats.recv));
+        throughput
+    }
+}
+
+/// Transfer rates in each direction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Throughput {
+    pub(crate) sent: Rate,
+    pub(crate) recv: Rate,
+}
+
+/// A transfer rate in one direction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Rate {
+    pub(crate) lines_per_sec: f64,
+    pub(crate) bytes_per_sec: f64,
+}
+
+impl Rate {
+    /// Compute the rate at which a counter went from `old` to `new` over
+    /// `secs` seconds
+    // Counts are nowhere near large enough for the conversion to lose
+    // meaningful precision
+    #[allow(clippy::cast_precision_loss)]
+    fn between(old: Counter, new: Counter, secs: f64) -> Rate {
+        if secs <= 0.0 {
+            return Rate::default();
+        }
+        Rate {
+            lines_per_sec: new.lines.saturating_sub(old.lines) as f64 / secs,
+            bytes_per_sec: new.bytes.saturating_sub(old.bytes) as f64 / secs,
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} lines/s, {:.1} KiB/s",
+            self.lines_per_sec,
+            self.bytes_per_sec / 1024.0
+        )
+    }
+}
+
+/// Limits on the total number of bytes that may be transferred in each
+/// direction over the course of a session
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Budget {
+    pub(crate) max_send_bytes: Option<u64>,
+    pub(crate) max_recv_bytes: Option<u64>,
+}
+
+impl Budget {
+    pub(crate) fn limit(&self, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::Send => self.max_send_bytes,
+            Direction::Recv => self.max_recv_bytes,
+        }
+    }
+
+    /// Returns `true` if transferring a further `bytes` bytes in the given
+    /// direction would not take the session's total over the limit
+    pub(crate) fn allows(&self, stats: &SessionStats, direction: Direction, bytes: usize) -> bool {
+        self.limit(direction).map_or(true, |limit| {
+            stats.counter(direction).bytes.saturating_add(to_u64(bytes)) <= limit
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Direction {
+    Send,
+    Recv,
+}
+
+impl Direction {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Send => "send",
+            Direction::Recv => "recv",
+        }
+    }
+}
+
+pub(crate) fn to_u64(n: usize) -> u64 {
+    u64::try_from(n).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_budget() {
+        let budget = Budget::default();
+        let mut stats = SessionStats::default();
+        stats.recv.record(1 << 20);
+        assert!(budget.allows(&stats, Direction::Recv, 1 << 20));
+        assert!(budget.allows(&stats, Direction::Send, 1 << 20));
+    }
+
+    #[test]
+    fn test_budget_boundary() {
+        let budget = Budget {
+            max_send_bytes: Some(10),
+            max_recv_bytes: None,
+        };
+        let mut stats = SessionStats::default();
+        stats.sent.record(4);
+        assert!(budget.allows(&stats, Direction::Send, 6));
+        assert!(!budget.allows(&stats, Direction::Send, 7));
+        assert!(budget.allows(&stats, Direction::Recv, 7));
+        assert_eq!(stats.sent, Counter { lines: 1, bytes: 4 });
+    }
+
+    #[test]
+    fn test_display_snapshot() {
+        let mut snapshot = StatsSnapshot {
+            sent: Counter {
+                lines: 2,
+                bytes: 14,
+            },
+            recv: Counter { lines: 0, bytes: 0 },
+            duration: Duration::from_millis(12345),
+            idle: None,
+        };
+        assert_eq!(
+            snapshot.to_string(),
+            "Sent 2 lines (14 bytes), received 0 lines (0 bytes) over 12.3s; nothing received"
+        );
+        snapshot.idle = Some(Duration::from_millis(1500));
+        assert!(snapshot.to_string().ends_with("; last received 1.5s ago"));
+    }
+
+    #[test]
+    fn test_throughput_window() {
+        let start = Instant::now();
+        let mut meter = ThroughputMeter::default();
+        let mut stats = SessionStats::default();
+        assert_eq!(meter.sample(start, &stats), Throughput::default());
+        for i in 1..=10 {
+            stats.recv.record(2048);
+            stats.recv.record(2048);
+            if i <= 5 {
+                stats.sent.record(10);
+            }
+            let tp = meter.sample(start + Duration::from_secs(i), &stats);
+            assert_eq!(
+                tp.recv,
+                Rate {
+                    lines_per_sec: 2.0,
+                    bytes_per_sec: 4096.0
+                }
+            );
+            if i == 10 {
+                // Nothing was sent during the last five seconds
+                assert_eq!(tp.sent, Rate::default());
+            }
+        }
+    }
+
+    #[test]
+    fn test_display_rate() {
+        let rate = Rate {
+            lines_per_sec: 2.5,
+            bytes_per_sec: 1536.0,
+        };
+        assert_eq!(rate.to_string(), "2.5 lines/s, 1.5 KiB/s");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::stats::{Rate, SessionStats};
+use crossterm::{
+    cursor::{MoveTo, MoveUp, RestorePosition, SavePosition},
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType},
+    QueueableCommand,
+};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// How often the status line is redrawn
+pub(crate) const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A line at the bottom of the terminal showing the state of the connection,
+/// kept separate from the prompt & scrolling output via the terminal's
+/// scrolling region.
+///
+/// Text written through the line editor's `SharedWriter` is only output
+/// while the `Editor` is being polled, which happens on the same task that
+/// draws the status line, so the two never write to the terminal at the same
+/// time.  The editor only moves the cursor relative to its current position,
+/// so restricting scrolling to the rows above the status line leaves the
+/// prompt undisturbed.
+#[derive(Debug)]
+pub(crate) struct StatusLine {
+    /// Terminal size as of the last draw
+    size: (u16, u16),
+    endpoint: String,
+}
+
+impl StatusLine {
+    /// Reserve the bottom row of the terminal for the status line.  `endpoint`
+    /// is the host & port shown in the line.
+    pub(crate) fn install(endpoint: String) -> io::Result<StatusLine> {
+        let status = StatusLine {
+            size: terminal::size()?,
+            endpoint,
+        };
+        let mut out = io::stdout().lock();
+        // Make sure the cursor isn't on the bottom row, scrolling the screen
+        // up if necessary
+        out.queue(Print("\n"))?.queue(MoveUp(1))?;
+        status.set_region(&mut out)?;
+        out.flush()?;
+        Ok(status)
+    }
+
+    /// Redraw the status line for the current state of the session.  `rate`
+    /// is the recent rate at which data has been received, shown under
+    /// `--show-throughput`.
+    pub(crate) fn draw(
+        &mut self,
+        stats: &SessionStats,
+        write_closed: bool,
+        rate: Option<Rate>,
+    ) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        let size = terminal::size()?;
+        if size != self.size {
+            self.size = size;
+            self.set_region(&mut out)?;
+        }
+        let text = status_text(&self.endpoint, stats, write_closed, rate);
+        let text = text
+            .chars()
+            .take(usize::from(self.size.0))
+            .collect::<String>();
+        out.queue(SavePosition)?
+            .queue(MoveTo(0, self.size.1.saturating_sub(1)))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(SetAttribute(Attribute::Reverse))?
+            .queue(Print(text))?
+            .queue(SetAttribute(Attribute::Reset))?
+            .queue(RestorePosition)?;
+        out.flush()
+    }
+
+    /// Change the host & port shown in the line, as after `/connect`
+    pub(crate) fn set_endpoint(&mut self, endpoint: String) {
+        self.endpoint = endpoint;
+    }
+
+    /// Clear the status line and give the bottom row back to the scrolling
+    /// region
+    pub(crate) fn remove(self) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.queue(SavePosition)?
+            .queue(Print("\x1B[r"))?
+            .queue(RestorePosition)?
+            .queue(SavePosition)?
+            .queue(MoveTo(0, self.size.1.saturating_sub(1)))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(RestorePosition)?;
+        out.flush()
+    }
+
+    /// Limit scrolling to all rows but the last.  Setting the scrolling
+    /// region moves the cursor to the top of the screen, so the cursor
+    /// position is saved & restored around it.
+    fn set_region<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let rows = self.size.1.saturating_sub(1).max(1);
+        out.queue(SavePosition)?
+            .queue(Print(format!("\x1B[1;{rows}r")))?
+            .queue(RestorePosition)?;
+        Ok(())
+    }
+}
+
+fn status_text(
+    endpoint: &str,
+    stats: &SessionStats,
+    write_closed: bool,
+    rate: Option<Rate>,
+) -> String {
+    let state = if write_closed {
+        "sending shut down"
+    } else {
+        "connected"
+    };
+    let rate = rate.map(|r| format!("| in {r} ")).unwrap_or_default();
+    format!(
+        " {state}: {endpoint} | sent {} | received {} | idle {}s {rate}",
+        human_bytes(stats.sent.bytes),
+        human_bytes(stats.recv.bytes),
+        stats.idle().as_secs(),
+    )
+}
+
+/// Format a byte count using binary prefixes, truncated to one decimal place
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut divisor = 1024u64;
+    let mut unit = UNITS[0];
+    for &u in &UNITS[1..] {
+        if bytes / divisor < 1024 {
+            break;
+        }
+        divisor <<= 10;
+        unit = u;
+    }
+    let tenths = (bytes % divisor) / (divisor / 10);
+    format!("{}.{tenths} {unit}", bytes / divisor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, "0 B")]
+    #[case(1023, "1023 B")]
+    #[case(1024, "1.0 KiB")]
+    #[case(1536, "1.5 KiB")]
+    #[case(5 << 20, "5.0 MiB")]
+    #[case(3 << 40, "3.0 TiB")]
+    #[case(5000 << 40, "5000.0 TiB")]
+    fn test_human_bytes(#[case] bytes: u64, #[case] s: &str) {
+        assert_eq!(human_bytes(bytes), s);
+    }
+
+    #[test]
+    fn test_status_text() {
+        let mut stats = SessionStats::default();
+        stats.record_send(42);
+        stats.record_recv(2048);
+        assert_eq!(
+            status_text("example.com:80", &stats, false, None),
+            " connected: example.com:80 | sent 42 B | received 2.0 KiB | idle 0s "
+        );
+        assert!(
+            status_text("example.com:80", &stats, true, None).starts_with(" sending shut down: ")
+        );
+        let rate = Rate {
+            lines_per_sec: 4.0,
+            bytes_per_sec: 512.0,
+        };
+        assert!(status_text("example.com:80", &stats, false, Some(rate))
+            .ends_with(" | idle 0s | in 4.0 lines/s, 0.5 KiB/s "));
+    }
+}
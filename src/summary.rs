@@ -0,0 +1,247 @@
+use crate::errors::ErrorKind;
+use crate::events::{ms, rate, ClosedBy, Event};
+use crate::stats::{to_u64, Counter};
+use crate::util::{now, JsonStrMap, TIMESTAMP_FMT};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Aggregate statistics for the whole run, written as a single JSON document
+/// to the file given with `--summary-file` when `confab` exits
+#[derive(Debug)]
+pub(crate) struct Summary {
+    fp: File,
+    host: String,
+    port: u16,
+    start: OffsetDateTime,
+    /// Number of connections established (or accepted, under `--listen`)
+    connections: u64,
+    sent: Counter,
+    recv: Counter,
+    /// Length in bytes of the longest line sent
+    longest_sent: u64,
+    /// Length in bytes of the longest line received
+    longest_recv: u64,
+    /// Total time that connections were open
+    connected: Duration,
+    errors: u64,
+    /// The kind & message of the most recent error
+    last_error: Option<(ErrorKind, String)>,
+    closed_by: Option<ClosedBy>,
+}
+
+impl Summary {
+    /// Create the file at `path`, truncating it if it already exists.  Nothing
+    /// is written to it until the end of the run.
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> io::Result<Summary> {
+        Ok(Summary::new(File::create(path)?))
+    }
+
+    fn new(fp: File) -> Summary {
+        Summary {
+            fp,
+            host: String::new(),
+            port: 0,
+            start: now(),
+            connections: 0,
+            sent: Counter::default(),
+            recv: Counter::default(),
+            longest_sent: 0,
+            longest_recv: 0,
+            connected: Duration::ZERO,
+            errors: 0,
+            last_error: None,
+            closed_by: None,
+        }
+    }
+
+    /// Update the statistics affected by an event
+    pub(crate) fn observe(&mut self, event: &Event) {
+        match event {
+            Event::SessionStart {
+                timestamp,
+                host,
+                port,
+                ..
+            } => {
+                self.start = *timestamp;
+                self.host.clone_from(host);
+                self.port = *port;
+            }
+            Event::ConnectFinish { .. } | Event::Accepted { .. } => self.connections += 1,
+            Event::Send { data, .. } => {
+                self.sent.record(data.len());
+                self.longest_sent = self.longest_sent.max(to_u64(data.len()));
+            }
+            Event::Recv { data, .. } => {
+                self.recv.record(data.len());
+                self.longest_recv = self.longest_recv.max(to_u64(data.len()));
+            }
+            Event::Disconnect {
+                closed_by,
+                duration,
+                ..
+            } => {
+                self.closed_by = Some(*closed_by);
+                self.connected += *duration;
+            }
+            Event::Error { kind, data, .. } => {
+                self.errors += 1;
+                self.last_error = Some((*kind, data.clone()));
+            }
+            _ => (),
+        }
+    }
+
+    /// Write the summary for a run that is ending with the given exit status
+    pub(crate) fn write(mut self, exit_code: u8, reason: ExitReason) -> io::Result<()> {
+        let doc = self.to_json(exit_code, reason, now());
+        writeln!(self.fp, "{doc}")?;
+        self.fp.flush()
+    }
+
+    fn to_json(&self, exit_code: u8, reason: ExitReason, end: OffsetDateTime) -> String {
+        let timestamp = |t: OffsetDateTime| {
+            t.format(&TIMESTAMP_FMT)
+                .expect("formatting a datetime as RFC 3339 should not fail")
+        };
+        let reason = match reason {
+            ExitReason::Closed => match self.closed_by {
+                Some(ClosedBy::Peer) => "closed-by-peer",
+                Some(ClosedBy::Local) | None => "closed-by-local",
+            },
+            ExitReason::BudgetExceeded => "budget-exceeded",
+            ExitReason::Error => "error",
+        };
+        let json = JsonStrMap::new()
+            .field("host", &self.host)
+            .raw_field("port", &self.port.to_string())
+            .field("start", &timestamp(self.start))
+            .field("end", &timestamp(end))
+            .raw_field(
+                "duration_ms",
+                &ms((end - self.start).try_into().unwrap_or_default()),
+            )
+            .raw_field("connected_ms", &ms(self.connected))
+            .raw_field("connections", &self.connections.to_string())
+            .raw_field("lines_sent", &self.sent.lines.to_string())
+            .raw_field("bytes_sent", &self.sent.bytes.to_string())
+            .raw_field("longest_line_sent", &self.longest_sent.to_string())
+            .raw_field(
+                "lines_sent_per_sec",
+                &rate(per_sec(self.sent.lines, self.connected)),
+            )
+            .raw_field("lines_recv", &self.recv.lines.to_string())
+            .raw_field("bytes_recv", &self.recv.bytes.to_string())
+            .raw_field("longest_line_recv", &self.longest_recv.to_string())
+            .raw_field(
+                "lines_recv_per_sec",
+                &rate(per_sec(self.recv.lines, self.connected)),
+            )
+            .raw_field("errors", &self.errors.to_string());
+        let json = match self.last_error.as_ref() {
+            Some((kind, message)) if reason == "error" => json
+                .field("error_kind", kind.as_str())
+                .field("error", message),
+            _ => json
+                .raw_field("error_kind", "null")
+                .raw_field("error", "null"),
+        };
+        json.field("exit_reason", reason)
+            .raw_field("exit_code", &exit_code.to_string())
+            .finish()
+    }
+}
+
+/// Why the run ended, as far as the runner can tell
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ExitReason {
+    /// The connection was closed normally, by either side
+    Closed,
+    /// A `--max-send-bytes` or `--max-recv-bytes` budget was exhausted
+    BudgetExceeded,
+    Error,
+}
+
+/// Return the average rate per second of `n` occurrences over `d`, or zero
+/// if `d` is zero
+// Counts are nowhere near large enough for the conversion to lose meaningful
+// precision
+#[allow(clippy::cast_precision_loss)]
+fn per_sec(n: u64, d: Duration) -> f64 {
+    let secs = d.as_secs_f64();
+    if secs > 0.0 {
+        n as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::InetError;
+    use crate::stats::StatsSnapshot;
+    use time::macros::datetime;
+
+    fn summary() -> Summary {
+        let tmpfile = tempfile::tempfile().unwrap();
+        let mut summary = Summary::new(tmpfile);
+        summary.observe(&Event::session_start("example.com", 25, Vec::new()));
+        summary.start = datetime!(2026-10-17 12:00:00 UTC);
+        summary
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = summary();
+        summary.observe(&Event::recv(
+            String::from("220 example.com ESMTP\r\n"),
+            Duration::ZERO,
+            false,
+        ));
+        summary.observe(&Event::send(String::from("QUIT\r\n"), Duration::ZERO));
+        summary.observe(&Event::recv(
+            String::from("221 Bye\r\n"),
+            Duration::ZERO,
+            false,
+        ));
+        let snapshot = StatsSnapshot {
+            sent: summary.sent,
+            recv: summary.recv,
+            duration: Duration::from_secs(2),
+            idle: None,
+        };
+        summary.observe(&Event::disconnect(ClosedBy::Peer, &snapshot));
+        assert_eq!(
+            summary.to_json(0, ExitReason::Closed, datetime!(2026-10-17 12:00:02.5 UTC)),
+            concat!(
+                r#"{"host": "example.com", "port": 25, "start": "2026-10-17T12:00:00.000000+00:00", "#,
+                r#""end": "2026-10-17T12:00:02.500000+00:00", "duration_ms": 2500.000, "#,
+                r#""connected_ms": 2000.000, "connections": 0, "lines_sent": 1, "bytes_sent": 6, "#,
+                r#""longest_line_sent": 6, "lines_sent_per_sec": 0.500, "lines_recv": 2, "#,
+                r#""bytes_recv": 32, "longest_line_recv": 23, "lines_recv_per_sec": 1.000, "#,
+                r#""errors": 0, "error_kind": null, "error": null, "#,
+                r#""exit_reason": "closed-by-peer", "exit_code": 0}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_summary_error() {
+        let mut summary = summary();
+        let e = InetError::NoResponse;
+        summary.observe(&Event::error(e.kind(), &e));
+        let json = summary.to_json(
+            e.exit_code(),
+            ExitReason::Error,
+            datetime!(2026-10-17 12:00:01 UTC),
+        );
+        assert!(
+            json.ends_with(r#""errors": 1, "error_kind": "recv", "error": "connection closed before the expected response was received", "exit_reason": "error", "exit_code": 4}"#),
+            "{json}"
+        );
+    }
+}
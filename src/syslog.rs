@@ -0,0 +1,179 @@
+use crate::util::TIMESTAMP_FMT;
+use clap::ValueEnum;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// Timestamp format of RFC 3164 headers, e.g., "Oct  7 09:05:01"
+static RFC3164_TIMESTAMP_FMT: &[FormatItem<'_>] =
+    format_description!("[month repr:short] [day padding:space] [hour]:[minute]:[second]");
+
+/// The syslog header format used by `--syslog-format`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum SyslogFormat {
+    /// The structured format of RFC 5424
+    Rfc5424,
+    /// The legacy BSD format of RFC 3164
+    Rfc3164,
+}
+
+/// A syslog facility, in order of numeric code
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum Facility {
+    Kern,
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Ntp,
+    Security,
+    Console,
+    SolarisCron,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+/// A syslog severity, in order of numeric code
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum Severity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+/// Wraps lines of input in syslog headers before they are sent, for testing
+/// syslog receivers
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct SyslogWrapper {
+    pub(crate) format: SyslogFormat,
+    pub(crate) facility: Facility,
+    pub(crate) severity: Severity,
+    /// The HOSTNAME field; if `None`, a placeholder is used
+    pub(crate) hostname: Option<String>,
+    /// The APP-NAME field (RFC 5424) or TAG (RFC 3164)
+    pub(crate) app_name: String,
+    pub(crate) pid: u32,
+}
+
+impl SyslogWrapper {
+    /// The PRI value: the facility code times eight plus the severity code
+    fn priority(&self) -> u8 {
+        (self.facility as u8) * 8 + self.severity as u8
+    }
+
+    /// Return `line` with a syslog header for the given time prepended
+    pub(crate) fn wrap(&self, line: &str, when: OffsetDateTime) -> String {
+        let pri = self.priority();
+        match self.format {
+            SyslogFormat::Rfc5424 => {
+                let timestamp = when
+                    .format(&TIMESTAMP_FMT)
+                    .expect("formatting a datetime as RFC 3339 should not fail");
+                let hostname = self.hostname.as_deref().unwrap_or("-");
+                format!(
+                    "<{pri}>1 {timestamp} {hostname} {} {} - - {line}",
+                    self.app_name, self.pid
+                )
+            }
+            SyslogFormat::Rfc3164 => {
+                let timestamp = when
+                    .format(&RFC3164_TIMESTAMP_FMT)
+                    .expect("formatting a datetime as an RFC 3164 timestamp should not fail");
+                let hostname = self.hostname.as_deref().unwrap_or("localhost");
+                format!(
+                    "<{pri}>{timestamp} {hostname} {}[{}]: {line}",
+                    self.app_name, self.pid
+                )
+            }
+        }
+    }
+}
+
+/// Return the name of the local host, if it can be determined
+pub(crate) fn local_hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .chain(std::env::var("COMPUTERNAME"))
+        .map(|name| name.trim().to_owned())
+        .find(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use time::macros::datetime;
+
+    fn wrapper(format: SyslogFormat) -> SyslogWrapper {
+        SyslogWrapper {
+            format,
+            facility: Facility::Local4,
+            severity: Severity::Warning,
+            hostname: Some(String::from("web1")),
+            app_name: String::from("confab"),
+            pid: 4242,
+        }
+    }
+
+    #[rstest]
+    #[case(
+        SyslogFormat::Rfc5424,
+        "<164>1 2026-10-07T09:05:01.250000+02:00 web1 confab 4242 - - disk full"
+    )]
+    #[case(
+        SyslogFormat::Rfc3164,
+        "<164>Oct  7 09:05:01 web1 confab[4242]: disk full"
+    )]
+    fn test_wrap(#[case] format: SyslogFormat, #[case] wrapped: &str) {
+        let when = datetime!(2026-10-07 09:05:01.25 +02:00);
+        assert_eq!(wrapper(format).wrap("disk full", when), wrapped);
+    }
+
+    #[rstest]
+    #[case(Facility::Kern, Severity::Emerg, 0)]
+    #[case(Facility::User, Severity::Notice, 13)]
+    #[case(Facility::Local7, Severity::Debug, 191)]
+    fn test_priority(#[case] facility: Facility, #[case] severity: Severity, #[case] pri: u8) {
+        let wrapper = SyslogWrapper {
+            facility,
+            severity,
+            ..wrapper(SyslogFormat::Rfc5424)
+        };
+        assert_eq!(wrapper.priority(), pri);
+    }
+
+    #[test]
+    fn test_missing_hostname() {
+        let when = datetime!(2026-10-07 09:05:01 UTC);
+        let mut wrapper = wrapper(SyslogFormat::Rfc5424);
+        wrapper.hostname = None;
+        assert_eq!(
+            wrapper.wrap("hi", when),
+            "<164>1 2026-10-07T09:05:01.000000+00:00 - confab 4242 - - hi"
+        );
+        wrapper.format = SyslogFormat::Rfc3164;
+        assert_eq!(
+            wrapper.wrap("hi", when),
+            "<164>Oct  7 09:05:01 localhost confab[4242]: hi"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The HOST argument on the command line, which may also give the port (as
+/// `HOST:PORT`) and whether to use TLS (as a `tcp://` or `tls://` URL)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Target {
+    /// Domain name or IP address, without any brackets
+    pub(crate) host: String,
+    pub(crate) port: Option<u16>,
+    /// Whether the target was given as a `tls://` URL
+    pub(crate) tls: bool,
+}
+
+impl Target {
+    /// Combine the target with the separate PORT argument, if any, and return
+    /// the host & port to connect to.  Exactly one of the two must give the
+    /// port.
+    pub(crate) fn endpoint(&self, port: Option<u16>) -> Result<(String, u16), EndpointError> {
+        match (self.port, port) {
+            (Some(p), None) | (None, Some(p)) => Ok((self.host.clone(), p)),
+            (Some(_), Some(_)) => Err(EndpointError::DoublePort),
+            (None, None) => Err(EndpointError::NoPort),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Target, ParseTargetError> {
+        let (authority, tls) = match s.split_once("://") {
+            Some((scheme, rest)) => {
+                let tls = match scheme.to_ascii_lowercase().as_str() {
+                    "tcp" => false,
+                    "tls" => true,
+                    "ws" | "wss" => return Err(ParseTargetError::WebSocket),
+                    _ => return Err(ParseTargetError::Scheme(scheme.to_owned())),
+                };
+                let authority = rest.strip_suffix('/').unwrap_or(rest);
+                if authority.contains(['/', '?', '#', '@']) {
+                    return Err(ParseTargetError::Extra);
+                }
+                (authority, tls)
+            }
+            None => (s, false),
+        };
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let (host, after) = rest.split_once(']').ok_or(ParseTargetError::Unclosed)?;
+            match after {
+                "" => (host, None),
+                _ => match after.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(ParseTargetError::Unclosed),
+                },
+            }
+        } else {
+            match authority.split_once(':') {
+                // A bare IPv6 address contains multiple colons and no port
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                _ => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(ParseTargetError::EmptyHost);
+        }
+        let port = port
+            .map(|p| {
+                p.parse::<u16>()
+                    .map_err(|_| ParseTargetError::Port(p.to_owned()))
+            })
+            .transpose()?;
+        Ok(Target {
+            host: host.to_owned(),
+            port,
+            tls,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum ParseTargetError {
+    #[error(r#"unsupported URL scheme {0:?}; expected "tcp" or "tls""#)]
+    Scheme(String),
+    #[error("WebSocket URLs are not supported; confab only speaks raw TCP & TLS")]
+    WebSocket,
+    #[error("URLs may only contain a scheme, host, and port")]
+    Extra,
+    #[error("missing closing ']' after IPv6 address")]
+    Unclosed,
+    #[error("host is empty")]
+    EmptyHost,
+    #[error("invalid port {0:?}")]
+    Port(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub(crate) enum EndpointError {
+    #[error("no port given; specify it as a separate argument or as HOST:PORT")]
+    NoPort,
+    #[error("port given both in HOST and as a separate argument")]
+    DoublePort,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("example.com", "example.com", None, false)]
+    #[case("example.com:80", "example.com", Some(80), false)]
+    #[case("127.0.0.1:8080", "127.0.0.1", Some(8080), false)]
+    #[case("::1", "::1", None, false)]
+    #[case("[::1]", "::1", None, false)]
+    #[case("[::1]:25", "::1", Some(25), false)]
+    #[case("tcp://example.com:80", "example.com", Some(80), false)]
+    #[case("tcp://example.com:80/", "example.com", Some(80), false)]
+    #[case("tls://example.com:443", "example.com", Some(443), true)]
+    #[case("TLS://example.com", "example.com", None, true)]
+    #[case("tls://[2001:db8::1]:993", "2001:db8::1", Some(993), true)]
+    fn test_parse_target(
+        #[case] s: &str,
+        #[case] host: &str,
+        #[case] port: Option<u16>,
+        #[case] tls: bool,
+    ) {
+        assert_eq!(
+            s.parse::<Target>(),
+            Ok(Target {
+                host: host.into(),
+                port,
+                tls
+            })
+        );
+    }
+
+    #[rstest]
+    #[case("http://example.com:80", ParseTargetError::Scheme("http".into()))]
+    #[case("ws://example.com:80/chat", ParseTargetError::WebSocket)]
+    #[case("wss://example.com", ParseTargetError::WebSocket)]
+    #[case("tcp://example.com:80/path", ParseTargetError::Extra)]
+    #[case("tls://user@example.com:443", ParseTargetError::Extra)]
+    #[case("[::1", ParseTargetError::Unclosed)]
+    #[case("[::1]80", ParseTargetError::Unclosed)]
+    #[case(":80", ParseTargetError::EmptyHost)]
+    #[case("tcp://", ParseTargetError::EmptyHost)]
+    #[case("example.com:http", ParseTargetError::Port("http".into()))]
+    #[case("example.com:65536", ParseTargetError::Port("65536".into()))]
+    #[case("example.com:", ParseTargetError::Port(String::new()))]
+    fn test_parse_bad_target(#[case] s: &str, #[case] err: ParseTargetError) {
+        assert_eq!(s.parse::<Target>(), Err(err));
+    }
+
+    #[rstest]
+    #[case("example.com", Some(80), Ok((String::from("example.com"), 80)))]
+    #[case("example.com:80", None, Ok((String::from("example.com"), 80)))]
+    #[case("example.com", None, Err(EndpointError::NoPort))]
+    #[case("example.com:80", Some(80), Err(EndpointError::DoublePort))]
+    fn test_endpoint(
+        #[case] target: &str,
+        #[case] port: Option<u16>,
+        #[case] r: Result<(String, u16), EndpointError>,
+    ) {
+        assert_eq!(target.parse::<Target>().unwrap().endpoint(port), r);
+    }
+}
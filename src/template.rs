@@ -0,0 +1,171 @@
+use crate::util::now;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use thiserror::Error;
+use time::format_description::well_known::Rfc3339;
+
+/// The most random hex digits that `%{rand:hexN}` can produce
+const MAX_RAND_LEN: usize = 64;
+
+/// Expands the `%{...}` templates in sent lines under `--expand-sends`:
+///
+/// - `%{now}` or `%{now:rfc3339}` — the current time in RFC 3339 format
+/// - `%{now:unix}` & `%{now:unix_ms}` — the current Unix time in seconds or
+///   milliseconds
+/// - `%{seq}` — the number of lines that have used `%{seq}` so far, starting
+///   at 1
+/// - `%{rand:hexN}` — `N` random lowercase hexadecimal digits
+/// - `%%` — a literal `%`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Templates {
+    /// The value of `%{seq}` in the last line that used it
+    seq: u64,
+}
+
+impl Templates {
+    /// Replace the templates in `line`.  All occurrences of `%{seq}` in a
+    /// single line expand to the same number.  If the line contains an
+    /// invalid template, an error is returned, and `%{seq}` is not advanced.
+    pub(crate) fn expand(&mut self, line: String) -> Result<String, TemplateError> {
+        if !line.contains('%') {
+            return Ok(line);
+        }
+        let seq = self.seq + 1;
+        let mut used_seq = false;
+        let mut out = String::with_capacity(line.len());
+        let mut rest = &*line;
+        while let Some(i) = rest.find('%') {
+            out.push_str(&rest[..i]);
+            let after = &rest[(i + 1)..];
+            if let Some(tail) = after.strip_prefix('%') {
+                out.push('%');
+                rest = tail;
+            } else if let Some(body) = after.strip_prefix('{') {
+                let Some((spec, tail)) = body.split_once('}') else {
+                    return Err(TemplateError::Unterminated);
+                };
+                match spec.split_once(':').unwrap_or((spec, "")) {
+                    ("now", "" | "rfc3339") => out.push_str(
+                        &now()
+                            .format(&Rfc3339)
+                            .expect("formatting the current time as RFC 3339 should not fail"),
+                    ),
+                    ("now", "unix") => out.push_str(&now().unix_timestamp().to_string()),
+                    ("now", "unix_ms") => {
+                        out.push_str(&(now().unix_timestamp_nanos() / 1_000_000).to_string());
+                    }
+                    ("seq", "") => {
+                        out.push_str(&seq.to_string());
+                        used_seq = true;
+                    }
+                    ("rand", arg) => match arg
+                        .strip_prefix("hex")
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|n| (1..=MAX_RAND_LEN).contains(n))
+                    {
+                        Some(len) => out.push_str(&random_hex(len)),
+                        None => return Err(TemplateError::Unknown(spec.to_owned())),
+                    },
+                    _ => return Err(TemplateError::Unknown(spec.to_owned())),
+                }
+                rest = tail;
+            } else {
+                out.push('%');
+                rest = after;
+            }
+        }
+        out.push_str(rest);
+        if used_seq {
+            self.seq = seq;
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub(crate) enum TemplateError {
+    #[error("unknown template: %{{{0}}}")]
+    Unknown(String),
+    #[error("unterminated %{{ template")]
+    Unterminated,
+}
+
+/// Return `len` random lowercase hexadecimal digits
+fn random_hex(len: usize) -> String {
+    let mut s =
+        std::iter::repeat_with(|| format!("{:016x}", RandomState::new().build_hasher().finish()))
+            .take(len.div_ceil(16))
+            .collect::<String>();
+    s.truncate(len);
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_expand_seq() {
+        let mut tmpl = Templates::default();
+        assert_eq!(
+            tmpl.expand(String::from("id=%{seq} ack=%{seq}")),
+            Ok(String::from("id=1 ack=1"))
+        );
+        assert_eq!(
+            tmpl.expand(String::from("no seq here")),
+            Ok(String::from("no seq here"))
+        );
+        assert_eq!(
+            tmpl.expand(String::from("%{seq} %{bogus}")),
+            Err(TemplateError::Unknown(String::from("bogus")))
+        );
+        assert_eq!(tmpl.expand(String::from("%{seq}")), Ok(String::from("2")));
+    }
+
+    #[test]
+    fn test_expand_rand() {
+        let mut tmpl = Templates::default();
+        let line = tmpl
+            .expand(String::from("%{rand:hex8}|%{rand:hex20}"))
+            .unwrap();
+        let (short, long) = line.split_once('|').unwrap();
+        assert_eq!(short.len(), 8);
+        assert_eq!(long.len(), 20);
+        assert!(line
+            .chars()
+            .all(|c| c == '|' || c.is_ascii_digit() || ('a'..='f').contains(&c)));
+    }
+
+    #[test]
+    fn test_expand_now() {
+        let mut tmpl = Templates::default();
+        let before = now().unix_timestamp();
+        let line = tmpl.expand(String::from("%{now:unix}")).unwrap();
+        let t = line.parse::<i64>().unwrap();
+        assert!(t >= before && t <= now().unix_timestamp());
+        let line = tmpl.expand(String::from("%{now}")).unwrap();
+        assert!(time::OffsetDateTime::parse(&line, &Rfc3339).is_ok());
+    }
+
+    #[rstest]
+    #[case("100%% done", "100% done")]
+    #[case("%%{seq}", "%{seq}")]
+    #[case("50% off", "50% off")]
+    #[case("trailing %", "trailing %")]
+    fn test_expand_literal(#[case] line: &str, #[case] expanded: &str) {
+        let mut tmpl = Templates::default();
+        assert_eq!(tmpl.expand(String::from(line)), Ok(String::from(expanded)));
+    }
+
+    #[rstest]
+    #[case("%{now:iso}", TemplateError::Unknown(String::from("now:iso")))]
+    #[case("%{rand:hex0}", TemplateError::Unknown(String::from("rand:hex0")))]
+    #[case("%{rand:hex65}", TemplateError::Unknown(String::from("rand:hex65")))]
+    #[case("%{seq:2}", TemplateError::Unknown(String::from("seq:2")))]
+    #[case("%{seq", TemplateError::Unterminated)]
+    fn test_expand_bad(#[case] line: &str, #[case] err: TemplateError) {
+        let mut tmpl = Templates::default();
+        assert_eq!(tmpl.expand(String::from(line)), Err(err));
+    }
+}
@@ -0,0 +1,395 @@
+use crate::flush::{FlushPolicy, FlushTimer};
+use crate::memory::{MemoryBudget, Retained, RetainedQueue};
+use crate::render::{Rendered, Renderer};
+use futures_util::FutureExt; // now_or_never()
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, timeout};
+
+pub(crate) type Sink = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A handle for writing text to the terminal via a background task.
+///
+/// Writes never block the caller; if the terminal stops accepting output
+/// (e.g., because the user pressed Ctrl-S), text is buffered until it
+/// resumes, at which point a warning is displayed if the terminal was stalled
+/// for longer than a configured threshold.  The warning is rendered as a note
+/// by the given [`Renderer`], so it goes to stderr if the output format calls
+/// for that.  Buffered text counts against the
+/// session's memory budget, and the oldest text is discarded if the budget is
+/// exceeded.  Text is flushed to the sink according to a [`FlushPolicy`].
+pub(crate) struct TermWriter {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TermWriter {
+    pub(crate) fn new(
+        sink: Sink,
+        stall_threshold: Duration,
+        renderer: Box<dyn Renderer>,
+        budget: MemoryBudget,
+        flush: FlushPolicy,
+    ) -> TermWriter {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(RetainedQueue::new(budget)),
+            notify: Notify::new(),
+            backlog: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        });
+        let task = WriterTask {
+            sink: BufWriter::new(sink),
+            shared: Arc::clone(&shared),
+            stall_threshold,
+            renderer,
+            flush: FlushTimer::new(flush),
+        };
+        let handle = tokio::spawn(task.run());
+        TermWriter {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    pub(crate) fn write(&mut self, text: String) -> io::Result<()> {
+        self.send(Command::Write(text))
+    }
+
+    /// Direct all text written after this point to the given sink
+    pub(crate) fn set_sink(&mut self, sink: Sink) -> io::Result<()> {
+        self.send(Command::SetSink(sink))
+    }
+
+    /// Wait for all text written so far to be written out & flushed
+    pub(crate) async fn sync(&mut self) -> io::Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Sync(sender))?;
+        // If the task exited before getting to our command, report why
+        receiver.await.map_err(|_| self.stopped())
+    }
+
+    /// Returns `true` if there is text that has not yet been written
+    pub(crate) fn pending(&self) -> bool {
+        self.shared.backlog.load(Ordering::Acquire) > 0
+    }
+
+    /// Wait for all text to be written and shut down the background task
+    pub(crate) async fn close(&mut self) -> io::Result<()> {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.notify.notify_one();
+        match self.handle.take() {
+            Some(handle) => handle.await.unwrap_or_else(|e| Err(io::Error::other(e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for the background task to exit due to an error and return the
+    /// error.
+    ///
+    /// This method is cancel-safe.  It never returns while the task is
+    /// running normally.
+    pub(crate) async fn failed(&mut self) -> io::Error {
+        let Some(handle) = self.handle.as_mut() else {
+            return std::future::pending().await;
+        };
+        let r = handle.await;
+        self.handle = None;
+        match r {
+            Ok(Err(e)) => e,
+            Ok(Ok(())) => io::Error::other("terminal writer has stopped"),
+            Err(e) => io::Error::other(e),
+        }
+    }
+
+    fn send(&mut self, cmd: Command) -> io::Result<()> {
+        if self.handle.as_ref().map_or(true, JoinHandle::is_finished) {
+            // The task has exited, presumably due to an error
+            return Err(self.stopped());
+        }
+        self.shared.backlog.fetch_add(1, Ordering::AcqRel);
+        let evicted = self.shared.lock_queue().push_back(cmd);
+        self.shared.backlog.fetch_sub(evicted, Ordering::AcqRel);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// Return the error that caused the task to exit
+    fn stopped(&mut self) -> io::Error {
+        match self.handle.take().and_then(FutureExt::now_or_never) {
+            Some(Ok(Err(e))) => e,
+            _ => io::Error::other("terminal writer has stopped"),
+        }
+    }
+}
+
+/// State shared between a `TermWriter` and its task
+struct Shared {
+    queue: Mutex<RetainedQueue<Command>>,
+    notify: Notify,
+    /// Number of commands sent to the task that it has not yet finished
+    backlog: AtomicUsize,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    fn lock_queue(&self) -> MutexGuard<'_, RetainedQueue<Command>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+enum Command {
+    Write(String),
+    SetSink(Sink),
+    Sync(oneshot::Sender<()>),
+}
+
+impl Retained for Command {
+    fn mem_size(&self) -> usize {
+        match self {
+            Command::Write(text) => text.mem_size(),
+            Command::SetSink(_) | Command::Sync(_) => 0,
+        }
+    }
+}
+
+struct WriterTask {
+    sink: BufWriter<Sink>,
+    shared: Arc<Shared>,
+    stall_threshold: Duration,
+    renderer: Box<dyn Renderer>,
+    flush: FlushTimer,
+}
+
+impl WriterTask {
+    async fn run(mut self) -> io::Result<()> {
+        loop {
+            let cmd = self.shared.lock_queue().pop_front();
+            let Some(cmd) = cmd else {
+                if self.shared.closed.load(Ordering::Acquire) {
+                    return self.flush().await;
+                }
+                let flush_at = self.flush.due();
+                tokio::select! {
+                    () = self.shared.notify.notified() => (),
+                    () = sleep_until(flush_at.unwrap_or_else(Instant::now).into()), if flush_at.is_some() => {
+                        self.flush().await?;
+                    }
+                }
+                continue;
+            };
+            match cmd {
+                Command::Write(text) => self.write(text).await?,
+                Command::SetSink(sink) => {
+                    self.flush().await?;
+                    self.sink = BufWriter::new(sink);
+                }
+                Command::Sync(sender) => {
+                    self.flush().await?;
+                    let _ = sender.send(());
+                }
+            }
+            self.shared.backlog.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    async fn write(&mut self, text: String) -> io::Result<()> {
+        let start = Instant::now();
+        let flush = self.flush.wrote(text.contains('\n'), start);
+        let stalled = {
+            let fut = write_text(&mut self.sink, &text, flush);
+            tokio::pin!(fut);
+            match timeout(self.stall_threshold, &mut fut).await {
+                Ok(r) => r.map(|()| false)?,
+                // The terminal is wedged.  Keep waiting for it (while the
+                // network side carries on, queuing up further output), and
+                // say so once it recovers.
+                Err(_) => fut.await.map(|()| true)?,
+            }
+        };
+        if !stalled {
+            return Ok(());
+        }
+        // Subtract one for the current command
+        let waiting = self
+            .shared
+            .backlog
+            .load(Ordering::Acquire)
+            .saturating_sub(1);
+        let held = if waiting == 1 {
+            "message was"
+        } else {
+            "messages were"
+        };
+        let msg = format!(
+            "Output was blocked for {:.1} seconds; {waiting} {held} held back",
+            start.elapsed().as_secs_f64()
+        );
+        match self.renderer.note('*', &msg) {
+            Rendered::Stdout(text) => {
+                self.flush.flushed();
+                write_text(&mut self.sink, &text, true).await
+            }
+            Rendered::Stderr(text) => tokio::io::stderr().write_all(text.as_bytes()).await,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.flush.flushed();
+        self.sink.flush().await
+    }
+}
+
+async fn write_text(sink: &mut BufWriter<Sink>, text: &str, flush: bool) -> io::Result<()> {
+    sink.write_all(text.as_bytes()).await?;
+    if flush {
+        sink.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{ConsoleRenderer, OutputFormat};
+    use tokio::io::AsyncReadExt;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_write() {
+        let (sink, mut reader) = tokio::io::duplex(1024);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_secs(5),
+            Box::new(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Always,
+        );
+        writer.write(String::from("foo\n")).unwrap();
+        writer.write(String::from("bar\n")).unwrap();
+        writer.close().await.unwrap();
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "foo\nbar\n");
+        assert!(!writer.pending());
+    }
+
+    #[tokio::test]
+    async fn test_flush_line() {
+        let (sink, mut reader) = tokio::io::duplex(1024);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_secs(5),
+            Box::new(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Line,
+        );
+        writer.write(String::from("login: ")).unwrap();
+        let mut buf = [0; 64];
+        assert!(timeout(Duration::from_millis(100), reader.read(&mut buf))
+            .await
+            .is_err());
+        writer.write(String::from("root\n")).unwrap();
+        let n = timeout(Duration::from_secs(1), reader.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"login: root\n");
+        writer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flush_interval() {
+        let (sink, mut reader) = tokio::io::duplex(1024);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_secs(5),
+            Box::new(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Interval(Duration::from_millis(200)),
+        );
+        writer.write(String::from("foo\n")).unwrap();
+        writer.write(String::from("bar\n")).unwrap();
+        let mut buf = [0; 64];
+        assert!(timeout(Duration::from_millis(100), reader.read(&mut buf))
+            .await
+            .is_err());
+        let n = timeout(Duration::from_secs(1), reader.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"foo\nbar\n");
+        writer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stalled_write() {
+        let (sink, mut reader) = tokio::io::duplex(4);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_millis(50),
+            Box::new(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Always,
+        );
+        writer.write(String::from("foobar\n")).unwrap();
+        writer.write(String::from("quux\n")).unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(writer.pending());
+        let mut buf = String::new();
+        let (r1, r2) = tokio::join!(writer.close(), reader.read_to_string(&mut buf));
+        r1.unwrap();
+        r2.unwrap();
+        let mut lines = buf.lines();
+        assert_eq!(lines.next(), Some("foobar"));
+        let warning = lines.next().unwrap();
+        assert!(warning.starts_with("* Output was blocked for "));
+        assert!(warning.ends_with(" seconds; 1 message was held back"));
+        assert_eq!(lines.next(), Some("quux"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_write_json() {
+        let (sink, mut reader) = tokio::io::duplex(4);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_millis(50),
+            OutputFormat::Json.renderer(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Always,
+        );
+        writer.write(String::from("foobar\n")).unwrap();
+        writer.write(String::from("quux\n")).unwrap();
+        sleep(Duration::from_millis(100)).await;
+        let mut buf = String::new();
+        let (r1, r2) = tokio::join!(writer.close(), reader.read_to_string(&mut buf));
+        r1.unwrap();
+        r2.unwrap();
+        // The warning goes to stderr instead of into the JSON output
+        assert_eq!(buf, "foobar\nquux\n");
+    }
+
+    #[tokio::test]
+    async fn test_closed_sink() {
+        let (sink, reader) = tokio::io::duplex(1024);
+        drop(reader);
+        let mut writer = TermWriter::new(
+            Box::new(sink),
+            Duration::from_secs(5),
+            Box::new(ConsoleRenderer::default()),
+            MemoryBudget::default(),
+            FlushPolicy::Always,
+        );
+        writer.write(String::from("foo\n")).unwrap();
+        let e = timeout(Duration::from_secs(1), writer.failed())
+            .await
+            .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+        assert!(writer.write(String::from("bar\n")).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crossterm::{terminal::SetTitle, QueueableCommand};
+use std::io::{self, Write};
+
+/// The state of the session as shown in the window title
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum SessionState {
+    Connecting,
+    Connected,
+    /// Connecting again after `/connect`
+    Reconnecting,
+    Closed,
+}
+
+impl SessionState {
+    fn as_str(self) -> &'static str {
+        match self {
+            SessionState::Connecting => "connecting",
+            SessionState::Connected => "connected",
+            SessionState::Reconnecting => "reconnecting",
+            SessionState::Closed => "closed",
+        }
+    }
+}
+
+/// Keeps the terminal's window/tab title set to the endpoint & state of the
+/// session under `--set-title`, so that several sessions can be told apart
+#[derive(Debug)]
+pub(crate) struct WindowTitle {
+    endpoint: String,
+}
+
+impl WindowTitle {
+    /// `endpoint` is the host & port shown in the title
+    pub(crate) fn new(endpoint: String) -> WindowTitle {
+        WindowTitle { endpoint }
+    }
+
+    /// Change the host & port shown in the title, as after `/connect`
+    pub(crate) fn set_endpoint(&mut self, endpoint: String) {
+        self.endpoint = endpoint;
+    }
+
+    /// Set the title to show the given state
+    pub(crate) fn set(&self, state: SessionState) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.queue(SetTitle(title_text(&self.endpoint, state)))?;
+        out.flush()
+    }
+}
+
+fn title_text(endpoint: &str, state: SessionState) -> String {
+    // Control characters would end the escape sequence early
+    let endpoint = endpoint
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    format!("confab {endpoint} [{}]", state.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_text() {
+        assert_eq!(
+            title_text("example.com:80", SessionState::Connected),
+            "confab example.com:80 [connected]"
+        );
+        assert_eq!(
+            title_text("evil\x07\x1B]0;x:80", SessionState::Reconnecting),
+            "confab evil]0;x:80 [reconnecting]"
+        );
+    }
+}
@@ -1,5 +1,9 @@
+mod cleartext;
+pub(crate) use self::cleartext::*;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "rustls")] {
+        mod certinfo;
         mod rustls;
         pub(crate) use self::rustls::*;
     } else if #[cfg(feature = "native")] {
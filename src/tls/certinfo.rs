@@ -0,0 +1,284 @@
+//! Just enough of a DER parser to extract the parts of an X.509 certificate
+//! that are shown when the certificate fails verification
+use itertools::Itertools; // join
+use std::net::IpAddr;
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0C;
+const PRINTABLE_STRING: u8 = 0x13;
+const TELETEX_STRING: u8 = 0x14;
+const IA5_STRING: u8 = 0x16;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+/// `[0]`, wrapping the version in a `TBSCertificate`
+const VERSION: u8 = 0xA0;
+/// `[3]`, wrapping the extensions in a `TBSCertificate`
+const EXTENSIONS: u8 = 0xA3;
+/// `[2]`, the `dNSName` choice of a `GeneralName`
+const DNS_NAME: u8 = 0x82;
+/// `[7]`, the `iPAddress` choice of a `GeneralName`
+const IP_ADDRESS: u8 = 0x87;
+
+/// The encoded OID of the subjectAltName extension, 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1D, 0x11];
+
+/// The parts of a certificate shown when it fails verification
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct CertInfo {
+    pub(crate) subject: String,
+    pub(crate) issuer: String,
+    pub(crate) not_before: String,
+    pub(crate) not_after: String,
+    /// The DNS names & IP addresses in the certificate's subjectAltName
+    /// extension
+    pub(crate) names: Vec<String>,
+}
+
+impl CertInfo {
+    /// Parse a DER-encoded certificate.  Returns `None` if it is malformed.
+    pub(crate) fn parse(der: &[u8]) -> Option<CertInfo> {
+        let mut cert = Reader(Reader(der).expect(SEQUENCE)?);
+        let mut tbs = Reader(cert.expect(SEQUENCE)?);
+        tbs.skip(VERSION);
+        tbs.expect(INTEGER)?; // serialNumber
+        tbs.expect(SEQUENCE)?; // signature
+        let issuer = format_name(tbs.expect(SEQUENCE)?)?;
+        let mut validity = Reader(tbs.expect(SEQUENCE)?);
+        let not_before = format_time(validity.next()?)?;
+        let not_after = format_time(validity.next()?)?;
+        let subject = format_name(tbs.expect(SEQUENCE)?)?;
+        tbs.expect(SEQUENCE)?; // subjectPublicKeyInfo
+        let mut names = Vec::new();
+        while let Some((tag, value)) = tbs.next() {
+            if tag == EXTENSIONS {
+                names = alt_names(value)?;
+            }
+        }
+        Some(CertInfo {
+            subject,
+            issuer,
+            not_before,
+            not_after,
+            names,
+        })
+    }
+}
+
+/// A cursor over a sequence of DER-encoded values
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Read the next value, returning its tag and contents
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let width = usize::from(first & 0x7F);
+            if width == 0 || width > 4 || rest.len() < width {
+                return None;
+            }
+            let (len_bytes, after) = rest.split_at(width);
+            rest = after;
+            len_bytes
+                .iter()
+                .fold(0, |acc, &b| (acc << 8) | usize::from(b))
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        self.0 = rest;
+        Some((tag, value))
+    }
+
+    /// Read the next value, which must have the given tag, and return its
+    /// contents
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.next()
+            .and_then(|(t, value)| (t == tag).then_some(value))
+    }
+
+    /// Skip over the next value if it has the given tag
+    fn skip(&mut self, tag: u8) {
+        if self.0.first() == Some(&tag) {
+            let _ = self.next();
+        }
+    }
+}
+
+/// Format a distinguished name as comma-separated `TYPE=value` pairs in the
+/// order that they are encoded, e.g., `C=US, O=Example Org, CN=example.com`
+fn format_name(name: &[u8]) -> Option<String> {
+    let mut rdns = Reader(name);
+    let mut attrs = Vec::new();
+    while let Some(rdn) = rdns.expect(SET) {
+        let mut atvs = Reader(rdn);
+        while let Some(atv) = atvs.expect(SEQUENCE) {
+            let mut atv = Reader(atv);
+            let oid = atv.expect(OBJECT_IDENTIFIER)?;
+            let (tag, value) = atv.next()?;
+            let value = match tag {
+                UTF8_STRING | PRINTABLE_STRING | TELETEX_STRING | IA5_STRING => {
+                    String::from_utf8_lossy(value).into_owned()
+                }
+                _ => format!("#{}", value.iter().map(|b| format!("{b:02x}")).join("")),
+            };
+            attrs.push(format!("{}={value}", attribute_type(oid)));
+        }
+    }
+    if !rdns.0.is_empty() {
+        return None;
+    }
+    Some(attrs.join(", "))
+}
+
+/// Return the short name of a well-known attribute type, or else its OID in
+/// dotted-decimal form
+fn attribute_type(oid: &[u8]) -> String {
+    let name = match oid {
+        [0x55, 0x04, 0x03] => "CN",
+        [0x55, 0x04, 0x06] => "C",
+        [0x55, 0x04, 0x07] => "L",
+        [0x55, 0x04, 0x08] => "ST",
+        [0x55, 0x04, 0x0A] => "O",
+        [0x55, 0x04, 0x0B] => "OU",
+        [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x01] => "emailAddress",
+        _ => return dotted_oid(oid),
+    };
+    String::from(name)
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for &b in oid {
+        arc = (arc << 7) | u64::from(b & 0x7F);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = arc.min(80) / 40;
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter().join(".")
+}
+
+/// Format a `UTCTime` or `GeneralizedTime` as `YYYY-MM-DD HH:MM:SS UTC`
+fn format_time((tag, value): (u8, &[u8])) -> Option<String> {
+    let s = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        UTC_TIME => {
+            let yy = s.get(..2)?;
+            let century = if yy < "50" { "20" } else { "19" };
+            (format!("{century}{yy}"), s.get(2..)?)
+        }
+        GENERALIZED_TIME => {
+            (String::from(s.get(..4)?), s.get(4..)?)
+        }
+        _ => return None,
+    };
+    if rest.len() != 10 || !year.chars().chain(rest.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!(
+        "{year}-{}-{} {}:{}:{} UTC",
+        &rest[0..2],
+        &rest[2..4],
+        &rest[4..6],
+        &rest[6..8],
+        &rest[8..10]
+    ))
+}
+
+/// Extract the DNS names & IP addresses from the subjectAltName extension in
+/// a certificate's extensions
+fn alt_names(extensions: &[u8]) -> Option<Vec<String>> {
+    let mut exts = Reader(Reader(extensions).expect(SEQUENCE)?);
+    while let Some(ext) = exts.expect(SEQUENCE) {
+        let mut ext = Reader(ext);
+        if ext.expect(OBJECT_IDENTIFIER)? != SUBJECT_ALT_NAME {
+            continue;
+        }
+        ext.skip(BOOLEAN); // critical
+        let mut general_names = Reader(Reader(ext.expect(OCTET_STRING)?).expect(SEQUENCE)?);
+        let mut names = Vec::new();
+        while let Some((tag, value)) = general_names.next() {
+            match tag {
+                DNS_NAME => names.push(String::from_utf8_lossy(value).into_owned()),
+                IP_ADDRESS => {
+                    if let Ok(octets) = <[u8; 4]>::try_from(value) {
+                        names.push(IpAddr::from(octets).to_string());
+                    } else if let Ok(octets) = <[u8; 16]>::try_from(value) {
+                        names.push(IpAddr::from(octets).to_string());
+                    }
+                }
+                _ => (),
+            }
+        }
+        return Some(names);
+    }
+    Some(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_parse() {
+        let info = CertInfo::parse(include_bytes!("testdata/example.der")).unwrap();
+        assert_eq!(
+            info,
+            CertInfo {
+                subject: String::from("C=US, O=Example Org, CN=example.com"),
+                issuer: String::from("C=US, O=Example Org, CN=example.com"),
+                not_before: String::from("2026-10-17 08:16:25 UTC"),
+                not_after: String::from("2026-11-16 08:16:25 UTC"),
+                names: vec![
+                    String::from("example.com"),
+                    String::from("www.example.com"),
+                    String::from("127.0.0.1"),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let der = include_bytes!("testdata/example.der");
+        assert_eq!(CertInfo::parse(&der[..200]), None);
+    }
+
+    #[rstest]
+    #[case(UTC_TIME, "261017081625Z", Some("2026-10-17 08:16:25 UTC"))]
+    #[case(UTC_TIME, "991231235959Z", Some("1999-12-31 23:59:59 UTC"))]
+    #[case(GENERALIZED_TIME, "20501017081625Z", Some("2050-10-17 08:16:25 UTC"))]
+    #[case(UTC_TIME, "261017081625", None)]
+    #[case(UTC_TIME, "2610170816Z", None)]
+    #[case(OCTET_STRING, "261017081625Z", None)]
+    fn test_format_time(#[case] tag: u8, #[case] value: &str, #[case] formatted: Option<&str>) {
+        assert_eq!(
+            format_time((tag, value.as_bytes())).as_deref(),
+            formatted
+        );
+    }
+
+    #[rstest]
+    #[case(&[0x55, 0x04, 0x05], "2.5.4.5")]
+    #[case(&[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D], "1.2.840.113549")]
+    fn test_dotted_oid(#[case] oid: &[u8], #[case] dotted: &str) {
+        assert_eq!(dotted_oid(oid), dotted);
+    }
+}
@@ -0,0 +1,164 @@
+use bytes::BytesMut;
+use pin_project_lite::pin_project;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// If this many bytes of cleartext are received without a newline, they are
+/// diverted as-is rather than waiting for the rest of the line.
+const MAX_CLEARTEXT_LINE: usize = 16384;
+
+pin_project! {
+    /// A wrapper around a stream that is about to be upgraded to TLS that
+    /// diverts any complete lines of cleartext sent by the server before its
+    /// first TLS record, so that they can be reported instead of being fed to
+    /// the TLS handshake.
+    ///
+    /// Once the start of a TLS record is seen, all further data is passed
+    /// through unchanged.
+    #[derive(Debug)]
+    pub(crate) struct CleartextGuard<S> {
+        #[pin]
+        inner: S,
+        // Data read from `inner` that has not yet been diverted or passed on
+        buf: BytesMut,
+        passthrough: bool,
+        cleartext: Cleartext,
+    }
+}
+
+impl<S> CleartextGuard<S> {
+    pub(crate) fn new(inner: S) -> (CleartextGuard<S>, Cleartext) {
+        let cleartext = Cleartext::default();
+        let guard = CleartextGuard {
+            inner,
+            buf: BytesMut::new(),
+            passthrough: false,
+            cleartext: cleartext.clone(),
+        };
+        (guard, cleartext)
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for CleartextGuard<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        while !*this.passthrough {
+            if let Some(&b) = this.buf.first() {
+                if is_tls_record_start(b) {
+                    *this.passthrough = true;
+                    break;
+                }
+                if let Some(i) = this.buf.iter().position(|&c| c == b'\n') {
+                    this.cleartext.push(&this.buf.split_to(i + 1));
+                    continue;
+                } else if this.buf.len() >= MAX_CLEARTEXT_LINE {
+                    this.cleartext.push(&this.buf.split());
+                    continue;
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            let mut chunkbuf = ReadBuf::new(&mut chunk);
+            ready!(this.inner.as_mut().poll_read(cx, &mut chunkbuf))?;
+            if chunkbuf.filled().is_empty() {
+                // EOF
+                this.cleartext.push(&this.buf.split());
+                *this.passthrough = true;
+            } else {
+                this.buf.extend_from_slice(chunkbuf.filled());
+            }
+        }
+        if this.buf.is_empty() {
+            this.inner.poll_read(cx, out)
+        } else {
+            let n = out.remaining().min(this.buf.len());
+            out.put_slice(&this.buf.split_to(n));
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for CleartextGuard<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+/// A handle for retrieving the cleartext diverted by a `CleartextGuard`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Cleartext(Arc<Mutex<Vec<u8>>>);
+
+impl Cleartext {
+    fn push(&self, data: &[u8]) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(data);
+    }
+
+    /// Remove & return all of the cleartext diverted so far
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Returns true if `b` is a valid TLS record content type (change cipher
+/// spec, alert, handshake, or application data)
+fn is_tls_record_start(b: u8) -> bool {
+    (0x14..=0x17).contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_divert_cleartext() {
+        let data = &b"220 smtp.example.com ESMTP\r\nHello\n\x16\x03\x03\x00\x02\n\x01"[..];
+        let (mut guard, cleartext) = CleartextGuard::new(data);
+        let mut buf = Vec::new();
+        guard.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"\x16\x03\x03\x00\x02\n\x01");
+        assert_eq!(cleartext.take(), b"220 smtp.example.com ESMTP\r\nHello\n");
+        assert!(cleartext.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_cleartext() {
+        let data = &b"\x16\x03\x03\x00\x02"[..];
+        let (mut guard, cleartext) = CleartextGuard::new(data);
+        let mut buf = Vec::new();
+        guard.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        assert!(cleartext.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_cleartext() {
+        let data = &b"Hello\nNo newline"[..];
+        let (mut guard, cleartext) = CleartextGuard::new(data);
+        let mut buf = Vec::new();
+        guard.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(cleartext.take(), data);
+    }
+}
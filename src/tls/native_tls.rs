@@ -1,7 +1,43 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
 use thiserror::Error;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 
-pub(crate) type TlsStream = tokio_native_tls::TlsStream<TcpStream>;
+pub(crate) type TlsStream<S> = tokio_native_tls::TlsStream<S>;
+
+/// A certificate & private key for acting as a TLS server under `--listen`
+#[derive(Clone)]
+pub(crate) struct ServerIdentity(tokio_native_tls::TlsAcceptor);
+
+impl ServerIdentity {
+    /// Load a PEM certificate chain and PKCS #8 private key from the given
+    /// files
+    pub(crate) fn load(cert: &Path, key: &Path) -> Result<ServerIdentity, TlsError> {
+        let cert = std::fs::read(cert).map_err(TlsError::ReadIdentity)?;
+        let key = std::fs::read(key).map_err(TlsError::ReadIdentity)?;
+        let identity = tokio_native_tls::native_tls::Identity::from_pkcs8(&cert, &key).map_err(TlsError::Identity)?;
+        let acceptor = tokio_native_tls::native_tls::TlsAcceptor::new(identity).map_err(TlsError::Identity)?;
+        Ok(ServerIdentity(acceptor.into()))
+    }
+}
+
+impl fmt::Debug for ServerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerIdentity").finish_non_exhaustive()
+    }
+}
+
+/// Client-side TLS state kept for the life of a `Connector`.  native-tls does
+/// not expose session resumption, so there is nothing to keep.
+#[derive(Debug, Default)]
+pub(crate) struct ClientContext;
+
+impl ClientContext {
+    pub(crate) fn new() -> ClientContext {
+        ClientContext
+    }
+}
 
 #[derive(Debug, Error)]
 pub(crate) enum TlsError {
@@ -9,13 +45,57 @@ pub(crate) enum TlsError {
     Connector(#[source] tokio_native_tls::native_tls::Error),
     #[error("failed to establish TLS connection")]
     Connect(#[source] tokio_native_tls::native_tls::Error),
+    #[error("failed to read TLS certificate & key")]
+    ReadIdentity(#[source] io::Error),
+    #[error("failed to load TLS certificate & key")]
+    Identity(#[source] tokio_native_tls::native_tls::Error),
+}
+
+impl TlsError {
+    /// Additional lines of detail to show beneath the error message.
+    /// native-tls does not expose why certificate verification failed, so
+    /// there are none.
+    pub(crate) fn details(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
-pub(crate) async fn connect(conn: TcpStream, servername: &str) -> Result<TlsStream, TlsError> {
+pub(crate) async fn connect<S>(
+    conn: S,
+    servername: &str,
+    sni: bool,
+    _context: &ClientContext,
+) -> Result<TlsStream<S>, TlsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // native-tls recognizes IP addresses on its own (and does not send SNI
+    // for them), but not ones in brackets
+    let servername = servername
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(servername);
     tokio_native_tls::TlsConnector::from(
-        tokio_native_tls::native_tls::TlsConnector::new().map_err(TlsError::Connector)?,
+        tokio_native_tls::native_tls::TlsConnector::builder()
+            .use_sni(sni)
+            .build()
+            .map_err(TlsError::Connector)?,
     )
     .connect(servername, conn)
     .await
     .map_err(TlsError::Connect)
 }
+
+/// Returns whether the handshake resumed an earlier TLS session, which
+/// native-tls cannot tell us
+pub(crate) fn resumed<S>(_stream: &TlsStream<S>) -> Option<bool> {
+    None
+}
+
+/// Perform the server side of a TLS handshake with a client
+pub(crate) async fn accept<S>(conn: S, identity: &ServerIdentity) -> Result<TlsStream<S>, TlsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    identity.0.accept(conn).await.map_err(TlsError::Connect)
+}
@@ -1,15 +1,303 @@
+use super::certinfo::CertInfo;
+use crate::tofu::TofuError;
 use itertools::Itertools; // join
-use rustls_pki_types::{InvalidDnsNameError, ServerName};
-use std::io;
-use std::sync::Arc;
+use rustls_pki_types::{
+    pem::PemObject, CertificateDer, InvalidDnsNameError, PrivateKeyDer, ServerName, UnixTime,
+};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
 use thiserror::Error;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::{
-    rustls::{ClientConfig, RootCertStore},
-    TlsConnector,
+    rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            WebPkiServerVerifier,
+        },
+        CertificateError, ClientConfig, DigitallySignedStruct, HandshakeKind, KeyLog,
+        RootCertStore, ServerConfig, SignatureScheme,
+    },
+    TlsAcceptor, TlsConnector,
 };
 
-pub(crate) type TlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+pub(crate) type TlsStream<S> = tokio_rustls::TlsStream<S>;
+
+/// A certificate & private key for acting as a TLS server under `--listen`
+#[derive(Clone, Debug)]
+pub(crate) struct ServerIdentity(Arc<ServerConfig>);
+
+impl ServerIdentity {
+    /// Load a PEM certificate chain and private key from the given files
+    pub(crate) fn load(cert: &Path, key: &Path) -> Result<ServerIdentity, TlsError> {
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(|e| TlsError::LoadIdentity(format!("{}: {e}", cert.display())))?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| TlsError::LoadIdentity(format!("{}: {e}", key.display())))?;
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| TlsError::LoadIdentity(e.to_string()))?;
+        Ok(ServerIdentity(Arc::new(config)))
+    }
+}
+
+/// A file to which the secrets of TLS sessions are appended in the NSS key log
+/// format, as set by `--keylog` or `SSLKEYLOGFILE`, so that captures of the
+/// session can be decrypted with tools like Wireshark
+#[derive(Debug)]
+pub(crate) struct KeyLogFile(Mutex<File>);
+
+impl KeyLogFile {
+    pub(crate) fn open(path: &Path) -> io::Result<KeyLogFile> {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map(|fp| KeyLogFile(Mutex::new(fp)))
+    }
+}
+
+impl KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut line = String::from(label);
+        for bytes in [client_random, secret] {
+            line.push(' ');
+            for b in bytes {
+                let _ = write!(line, "{b:02x}");
+            }
+        }
+        line.push('\n');
+        // There's nowhere to report a failure to write, so it's ignored.
+        if let Ok(mut fp) = self.0.lock() {
+            let _ = fp.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// Client-side TLS configuration, built on first use and then kept for the
+/// life of the `Connector` so that later connections can resume the TLS
+/// sessions of earlier ones using the tickets cached by rustls
+#[derive(Debug, Default)]
+pub(crate) struct ClientContext {
+    config: OnceLock<(Arc<ClientConfig>, Arc<RecordingVerifier>)>,
+    keylog: Option<Arc<dyn KeyLog>>,
+    /// Whether to accept server certificates that are not signed by a
+    /// trusted CA, as they are checked against the `--tofu` store instead
+    tofu: bool,
+}
+
+impl ClientContext {
+    pub(crate) fn new() -> ClientContext {
+        ClientContext::default()
+    }
+
+    /// Write the secrets of TLS sessions to the given key log file
+    pub(crate) fn keylog(mut self, keylog: KeyLogFile) -> ClientContext {
+        self.keylog = Some(Arc::new(keylog));
+        self
+    }
+
+    /// Accept any server certificate during the handshake, leaving it to the
+    /// caller to check the certificate's fingerprint against the `--tofu`
+    /// store with `peer_fingerprint()`
+    pub(crate) fn tofu(mut self) -> ClientContext {
+        self.tofu = true;
+        self
+    }
+
+    fn config(&self, sni: bool) -> Result<(Arc<ClientConfig>, Arc<RecordingVerifier>), TlsError> {
+        if let Some((config, verifier)) = self.config.get() {
+            return Ok((Arc::clone(config), Arc::clone(verifier)));
+        }
+        let certs = rustls_native_certs::load_native_certs();
+        if !certs.errors.is_empty() {
+            let msg = certs.errors.into_iter().map(|e| e.to_string()).join("; ");
+            return Err(TlsError::LoadStore(msg));
+        }
+        let mut root_cert_store = RootCertStore::empty();
+        let (good, bad) = root_cert_store.add_parsable_certificates(certs.certs);
+        if good == 0 {
+            return Err(TlsError::AddCerts { bad });
+        }
+        let verifier = Arc::new(RecordingVerifier {
+            inner: WebPkiServerVerifier::builder(Arc::new(root_cert_store))
+                .build()
+                .map_err(|e| TlsError::LoadStore(e.to_string()))?,
+            rejected: Mutex::new(None),
+            tofu: self.tofu,
+        });
+        let dyn_verifier: Arc<dyn ServerCertVerifier> = verifier.clone();
+        let mut config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(dyn_verifier)
+            .with_no_client_auth();
+        config.enable_sni = sni;
+        if let Some(keylog) = self.keylog.as_ref() {
+            config.key_log = Arc::clone(keylog);
+        }
+        // Note to self: To make use of client certs, replace
+        // with_no_client_auth() with with_client_auth_cert(...).
+        let (config, verifier) = self.config.get_or_init(|| (Arc::new(config), verifier));
+        Ok((Arc::clone(config), Arc::clone(verifier)))
+    }
+}
+
+/// The standard certificate verifier, wrapped so as to keep details of the
+/// last certificate that it rejected for use in error messages
+#[derive(Debug)]
+struct RecordingVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    rejected: Mutex<Option<Rejected>>,
+    /// Whether to accept any certificate, as under `--tofu`.  Handshake
+    /// signatures are still verified.
+    tofu: bool,
+}
+
+impl RecordingVerifier {
+    fn take_rejected(&self) -> Option<Rejected> {
+        self.rejected
+            .lock()
+            .ok()
+            .and_then(|mut rejected| rejected.take())
+    }
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        if self.tofu {
+            return Ok(ServerCertVerified::assertion());
+        }
+        let r = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        if r.is_err() {
+            // The issuer that was not recognized is that of the last
+            // certificate in the chain the server sent
+            let top_issuer = intermediates
+                .last()
+                .and_then(|cert| CertInfo::parse(cert))
+                .map(|info| info.issuer);
+            if let (Some(cert), Ok(mut rejected)) =
+                (CertInfo::parse(end_entity), self.rejected.lock())
+            {
+                *rejected = Some(Rejected {
+                    top_issuer: top_issuer.unwrap_or_else(|| cert.issuer.clone()),
+                    cert,
+                });
+            }
+        }
+        r
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// A server certificate that failed verification
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Rejected {
+    cert: CertInfo,
+    /// The issuer of the last certificate in the chain sent by the server
+    top_issuer: String,
+}
+
+/// Why & how a server's certificate failed verification
+#[derive(Clone, Debug)]
+pub(crate) struct VerifyError {
+    problem: CertificateError,
+    servername: String,
+    rejected: Rejected,
+}
+
+impl VerifyError {
+    /// Lines describing the certificate properties that led to the failure
+    fn details(&self) -> Vec<String> {
+        let cert = &self.rejected.cert;
+        let mut lines = vec![format!("subject: {}", cert.subject)];
+        match self.problem {
+            CertificateError::Expired => {
+                lines.push(format!("expired: {}", cert.not_after));
+            }
+            CertificateError::NotValidYet => {
+                lines.push(format!("not valid before: {}", cert.not_before));
+            }
+            CertificateError::NotValidForName => {
+                lines.push(format!("expected name: {}", self.servername));
+                if cert.names.is_empty() {
+                    lines.push(String::from("certificate names: (none)"));
+                } else {
+                    lines.push(format!("certificate names: {}", cert.names.join(", ")));
+                }
+            }
+            CertificateError::UnknownIssuer => {
+                lines.push(format!("unknown issuer: {}", self.rejected.top_issuer));
+            }
+            _ => {
+                lines.push(format!("issuer: {}", cert.issuer));
+                lines.push(format!(
+                    "valid from {} to {}",
+                    cert.not_before, cert.not_after
+                ));
+            }
+        }
+        lines
+    }
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.problem {
+            CertificateError::Expired => write!(f, "server certificate has expired"),
+            CertificateError::NotValidYet => write!(f, "server certificate is not yet valid"),
+            CertificateError::NotValidForName => write!(
+                f,
+                "server certificate is not valid for {}",
+                self.servername
+            ),
+            CertificateError::UnknownIssuer => {
+                write!(f, "server certificate was issued by an unknown CA")
+            }
+            ref e => write!(f, "invalid server certificate: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
 
 #[derive(Debug, Error)]
 pub(crate) enum TlsError {
@@ -21,28 +309,219 @@ pub(crate) enum TlsError {
     ServerName(#[from] InvalidDnsNameError),
     #[error("failed to establish TLS connection")]
     Connect(#[source] io::Error),
+    #[error(transparent)]
+    Verify(Box<VerifyError>),
+    #[error("failed to load TLS certificate & key: {0}")]
+    LoadIdentity(String),
+    #[error("server sent no certificate")]
+    NoCertificate,
+    #[error(transparent)]
+    Tofu(#[from] TofuError),
+}
+
+impl TlsError {
+    /// Additional lines of detail to show beneath the error message
+    pub(crate) fn details(&self) -> Vec<String> {
+        match self {
+            TlsError::Verify(e) => e.details(),
+            TlsError::Tofu(e) => e.details(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub(crate) async fn connect<S>(
+    conn: S,
+    servername: &str,
+    sni: bool,
+    context: &ClientContext,
+) -> Result<TlsStream<S>, TlsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (config, verifier) = context.config(sni)?;
+    // Don't report a rejection left over from an earlier connection
+    let _ = verifier.take_rejected();
+    let connector = TlsConnector::from(config);
+    match connector.connect(server_name(servername)?, conn).await {
+        Ok(stream) => Ok(TlsStream::Client(stream)),
+        Err(e) => {
+            let problem = e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<tokio_rustls::rustls::Error>());
+            match (problem, verifier.take_rejected()) {
+                (Some(tokio_rustls::rustls::Error::InvalidCertificate(problem)), Some(rejected)) => {
+                    Err(TlsError::Verify(Box::new(VerifyError {
+                        problem: problem.clone(),
+                        servername: servername.to_owned(),
+                        rejected,
+                    })))
+                }
+                _ => Err(TlsError::Connect(e)),
+            }
+        }
+    }
+}
+
+/// Returns whether the handshake for a client connection resumed an earlier
+/// TLS session, or `None` if this cannot be determined
+pub(crate) fn resumed<S>(stream: &TlsStream<S>) -> Option<bool> {
+    match stream {
+        TlsStream::Client(s) => s
+            .get_ref()
+            .1
+            .handshake_kind()
+            .map(|kind| kind == HandshakeKind::Resumed),
+        TlsStream::Server(_) => None,
+    }
 }
 
-pub(crate) async fn connect(conn: TcpStream, servername: &str) -> Result<TlsStream, TlsError> {
-    let certs = rustls_native_certs::load_native_certs();
-    if !certs.errors.is_empty() {
-        let msg = certs.errors.into_iter().map(|e| e.to_string()).join("; ");
-        return Err(TlsError::LoadStore(msg));
-    }
-    let mut root_cert_store = RootCertStore::empty();
-    let (good, bad) = root_cert_store.add_parsable_certificates(certs.certs);
-    if good == 0 {
-        return Err(TlsError::AddCerts { bad });
-    }
-    let config = ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth();
-    // Note to self: To make use of client certs, replace
-    // with_no_client_auth() with with_client_auth_cert(...).
-    let connector = TlsConnector::from(Arc::new(config));
-    let dnsname = ServerName::try_from(servername)?.to_owned();
-    connector
-        .connect(dnsname, conn)
+/// Return the SHA-256 fingerprint of the certificate presented by the server
+/// on a client connection, formatted as colon-separated pairs of uppercase
+/// hex digits
+pub(crate) fn peer_fingerprint<S>(stream: &TlsStream<S>) -> Result<String, TlsError> {
+    let TlsStream::Client(s) = stream else {
+        return Err(TlsError::NoCertificate);
+    };
+    let cert = s
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(<[_]>::first)
+        .ok_or(TlsError::NoCertificate)?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert);
+    Ok(digest
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .join(":"))
+}
+
+/// Perform the server side of a TLS handshake with a client
+pub(crate) async fn accept<S>(conn: S, identity: &ServerIdentity) -> Result<TlsStream<S>, TlsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    TlsAcceptor::from(Arc::clone(&identity.0))
+        .accept(conn)
         .await
+        .map(TlsStream::Server)
         .map_err(TlsError::Connect)
 }
+
+/// Convert a domain name or IP address (which may be enclosed in brackets, as
+/// in URLs) to a `ServerName`.  For IP addresses, the server's certificate is
+/// checked for a matching IP address SAN, and no SNI is sent.
+fn server_name(s: &str) -> Result<ServerName<'static>, InvalidDnsNameError> {
+    let bare = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s);
+    match bare.parse::<IpAddr>() {
+        Ok(ip) => Ok(ServerName::from(ip)),
+        Err(_) => Ok(ServerName::try_from(s)?.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[rstest]
+    #[case("127.0.0.1", IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    #[case("::1", IpAddr::V6(Ipv6Addr::LOCALHOST))]
+    #[case("[::1]", IpAddr::V6(Ipv6Addr::LOCALHOST))]
+    fn test_server_name_ip(#[case] s: &str, #[case] ip: IpAddr) {
+        assert_eq!(server_name(s).unwrap(), ServerName::from(ip));
+    }
+
+    #[test]
+    fn test_server_name_dns() {
+        assert_matches::assert_matches!(
+            server_name("example.com"),
+            Ok(ServerName::DnsName(name)) if name.as_ref() == "example.com"
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("[example.com]")]
+    #[case("exa mple.com")]
+    fn test_server_name_invalid(#[case] s: &str) {
+        assert!(server_name(s).is_err());
+    }
+
+    #[test]
+    fn test_keylog_file() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let keylog = KeyLogFile::open(tmp.path()).unwrap();
+        keylog.log("CLIENT_RANDOM", &[0x01, 0xAB], &[0xFF, 0x00, 0x10]);
+        keylog.log("SERVER_TRAFFIC_SECRET_0", &[0x02], &[0x0C]);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path()).unwrap(),
+            "CLIENT_RANDOM 01ab ff0010\nSERVER_TRAFFIC_SECRET_0 02 0c\n"
+        );
+    }
+
+    fn verify_error(problem: CertificateError, servername: &str) -> VerifyError {
+        VerifyError {
+            problem,
+            servername: String::from(servername),
+            rejected: Rejected {
+                cert: CertInfo::parse(include_bytes!("testdata/example.der")).unwrap(),
+                top_issuer: String::from("O=Example Org, CN=Example Intermediate CA"),
+            },
+        }
+    }
+
+    #[test]
+    fn test_verify_error_expired() {
+        let e = verify_error(CertificateError::Expired, "example.com");
+        assert_eq!(e.to_string(), "server certificate has expired");
+        assert_eq!(
+            e.details(),
+            [
+                "subject: C=US, O=Example Org, CN=example.com",
+                "expired: 2026-11-16 08:16:25 UTC",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_error_name() {
+        let e = verify_error(CertificateError::NotValidForName, "example.net");
+        assert_eq!(
+            e.to_string(),
+            "server certificate is not valid for example.net"
+        );
+        assert_eq!(
+            e.details(),
+            [
+                "subject: C=US, O=Example Org, CN=example.com",
+                "expected name: example.net",
+                "certificate names: example.com, www.example.com, 127.0.0.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_error_unknown_issuer() {
+        let e = TlsError::Verify(Box::new(verify_error(
+            CertificateError::UnknownIssuer,
+            "example.com",
+        )));
+        assert_eq!(
+            e.to_string(),
+            "server certificate was issued by an unknown CA"
+        );
+        assert_eq!(
+            e.details(),
+            [
+                "subject: C=US, O=Example Org, CN=example.com",
+                "unknown issuer: O=Example Org, CN=Example Intermediate CA",
+            ]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Name of the store file within confab's data directory
+const STORE_NAME: &str = "known_certs";
+
+/// A file of server certificate fingerprints trusted on first use under
+/// `--tofu`, keyed by `HOST:PORT`.  Each line contains an endpoint and the
+/// SHA-256 fingerprint of its certificate, separated by whitespace; blank
+/// lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TofuStore {
+    path: PathBuf,
+}
+
+/// The outcome of checking a certificate against a `TofuStore`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Trust {
+    /// The certificate matches the one recorded for the endpoint
+    Known,
+    /// Nothing was recorded for the endpoint, so the certificate has now been
+    /// recorded
+    New,
+}
+
+impl TofuStore {
+    pub(crate) fn new(path: PathBuf) -> TofuStore {
+        TofuStore { path }
+    }
+
+    /// Return the default location of the store: `confab/known_certs` in the
+    /// user's data directory (`$XDG_DATA_HOME`, `~/.local/share`, or
+    /// `%APPDATA%`)
+    pub(crate) fn default_path() -> Option<PathBuf> {
+        let nonempty = |var| std::env::var_os(var).filter(|v| !v.is_empty());
+        let datadir = nonempty("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| nonempty("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+            .or_else(|| nonempty("APPDATA").map(PathBuf::from))?;
+        Some(datadir.join("confab").join(STORE_NAME))
+    }
+
+    /// Check the fingerprint of the certificate presented by `endpoint`
+    /// against the store, recording it if the endpoint has not been seen
+    /// before
+    pub(crate) fn check(&self, endpoint: &str, fingerprint: &str) -> Result<Trust, TofuError> {
+        match self.lookup(endpoint)? {
+            Some(expected) if expected.eq_ignore_ascii_case(fingerprint) => Ok(Trust::Known),
+            Some(expected) => Err(TofuError::Mismatch {
+                endpoint: endpoint.to_owned(),
+                expected,
+                actual: fingerprint.to_owned(),
+                path: self.path.clone(),
+            }),
+            None => {
+                self.record(endpoint, fingerprint)
+                    .map_err(|source| TofuError::Write {
+                        path: self.path.clone(),
+                        source,
+                    })?;
+                Ok(Trust::New)
+            }
+        }
+    }
+
+    /// Return the fingerprint recorded for `endpoint`, if any
+    fn lookup(&self, endpoint: &str) -> Result<Option<String>, TofuError> {
+        let content = match read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(TofuError::Read {
+                    path: self.path.clone(),
+                    source,
+                })
+            }
+        };
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|ln| !ln.is_empty() && !ln.starts_with('#'))
+            .filter_map(|ln| ln.split_once(char::is_whitespace))
+            .find(|&(ep, _)| ep == endpoint)
+            .map(|(_, fp)| fp.trim().to_owned()))
+    }
+
+    fn record(&self, endpoint: &str, fingerprint: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            create_dir_all(parent)?;
+        }
+        let mut fp = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        writeln!(fp, "{endpoint} {fingerprint}")
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum TofuError {
+    #[error("failed to read trusted certificate store {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to update trusted certificate store {}", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("server certificate for {endpoint} has changed since it was first trusted")]
+    Mismatch {
+        endpoint: String,
+        expected: String,
+        actual: String,
+        path: PathBuf,
+    },
+}
+
+impl TofuError {
+    /// Additional lines of detail to show beneath the error message
+    pub(crate) fn details(&self) -> Vec<String> {
+        match self {
+            TofuError::Mismatch {
+                expected,
+                actual,
+                path,
+                ..
+            } => vec![
+                format!("trusted fingerprint: {expected}"),
+                format!("presented fingerprint: {actual}"),
+                format!(
+                    "to trust the new certificate, remove the entry from {}",
+                    path.display()
+                ),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("sub").join(STORE_NAME);
+        let store = TofuStore::new(path.clone());
+        assert_eq!(store.check("example.com:443", "AA:BB").unwrap(), Trust::New);
+        assert_eq!(
+            store.check("example.com:443", "aa:bb").unwrap(),
+            Trust::Known
+        );
+        assert_eq!(store.check("example.com:993", "CC:DD").unwrap(), Trust::New);
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            "example.com:443 AA:BB\nexample.com:993 CC:DD\n"
+        );
+        let e = store.check("example.com:993", "EE:FF").unwrap_err();
+        assert_matches!(e, TofuError::Mismatch { ref expected, ref actual, .. } => {
+            assert_eq!(expected, "CC:DD");
+            assert_eq!(actual, "EE:FF");
+        });
+        assert_eq!(
+            e.to_string(),
+            "server certificate for example.com:993 has changed since it was first trusted"
+        );
+    }
+
+    #[test]
+    fn test_lookup_skips_comments() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join(STORE_NAME);
+        std::fs::write(
+            &path,
+            "# lab hosts\n\n  db.lab:5432\tAA:BB  \nweb.lab:443 CC:DD\n",
+        )
+        .unwrap();
+        let store = TofuStore::new(path);
+        assert_eq!(store.check("db.lab:5432", "AA:BB").unwrap(), Trust::Known);
+        assert_eq!(store.check("web.lab:443", "CC:DD").unwrap(), Trust::Known);
+    }
+}
@@ -0,0 +1,797 @@
+//! Reading session transcripts
+//!
+//! A transcript, as written by `confab --transcript`, is a JSON Lines file in
+//! which each line is an object describing an event in the session.  See the
+//! "Transcript Format" section of the README for the meanings of the events &
+//! their fields.
+//!
+//! ```no_run
+//! for event in confab::transcript::read("session.jsonl") {
+//!     match event {
+//!         Ok(confab::transcript::Event::Recv { data, .. }) => print!("{data}"),
+//!         Ok(_) => (),
+//!         Err(e) => eprintln!("{e}"),
+//!     }
+//! }
+//! ```
+use async_stream::stream;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures_util::Stream;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use thiserror::Error;
+use time::OffsetDateTime;
+use tokio::io::AsyncBufReadExt;
+
+/// The version of the transcript format written by this version of `confab`,
+/// as recorded in the `format_version` fields of the "session-start" and
+/// "connection-start" events.
+///
+/// Transcripts written before this field was introduced are treated as
+/// version 1.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// An event recorded in a transcript
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", tag = "event")]
+#[non_exhaustive]
+pub enum Event {
+    /// Emitted at the start of the session, before connecting
+    SessionStart {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The version of the transcript format; see [`FORMAT_VERSION`]
+        format_version: u32,
+        /// The version of `confab` that wrote the transcript
+        confab_version: String,
+        /// The remote host specified on the command line
+        host: String,
+        /// The remote port specified on the command line
+        port: u16,
+        /// The values of the options in effect for the session, keyed by
+        /// long option name.  The value of an option that can be given
+        /// multiple times is an array of strings; all other values are
+        /// strings.
+        options: BTreeMap<String, serde_json::Value>,
+    },
+    /// Emitted just before starting to connect to the remote server
+    ConnectionStart {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The version of the transcript format; see [`FORMAT_VERSION`]
+        #[serde(default = "format_version_1")]
+        format_version: u32,
+        /// The remote host specified on the command line
+        host: String,
+        /// The remote port specified on the command line
+        port: u16,
+    },
+    /// Emitted with `--listen` once the listening socket has been bound, just
+    /// before waiting for a client to connect
+    ListenStart {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The local address on which connections are accepted
+        ip: IpAddr,
+        port: u16,
+    },
+    /// Emitted with `--listen` after a client has connected (but before
+    /// negotiating TLS, if applicable)
+    Accepted {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        peer_ip: IpAddr,
+        peer_port: u16,
+    },
+    /// Emitted with `--serial` after opening the serial device
+    SerialOpened {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        device: String,
+        baud: u32,
+    },
+    /// Emitted with `--exec` after starting the program
+    ProcessStarted {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The program & its arguments
+        command: Vec<String>,
+        /// The program's process ID, if it could be determined
+        #[serde(default)]
+        pid: Option<u32>,
+    },
+    /// Emitted after the remote host has been resolved
+    DnsResolved {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        host: String,
+        addresses: Vec<IpAddr>,
+        /// Whether the addresses came from `--resolve` rather than DNS
+        #[serde(rename = "override")]
+        overridden: bool,
+    },
+    /// Emitted under `--addr-file` before trying to connect to each address
+    ConnectAttempt {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        ip: IpAddr,
+        port: u16,
+    },
+    /// Emitted under `--addr-file` when connecting to an address fails,
+    /// before the next address is tried
+    ConnectFailed {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        ip: IpAddr,
+        port: u16,
+        /// The OS error code, if any
+        #[serde(default)]
+        os_error: Option<i32>,
+        /// A human-readable error message
+        data: String,
+    },
+    /// Emitted after connecting successfully (but before negotiating TLS, if
+    /// applicable)
+    ConnectionComplete {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        peer_ip: IpAddr,
+        /// Whether `TCP_NODELAY` was set on the connection
+        #[serde(default)]
+        tcp_nodelay: bool,
+        /// The idle time in seconds after which TCP keepalive probes are
+        /// sent, if keepalive was enabled
+        #[serde(default)]
+        tcp_keepalive: Option<u64>,
+        /// The IP time-to-live set on the connection, if any
+        #[serde(default)]
+        ttl: Option<u32>,
+    },
+    /// Emitted before starting the TLS handshake
+    TlsStart {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+    },
+    /// Emitted for each line of cleartext received before the TLS handshake
+    PreTlsData {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        data: String,
+    },
+    /// Emitted after the TLS handshake when the server's certificate is
+    /// recorded in the `--tofu` store for the first time
+    CertificateTrusted {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// SHA-256 fingerprint of the certificate, as colon-separated pairs
+        /// of hex digits
+        fingerprint: String,
+    },
+    /// Emitted after completing the TLS handshake
+    TlsComplete {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Whether the handshake resumed an earlier TLS session.  This is
+        /// `None` if it could not be determined or the transcript was written
+        /// before the field was introduced.
+        #[serde(default)]
+        resumed: Option<bool>,
+    },
+    /// Emitted once the connection has been established, giving the time in
+    /// milliseconds spent on each stage
+    Timing {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        dns_ms: f64,
+        connect_ms: f64,
+        /// `None` if TLS was not used
+        tls_ms: Option<f64>,
+        total_ms: f64,
+    },
+    /// A line received from the remote server, including trailing newline
+    /// (if any)
+    Recv {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Milliseconds since the connection was established (absent in
+        /// transcripts before format version 2)
+        #[serde(default)]
+        elapsed_ms: Option<f64>,
+        data: String,
+        /// The line's bytes exactly as received, before decoding; only
+        /// recorded under `--transcript-raw-bytes`
+        #[serde(
+            default,
+            rename = "data_base64",
+            deserialize_with = "deserialize_base64"
+        )]
+        raw: Option<Vec<u8>>,
+        /// Whether the line was broken off at `--max-line-length`, in which
+        /// case its remainder is given by the next `Recv` event
+        #[serde(default)]
+        partial: bool,
+    },
+    /// A line sent to the remote server, including trailing newline
+    Send {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Milliseconds since the connection was established (absent in
+        /// transcripts before format version 2)
+        #[serde(default)]
+        elapsed_ms: Option<f64>,
+        data: String,
+        /// The bytes put on the wire for the line, after encoding; only
+        /// recorded under `--transcript-raw-bytes`
+        #[serde(
+            default,
+            rename = "data_base64",
+            deserialize_with = "deserialize_base64"
+        )]
+        raw: Option<Vec<u8>>,
+    },
+    /// Emitted when the write side of the connection is shut down, after
+    /// which only receiving is possible
+    ShutdownWrite {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+    },
+    /// Emitted when the session is ended for exhausting a byte budget
+    BudgetExceeded {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        direction: Direction,
+        limit: u64,
+    },
+    /// Emitted before sending a line that is longer than
+    /// `--max-send-length`, or in place of sending it under `--strict`
+    SendTooLong {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Length of the line in bytes, once encoded & terminated
+        length: usize,
+        limit: usize,
+        /// Whether the line was not sent
+        refused: bool,
+    },
+    /// Emitted at the end of the session if any received lines were not
+    /// displayed
+    LinesHidden {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        count: u64,
+    },
+    /// Emitted at the end of the session if any buffered items were
+    /// discarded to stay under the memory cap
+    MemoryEvicted {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        count: u64,
+    },
+    /// A received line was not valid UTF-8 under `--encoding utf8-strict`
+    EncodingError {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Position of the first invalid byte sequence within the line
+        offset: u64,
+        /// The first invalid byte sequence, as space-separated hex pairs
+        bytes: String,
+    },
+    /// The first bytes received looked like a binary protocol rather than
+    /// lines of text
+    ProtocolMismatch {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The protocol detected: "tls", "gzip", or "http2"
+        protocol: String,
+    },
+    /// Nothing was received within the `--banner-warning` window after
+    /// connecting
+    NoBanner {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Length of the window in milliseconds
+        wait_ms: f64,
+    },
+    /// Recent transfer rates, emitted periodically under `--show-throughput`
+    Throughput {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Length in milliseconds of the period over which the rates were
+        /// measured
+        window_ms: f64,
+        lines_sent_per_sec: f64,
+        bytes_sent_per_sec: f64,
+        lines_recv_per_sec: f64,
+        bytes_recv_per_sec: f64,
+    },
+    /// The results of sending a line repeatedly with `/repeat` or `--flood`
+    RepeatSummary {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The line that was sent
+        data: String,
+        /// The number of times the line was sent
+        sent: u64,
+        /// The number of sends that received a response
+        responses: u64,
+        /// Response time statistics in milliseconds; absent if there were no
+        /// responses
+        #[serde(default)]
+        latency_min_ms: Option<f64>,
+        #[serde(default)]
+        latency_avg_ms: Option<f64>,
+        #[serde(default)]
+        latency_p95_ms: Option<f64>,
+    },
+    /// Emitted when a codec setting is changed with the `/set` command
+    SettingChanged {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The name of the setting, e.g., `"encoding"`
+        setting: String,
+        /// The new value, as given to `/set`
+        value: String,
+    },
+    /// Emitted when the user records an annotation with the `/note` command
+    Annotation {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The text of the annotation
+        data: String,
+    },
+    /// Traffic statistics for the session, emitted just before disconnecting
+    SessionStats {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        lines_sent: u64,
+        bytes_sent: u64,
+        lines_recv: u64,
+        bytes_recv: u64,
+        duration_ms: f64,
+        /// `None` if nothing was received
+        idle_ms: Option<f64>,
+    },
+    /// Emitted when the connection is closed
+    Disconnect {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// Which end ended the session.  This and the following fields are
+        /// `None` for transcripts written before they were introduced.
+        #[serde(default)]
+        closed_by: Option<ClosedBy>,
+        /// Milliseconds since the connection was established
+        #[serde(default)]
+        duration_ms: Option<f64>,
+        #[serde(default)]
+        lines_sent: Option<u64>,
+        #[serde(default)]
+        lines_recv: Option<u64>,
+    },
+    /// Emitted when a fatal error occurs
+    Error {
+        #[serde(with = "time::serde::rfc3339")]
+        timestamp: OffsetDateTime,
+        /// The class of the error.  This is `None` for transcripts written
+        /// before the field was introduced.
+        #[serde(default)]
+        kind: Option<ErrorKind>,
+        /// The OS error code underlying the error, if any
+        #[serde(default)]
+        os_error: Option<i32>,
+        /// A human-readable error message
+        data: String,
+        /// Additional lines describing the error in more detail, e.g., why a
+        /// TLS certificate was rejected
+        #[serde(default)]
+        details: Vec<String>,
+    },
+}
+
+impl Event {
+    /// Returns the time at which the event occurred
+    pub fn timestamp(&self) -> OffsetDateTime {
+        match *self {
+            Event::SessionStart { timestamp, .. }
+            | Event::ConnectionStart { timestamp, .. }
+            | Event::ListenStart { timestamp, .. }
+            | Event::Accepted { timestamp, .. }
+            | Event::SerialOpened { timestamp, .. }
+            | Event::ProcessStarted { timestamp, .. }
+            | Event::DnsResolved { timestamp, .. }
+            | Event::ConnectAttempt { timestamp, .. }
+            | Event::ConnectFailed { timestamp, .. }
+            | Event::ConnectionComplete { timestamp, .. }
+            | Event::TlsStart { timestamp }
+            | Event::PreTlsData { timestamp, .. }
+            | Event::CertificateTrusted { timestamp, .. }
+            | Event::TlsComplete { timestamp, .. }
+            | Event::Timing { timestamp, .. }
+            | Event::Recv { timestamp, .. }
+            | Event::Send { timestamp, .. }
+            | Event::BudgetExceeded { timestamp, .. }
+            | Event::SendTooLong { timestamp, .. }
+            | Event::LinesHidden { timestamp, .. }
+            | Event::MemoryEvicted { timestamp, .. }
+            | Event::EncodingError { timestamp, .. }
+            | Event::ProtocolMismatch { timestamp, .. }
+            | Event::NoBanner { timestamp, .. }
+            | Event::Throughput { timestamp, .. }
+            | Event::RepeatSummary { timestamp, .. }
+            | Event::SettingChanged { timestamp, .. }
+            | Event::Annotation { timestamp, .. }
+            | Event::SessionStats { timestamp, .. }
+            | Event::ShutdownWrite { timestamp }
+            | Event::Disconnect { timestamp, .. }
+            | Event::Error { timestamp, .. } => timestamp,
+        }
+    }
+}
+
+fn format_version_1() -> u32 {
+    1
+}
+
+fn deserialize_base64<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+        .decode(s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// The direction of traffic that a byte budget applies to
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Recv,
+}
+
+/// Which end of the connection ended the session, as recorded in a
+/// "disconnect" event
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClosedBy {
+    /// The session was ended by `confab`, e.g., because the user quit, input
+    /// ran out, or `--until` matched.  This includes the case where the
+    /// remote end closed the connection in response to `confab` shutting
+    /// down its side.
+    Local,
+    /// The remote end closed the connection on its own
+    Peer,
+}
+
+/// The class of a fatal error, as recorded in an "error" event
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// Resolving the remote host or connecting to it failed
+    Connect,
+    /// The TLS handshake failed
+    Tls,
+    /// Sending data to the server failed
+    Send,
+    /// Receiving data from the server failed, or the server did not send
+    /// what was expected
+    Recv,
+    /// Reading from or writing to the terminal or startup script failed
+    Interface,
+}
+
+/// Error returned when a transcript cannot be read
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("failed to open transcript")]
+    Open(#[source] io::Error),
+    #[error("failed to read transcript")]
+    Read(#[source] io::Error),
+    #[error("invalid event on line {lineno} of transcript")]
+    Parse {
+        /// The (one-based) number of the offending line
+        lineno: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Read the events from the transcript at the given path.
+///
+/// If the file cannot be opened, the iterator yields a single
+/// [`ReadError::Open`].  Blank lines are skipped.
+pub fn read<P: AsRef<Path>>(path: P) -> Events<BufReader<File>> {
+    match File::open(path) {
+        Ok(fp) => from_reader(BufReader::new(fp)),
+        Err(e) => Events {
+            lines: None,
+            open_error: Some(e),
+            lineno: 0,
+        },
+    }
+}
+
+/// Read the events from a transcript in the given reader
+pub fn from_reader<R: BufRead>(reader: R) -> Events<R> {
+    Events {
+        lines: Some(reader.lines()),
+        open_error: None,
+        lineno: 0,
+    }
+}
+
+/// Asynchronously read the events from the transcript at the given path.
+///
+/// This behaves the same as [`read()`].
+pub fn read_async<P: AsRef<Path>>(path: P) -> impl Stream<Item = Result<Event, ReadError>> + Send {
+    let path = path.as_ref().to_owned();
+    stream! {
+        let fp = match tokio::fs::File::open(path).await {
+            Ok(fp) => fp,
+            Err(e) => {
+                yield Err(ReadError::Open(e));
+                return;
+            }
+        };
+        let mut lines = tokio::io::BufReader::new(fp).lines();
+        let mut lineno = 0;
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    lineno += 1;
+                    if let Some(r) = parse(lineno, &line) {
+                        yield r;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    yield Err(ReadError::Read(e));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the events in a transcript, returned by [`read()`] and
+/// [`from_reader()`]
+#[derive(Debug)]
+pub struct Events<R> {
+    lines: Option<io::Lines<R>>,
+    open_error: Option<io::Error>,
+    lineno: usize,
+}
+
+impl<R: BufRead> Iterator for Events<R> {
+    type Item = Result<Event, ReadError>;
+
+    fn next(&mut self) -> Option<Result<Event, ReadError>> {
+        if let Some(e) = self.open_error.take() {
+            return Some(Err(ReadError::Open(e)));
+        }
+        loop {
+            match self.lines.as_mut()?.next()? {
+                Ok(line) => {
+                    self.lineno += 1;
+                    if let Some(r) = parse(self.lineno, &line) {
+                        return Some(r);
+                    }
+                }
+                Err(e) => {
+                    self.lines = None;
+                    return Some(Err(ReadError::Read(e)));
+                }
+            }
+        }
+    }
+}
+
+/// Parse a line of a transcript, returning `None` if it is blank
+fn parse(lineno: usize, line: &str) -> Option<Result<Event, ReadError>> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(serde_json::from_str(line).map_err(|source| ReadError::Parse { lineno, source }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use futures_util::StreamExt;
+    use std::io::Write;
+    use time::macros::datetime;
+
+    static TRANSCRIPT: &str = concat!(
+        r#"{"timestamp": "2024-05-01T12:34:56.5-04:00", "event": "connection-start", "host": "localhost", "port": 80}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:56.75-04:00", "event": "timing", "dns_ms": 0.5, "connect_ms": 1.25, "total_ms": 1.75}"#,
+        "\n\n",
+        r#"{"timestamp": "2024-05-01T12:34:57-04:00", "event": "recv", "data": "Hello!\r\n"}"#,
+        "\n",
+        r#"{"timestamp": "2024-05-01T12:34:58-04:00", "event": "budget-exceeded", "direction": "send", "limit": 10}"#,
+        "\n",
+    );
+
+    #[test]
+    fn test_from_reader() {
+        let mut events = from_reader(TRANSCRIPT.as_bytes());
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            Event::ConnectionStart {
+                timestamp: datetime!(2024-05-01 12:34:56.5 -04:00),
+                format_version: 1,
+                host: "localhost".into(),
+                port: 80,
+            }
+        );
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            Event::Timing {
+                timestamp: datetime!(2024-05-01 12:34:56.75 -04:00),
+                dns_ms: 0.5,
+                connect_ms: 1.25,
+                tls_ms: None,
+                total_ms: 1.75,
+            }
+        );
+        let ev = events.next().unwrap().unwrap();
+        assert_eq!(ev.timestamp(), datetime!(2024-05-01 12:34:57 -04:00));
+        assert_matches!(ev, Event::Recv { data, .. } => {
+            assert_eq!(data, "Hello!\r\n");
+        });
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::BudgetExceeded {
+                direction: Direction::Send,
+                limit: 10,
+                ..
+            }))
+        );
+        assert_matches!(events.next(), None);
+    }
+
+    #[test]
+    fn test_format_version_2() {
+        let src = concat!(
+            r#"{"timestamp": "2024-05-01T12:34:56.000000-04:00", "event": "connection-start", "format_version": 2, "host": "localhost", "port": 80}"#,
+            "\n",
+            r#"{"timestamp": "2024-05-01T12:34:56.123456-04:00", "event": "send", "elapsed_ms": 12.345, "data": "Hi\n"}"#,
+            "\n",
+        );
+        let mut events = from_reader(src.as_bytes());
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::ConnectionStart {
+                format_version: 2,
+                ..
+            }))
+        );
+        assert_eq!(
+            events.next().unwrap().unwrap(),
+            Event::Send {
+                timestamp: datetime!(2024-05-01 12:34:56.123456 -04:00),
+                elapsed_ms: Some(12.345),
+                data: "Hi\n".into(),
+                raw: None,
+            }
+        );
+        assert_matches!(events.next(), None);
+    }
+
+    #[test]
+    fn test_raw_bytes() {
+        let src = concat!(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "recv", "elapsed_ms": 1.000, "data": "Libert\ufffd\n", "data_base64": "TGliZXJ06Qo="}"#,
+            "\n",
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "send", "elapsed_ms": 2.000, "data": "Hi\n", "data_base64": "!!"}"#,
+            "\n",
+        );
+        let mut events = from_reader(src.as_bytes());
+        assert_matches!(events.next(), Some(Ok(Event::Recv { data, raw, .. })) => {
+            assert_eq!(data, "Libert\u{FFFD}\n");
+            assert_eq!(raw.as_deref(), Some(&b"Libert\xE9\n"[..]));
+        });
+        assert_matches!(events.next(), Some(Err(ReadError::Parse { lineno: 2, .. })));
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let src = concat!(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "error", "kind": "recv", "os_error": 104, "data": "failed to receive line from server: Connection reset by peer (os error 104)"}"#,
+            "\n",
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "error", "data": "timed out waiting for banner from server"}"#,
+            "\n",
+        );
+        let mut events = from_reader(src.as_bytes());
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::Error {
+                kind: Some(ErrorKind::Recv),
+                os_error: Some(104),
+                ..
+            }))
+        );
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::Error {
+                kind: None,
+                os_error: None,
+                ..
+            }))
+        );
+        assert_matches!(events.next(), None);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let src = concat!(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "disconnect", "closed_by": "peer", "duration_ms": 1500.250, "lines_sent": 2, "lines_recv": 3}"#,
+            "\n",
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "disconnect"}"#,
+            "\n",
+        );
+        let mut events = from_reader(src.as_bytes());
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::Disconnect {
+                closed_by: Some(ClosedBy::Peer),
+                duration_ms: Some(1500.25),
+                lines_sent: Some(2),
+                lines_recv: Some(3),
+                ..
+            }))
+        );
+        assert_matches!(
+            events.next(),
+            Some(Ok(Event::Disconnect {
+                closed_by: None,
+                duration_ms: None,
+                ..
+            }))
+        );
+        assert_matches!(events.next(), None);
+    }
+
+    #[test]
+    fn test_malformed_line() {
+        let src = concat!(
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "disconnect"}"#,
+            "\n",
+            r#"{"timestamp": "2024-05-01T12:34:56-04:00", "event": "bogus"}"#,
+            "\n",
+        );
+        let mut events = from_reader(src.as_bytes());
+        assert_matches!(events.next(), Some(Ok(Event::Disconnect { .. })));
+        let e = events.next().unwrap().unwrap_err();
+        assert_matches!(e, ReadError::Parse { lineno: 2, .. });
+        assert_eq!(e.to_string(), "invalid event on line 2 of transcript");
+        assert_matches!(events.next(), None);
+    }
+
+    #[test]
+    fn test_read_missing() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut events = read(tmpdir.path().join("nonexistent.jsonl"));
+        assert_matches!(events.next(), Some(Err(ReadError::Open(_))));
+        assert_matches!(events.next(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_async() {
+        let mut tmpfile = tempfile::NamedTempFile::new().unwrap();
+        tmpfile.write_all(TRANSCRIPT.as_bytes()).unwrap();
+        tmpfile.flush().unwrap();
+        let events = read_async(tmpfile.path()).collect::<Vec<_>>().await;
+        let expected = from_reader(TRANSCRIPT.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(events.len(), 4);
+        for (actual, expected) in std::iter::zip(events, expected) {
+            assert_eq!(actual.unwrap(), expected.unwrap());
+        }
+    }
+}
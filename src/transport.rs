@@ -0,0 +1,20 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream over which a session can be carried out: a plain TCP
+/// connection, a TLS connection, a serial port, etc.
+///
+/// Every connection is boxed into a [`BoxedTransport`] once it has been
+/// established, so adding a new kind of transport only requires a new way of
+/// opening one in `Connector`; the session loop and the `Reporter` are
+/// unaffected.
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// A transport with its concrete type erased
+pub(crate) type BoxedTransport = Box<dyn Transport>;
+
+/// Erase the concrete type of a transport
+pub(crate) fn boxed<T: Transport + 'static>(conn: T) -> BoxedTransport {
+    Box::new(conn)
+}
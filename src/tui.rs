@@ -0,0 +1,339 @@
+use crate::util::cursor_position;
+use ansi_to_tui::IntoText;
+use crossterm::{
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Position},
+    style::Stylize,
+    text::{Line, Span, Text},
+    widgets::Paragraph,
+    Terminal,
+};
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Maximum number of lines of output kept in the output pane
+const MAX_OUTPUT_LINES: usize = 10000;
+
+/// Tab stops in the output pane occur every this many columns
+const TAB_WIDTH: usize = 8;
+
+/// A request to scroll the output pane, made by pressing a key bound to
+/// `scroll-up` or `scroll-down`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Scroll {
+    Up,
+    Down,
+}
+
+/// The full-screen interface used under `--tui`: a scrollable pane of output
+/// above a separator and a separate input line.
+///
+/// The screen is drawn on the terminal's alternate screen, which is left when
+/// the `Screen` is dropped.
+pub(crate) struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    output: OutputPane,
+}
+
+impl Screen {
+    pub(crate) fn new() -> io::Result<Screen> {
+        let mut stdout = io::stdout();
+        stdout.execute(EnterAlternateScreen)?;
+        match Terminal::new(CrosstermBackend::new(stdout)) {
+            Ok(terminal) => Ok(Screen {
+                terminal,
+                output: OutputPane::default(),
+            }),
+            Err(e) => {
+                let _ = io::stdout().execute(LeaveAlternateScreen);
+                Err(e)
+            }
+        }
+    }
+
+    /// Append output to the output pane.  The screen is not redrawn until
+    /// [`Screen::draw()`] is called.
+    pub(crate) fn push_output(&mut self, data: &[u8]) {
+        self.output.push(data);
+    }
+
+    pub(crate) fn scroll(&mut self, scroll: Scroll) {
+        self.output.scroll(scroll);
+    }
+
+    /// Discard all output shown so far and redraw the screen from scratch
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        self.output.clear();
+        self.terminal.clear()
+    }
+
+    /// Draw the output pane and the input line, which consists of `prompt`
+    /// followed by `line`, with the cursor at byte offset `cursor` in `line`
+    pub(crate) fn draw(&mut self, prompt: &str, line: &str, cursor: usize) -> io::Result<()> {
+        let output = &mut self.output;
+        self.terminal.draw(|frame| {
+            let area = frame.area();
+            let width = usize::from(area.width).max(1);
+            let input = wrap(Line::from(vec![Span::raw(prompt), Span::raw(line)]), width);
+            let (cursor_row, cursor_col) = cursor_position(
+                &format!("{prompt}{}", line.get(..cursor).unwrap_or(line)),
+                line.get(cursor..).unwrap_or_default(),
+                width,
+            );
+            let max_height = usize::from(area.height / 2).max(1);
+            let height = input.len().max(cursor_row + 1).min(max_height);
+            // Keep the cursor visible if the input is too tall for its area
+            let skip = (cursor_row + 1).saturating_sub(height);
+            let [pane_area, sep_area, input_area] = Layout::vertical([
+                Constraint::Min(0),
+                Constraint::Length(1),
+                Constraint::Length(to_u16(height)),
+            ])
+            .areas(area);
+            frame.render_widget(
+                Paragraph::new(Text::from(
+                    output.visible(width, usize::from(pane_area.height)),
+                )),
+                pane_area,
+            );
+            frame.render_widget(separator(width, output.scrolled()), sep_area);
+            frame.render_widget(
+                Paragraph::new(Text::from(input.into_iter().skip(skip).collect::<Vec<_>>())),
+                input_area,
+            );
+            frame.set_cursor_position(Position::new(
+                input_area.x + to_u16(cursor_col),
+                input_area.y + to_u16(cursor_row - skip),
+            ));
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.terminal.show_cursor();
+        let _ = self.terminal.backend_mut().execute(LeaveAlternateScreen);
+    }
+}
+
+/// The lines of output shown on a [`Screen`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct OutputPane {
+    /// Lines of output, without line endings but with any ANSI styling
+    lines: VecDeque<String>,
+    /// Whether the last element of `lines` has not been terminated by a
+    /// newline yet
+    partial: bool,
+    /// Number of rows by which the view is scrolled up from the bottom
+    offset: usize,
+    /// Height of the pane when it was last drawn, used as the page size for
+    /// scrolling
+    height: usize,
+    /// Width of the pane when it was last drawn
+    width: usize,
+}
+
+impl OutputPane {
+    fn push(&mut self, data: &[u8]) {
+        let text = String::from_utf8_lossy(data);
+        let mut added = 0;
+        for chunk in text.split_inclusive('\n') {
+            let complete = chunk.ends_with('\n');
+            let chunk = chunk.strip_suffix('\n').unwrap_or(chunk);
+            let chunk = chunk.strip_suffix('\r').unwrap_or(chunk);
+            match self.lines.back_mut() {
+                Some(last) if self.partial => last.push_str(chunk),
+                _ => {
+                    self.lines.push_back(chunk.to_owned());
+                    if self.lines.len() > MAX_OUTPUT_LINES {
+                        self.lines.pop_front();
+                    }
+                    if self.offset > 0 {
+                        added += wrap(parse(chunk), self.width.max(1)).len();
+                    }
+                }
+            }
+            self.partial = !complete;
+        }
+        if self.offset > 0 {
+            // Keep the view in place while the user is looking back
+            self.offset += added;
+        }
+    }
+
+    fn scroll(&mut self, scroll: Scroll) {
+        let page = self.height.saturating_sub(1).max(1);
+        match scroll {
+            Scroll::Up => self.offset += page,
+            Scroll::Down => self.offset = self.offset.saturating_sub(page),
+        }
+    }
+
+    fn scrolled(&self) -> bool {
+        self.offset > 0
+    }
+
+    fn clear(&mut self) {
+        self.lines.clear();
+        self.partial = false;
+        self.offset = 0;
+    }
+
+    /// Return the rows of output to show in a pane of the given size,
+    /// clamping the scroll offset to the amount of output available
+    fn visible(&mut self, width: usize, height: usize) -> Vec<Line<'static>> {
+        self.width = width;
+        self.height = height;
+        let needed = height + self.offset;
+        // Rows from the bottom up
+        let mut rows = Vec::new();
+        for line in self.lines.iter().rev() {
+            rows.extend(wrap(parse(line), width).into_iter().rev());
+            if rows.len() >= needed {
+                break;
+            }
+        }
+        self.offset = self.offset.min(rows.len().saturating_sub(height));
+        let mut visible = rows
+            .into_iter()
+            .skip(self.offset)
+            .take(height)
+            .collect::<Vec<_>>();
+        visible.reverse();
+        visible
+    }
+}
+
+/// Convert a line of output containing ANSI styling into a `Line`
+fn parse(line: &str) -> Line<'static> {
+    match line.as_bytes().into_text() {
+        Ok(text) => Line::from(
+            text.lines
+                .into_iter()
+                .flat_map(|ln| {
+                    let style = ln.style;
+                    ln.spans
+                        .into_iter()
+                        .map(move |span| span.patch_style(style))
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => Line::raw(line.to_owned()),
+    }
+}
+
+/// Break a line into rows no wider than `width` columns, expanding tabs
+fn wrap(line: Line<'_>, width: usize) -> Vec<Line<'static>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut column = 0;
+    for span in line.spans {
+        let mut chunk = String::new();
+        for g in span.content.graphemes(true) {
+            let (g, w) = if g == "\t" {
+                let w = TAB_WIDTH - column % TAB_WIDTH;
+                (" ".repeat(w), w)
+            } else {
+                (g.to_owned(), g.width())
+            };
+            if column + w > width && column > 0 {
+                if !chunk.is_empty() {
+                    row.push(Span::styled(std::mem::take(&mut chunk), span.style));
+                }
+                rows.push(Line::from(std::mem::take(&mut row)));
+                column = 0;
+            }
+            chunk.push_str(&g);
+            column += w;
+        }
+        if !chunk.is_empty() {
+            row.push(Span::styled(chunk, span.style));
+        }
+    }
+    rows.push(Line::from(row));
+    rows
+}
+
+/// The line between the output pane and the input line, which indicates
+/// whether there is more output below the view
+fn separator(width: usize, scrolled: bool) -> Line<'static> {
+    let label = if scrolled {
+        " More below (PageDown) "
+    } else {
+        ""
+    };
+    let rule = "─".repeat(width.saturating_sub(label.width() + 2));
+    Line::from(format!("──{label}{rule}")).dim()
+}
+
+fn to_u16(n: usize) -> u16 {
+    u16::try_from(n).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(rows: &[Line<'_>]) -> Vec<String> {
+        rows.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(plain(&wrap(Line::raw("abcdefg"), 3)), ["abc", "def", "g"]);
+        assert_eq!(plain(&wrap(Line::raw("abc"), 3)), ["abc"]);
+        assert_eq!(plain(&wrap(Line::raw(""), 3)), [""]);
+        assert_eq!(plain(&wrap(Line::raw("a\tb"), 20)), ["a       b"]);
+        assert_eq!(plain(&wrap(Line::raw("ab日本"), 3)), ["ab", "日", "本"]);
+    }
+
+    #[test]
+    fn test_parse_styled() {
+        let line = parse("< \x1B[7m^@\x1B[0mX");
+        assert_eq!(line.to_string(), "< ^@X");
+        assert!(line.spans.iter().any(|s| s.content == "^@"
+            && s.style
+                .add_modifier
+                .contains(ratatui::style::Modifier::REVERSED)));
+    }
+
+    #[test]
+    fn test_push_partial() {
+        let mut pane = OutputPane::default();
+        pane.push(b"< one\n< tw");
+        pane.push(b"o\r\n");
+        pane.push(b"< three\n");
+        assert_eq!(pane.lines, ["< one", "< two", "< three"]);
+        assert!(!pane.partial);
+    }
+
+    #[test]
+    fn test_visible() {
+        let mut pane = OutputPane::default();
+        for i in 0..10 {
+            pane.push(format!("line {i}\n").as_bytes());
+        }
+        assert_eq!(plain(&pane.visible(20, 3)), ["line 7", "line 8", "line 9"]);
+        pane.scroll(Scroll::Up);
+        assert_eq!(plain(&pane.visible(20, 3)), ["line 5", "line 6", "line 7"]);
+        // New output doesn't move the view while scrolled
+        pane.push(b"line 10\n");
+        assert_eq!(plain(&pane.visible(20, 3)), ["line 5", "line 6", "line 7"]);
+        for _ in 0..10 {
+            pane.scroll(Scroll::Up);
+        }
+        assert_eq!(plain(&pane.visible(20, 3)), ["line 0", "line 1", "line 2"]);
+        for _ in 0..10 {
+            pane.scroll(Scroll::Down);
+        }
+        assert!(!pane.scrolled());
+        assert_eq!(plain(&pane.visible(20, 3)), ["line 8", "line 9", "line 10"]);
+    }
+}
@@ -1,4 +1,5 @@
 use crossterm::style::{StyledContent, Stylize};
+use encoding_rs::{Encoding, UTF_8};
 use itertools::Itertools; // for chunk_by()
 use std::borrow::Cow;
 use std::fmt::{self, Display, Write};
@@ -8,9 +9,16 @@ use time::format_description::FormatItem;
 use time::macros::format_description;
 use time::OffsetDateTime;
 use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub(crate) static HMS_FMT: &[FormatItem<'_>] = format_description!("[hour]:[minute]:[second]");
 
+/// RFC 3339 timestamp format with microsecond precision, used in transcripts
+pub(crate) static TIMESTAMP_FMT: &[FormatItem<'_>] = format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:6][offset_hour sign:mandatory]:[offset_minute]"
+);
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct JsonStrMap {
     buf: String,
@@ -58,7 +66,7 @@ impl Default for JsonStrMap {
     }
 }
 
-fn write_json_str<W: Write>(s: &str, writer: &mut W) -> fmt::Result {
+pub(crate) fn write_json_str<W: Write>(s: &str, writer: &mut W) -> fmt::Result {
     writer.write_char('"')?;
     for c in s.chars() {
         match c {
@@ -82,37 +90,77 @@ fn write_json_str<W: Write>(s: &str, writer: &mut W) -> fmt::Result {
     Ok(())
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub(crate) enum CharEncoding {
     Utf8,
+    /// UTF-8 in which invalid byte sequences are reported as errors
+    Utf8Strict,
     Utf8Latin1,
     Latin1,
+    /// Any other ASCII-compatible encoding supported by `encoding_rs`
+    Other(&'static Encoding),
 }
 
 impl CharEncoding {
-    pub(crate) fn is_utf8(&self) -> bool {
-        matches!(self, CharEncoding::Utf8 | CharEncoding::Utf8Latin1)
-    }
-
     pub(crate) fn encode<'a>(&'a self, s: &'a str) -> Cow<'a, [u8]> {
         match self {
-            CharEncoding::Utf8 | CharEncoding::Utf8Latin1 => Cow::from(s.as_bytes()),
+            CharEncoding::Utf8 | CharEncoding::Utf8Strict | CharEncoding::Utf8Latin1 => {
+                Cow::from(s.as_bytes())
+            }
             CharEncoding::Latin1 => Cow::from(
                 s.chars()
                     .map(|c| u8::try_from(c).unwrap_or(b'?'))
                     .collect::<Vec<_>>(),
             ),
+            // encoding_rs converts unmappable characters to HTML numeric
+            // character references; callers should use
+            // `replace_unencodable()` first in order to avoid that.
+            CharEncoding::Other(enc) => enc.encode(s).0,
         }
     }
 
-    pub(crate) fn decode(&self, bs: Vec<u8>) -> String {
+    /// Decode bytes received from the server.  Only `Utf8Strict` can fail;
+    /// the other encodings replace or reinterpret invalid input.
+    pub(crate) fn decode(&self, bs: Vec<u8>) -> Result<String, DecodeError> {
         match self {
-            CharEncoding::Utf8 => String::from_utf8_lossy(&bs).into_owned(),
+            CharEncoding::Utf8 => Ok(String::from_utf8_lossy(&bs).into_owned()),
+            CharEncoding::Utf8Strict => String::from_utf8(bs).map_err(DecodeError::new),
             CharEncoding::Utf8Latin1 => match String::from_utf8(bs) {
-                Ok(s) => s,
-                Err(e) => decode_latin1(e.into_bytes()),
+                Ok(s) => Ok(s),
+                Err(e) => Ok(decode_latin1(e.into_bytes())),
             },
-            CharEncoding::Latin1 => decode_latin1(bs),
+            CharEncoding::Latin1 => Ok(decode_latin1(bs)),
+            CharEncoding::Other(enc) => Ok(enc.decode_without_bom_handling(&bs).0.into_owned()),
+        }
+    }
+
+    /// Decode bytes, replacing any invalid sequences with the replacement
+    /// character even under `Utf8Strict`
+    pub(crate) fn decode_lossy(&self, bs: Vec<u8>) -> String {
+        self.decode(bs).unwrap_or_else(|e| e.lossy)
+    }
+
+    /// Replace any characters in `s` that cannot be represented in the
+    /// encoding with question marks
+    pub(crate) fn replace_unencodable(&self, s: String) -> String {
+        match self {
+            CharEncoding::Utf8 | CharEncoding::Utf8Strict | CharEncoding::Utf8Latin1 => s,
+            CharEncoding::Latin1 => latin1ify(s),
+            CharEncoding::Other(enc) => {
+                if !enc.encode(&s).2 {
+                    return s;
+                }
+                let mut buf = [0u8; 4];
+                s.chars()
+                    .map(|c| {
+                        if enc.encode(c.encode_utf8(&mut buf)).2 {
+                            '?'
+                        } else {
+                            c
+                        }
+                    })
+                    .collect()
+            }
         }
     }
 }
@@ -123,19 +171,80 @@ impl FromStr for CharEncoding {
     fn from_str(s: &str) -> Result<CharEncoding, CharEncodingLookupError> {
         if s.eq_ignore_ascii_case("utf8") {
             Ok(CharEncoding::Utf8)
+        } else if s.eq_ignore_ascii_case("utf8-strict") {
+            Ok(CharEncoding::Utf8Strict)
         } else if s.eq_ignore_ascii_case("utf8-latin1") {
             Ok(CharEncoding::Utf8Latin1)
         } else if s.eq_ignore_ascii_case("latin1") {
             Ok(CharEncoding::Latin1)
         } else {
-            Err(CharEncodingLookupError)
+            match Encoding::for_label(s.as_bytes()) {
+                Some(enc) if enc == UTF_8 => Ok(CharEncoding::Utf8),
+                // Line splitting only works if newlines are encoded as in
+                // ASCII and never occur inside multibyte sequences (which
+                // rules out UTF-16 and ISO-2022-JP).
+                Some(enc) if enc.is_ascii_compatible() => Ok(CharEncoding::Other(enc)),
+                Some(enc) => Err(CharEncodingLookupError::Incompatible(enc.name())),
+                None => Err(CharEncodingLookupError::Unknown),
+            }
+        }
+    }
+}
+
+impl Display for CharEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CharEncoding::Utf8 => write!(f, "utf8"),
+            CharEncoding::Utf8Strict => write!(f, "utf8-strict"),
+            CharEncoding::Utf8Latin1 => write!(f, "utf8-latin1"),
+            CharEncoding::Latin1 => write!(f, "latin1"),
+            CharEncoding::Other(enc) => write!(f, "{}", enc.name()),
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
-#[error("invalid character encoding name")]
-pub(crate) struct CharEncodingLookupError;
+pub(crate) enum CharEncodingLookupError {
+    #[error("invalid character encoding name")]
+    Unknown,
+    #[error("{0} encoding is not supported, as it is not ASCII-compatible")]
+    Incompatible(&'static str),
+}
+
+/// An invalid byte sequence in data received under `--encoding utf8-strict`
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("invalid UTF-8 sequence {} at byte {offset}", hex_bytes(.bytes))]
+pub(crate) struct DecodeError {
+    /// Position of the first invalid sequence within the line
+    pub(crate) offset: usize,
+    /// The first invalid sequence
+    pub(crate) bytes: Vec<u8>,
+    /// The line decoded with invalid sequences replaced by the replacement
+    /// character
+    pub(crate) lossy: String,
+}
+
+impl DecodeError {
+    fn new(e: std::string::FromUtf8Error) -> DecodeError {
+        let err = e.utf8_error();
+        let bs = e.as_bytes();
+        let offset = err.valid_up_to();
+        let end = err.error_len().map_or(bs.len(), |n| offset + n);
+        DecodeError {
+            offset,
+            bytes: bs[offset..end].to_vec(),
+            lossy: String::from_utf8_lossy(bs).into_owned(),
+        }
+    }
+}
+
+/// Format bytes as space-separated pairs of lowercase hex digits
+pub(crate) fn hex_bytes(bs: &[u8]) -> String {
+    bs.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
 pub(crate) fn chomp(s: &str) -> &str {
     let s = s.strip_suffix('\n').unwrap_or(s);
@@ -191,6 +300,41 @@ fn decode_latin1(bs: Vec<u8>) -> String {
     bs.into_iter().map(char::from).collect()
 }
 
+/// Return the row & column, counted from zero, at which the cursor is left by
+/// printing `text` on a terminal `width` columns wide, starting at column
+/// `start` of row 0.
+///
+/// As on a terminal, a double-width character that does not fit in the rest
+/// of a row is moved to the start of the next row, and combining marks take
+/// up no space.  If the text exactly fills its last row, the column returned
+/// is `width`.
+pub(crate) fn end_position(text: &str, start: usize, width: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, start);
+    for g in text.graphemes(true) {
+        let w = g.width();
+        if column + w > width && column > 0 {
+            row += 1;
+            column = 0;
+        }
+        column += w;
+    }
+    (row, column)
+}
+
+/// Return the row & column, counted from zero, at which the cursor should be
+/// placed on a terminal `width` columns wide in order to sit on the first
+/// character of `after` when `before` and then `after` are printed starting
+/// at the beginning of a row
+pub(crate) fn cursor_position(before: &str, after: &str, width: usize) -> (usize, usize) {
+    let (row, column) = end_position(before, 0, width);
+    let next = after.graphemes(true).next().map_or(1, |g| g.width().max(1));
+    if column + next > width && column > 0 {
+        (row + 1, 0)
+    } else {
+        (row, column)
+    }
+}
+
 pub(crate) fn now() -> OffsetDateTime {
     OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
 }
@@ -206,6 +350,33 @@ mod tests {
     use super::*;
     use rstest::rstest;
 
+    #[rstest]
+    #[case("abcdefgh", 0, (1, 3))]
+    #[case("abcdefghij", 0, (1, 5))]
+    #[case("abcde", 0, (0, 5))]
+    #[case("abc", 3, (1, 1))]
+    #[case("日本語", 0, (1, 2))]
+    #[case("a日本語", 0, (1, 2))]
+    #[case("ab日本語", 0, (1, 4))]
+    #[case("e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}", 0, (0, 5))]
+    fn test_end_position(#[case] text: &str, #[case] start: usize, #[case] pos: (usize, usize)) {
+        assert_eq!(end_position(text, start, 5), pos);
+    }
+
+    #[rstest]
+    #[case("> ab", "c", (0, 4))]
+    #[case("> abc", "", (1, 0))]
+    #[case("> ab", "日", (1, 0))]
+    #[case("> a", "日", (0, 3))]
+    #[case("> 日", "本", (1, 0))]
+    fn test_cursor_position(
+        #[case] before: &str,
+        #[case] after: &str,
+        #[case] pos: (usize, usize),
+    ) {
+        assert_eq!(cursor_position(before, after, 5), pos);
+    }
+
     #[rstest]
     #[case("foobar", r#""foobar""#)]
     #[case("foo / bar", r#""foo / bar""#)]
@@ -264,26 +435,89 @@ mod tests {
     #[test]
     fn test_decode_latin1() {
         let bs = b"Snow\xE9mon: \xE2\x98\x83!".to_vec();
-        assert_eq!(CharEncoding::Latin1.decode(bs), "Snowémon: â\u{98}\u{83}!");
+        assert_eq!(
+            CharEncoding::Latin1.decode_lossy(bs),
+            "Snowémon: â\u{98}\u{83}!"
+        );
+    }
+
+    #[test]
+    fn test_encode_shift_jis() {
+        let enc = "shift_jis".parse::<CharEncoding>().unwrap();
+        let s = enc.replace_unencodable(String::from("日本語: ☃ é!"));
+        assert_eq!(s, "日本語: ? ?!");
+        assert_eq!(enc.encode(&s), &b"\x93\xfa\x96{\x8c\xea: ? ?!"[..]);
+    }
+
+    #[test]
+    fn test_decode_koi8r() {
+        let enc = "koi8-r".parse::<CharEncoding>().unwrap();
+        assert_eq!(
+            enc.decode_lossy(b"\xf0\xd2\xc9\xd7\xc5\xd4!".to_vec()),
+            "Привет!"
+        );
+    }
+
+    #[rstest]
+    #[case("UTF8", CharEncoding::Utf8)]
+    #[case("utf8-strict", CharEncoding::Utf8Strict)]
+    #[case("utf-8", CharEncoding::Utf8)]
+    #[case("Latin1", CharEncoding::Latin1)]
+    #[case("iso-8859-1", CharEncoding::Other(encoding_rs::WINDOWS_1252))]
+    #[case("gbk", CharEncoding::Other(encoding_rs::GBK))]
+    fn test_parse_encoding(#[case] s: &str, #[case] enc: CharEncoding) {
+        assert_eq!(s.parse::<CharEncoding>(), Ok(enc));
+    }
+
+    #[rstest]
+    #[case("utf-16le", CharEncodingLookupError::Incompatible("UTF-16LE"))]
+    #[case("iso-2022-jp", CharEncodingLookupError::Incompatible("ISO-2022-JP"))]
+    #[case("klingon", CharEncodingLookupError::Unknown)]
+    fn test_parse_bad_encoding(#[case] s: &str, #[case] err: CharEncodingLookupError) {
+        assert_eq!(s.parse::<CharEncoding>(), Err(err));
     }
 
     #[test]
     fn test_decode_utf8() {
         let bs = b"Snow\xC3\xA9mon: \xE2\x98!".to_vec();
-        assert_eq!(CharEncoding::Utf8.decode(bs), "Snowémon: \u{fffd}!");
+        assert_eq!(CharEncoding::Utf8.decode_lossy(bs), "Snowémon: \u{fffd}!");
+    }
+
+    #[test]
+    fn test_decode_utf8strict() {
+        let bs = b"Snow\xC3\xA9mon: \xE2\x98\x83!".to_vec();
+        assert_eq!(
+            CharEncoding::Utf8Strict.decode(bs),
+            Ok(String::from("Snowémon: ☃!"))
+        );
+        let bs = b"Snow\xC3\xA9mon: \xE2\x98!".to_vec();
+        let e = CharEncoding::Utf8Strict.decode(bs).unwrap_err();
+        assert_eq!(e.offset, 11);
+        assert_eq!(e.bytes, b"\xE2\x98");
+        assert_eq!(e.lossy, "Snowémon: \u{fffd}!");
+        assert_eq!(e.to_string(), "invalid UTF-8 sequence e2 98 at byte 11");
+    }
+
+    #[test]
+    fn test_decode_utf8strict_truncated() {
+        let e = CharEncoding::Utf8Strict
+            .decode(b"abc\xF0\x9F".to_vec())
+            .unwrap_err();
+        assert_eq!(e.offset, 3);
+        assert_eq!(e.bytes, b"\xF0\x9F");
     }
 
     #[test]
     fn test_decode_utf8latin1_good() {
         let bs = b"Snow\xC3\xA9mon: \xE2\x98\x83!".to_vec();
-        assert_eq!(CharEncoding::Utf8Latin1.decode(bs), "Snowémon: ☃!");
+        assert_eq!(CharEncoding::Utf8Latin1.decode_lossy(bs), "Snowémon: ☃!");
     }
 
     #[test]
     fn test_decode_utf8latin1_fallback() {
         let bs = b"Snow\xC3\xA9mon: \xE2\x98!".to_vec();
         assert_eq!(
-            CharEncoding::Utf8Latin1.decode(bs),
+            CharEncoding::Utf8Latin1.decode_lossy(bs),
             "Snow\u{c3}\u{a9}mon: \u{e2}\u{98}!"
         );
     }
@@ -1,27 +1,24 @@
 // <https://github.com/zhiburt/expectrl/issues/52>
 #![cfg(test)]
 #![cfg(unix)]
+mod common;
 use assert_matches::assert_matches;
+use common::testing_server;
+use confab::transcript::{self, Event};
 use expectrl::session::{log, OsProcess, OsProcessStream, Session};
 use expectrl::stream::log::LogStream;
 use expectrl::{ControlCode, Eof, Regex};
-use futures_util::{SinkExt, StreamExt};
-use serde::Deserialize;
-use serde_jsonlines::json_lines;
 use std::borrow::Cow;
 use std::ffi::OsStr;
-use std::io::{Seek, Write};
-use std::net::{IpAddr, SocketAddr};
+use std::io::{BufRead, BufReader, Seek, Write};
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::time::Duration;
 use tempfile::{tempdir, NamedTempFile, TempDir};
-use time::OffsetDateTime;
-use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
-use tokio::sync::oneshot::{channel, Sender};
+use tokio::sync::oneshot::channel;
 use tokio::time::sleep;
-use tokio_util::codec::{AnyDelimiterCodec, Framed};
 
 #[cfg(unix)]
 use expectrl::WaitStatus;
@@ -32,6 +29,7 @@ struct Tester {
     cmd: Command,
     transcript: bool,
     show_times: bool,
+    verbose: bool,
 }
 
 impl Tester {
@@ -40,6 +38,7 @@ impl Tester {
             cmd: Command::new(env!("CARGO_BIN_EXE_confab")),
             transcript: false,
             show_times: false,
+            verbose: false,
         }
     }
 
@@ -58,6 +57,11 @@ impl Tester {
         self
     }
 
+    fn verbose(mut self) -> Tester {
+        self.verbose = true;
+        self
+    }
+
     async fn build(mut self) -> Runner {
         let (sender, receiver) = channel();
         tokio::spawn(async move { testing_server(sender).await });
@@ -71,6 +75,9 @@ impl Tester {
         if self.show_times {
             self.cmd.arg("--show-times");
         }
+        if self.verbose {
+            self.cmd.arg("--verbose");
+        }
         self.cmd.arg(addr.ip().to_string());
         self.cmd.arg(addr.port().to_string());
         let mut p = log(
@@ -84,6 +91,7 @@ impl Tester {
             addr,
             transcript,
             show_times: self.show_times,
+            verbose: self.verbose,
         };
         runner.connect().await;
         runner.get("Welcome to the confab Test Server!").await;
@@ -96,12 +104,28 @@ struct Runner {
     addr: SocketAddr,
     transcript: Option<Transcript>,
     show_times: bool,
+    verbose: bool,
 }
 
 impl Runner {
     async fn connect(&mut self) {
         self.expect("* Connecting ...").await;
+        if self.verbose {
+            let ip = self.addr.ip();
+            self.expect(format!("* Resolved {ip} to {ip}")).await;
+        }
         self.expect(format!("* Connected to {}", self.addr)).await;
+        if self.verbose {
+            let r = self
+                .p
+                .expect(Regex(
+                    r"\* Timing: DNS [0-9.]+ms, connect [0-9.]+ms, total [0-9.]+ms",
+                ))
+                .await;
+            if let Err(e) = r {
+                panic!("confab did not print timing: {e}");
+            }
+        }
     }
 
     async fn finish(mut self) {
@@ -116,6 +140,17 @@ impl Runner {
         }
     }
 
+    async fn exit_failure(mut self, code: i32) {
+        self.p.expect(Eof).await.unwrap();
+        #[cfg(unix)]
+        assert_eq!(
+            self.p.wait().unwrap(),
+            WaitStatus::Exited(self.p.pid(), code)
+        );
+        #[cfg(windows)]
+        assert_eq!(self.p.wait(None).unwrap(), code);
+    }
+
     async fn expect<S: AsRef<str> + Send>(&mut self, s: S) {
         static TIME_RGX: &str = r"\[[0-9]{2}:[0-9]{2}:[0-9]{2}\]";
         let s = s.as_ref();
@@ -139,6 +174,15 @@ impl Runner {
         self.transcribe(entry.transcription());
     }
 
+    /// Paste text into the line editor using bracketed paste
+    async fn paste(&mut self, text: &str) {
+        self.p.expect("confab> ").await.unwrap();
+        self.p
+            .send(format!("\x1B[200~{text}\x1B[201~"))
+            .await
+            .unwrap();
+    }
+
     async fn script_enter<S: Into<Sent> + Send>(&mut self, entry: S) {
         let entry = entry.into();
         self.expect(entry.printed()).await;
@@ -192,28 +236,58 @@ impl Transcript {
     }
 
     fn check(&self, addr: SocketAddr) {
-        let mut events = json_lines::<Event, _>(&self.path).unwrap();
-        assert_matches!(events.next(), Some(Ok(Event::ConnectionStart {host, port, ..})) => {
+        let mut events = transcript::read(&self.path);
+        assert_matches!(events.next(), Some(Ok(Event::SessionStart {format_version, host, port, ..})) => {
+            assert_eq!(format_version, transcript::FORMAT_VERSION);
+            assert_eq!(host, addr.ip().to_string());
+            assert_eq!(port, addr.port());
+        });
+        assert_matches!(events.next(), Some(Ok(Event::ConnectionStart {format_version, host, port, ..})) => {
+            assert_eq!(format_version, transcript::FORMAT_VERSION);
             assert_eq!(host, addr.ip().to_string());
             assert_eq!(port, addr.port());
         });
+        assert_matches!(events.next(), Some(Ok(Event::DnsResolved {host, addresses, overridden: false, ..})) => {
+            assert_eq!(host, addr.ip().to_string());
+            assert_eq!(addresses, [addr.ip()]);
+        });
         assert_matches!(events.next(), Some(Ok(Event::ConnectionComplete {peer_ip, ..})) => {
             assert_eq!(peer_ip, addr.ip());
         });
+        assert_matches!(events.next(), Some(Ok(Event::Timing {dns_ms, connect_ms, tls_ms: None, total_ms, ..})) => {
+            assert!(dns_ms >= 0.0);
+            assert!(connect_ms >= 0.0);
+            assert!(total_ms >= connect_ms);
+        });
+        let mut last_elapsed = 0.0;
         for msg in &self.messages {
-            match msg {
+            let elapsed = match msg {
                 Msg::Recv(s) => {
-                    assert_matches!(events.next(), Some(Ok(Event::Recv { data, .. })) => {
+                    assert_matches!(events.next(), Some(Ok(Event::Recv { elapsed_ms: Some(elapsed), data, .. })) => {
                         assert_eq!(&data, s, "{:?}", s.as_ref());
-                    });
+                        elapsed
+                    })
                 }
                 Msg::Send(s) => {
-                    assert_matches!(events.next(), Some(Ok(Event::Send { data, .. })) => {
+                    assert_matches!(events.next(), Some(Ok(Event::Send { elapsed_ms: Some(elapsed), data, .. })) => {
                         assert_eq!(&data, s, "{:?}", s.as_ref());
-                    });
+                        elapsed
+                    })
                 }
-            }
+            };
+            assert!(elapsed >= last_elapsed);
+            last_elapsed = elapsed;
         }
+        let sent = self
+            .messages
+            .iter()
+            .filter(|m| matches!(m, Msg::Send(_)))
+            .count();
+        let recv = self.messages.len() - sent;
+        assert_matches!(events.next(), Some(Ok(Event::SessionStats { lines_sent, lines_recv, .. })) => {
+            assert_eq!(lines_sent, u64::try_from(sent).unwrap());
+            assert_eq!(lines_recv, u64::try_from(recv).unwrap());
+        });
         assert_matches!(events.next(), Some(Ok(Event::Disconnect { .. })));
         assert_matches!(events.next(), None);
     }
@@ -290,137 +364,12 @@ impl From<&'static str> for Recv {
     }
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq)]
-#[serde(rename_all = "kebab-case", tag = "event")]
-enum Event {
-    ConnectionStart {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-        host: String,
-        port: u16,
-    },
-    ConnectionComplete {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-        peer_ip: IpAddr,
-    },
-    TlsStart {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-    },
-    TlsComplete {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-    },
-    Recv {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-        data: String,
-    },
-    Send {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-        data: String,
-    },
-    Disconnect {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-    },
-    Error {
-        #[serde(with = "time::serde::rfc3339")]
-        timestamp: OffsetDateTime,
-        data: String,
-    },
-}
-
 #[derive(Debug, Eq, PartialEq)]
 enum Msg {
     Recv(Cow<'static, str>),
     Send(Cow<'static, str>),
 }
 
-async fn testing_server(sender: Sender<SocketAddr>) {
-    let listener = TcpListener::bind("127.0.0.1:0")
-        .await
-        .expect("Error binding listener");
-    sender
-        .send(
-            listener
-                .local_addr()
-                .expect("Error getting listener's local address"),
-        )
-        .expect("Error sending address");
-    let (socket, _) = listener
-        .accept()
-        .await
-        .expect("Error listening for connection");
-    drop(listener);
-    let mut frame = Framed::new(
-        socket,
-        AnyDelimiterCodec::new_with_max_length(b"\n".to_vec(), b"\n".to_vec(), 65535),
-    );
-    frame
-        .send("Welcome to the confab Test Server!")
-        .await
-        .unwrap();
-    let mut i: usize = 1;
-    loop {
-        tokio::select! {
-            () = sleep(Duration::from_secs(1)) => {
-                frame.send(format!("Ping {i}")).await.unwrap();
-                i += 1;
-            },
-            r = frame.next() => match r {
-                Some(Ok(line)) => {
-                    let repr = if let Ok(s) = std::str::from_utf8(line.as_ref()) {
-                        format!("{s:?}")
-                    } else {
-                        format!("{line:?}")
-                    };
-                    frame.send(format!("You sent: {repr}")).await.unwrap();
-                    let line = if line.ends_with(&b"\r"[..]) {
-                        line.slice(..(line.len() - 1))
-                    } else { line };
-                    if line == "quit" {
-                        frame.send("Goodbye.").await.unwrap();
-                        break;
-                    } else if line == "pieces" {
-                        let conn = frame.get_mut();
-                        conn.write_all(b"This line is|").await.unwrap();
-                        sleep(Duration::from_millis(50)).await;
-                        conn.write_all(b"being sent in|").await.unwrap();
-                        sleep(Duration::from_millis(50)).await;
-                        conn.write_all(b"pieces.|").await.unwrap();
-                        sleep(Duration::from_millis(50)).await;
-                        conn.write_all(b"Did you get it all?\n").await.unwrap();
-                    } else if line == "long" {
-                        frame.send(concat!(
-                            "This is a very long line.  I'm not going t",
-                            "o bore you with the details, so instead I'",
-                            "ll bore you with some mangled Cicero: Lore",
-                            "m ipsum dolor sit amet, consectetur adipis",
-                            "icing elit, sed do eiusmod tempor incididu",
-                            "nt ut labore et dolore magna aliqua.  Ut e",
-                            "nim ad minim veniam, quis nostrud exercita",
-                            "tion ullamco laboris nisi ut aliquip ex ea",
-                            " commodo consequat."
-                        )).await.unwrap();
-                    } else if line == "bytes" {
-                        let conn = frame.get_mut();
-                        conn.write_all(b"Here is some non-UTF-8 data:\n").await.unwrap();
-                        conn.write_all(b"Latin-1: Libert\xE9, \xE9galit\xE9, fraternit\xE9\n").await.unwrap();
-                        conn.write_all(b"General garbage: \x89\xAB\xCD\xEF\n").await.unwrap();
-                    } else if line == "crlf" {
-                        frame.send("CR LF:\r").await.unwrap();
-                    }
-                }
-                Some(Err(e)) => panic!("Error reading from connection: {e}"),
-                None => break,
-            }
-        }
-    }
-}
-
 #[tokio::test]
 async fn test_quit_session() {
     let mut r = Tester::new().build().await;
@@ -469,10 +418,10 @@ async fn test_piecemeal_line() {
 
 #[tokio::test]
 async fn test_long_line() {
-    fn unterminated(s: &'static str) -> Recv {
+    fn split(printed: &'static str, transcription: &'static str) -> Recv {
         Recv {
-            printed: s,
-            transcription: Some(s),
+            printed,
+            transcription: Some(transcription),
         }
     }
 
@@ -484,23 +433,48 @@ async fn test_long_line() {
         .await;
     r.enter("long").await;
     r.get(r#"You sent: "long""#).await;
-    r.get(unterminated("This is a very long line.  I'm not going t"))
-        .await;
-    r.get(unterminated("o bore you with the details, so instead I'"))
-        .await;
-    r.get(unterminated("ll bore you with some mangled Cicero: Lore"))
-        .await;
-    r.get(unterminated("m ipsum dolor sit amet, consectetur adipis"))
-        .await;
-    r.get(unterminated("icing elit, sed do eiusmod tempor incididu"))
-        .await;
-    r.get(unterminated("nt ut labore et dolore magna aliqua.  Ut e"))
-        .await;
-    r.get(unterminated("nim ad minim veniam, quis nostrud exercita"))
-        .await;
-    r.get(unterminated("tion ullamco laboris nisi ut aliquip ex ea"))
+    r.get(split(
+        "This is a very long line.  I'm not going t…",
+        "This is a very long line.  I'm not going t",
+    ))
+    .await;
+    r.get(split(
+        "…o bore you with the details, so instead I'…",
+        "o bore you with the details, so instead I'",
+    ))
+    .await;
+    r.get(split(
+        "…ll bore you with some mangled Cicero: Lore…",
+        "ll bore you with some mangled Cicero: Lore",
+    ))
+    .await;
+    r.get(split(
+        "…m ipsum dolor sit amet, consectetur adipis…",
+        "m ipsum dolor sit amet, consectetur adipis",
+    ))
+    .await;
+    r.get(split(
+        "…icing elit, sed do eiusmod tempor incididu…",
+        "icing elit, sed do eiusmod tempor incididu",
+    ))
+    .await;
+    r.get(split(
+        "…nt ut labore et dolore magna aliqua.  Ut e…",
+        "nt ut labore et dolore magna aliqua.  Ut e",
+    ))
+    .await;
+    r.get(split(
+        "…nim ad minim veniam, quis nostrud exercita…",
+        "nim ad minim veniam, quis nostrud exercita",
+    ))
+    .await;
+    r.get(split(
+        "…tion ullamco laboris nisi ut aliquip ex ea…",
+        "tion ullamco laboris nisi ut aliquip ex ea",
+    ))
+    .await;
+    r.get(split("… commodo consequat.", " commodo consequat.\n"))
         .await;
-    r.get(" commodo consequat.").await;
     r.quit().await;
 }
 
@@ -661,3 +635,704 @@ async fn quit_from_startup_script() {
     r.get("Goodbye.").await;
     r.finish().await;
 }
+
+#[tokio::test]
+async fn expect_banner() {
+    let mut r = Tester::new()
+        .arg("--expect-banner=^Welcome")
+        .transcript()
+        .build()
+        .await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn expect_banner_timeout() {
+    let mut r = Tester::new()
+        .arg("--expect-banner=^220 ")
+        .arg("--banner-timeout-ms=200")
+        .build()
+        .await;
+    r.expect("! timed out waiting for banner from server").await;
+    r.exit_failure(4).await;
+}
+
+#[tokio::test]
+async fn max_send_bytes() {
+    let mut r = Tester::new().arg("--max-send-bytes=10").build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("Again!\r\n").await.unwrap();
+    r.expect("* Send budget of 10 bytes exhausted; closing connection")
+        .await;
+    r.expect("* Disconnected").await;
+    r.exit_failure(6).await;
+}
+
+#[tokio::test]
+async fn max_recv_bytes() {
+    let mut r = Tester::new().arg("--max-recv-bytes=50").build().await;
+    r.enter("Hello!").await;
+    r.expect("* Receive budget of 50 bytes exhausted; closing connection")
+        .await;
+    r.expect("* Disconnected").await;
+    r.exit_failure(6).await;
+}
+
+#[tokio::test]
+async fn head() {
+    let mut r = Tester::new().arg("--head=1").build().await;
+    r.enter("Hello!").await;
+    // Give the server's reply time to arrive:
+    sleep(Duration::from_millis(200)).await;
+    r.p.send(ControlCode::EndOfTransmission).await.unwrap();
+    r.expect("* Received lines not displayed: ").await;
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn paste_ask() {
+    let mut r = Tester::new().arg("--paste-mode=ask").build().await;
+    r.paste("foo\r\nbar\r\n").await;
+    r.expect("* Send 2 lines? [y/N]").await;
+    r.p.send("y\r\n").await.unwrap();
+    r.expect("> foo").await;
+    r.expect("> bar").await;
+    r.get(r#"You sent: "foo""#).await;
+    r.get(r#"You sent: "bar""#).await;
+    r.paste("baz\r\nquux\r\n").await;
+    r.expect("* Send 2 lines? [y/N]").await;
+    r.p.send("n\r\n").await.unwrap();
+    r.expect("* Paste discarded").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn paste_commands() {
+    let mut r = Tester::new().arg("--paste-mode=ask").build().await;
+    r.paste("foo\r\n/stats\r\n//bar\r\n").await;
+    r.expect("> foo").await;
+    r.expect("* Sent 1 lines").await;
+    r.expect("> /bar").await;
+    r.get(r#"You sent: "foo""#).await;
+    r.get(r#"You sent: "/bar""#).await;
+    r.paste("baz\r\nquux").await;
+    r.expect("> baz").await;
+    r.get(r#"You sent: "baz""#).await;
+    r.p.send("\r\n").await.unwrap();
+    r.expect("> quux").await;
+    r.get(r#"You sent: "quux""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn cast() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("session.cast");
+    let mut r = Tester::new().arg("--cast").arg(&path).build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.quit().await;
+    let cast = std::fs::read_to_string(&path).unwrap();
+    let mut lines = cast.lines();
+    let header = serde_json::from_str::<serde_json::Value>(lines.next().unwrap()).unwrap();
+    assert_eq!(header["version"], 2);
+    let output = lines
+        .map(|ln| {
+            let event = serde_json::from_str::<serde_json::Value>(ln).unwrap();
+            assert!(event[0].is_f64());
+            assert_eq!(event[1], "o");
+            event[2].as_str().unwrap().to_owned()
+        })
+        .collect::<String>();
+    assert!(output.starts_with("* Connecting ...\r\n"));
+    assert!(output.contains("> Hello!\r\n"));
+    assert!(output.contains("< You sent: \"Hello!\"\r\n"));
+    assert!(output.contains("* Disconnected by peer after "));
+    assert!(output.ends_with(" (sent 2 lines, received 4 lines)\r\n"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connection_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg(addr.ip().to_string());
+    cmd.arg(addr.port().to_string());
+    let mut p = log(
+        Session::spawn(cmd).expect("Error spawning command"),
+        std::io::stdout(),
+    )
+    .unwrap();
+    p.set_expect_timeout(Some(Duration::from_millis(500)));
+    p.expect("* Connecting ...").await.unwrap();
+    p.expect("! failed to connect to server: ").await.unwrap();
+    p.expect(Eof).await.unwrap();
+    assert_eq!(p.wait().unwrap(), WaitStatus::Exited(p.pid(), 2));
+}
+
+#[tokio::test]
+async fn verbose() {
+    let mut r = Tester::new().verbose().transcript().build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn stats() {
+    let mut r = Tester::new().arg("--stats-on-exit").build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.send("/stats\r\n").await.unwrap();
+    r.expect("* Sent 1 lines (7 bytes), received 2 lines (")
+        .await;
+    r.p.send("//stats\r\n").await.unwrap();
+    r.expect("> /stats").await;
+    r.get(r#"You sent: "/stats""#).await;
+    r.p.send("/bogus\r\n").await.unwrap();
+    r.expect("* unknown command: /bogus").await;
+    r.p.send("quit\r\n").await.unwrap();
+    r.expect("> quit").await;
+    r.get(r#"You sent: "quit""#).await;
+    r.get("Goodbye.").await;
+    r.expect("* Sent 3 lines (").await;
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn scrollback() {
+    let mut r = Tester::new().build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.send("/search ^You sent\r\n").await.unwrap();
+    r.expect("* Matching lines in scrollback: 1").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.send("/last 2\r\n").await.unwrap();
+    r.expect("> Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.send("/search nowhere\r\n").await.unwrap();
+    r.expect("* No matching lines in scrollback").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn burst_quiet() {
+    let mut r = Tester::new().arg("--burst-quiet").arg("3").build().await;
+    r.enter("bytes").await;
+    r.expect("* Receiving a burst of lines; holding them back until it passes")
+        .await;
+    // The burst isn't over until a second has passed without enough lines
+    r.p.set_expect_timeout(Some(Duration::from_secs(2)));
+    r.expect(" lines received; use /show-burst to display them")
+        .await;
+    r.p.send("/show-burst\r\n").await.unwrap();
+    r.expect("< General garbage: ").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn broken_pipe() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg(addr.ip().to_string());
+    cmd.arg(addr.port().to_string());
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let mut p = cmd.spawn().expect("Error spawning command");
+    let stdout = p.stdout.take().unwrap();
+    let mut stdin = p.stdin.take().unwrap();
+    // The blocking calls must run outside the runtime so that the server can
+    // keep going.
+    let output = tokio::task::spawn_blocking(move || {
+        let mut lines = BufReader::new(stdout).lines();
+        let first = lines.next().unwrap().unwrap();
+        assert_eq!(first, "* Connecting ...");
+        drop(lines);
+        // Make the server send something for confab to fail to print
+        writeln!(stdin, "Hello!").unwrap();
+        p.wait_with_output().unwrap()
+    })
+    .await
+    .unwrap();
+    assert_eq!(output.status.code(), Some(141));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[tokio::test]
+async fn resolve_override() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg("--verbose");
+    cmd.arg("--resolve");
+    cmd.arg(format!("confab.invalid:{}:{}", addr.port(), addr.ip()));
+    cmd.arg("confab.invalid");
+    cmd.arg(addr.port().to_string());
+    let mut p = log(
+        Session::spawn(cmd).expect("Error spawning command"),
+        std::io::stdout(),
+    )
+    .unwrap();
+    p.set_expect_timeout(Some(Duration::from_millis(500)));
+    p.expect("* Connecting ...").await.unwrap();
+    p.expect(format!(
+        "* Resolved confab.invalid to {} (from --resolve)",
+        addr.ip()
+    ))
+    .await
+    .unwrap();
+    p.expect(format!("* Connected to {addr}")).await.unwrap();
+    let mut r = Runner {
+        p,
+        addr,
+        transcript: None,
+        show_times: false,
+        verbose: true,
+    };
+    r.get("Welcome to the confab Test Server!").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn queue_commands() {
+    let mut r = Tester::new()
+        .arg("--paste-mode=delay")
+        .arg("--paste-delay-ms=60000")
+        .build()
+        .await;
+    r.paste("foo\r\nbar\r\nbaz\r\n").await;
+    r.expect("> foo").await;
+    r.get(r#"You sent: "foo""#).await;
+    r.p.send("/queue\r\n").await.unwrap();
+    r.expect("* Lines waiting to be sent: 2").await;
+    r.expect("*    1. bar").await;
+    r.expect("*    2. baz").await;
+    r.p.send("/queue drop 3\r\n").await.unwrap();
+    r.expect("* No line 3 in send queue").await;
+    r.p.send("/queue drop 1\r\n").await.unwrap();
+    r.expect("* Removed line 1 from send queue: bar").await;
+    r.p.send("/queue clear\r\n").await.unwrap();
+    r.expect("* Removed 1 lines from send queue").await;
+    r.p.send("/queue\r\n").await.unwrap();
+    r.expect("* Send queue is empty").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn set_commands() {
+    let mut r = Tester::new().build().await;
+    r.p.send("/set encoding latin1\r\n").await.unwrap();
+    r.expect("* Set encoding to latin1").await;
+    r.enter("Fëanor").await;
+    r.get(r#"You sent: b"F\xebanor""#).await;
+    r.p.send("/set crlf on\r\n").await.unwrap();
+    r.expect("* Set crlf to on").await;
+    r.enter("crlf").await;
+    r.get(r#"You sent: "crlf\r""#).await;
+    r.get("CR LF:").await;
+    r.p.send("/set crlf off\r\n").await.unwrap();
+    r.expect("* Set crlf to off").await;
+    r.p.send("/set crlf maybe\r\n").await.unwrap();
+    r.expect("* usage: /set encoding <NAME> | crlf on|off | max-line-length <N>")
+        .await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn set_crlf_restores_send_ending() {
+    let mut r = Tester::new().arg("--send-ending").arg("cr").build().await;
+    r.p.send("/set crlf on\r\n").await.unwrap();
+    r.expect("* Set crlf to on").await;
+    r.p.send("/set crlf off\r\n").await.unwrap();
+    r.expect("* Set crlf to off").await;
+    // The line is terminated with a bare CR again, so the server doesn't see
+    // it until a line ending in LF is sent
+    r.enter("first").await;
+    // Wait for the line to be sent before changing the setting
+    r.expect("> first\n").await;
+    r.p.send("/set crlf on\r\n").await.unwrap();
+    r.expect("* Set crlf to on").await;
+    r.enter("second").await;
+    r.get(r#"You sent: "first\rsecond\r""#).await;
+    r.enter("quit").await;
+    r.get(r#"You sent: "quit\r""#).await;
+    r.get("Goodbye.").await;
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn line_continuation() {
+    let mut r = Tester::new().build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("foo \\\r\n").await.unwrap();
+    r.p.expect("... ").await.unwrap();
+    r.p.send("bar\r\n").await.unwrap();
+    r.expect("> foo bar\n").await;
+    r.get(r#"You sent: "foo bar""#).await;
+    // An escaped backslash at the end of a line is sent as a single
+    // backslash
+    r.p.send("dir C:\\\\\r\n").await.unwrap();
+    r.expect("> dir C:\\\n").await;
+    r.get(r#"You sent: "dir C:\\""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn escapes() {
+    let mut r = Tester::new().arg("--escapes").transcript().build().await;
+    r.enter(Sent {
+        typed: r"\e[1mhi^G",
+        // The control characters are displayed in reverse video
+        printed: Some(""),
+        transcription: Some("\x1B[1mhi\x07\n"),
+    })
+    .await;
+    r.get(r#"You sent: "\u{1b}[1mhi\u{7}""#).await;
+    r.p.send("bad\\q\r\n").await.unwrap();
+    r.expect(r"* Line not sent: unknown escape sequence: \q")
+        .await;
+    r.enter("x^2").await;
+    r.get(r#"You sent: "x^2""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn quiet_filter() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg("--quiet");
+    cmd.arg("--no-echo");
+    cmd.arg("--filter=^You sent");
+    cmd.arg(addr.ip().to_string());
+    cmd.arg(addr.port().to_string());
+    let mut p = log(
+        Session::spawn(cmd).expect("Error spawning command"),
+        std::io::stdout(),
+    )
+    .unwrap();
+    p.set_expect_timeout(Some(Duration::from_millis(500)));
+    p.expect("confab> ").await.unwrap();
+    p.send("Hello!\r\n").await.unwrap();
+    let found = p.expect(r#"< You sent: "Hello!""#).await.unwrap();
+    let before = String::from_utf8_lossy(found.before());
+    assert!(!before.contains("* Connect"), "{before:?}");
+    assert!(!before.contains("Welcome"), "{before:?}");
+    p.send("quit\r\n").await.unwrap();
+    p.expect(r#"< You sent: "quit""#).await.unwrap();
+    let rest = p.expect(Eof).await.unwrap();
+    let rest = String::from_utf8_lossy(rest.before());
+    assert!(!rest.contains("Goodbye."), "{rest:?}");
+    assert!(!rest.contains("* Disconnected"), "{rest:?}");
+    assert_eq!(p.wait().unwrap(), WaitStatus::Exited(p.pid(), 0));
+}
+
+#[tokio::test]
+async fn output_file() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let tmpdir = tempdir().unwrap();
+    let outfile = tmpdir.path().join("output.txt");
+    std::fs::write(&outfile, "Previous contents\n").unwrap();
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg("--output");
+    cmd.arg(&outfile);
+    cmd.arg(addr.ip().to_string());
+    cmd.arg(addr.port().to_string());
+    let mut p = log(
+        Session::spawn(cmd).expect("Error spawning command"),
+        std::io::stdout(),
+    )
+    .unwrap();
+    p.set_expect_timeout(Some(Duration::from_millis(500)));
+    p.expect("confab> ").await.unwrap();
+    p.send("Hello!\r\n").await.unwrap();
+    p.expect(r#"< You sent: "Hello!""#).await.unwrap();
+    p.send("quit\r\n").await.unwrap();
+    p.expect(Eof).await.unwrap();
+    assert_eq!(p.wait().unwrap(), WaitStatus::Exited(p.pid(), 0));
+    let output = std::fs::read_to_string(&outfile).unwrap();
+    let lines = output
+        .lines()
+        .filter(|ln| !ln.starts_with("Ping "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "Previous contents",
+            "Welcome to the confab Test Server!",
+            r#"You sent: "Hello!""#,
+            r#"You sent: "quit""#,
+            "Goodbye.",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn ctrl_c_quit() {
+    let mut r = Tester::new().arg("--ctrl-c=quit").build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send(ControlCode::EndOfText).await.unwrap();
+    r.expect("^C").await;
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn ctrl_c_confirm() {
+    let mut r = Tester::new().arg("--ctrl-c=confirm").build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send(ControlCode::EndOfText).await.unwrap();
+    r.expect("* Quit? [y/N]").await;
+    r.p.send("n\r\n").await.unwrap();
+    r.expect("* Not quitting").await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.send(ControlCode::EndOfText).await.unwrap();
+    r.expect("* Quit? [y/N]").await;
+    r.p.send("y\r\n").await.unwrap();
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn ctrl_c_send_break() {
+    let mut r = Tester::new()
+        .arg("--ctrl-c=send-break")
+        .arg(r"--break-sequence=^C\n")
+        .build()
+        .await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send(ControlCode::EndOfText).await.unwrap();
+    r.expect("> \x1B[7m^C\x1B[0m").await;
+    r.get(r#"You sent: "\u{3}""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn break_command() {
+    let mut r = Tester::new().arg(r"--break-sequence=^C\n").build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("/break\r\n").await.unwrap();
+    r.expect("> \x1B[7m^C\x1B[0m").await;
+    r.get(r#"You sent: "\u{3}""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn empty_line_confirm() {
+    let mut r = Tester::new().arg("--empty-line-mode=confirm").build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("\r\n").await.unwrap();
+    r.expect("* Send empty line? [y/N]").await;
+    r.p.send("y\r\n").await.unwrap();
+    r.get(r#"You sent: """#).await;
+    r.p.send("\r\n").await.unwrap();
+    r.expect("* Send empty line? [y/N]").await;
+    r.p.send("\r\n").await.unwrap();
+    r.expect("* Empty line not sent").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn note_command() {
+    let mut r = Tester::new().build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("/note bug happens here\r\n").await.unwrap();
+    r.expect("* Note: bug happens here").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn transcript_per_connection() {
+    let tmpdir = tempdir().unwrap();
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let second = receiver.await.expect("Error receiving address from server");
+    let mut r = Tester::new()
+        .arg("--transcript")
+        .arg(tmpdir.path().join("conn-{conn}.jsonl"))
+        .arg("--transcript-per-connection")
+        .build()
+        .await;
+    let first = r.addr;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send(format!("/connect {} {}\r\n", second.ip(), second.port()))
+        .await
+        .unwrap();
+    r.expect("* Disconnected").await;
+    r.addr = second;
+    r.connect().await;
+    r.get("Welcome to the confab Test Server!").await;
+    r.quit().await;
+    for (i, addr) in [(1, first), (2, second)] {
+        let mut events = transcript::read(tmpdir.path().join(format!("conn-{i}.jsonl")));
+        assert_matches!(events.next(), Some(Ok(Event::SessionStart { host, port, .. })) => {
+            assert_eq!(host, first.ip().to_string());
+            assert_eq!(port, first.port());
+        });
+        assert_matches!(events.next(), Some(Ok(Event::ConnectionStart { host, port, .. })) => {
+            assert_eq!(host, addr.ip().to_string());
+            assert_eq!(port, addr.port());
+        });
+        let events = events.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_matches!(events.last(), Some(Event::Disconnect { .. }));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|ev| matches!(ev, Event::Disconnect { .. }))
+                .count(),
+            1
+        );
+    }
+}
+
+#[tokio::test]
+async fn keymap_file() {
+    let mut keymap = NamedTempFile::new().unwrap();
+    writeln!(keymap, "# F2 and F3").unwrap();
+    writeln!(keymap, "F2 submit Hello!").unwrap();
+    writeln!(keymap, "F3 insert \"qu\"").unwrap();
+    keymap.flush().unwrap();
+    let mut r = Tester::new()
+        .arg("--keymap")
+        .arg(keymap.path())
+        .build()
+        .await;
+    r.p.expect("confab> ").await.unwrap();
+    // F2
+    r.p.send("\x1BOQ").await.unwrap();
+    r.expect("> Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.expect("confab> ").await.unwrap();
+    // F3
+    r.p.send("\x1BOR").await.unwrap();
+    r.p.send("it\r\n").await.unwrap();
+    r.expect("> quit").await;
+    r.get(r#"You sent: "quit""#).await;
+    r.get("Goodbye.").await;
+    r.finish().await;
+}
+
+#[tokio::test]
+async fn tab_completion() {
+    let mut r = Tester::new().build().await;
+    r.enter("Greetings!").await;
+    r.get(r#"You sent: "Greetings!""#).await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("Gre\t").await.unwrap();
+    r.p.send(" & /st\t\r\n").await.unwrap();
+    r.expect("> Greetings & /st").await;
+    r.get(r#"You sent: "Greetings & /st""#).await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("/st\t\r\n").await.unwrap();
+    r.expect("* Sent 2 lines").await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn vi_keybind() {
+    let mut r = Tester::new().arg("--keybind=vi").build().await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("Hellx").await.unwrap();
+    // Give the escape time to arrive on its own so that it isn't read as
+    // Alt-x
+    sleep(Duration::from_millis(100)).await;
+    r.p.send("\x1B").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    r.p.send("xao!\r\n").await.unwrap();
+    r.expect("> Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.quit().await;
+}
+
+#[tokio::test]
+async fn tui() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let transcript = Transcript::new();
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.arg("--tui");
+    cmd.arg("--transcript");
+    cmd.arg(&transcript.path);
+    cmd.arg(addr.ip().to_string());
+    cmd.arg(addr.port().to_string());
+    let mut p = log(
+        Session::spawn(cmd).expect("Error spawning command"),
+        std::io::stdout(),
+    )
+    .unwrap();
+    p.get_process_mut().set_window_size(80, 24).unwrap();
+    p.set_expect_timeout(Some(Duration::from_millis(500)));
+    // The screen is drawn with cursor movements in place of spaces, so only
+    // look for single words.
+    p.expect("\x1B[?1049h").await.unwrap();
+    p.expect("confab>").await.unwrap();
+    p.send("Hello!\r").await.unwrap();
+    p.expect(r#""Hello!""#).await.unwrap();
+    p.send("quit\r").await.unwrap();
+    p.expect("Goodbye.").await.unwrap();
+    p.expect("Disconnected").await.unwrap();
+    p.expect("\x1B[?1049l").await.unwrap();
+    p.expect(Eof).await.unwrap();
+    assert_eq!(p.wait().unwrap(), WaitStatus::Exited(p.pid(), 0));
+    let sent = transcript::read(&transcript.path)
+        .filter_map(|ev| match ev {
+            Ok(Event::Send { data, .. }) => Some(data),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(sent, ["Hello!\n", "quit\n"]);
+}
+
+#[tokio::test]
+async fn shutdown_write_command() {
+    let mut r = Tester::new().build().await;
+    r.enter("Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.expect("confab> ").await.unwrap();
+    r.p.send("/shutdown-write\r\n").await.unwrap();
+    r.expect("* Shut down sending; waiting for the server to close the connection")
+        .await;
+    r.expect("* Disconnected").await;
+    r.p.expect(Eof).await.unwrap();
+    assert_eq!(r.p.wait().unwrap(), WaitStatus::Exited(r.p.pid(), 0));
+}
+
+#[tokio::test]
+async fn status_line() {
+    let mut r = Tester::new().arg("--status-line").build().await;
+    // The status line may have first been drawn before the banner was
+    // received, in which case it won't be seen again until the next redraw
+    r.p.set_expect_timeout(Some(Duration::from_secs(2)));
+    // The status line is drawn after the prompt, so send input directly
+    // rather than waiting for another prompt
+    r.expect(format!(" connected: {} | sent 0 B | received ", r.addr))
+        .await;
+    r.p.send("Hello!\r\n").await.unwrap();
+    r.expect("> Hello!").await;
+    r.get(r#"You sent: "Hello!""#).await;
+    r.p.expect(Regex(
+        r" connected: [^|]+ \| sent 7 B \| received [0-9]+ B \| idle [0-9]+s ",
+    ))
+    .await
+    .unwrap();
+    r.p.send("quit\r\n").await.unwrap();
+    r.get("Goodbye.").await;
+    r.expect("* Disconnected").await;
+    // The scrolling region is reset on exit
+    r.expect("\x1B[r").await;
+    r.p.expect(Eof).await.unwrap();
+    assert_eq!(r.p.wait().unwrap(), WaitStatus::Exited(r.p.pid(), 0));
+}
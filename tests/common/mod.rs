@@ -0,0 +1,91 @@
+//! Code shared between the integration test files
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot::Sender;
+use tokio::time::sleep;
+use tokio_util::codec::{AnyDelimiterCodec, Framed};
+
+pub(crate) async fn testing_server(sender: Sender<SocketAddr>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Error binding listener");
+    sender
+        .send(
+            listener
+                .local_addr()
+                .expect("Error getting listener's local address"),
+        )
+        .expect("Error sending address");
+    let (socket, _) = listener
+        .accept()
+        .await
+        .expect("Error listening for connection");
+    drop(listener);
+    let mut frame = Framed::new(
+        socket,
+        AnyDelimiterCodec::new_with_max_length(b"\n".to_vec(), b"\n".to_vec(), 65535),
+    );
+    frame
+        .send("Welcome to the confab Test Server!")
+        .await
+        .unwrap();
+    let mut i: usize = 1;
+    loop {
+        tokio::select! {
+            () = sleep(Duration::from_secs(1)) => {
+                frame.send(format!("Ping {i}")).await.unwrap();
+                i += 1;
+            },
+            r = frame.next() => match r {
+                Some(Ok(line)) => {
+                    let repr = if let Ok(s) = std::str::from_utf8(line.as_ref()) {
+                        format!("{s:?}")
+                    } else {
+                        format!("{line:?}")
+                    };
+                    frame.send(format!("You sent: {repr}")).await.unwrap();
+                    let line = if line.ends_with(&b"\r"[..]) {
+                        line.slice(..(line.len() - 1))
+                    } else { line };
+                    if line == "quit" {
+                        frame.send("Goodbye.").await.unwrap();
+                        break;
+                    } else if line == "pieces" {
+                        let conn = frame.get_mut();
+                        conn.write_all(b"This line is|").await.unwrap();
+                        sleep(Duration::from_millis(50)).await;
+                        conn.write_all(b"being sent in|").await.unwrap();
+                        sleep(Duration::from_millis(50)).await;
+                        conn.write_all(b"pieces.|").await.unwrap();
+                        sleep(Duration::from_millis(50)).await;
+                        conn.write_all(b"Did you get it all?\n").await.unwrap();
+                    } else if line == "long" {
+                        frame.send(concat!(
+                            "This is a very long line.  I'm not going t",
+                            "o bore you with the details, so instead I'",
+                            "ll bore you with some mangled Cicero: Lore",
+                            "m ipsum dolor sit amet, consectetur adipis",
+                            "icing elit, sed do eiusmod tempor incididu",
+                            "nt ut labore et dolore magna aliqua.  Ut e",
+                            "nim ad minim veniam, quis nostrud exercita",
+                            "tion ullamco laboris nisi ut aliquip ex ea",
+                            " commodo consequat."
+                        )).await.unwrap();
+                    } else if line == "bytes" {
+                        let conn = frame.get_mut();
+                        conn.write_all(b"Here is some non-UTF-8 data:\n").await.unwrap();
+                        conn.write_all(b"Latin-1: Libert\xE9, \xE9galit\xE9, fraternit\xE9\n").await.unwrap();
+                        conn.write_all(b"General garbage: \x89\xAB\xCD\xEF\n").await.unwrap();
+                    } else if line == "crlf" {
+                        frame.send("CR LF:\r").await.unwrap();
+                    }
+                }
+                Some(Err(e)) => panic!("Error reading from connection: {e}"),
+                None => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,1693 @@
+//! Tests that run confab with `--stdin-pipe` and plain pipes for stdin &
+//! stdout.  Unlike the tests in `cli.rs`, these do not need a pseudo-terminal
+//! and so can be run on all platforms.
+#![cfg(test)]
+mod common;
+use assert_matches::assert_matches;
+use common::testing_server;
+use confab::transcript::{self, ClosedBy, Event};
+use std::net::SocketAddr;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpListener;
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::oneshot::{channel, Sender};
+use tokio::time::{sleep, timeout};
+
+/// How long to wait for each line of output from confab
+const LINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct PipeSession {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: Lines<BufReader<ChildStdout>>,
+    addr: SocketAddr,
+}
+
+impl PipeSession {
+    /// Start the test server and run confab against it with the given
+    /// additional arguments
+    async fn start<I: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr>>(args: I) -> PipeSession {
+        let (sender, receiver) = channel();
+        tokio::spawn(async move { testing_server(sender).await });
+        let addr = receiver.await.expect("Error receiving address from server");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+            .arg("--stdin-pipe")
+            .args(args)
+            .arg(addr.ip().to_string())
+            .arg(addr.port().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("Error spawning command");
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut session = PipeSession {
+            child,
+            stdin,
+            stdout,
+            addr,
+        };
+        session.expect("* Connecting ...").await;
+        session
+            .expect(&format!("* Connected to {}", session.addr))
+            .await;
+        session.expect("< Welcome to the confab Test Server!").await;
+        session
+    }
+
+    async fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().expect("stdin should still be open");
+        stdin.write_all(line.as_bytes()).await.unwrap();
+        stdin.write_all(b"\n").await.unwrap();
+        stdin.flush().await.unwrap();
+    }
+
+    /// Read the next line of output, skipping over the test server's pings
+    async fn next_line(&mut self) -> Option<String> {
+        loop {
+            let line = timeout(LINE_TIMEOUT, self.stdout.next_line())
+                .await
+                .expect("Timed out waiting for output from confab")
+                .unwrap()?;
+            // Windows console conventions may add a CR
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if !line.starts_with("< Ping ") {
+                return Some(strip_disconnect_stats(line));
+            }
+        }
+    }
+
+    async fn expect(&mut self, expected: &str) {
+        assert_eq!(self.next_line().await.as_deref(), Some(expected));
+    }
+
+    /// Close confab's stdin and wait for it to exit, asserting that it
+    /// produced no further output
+    async fn finish(mut self) -> ExitStatus {
+        drop(self.stdin.take());
+        assert_eq!(self.next_line().await, None);
+        timeout(LINE_TIMEOUT, self.child.wait())
+            .await
+            .expect("Timed out waiting for confab to exit")
+            .unwrap()
+    }
+}
+
+#[tokio::test]
+async fn pipe_quit_session() {
+    let mut p = PipeSession::start::<_, &str>([]).await;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect(r#"< You sent: "Hello!""#).await;
+    p.send("quit").await;
+    p.expect("> quit").await;
+    p.expect(r#"< You sent: "quit""#).await;
+    p.expect("< Goodbye.").await;
+    p.expect("* Disconnected by peer").await;
+    assert!(p.finish().await.success());
+}
+
+#[tokio::test]
+async fn pipe_end_of_input() {
+    let mut p = PipeSession::start::<_, &str>([]).await;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect(r#"< You sent: "Hello!""#).await;
+    drop(p.stdin.take());
+    p.expect("* Disconnected").await;
+    assert!(p.finish().await.success());
+}
+
+#[tokio::test]
+async fn pipe_transcript() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let mut p = PipeSession::start(["--transcript".as_ref(), path.as_os_str()]).await;
+    let addr = p.addr;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect(r#"< You sent: "Hello!""#).await;
+    p.send("quit").await;
+    p.expect("> quit").await;
+    p.expect(r#"< You sent: "quit""#).await;
+    p.expect("< Goodbye.").await;
+    p.expect("* Disconnected by peer").await;
+    assert!(p.finish().await.success());
+    let mut events = transcript::read(&path)
+        .map(Result::unwrap)
+        .filter(|ev| !matches!(ev, Event::Recv { data, .. } if data.starts_with("Ping ")));
+    assert_matches!(events.next(), Some(Event::SessionStart { format_version, confab_version, host, port, options, .. }) => {
+        assert_eq!(format_version, transcript::FORMAT_VERSION);
+        assert_eq!(confab_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(host, addr.ip().to_string());
+        assert_eq!(port, addr.port());
+        assert_eq!(options["transcript"], path.to_str().unwrap());
+        assert_eq!(options["crlf"], "false");
+        assert_eq!(options["max-line-length"], "65535");
+        assert!(!options.contains_key("send"));
+        assert!(!options.contains_key("host"));
+    });
+    assert_matches!(events.next(), Some(Event::ConnectionStart { host, port, .. }) => {
+        assert_eq!(host, addr.ip().to_string());
+        assert_eq!(port, addr.port());
+    });
+    assert_matches!(events.next(), Some(Event::DnsResolved { .. }));
+    assert_matches!(events.next(), Some(Event::ConnectionComplete { peer_ip, .. }) => {
+        assert_eq!(peer_ip, addr.ip());
+    });
+    assert_matches!(events.next(), Some(Event::Timing { .. }));
+    for (is_send, expected) in [
+        (false, "Welcome to the confab Test Server!\n"),
+        (true, "Hello!\n"),
+        (false, "You sent: \"Hello!\"\n"),
+        (true, "quit\n"),
+        (false, "You sent: \"quit\"\n"),
+        (false, "Goodbye.\n"),
+    ] {
+        if is_send {
+            assert_matches!(events.next(), Some(Event::Send { data, .. }) => {
+                assert_eq!(data, expected);
+            });
+        } else {
+            assert_matches!(events.next(), Some(Event::Recv { data, .. }) => {
+                assert_eq!(data, expected);
+            });
+        }
+    }
+    assert_matches!(events.next(), Some(Event::SessionStats { .. }));
+    assert_matches!(
+        events.next(),
+        Some(Event::Disconnect {
+            closed_by: Some(ClosedBy::Peer),
+            lines_sent: Some(2),
+            lines_recv: Some(4),
+            ..
+        })
+    );
+    assert_matches!(events.next(), None);
+}
+
+/// Run confab against the test server with the given additional arguments
+/// and no input, returning its exit status and its output (minus the test
+/// server's pings)
+async fn run_single_shot(args: &[&str]) -> (ExitStatus, Vec<String>) {
+    let (addr, output) = run_confab(args).await;
+    let mut lines = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter(|ln| !ln.starts_with("< Ping "))
+        .map(|ln| strip_disconnect_stats(ln.strip_suffix('\r').unwrap_or(ln)))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.first().map(String::as_str), Some("* Connecting ..."));
+    let connected = lines.get(1).map(String::as_str).unwrap_or_default();
+    assert!(
+        connected.starts_with(&format!("* Connected to {addr}")),
+        "{connected:?}"
+    );
+    lines.drain(..2);
+    (output.status, lines)
+}
+
+/// Remove the session duration & line counts from a "Disconnected" message,
+/// as the duration varies from run to run
+fn strip_disconnect_stats(line: &str) -> String {
+    match line.split_once(" after ") {
+        Some((head, _)) if line.starts_with("* Disconnected") => head.to_owned(),
+        _ => line.to_owned(),
+    }
+}
+
+/// Run confab with the given arguments against a new testing server, with
+/// stdin closed, and return the server's address and confab's output
+async fn run_confab(args: &[&str]) -> (SocketAddr, Output) {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args(args)
+            .arg(addr.ip().to_string())
+            .arg(addr.port().to_string())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    (addr, output)
+}
+
+#[tokio::test]
+async fn single_shot_until() {
+    let (status, lines) = run_single_shot(&[
+        "--send",
+        "Hello!",
+        "--send",
+        "pieces",
+        "--until",
+        r"get it all\?$",
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "> pieces",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+            r#"< You sent: "pieces""#,
+            "< This line is|being sent in|pieces.|Did you get it all?",
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn flush_interval() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let (status, lines) = run_single_shot(&[
+        "--flush",
+        "interval:60000",
+        "--transcript",
+        path.to_str().unwrap(),
+        "--send",
+        "Hello!",
+        "--expect-lines",
+        "2",
+    ])
+    .await;
+    assert!(status.success());
+    // Everything held back is written out when the session ends
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+            "* Disconnected",
+        ]
+    );
+    let events = transcript::read(&path)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_matches!(events.last(), Some(Event::Disconnect { .. }));
+}
+
+#[tokio::test]
+async fn burst_quiet() {
+    let (status, lines) = run_single_shot(&[
+        "--burst-quiet",
+        "1",
+        "--send",
+        "Hello!",
+        "--send",
+        "quit",
+        "--until",
+        "^Goodbye",
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "> quit",
+            "< Welcome to the confab Test Server!",
+            "* Receiving a burst of lines; holding them back until it passes",
+            "* \u{2026} 3 lines received; use /show-burst to display them",
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn single_shot_expect_lines() {
+    let (status, lines) = run_single_shot(&["--send", "Hello!", "--expect-lines", "2"]).await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn options_from_env() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .env("CONFAB_SEND", "Hello!")
+            .env("CONFAB_EXPECT_LINES", "1")
+            .env("CONFAB_QUIET", "1")
+            .env("CONFAB_VERBOSE", "0")
+            // Options on the command line take precedence:
+            .arg("--expect-lines=2")
+            .arg(addr.ip().to_string())
+            .arg(addr.port().to_string())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .lines()
+        .filter(|ln| !ln.starts_with("< Ping "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn addr_file_failover() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    // Find a port that nothing is listening on
+    let dead = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("addrs.txt");
+    std::fs::write(&path, format!("# primary\n{dead}\n# fallback\n{addr}\n")).unwrap();
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args(["--send", "Hello!", "--expect-lines", "2", "--addr-file"])
+            .arg(&path)
+            .args(["db.example", "5432"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .lines()
+        .filter(|ln| !ln.starts_with("< Ping "))
+        .map(strip_disconnect_stats)
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 9, "{lines:#?}");
+    assert_eq!(lines[0], "* Connecting ...");
+    assert_eq!(lines[1], format!("* Trying {dead} ..."));
+    assert!(
+        lines[2].starts_with(&format!("! Failed to connect to {dead}: ")),
+        "{:?}",
+        lines[2]
+    );
+    assert_eq!(
+        lines[3..],
+        [
+            format!("* Trying {addr} ..."),
+            format!("* Connected to {addr}"),
+            String::from("> Hello!"),
+            String::from("< Welcome to the confab Test Server!"),
+            String::from(r#"< You sent: "Hello!""#),
+            String::from("* Disconnected"),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn exec_command() {
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args([
+                "--send",
+                "Hello!",
+                "--expect-lines=1",
+                "--exec",
+                "cat",
+                "-n",
+            ])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .lines()
+        .map(strip_disconnect_stats)
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4, "{stdout}");
+    assert!(lines[0].starts_with("* Started cat -n (PID "), "{stdout}");
+    assert_eq!(
+        lines[1..],
+        ["> Hello!", "<      1\tHello!", "* Disconnected"]
+    );
+}
+
+#[tokio::test]
+async fn url_target() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args(["--send", "quit", "--until", "^Goodbye"])
+            .arg(format!("tcp://{addr}"))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!("* Connected to {addr}")),
+        "{stdout}"
+    );
+    assert!(stdout.contains("< Goodbye."), "{stdout}");
+}
+
+#[tokio::test]
+async fn max_send_length() {
+    let (status, lines) = run_single_shot(&[
+        "--max-send-length=6",
+        "--send",
+        "Hi!",
+        "--send",
+        "Hello!",
+        "--until",
+        r#"^You sent: "Hello!"$"#,
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> Hi!",
+            "* Line of 7 bytes exceeds maximum send length of 6",
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hi!""#,
+            r#"< You sent: "Hello!""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn max_send_length_strict() {
+    let (status, lines) = run_single_shot(&[
+        "--max-send-length=6",
+        "--strict",
+        "--send",
+        "Hello!",
+        "--send",
+        "Hi!",
+        "--until",
+        r#"^You sent: "Hi!"$"#,
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "* Line of 7 bytes exceeds maximum send length of 6; not sent",
+            "> Hi!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hi!""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn single_shot_no_response() {
+    let (status, lines) = run_single_shot(&["--send", "quit", "--until", "^Hello"]).await;
+    assert_eq!(status.code(), Some(4));
+    assert_eq!(
+        lines,
+        [
+            "> quit",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "quit""#,
+            "< Goodbye.",
+            "! connection closed before the expected response was received",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn assert_recv() {
+    let (status, lines) = run_single_shot(&[
+        "--send",
+        "Hello!",
+        "--assert-recv",
+        "^Welcome",
+        "--assert-recv",
+        r#"^You sent: "Hello!"$"#,
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn assert_recv_unmet() {
+    let (status, lines) = run_single_shot(&[
+        "--send",
+        "quit",
+        "--assert-recv",
+        "^Goodbye",
+        "--assert-recv",
+        "^Welcome",
+    ])
+    .await;
+    assert_eq!(status.code(), Some(8));
+    assert_eq!(
+        lines,
+        [
+            "> quit",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "quit""#,
+            "< Goodbye.",
+            "* Disconnected by peer",
+            r#"! session ended before a line matching "^Welcome" was received"#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn assert_recv_timeout() {
+    let (status, lines) = run_single_shot(&[
+        "--send",
+        "Hello!",
+        "--assert-recv",
+        "^Hello",
+        "--assert-timeout",
+        "1",
+    ])
+    .await;
+    assert_eq!(status.code(), Some(8));
+    assert_eq!(
+        lines,
+        [
+            "> Hello!",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "Hello!""#,
+            r#"! timed out waiting for a line matching "^Hello""#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn summary_file() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("summary.json");
+    let (_, output) = run_confab(&[
+        "--send",
+        "quit",
+        "--until",
+        "^Hello",
+        "--summary-file",
+        path.to_str().unwrap(),
+    ])
+    .await;
+    assert_eq!(output.status.code(), Some(4));
+    let summary =
+        serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&path).unwrap())
+            .unwrap();
+    assert_eq!(summary["connections"], 1);
+    assert_eq!(summary["lines_sent"], 1);
+    assert_eq!(summary["bytes_sent"], 5);
+    assert_eq!(summary["lines_recv"], 3);
+    assert_eq!(summary["errors"], 1);
+    assert_eq!(summary["error_kind"], "recv");
+    assert_eq!(summary["exit_reason"], "error");
+    assert_eq!(summary["exit_code"], 4);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn event_commands() {
+    let tmpdir = tempdir().unwrap();
+    // Write to a temporary file & rename it so that the file is never seen
+    // half-written
+    let cmd = |name: &str, vars: &str| {
+        let path = tmpdir.path().join(name);
+        let path = path.to_str().unwrap();
+        format!("echo \"{vars}\" > {path}.tmp && mv {path}.tmp {path}")
+    };
+    let (addr, output) = run_confab(&[
+        "--send",
+        "quit",
+        "--until",
+        "^Goodbye",
+        "--on-connect-cmd",
+        &cmd("connect", "$CONFAB_EVENT $CONFAB_PEER"),
+        "--on-disconnect-cmd",
+        &cmd(
+            "disconnect",
+            "$CONFAB_EVENT $CONFAB_CLOSED_BY $CONFAB_LINES_SENT",
+        ),
+        "--on-error-cmd",
+        &cmd("error", "$CONFAB_EVENT"),
+    ])
+    .await;
+    assert!(output.status.success());
+    assert_eq!(
+        wait_for_file(&tmpdir.path().join("connect")).await,
+        format!("connect {addr}\n")
+    );
+    assert_eq!(
+        wait_for_file(&tmpdir.path().join("disconnect")).await,
+        "disconnect local 1\n"
+    );
+    assert!(!tmpdir.path().join("error").exists());
+
+    let (addr, output) = run_confab(&[
+        "--send",
+        "quit",
+        "--until",
+        "^Hello",
+        "--on-error-cmd",
+        &cmd("error", "$CONFAB_EVENT $CONFAB_ERROR_KIND $CONFAB_PORT"),
+    ])
+    .await;
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        wait_for_file(&tmpdir.path().join("error")).await,
+        format!("error recv {}\n", addr.port())
+    );
+}
+
+/// Wait for a file written by an event command to appear and return its
+/// contents
+#[cfg(unix)]
+async fn wait_for_file(path: &std::path::Path) -> String {
+    timeout(LINE_TIMEOUT, async {
+        loop {
+            if let Ok(s) = std::fs::read_to_string(path) {
+                return s;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("Timed out waiting for event command to run")
+}
+
+#[tokio::test]
+async fn output_format_raw() {
+    let (_, output) = run_confab(&[
+        "--output-format",
+        "raw",
+        "--send",
+        "quit",
+        "--until",
+        "^Goodbye",
+    ])
+    .await;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .split_inclusive('\n')
+        .filter(|ln| !ln.starts_with("Ping "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "Welcome to the confab Test Server!\n",
+            "You sent: \"quit\"\n",
+            "Goodbye.\n"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn output_format_json() {
+    let (addr, output) = run_confab(&[
+        "--output-format",
+        "json",
+        "--send",
+        "quit",
+        "--until",
+        "^Goodbye",
+    ])
+    .await;
+    assert!(output.status.success());
+    let events = transcript::from_reader(&*output.stdout)
+        .map(Result::unwrap)
+        .filter(|ev| !matches!(ev, Event::Recv { data, .. } if data.starts_with("Ping ")))
+        .collect::<Vec<_>>();
+    assert_matches!(events.first(), Some(Event::SessionStart { port, .. }) => {
+        assert_eq!(*port, addr.port());
+    });
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Send { data, .. } if data == "quit\n")));
+    assert_matches!(
+        events.iter().rev().nth(1),
+        Some(Event::SessionStats { lines_sent: 1, .. })
+    );
+    assert_matches!(
+        events.last(),
+        Some(Event::Disconnect {
+            closed_by: Some(ClosedBy::Local),
+            ..
+        })
+    );
+}
+
+#[tokio::test]
+async fn transcript_raw_bytes() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let (status, _) = run_single_shot(&[
+        "--transcript",
+        path.to_str().unwrap(),
+        "--transcript-raw-bytes",
+        "--crlf",
+        "--send",
+        "quit",
+        "--until",
+        "^Goodbye",
+    ])
+    .await;
+    assert!(status.success());
+    let events = transcript::read(&path)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert!(events.iter().any(|ev| matches!(
+        ev,
+        Event::Send { data, raw: Some(raw), .. } if data == "quit\r\n" && raw == b"quit\r\n"
+    )));
+    assert!(events.iter().any(|ev| matches!(
+        ev,
+        Event::Recv { data, raw: Some(raw), .. } if data == "Goodbye.\n" && raw == b"Goodbye.\n"
+    )));
+}
+
+#[tokio::test]
+async fn socket_options() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let (status, _) = run_single_shot(&[
+        "--tcp-nodelay",
+        "--tcp-keepalive",
+        "30",
+        "--ttl",
+        "64",
+        "--transcript",
+        path.to_str().unwrap(),
+        "--send",
+        "quit",
+    ])
+    .await;
+    assert!(status.success());
+    let events = transcript::read(&path)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_matches!(events.first(), Some(Event::SessionStart { options, .. }) => {
+        assert_eq!(options["tcp-nodelay"], "true");
+        assert_eq!(options["tcp-keepalive"], "30");
+        assert_eq!(options["send"], serde_json::json!(["quit"]));
+    });
+    let ev = events
+        .into_iter()
+        .find(|ev| matches!(ev, Event::ConnectionComplete { .. }));
+    assert_matches!(
+        ev,
+        Some(Event::ConnectionComplete {
+            tcp_nodelay: true,
+            tcp_keepalive: Some(30),
+            ttl: Some(64),
+            ..
+        })
+    );
+}
+
+/// A server that counts the lines it receives and only reports the count once
+/// the client shuts down its side of the connection
+async fn counting_server(sender: Sender<SocketAddr>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Error binding listener");
+    sender
+        .send(
+            listener
+                .local_addr()
+                .expect("Error getting listener's local address"),
+        )
+        .expect("Error sending address");
+    let (mut socket, _) = listener
+        .accept()
+        .await
+        .expect("Error listening for connection");
+    drop(listener);
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    let count = buf.iter().filter(|&&b| b == b'\n').count();
+    socket
+        .write_all(format!("Received {count} lines\n").as_bytes())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn eof_on_stdin_close() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { counting_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--eof-on-stdin-close")
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"foo\nbar\n").await.unwrap();
+    drop(stdin);
+    let output = timeout(LINE_TIMEOUT, child.wait_with_output())
+        .await
+        .expect("Timed out waiting for confab to exit")
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout
+            .lines()
+            .map(strip_disconnect_stats)
+            .collect::<Vec<_>>(),
+        [
+            "* Connecting ...",
+            &format!("* Connected to {addr}"),
+            "> foo",
+            "> bar",
+            "* Shut down sending; waiting for the server to close the connection",
+            "< Received 2 lines",
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn idle_script() {
+    let tmpdir = tempdir().unwrap();
+    let script = tmpdir.path().join("idle.txt");
+    std::fs::write(&script, "ping\npong\n").unwrap();
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { counting_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--eof-on-stdin-close")
+        .arg("--on-idle=1")
+        .arg("--idle-script")
+        .arg(&script)
+        .arg("--paste-delay-ms=50")
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let stdin = child.stdin.take().unwrap();
+    // Nothing is sent or received until the idle script runs.
+    sleep(Duration::from_millis(1500)).await;
+    drop(stdin);
+    let output = timeout(LINE_TIMEOUT, child.wait_with_output())
+        .await
+        .expect("Timed out waiting for confab to exit")
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout
+            .lines()
+            .map(strip_disconnect_stats)
+            .collect::<Vec<_>>(),
+        [
+            "* Connecting ...",
+            &format!("* Connected to {addr}"),
+            "> ping",
+            "> pong",
+            "* Shut down sending; waiting for the server to close the connection",
+            "< Received 2 lines",
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn doctor() {
+    let (addr, output) = run_confab(&["doctor"]).await;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 5, "{stdout}");
+    assert_eq!(lines[0], format!("Checking connection to {addr} ..."));
+    assert_eq!(lines[1], "");
+    assert!(lines[2].starts_with("DNS lookup     ok "));
+    assert!(lines[2].ends_with(&format!("  {}", addr.ip())));
+    assert!(lines[3].starts_with("TCP connect    ok "));
+    assert!(lines[3].ends_with(&format!("  connected to {addr}")));
+    assert!(lines[4].starts_with("Total "));
+}
+
+#[tokio::test]
+async fn transcript_grep() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let mut p = PipeSession::start(["--transcript".as_ref(), path.as_os_str()]).await;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect(r#"< You sent: "Hello!""#).await;
+    p.send("quit").await;
+    p.expect("> quit").await;
+    p.expect(r#"< You sent: "quit""#).await;
+    p.expect("< Goodbye.").await;
+    p.expect("* Disconnected by peer").await;
+    assert!(p.finish().await.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("transcript")
+        .arg("--grep=^You sent")
+        .arg(&path)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "< You sent: \"Hello!\"\n< You sent: \"quit\"\n"
+    );
+}
+
+#[tokio::test]
+async fn rewrite_recv() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let mut p = PipeSession::start([
+        "--rewrite".as_ref(),
+        r#"s/"(\w+)!"/<$1>/"#.as_ref(),
+        "--rewrite=s/SENT/got/i".as_ref(),
+        "--transcript".as_ref(),
+        path.as_os_str(),
+    ])
+    .await;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect("< You got: <Hello>").await;
+    p.send("quit").await;
+    p.expect("> quit").await;
+    p.expect(r#"< You got: "quit""#).await;
+    p.expect("< Goodbye.").await;
+    p.expect("* Disconnected by peer").await;
+    assert!(p.finish().await.success());
+    let received = transcript::read(&path)
+        .map(Result::unwrap)
+        .filter_map(|ev| match ev {
+            Event::Recv { data, .. } if !data.starts_with("Ping ") => Some(data),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        received,
+        [
+            "Welcome to the confab Test Server!\n",
+            "You sent: \"Hello!\"\n",
+            "You sent: \"quit\"\n",
+            "Goodbye.\n"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn send_ending_cr() {
+    let tmpdir = tempdir().unwrap();
+    let path = tmpdir.path().join("transcript.jsonl");
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { counting_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--send-ending=cr")
+        .arg("--eof-on-stdin-close")
+        .arg("--transcript")
+        .arg(&path)
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"foo\nbar\n").await.unwrap();
+    drop(stdin);
+    let output = timeout(LINE_TIMEOUT, child.wait_with_output())
+        .await
+        .expect("Timed out waiting for confab to exit")
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // The server only counts LFs
+    assert!(stdout.contains("< Received 0 lines\n"), "{stdout}");
+    let sent = transcript::read(&path)
+        .map(Result::unwrap)
+        .filter_map(|ev| match ev {
+            Event::Send { data, .. } => Some(data),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(sent, ["foo\r", "bar\r"]);
+}
+
+#[tokio::test]
+async fn capture_from_banner() {
+    let (status, lines) = run_single_shot(&[
+        "--expect-banner",
+        r"--capture=SERVER=^Welcome to the (\w+) Test",
+        "--send",
+        "I am ${SERVER}",
+        "--expect-lines=1",
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "< Welcome to the confab Test Server!",
+            "> I am confab",
+            r#"< You sent: "I am confab""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn expand_sends() {
+    let (status, lines) = run_single_shot(&[
+        "--expand-sends",
+        "--send",
+        "MSG %{seq} 100%%",
+        "--expect-lines=2",
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "> MSG 1 100%",
+            "< Welcome to the confab Test Server!",
+            r#"< You sent: "MSG 1 100%""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+/// Start the test server and run confab against it with the given arguments,
+/// keeping session state in `statedir`.  If `connect` is false, the server's
+/// address is not passed to confab, and the state is first edited to point to
+/// it instead.
+async fn run_session(statedir: &std::path::Path, args: &[&str], connect: bool) -> Output {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_confab"));
+    cmd.args(args)
+        .env("XDG_STATE_HOME", statedir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if connect {
+        cmd.arg(addr.ip().to_string()).arg(addr.port().to_string());
+    } else {
+        let path = statedir.join("confab").join("sessions").join("demo.json");
+        let mut state =
+            serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&path).unwrap())
+                .unwrap();
+        state["target"]["port"] = addr.port().into();
+        std::fs::write(&path, state.to_string()).unwrap();
+    }
+    timeout(LINE_TIMEOUT, cmd.output())
+        .await
+        .expect("Timed out waiting for confab to exit")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn session_state() {
+    let tmpdir = tempdir().unwrap();
+    let capture = r#"--capture=WORD=^You sent: "(\w+)""#;
+    let output = run_session(
+        tmpdir.path(),
+        &[
+            "--session=demo",
+            capture,
+            "--send=hello",
+            "--expect-lines=2",
+        ],
+        true,
+    )
+    .await;
+    assert!(output.status.success());
+    let state = serde_json::from_str::<serde_json::Value>(
+        &std::fs::read_to_string(tmpdir.path().join("confab/sessions/demo.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(state["target"]["host"], "127.0.0.1");
+    assert_eq!(state["variables"], serde_json::json!({"WORD": "hello"}));
+
+    // Without HOST & PORT, the saved endpoint is used
+    let output = run_session(
+        tmpdir.path(),
+        &[
+            "--session=demo",
+            capture,
+            "--send=again ${WORD}",
+            "--expect-lines=2",
+        ],
+        false,
+    )
+    .await;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(r#"< You sent: "again hello""#),
+        "{stdout:?}"
+    );
+}
+
+#[tokio::test]
+async fn flood() {
+    let (status, mut lines) =
+        run_single_shot(&["--expect-banner", "--flood", "PING", "--count", "3"]).await;
+    assert!(status.success());
+    let summary = lines.remove(lines.len() - 2);
+    assert!(
+        summary.starts_with(r#"* Sent "PING" 3 times; 3 responses; latency min "#),
+        "{summary:?}"
+    );
+    assert_eq!(
+        lines,
+        [
+            "< Welcome to the confab Test Server!",
+            "> PING",
+            r#"< You sent: "PING""#,
+            "> PING",
+            r#"< You sent: "PING""#,
+            "> PING",
+            r#"< You sent: "PING""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn recv_and_send_hooks() {
+    let (status, lines) = run_single_shot(&[
+        "--expect-banner",
+        "--recv-hook",
+        "tr a-z A-Z",
+        "--send-hook",
+        "sed s/ing/ong/",
+        "--send",
+        "ping",
+        "--expect-lines=1",
+    ])
+    .await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "< WELCOME TO THE CONFAB TEST SERVER!",
+            "> pong",
+            r#"< YOU SENT: "PING""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn inherited_fd() {
+    let (sender, receiver) = channel();
+    tokio::spawn(async move { testing_server(sender).await });
+    let addr = receiver.await.expect("Error receiving address from server");
+    let conn = std::net::TcpStream::connect(addr).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .args([
+            "--fd",
+            "0",
+            "--expect-banner",
+            "--send",
+            "ping",
+            "--expect-lines=1",
+        ])
+        .stdin(std::os::fd::OwnedFd::from(conn))
+        .stdout(Stdio::piped())
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout
+            .lines()
+            .map(strip_disconnect_stats)
+            .collect::<Vec<_>>(),
+        [
+            &*format!("* Connected to {addr}"),
+            "< Welcome to the confab Test Server!",
+            "> ping",
+            r#"< You sent: "ping""#,
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn listen() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .args([
+            "--listen",
+            "127.0.0.1:0",
+            "--send",
+            "ping",
+            "--expect-lines=1",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let first = timeout(LINE_TIMEOUT, stdout.next_line())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let addr = first
+        .strip_prefix("* Listening on ")
+        .and_then(|s| s.strip_suffix(" ..."))
+        .unwrap_or_else(|| panic!("Unexpected first line: {first:?}"))
+        .parse::<SocketAddr>()
+        .unwrap();
+    let conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let local = conn.local_addr().unwrap();
+    let (reader, mut writer) = conn.into_split();
+    let mut reader = BufReader::new(reader).lines();
+    let line = timeout(LINE_TIMEOUT, reader.next_line())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(line.as_deref(), Some("ping"));
+    writer.write_all(b"pong\n").await.unwrap();
+    let status = timeout(LINE_TIMEOUT, child.wait()).await.unwrap().unwrap();
+    assert!(status.success());
+    let mut rest = Vec::new();
+    while let Some(ln) = stdout.next_line().await.unwrap() {
+        rest.push(strip_disconnect_stats(&ln));
+    }
+    assert_eq!(
+        rest,
+        [
+            format!("* Accepted connection from {local}"),
+            String::from("> ping"),
+            String::from("< pong"),
+            String::from("* Disconnected"),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn receive_only() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--receive-only")
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let (mut socket, _) = timeout(LINE_TIMEOUT, listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"typed\n").await.unwrap();
+    // Closing stdin does not end the session
+    drop(stdin);
+    socket.write_all(b"tick\n").await.unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut lines = Vec::new();
+    while lines.last().map(String::as_str) != Some("< tick") {
+        let line = timeout(LINE_TIMEOUT, stdout.next_line())
+            .await
+            .expect("Timed out waiting for output from confab")
+            .unwrap()
+            .expect("confab exited early");
+        lines.push(line);
+    }
+    // Give confab a moment to start listening for Ctrl-C
+    sleep(Duration::from_millis(200)).await;
+    let pid = child.id().unwrap().to_string();
+    let r = Command::new("kill")
+        .args(["-INT", &pid])
+        .status()
+        .await
+        .unwrap();
+    assert!(r.success());
+    let status = timeout(LINE_TIMEOUT, child.wait()).await.unwrap().unwrap();
+    assert!(status.success());
+    while let Some(line) = stdout.next_line().await.unwrap() {
+        lines.push(strip_disconnect_stats(&line));
+    }
+    assert_eq!(
+        lines,
+        [
+            String::from("* Connecting ..."),
+            format!("* Connected to {addr}"),
+            String::from("< tick"),
+            String::from("* Disconnected"),
+        ]
+    );
+    let mut received = Vec::new();
+    socket.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty(), "confab sent {received:?}");
+}
+
+#[tokio::test]
+async fn retry_until_server_is_up() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .args(["--receive-only", "--retry", "10", "--retry-wait", "1"])
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    sleep(Duration::from_millis(500)).await;
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (mut socket, _) = timeout(LINE_TIMEOUT, listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    socket.write_all(b"Hello\n").await.unwrap();
+    drop(socket);
+    let output = timeout(LINE_TIMEOUT, child.wait_with_output())
+        .await
+        .expect("Timed out waiting for confab to exit")
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .lines()
+        .map(|ln| strip_disconnect_stats(ln.strip_suffix('\r').unwrap_or(ln)))
+        .collect::<Vec<_>>();
+    assert_eq!(lines[0], "* Connecting ...");
+    assert!(
+        lines[1].starts_with("! failed to connect to server: "),
+        "{:?}",
+        lines[1]
+    );
+    assert_eq!(lines[2], "* Retrying in 1s (10 attempts left) ...");
+    assert_eq!(
+        lines[3..],
+        [
+            String::from("* Connecting ..."),
+            format!("* Connected to {addr}"),
+            String::from("< Hello"),
+            String::from("* Disconnected by peer"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn retry_exhausted() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args(["--retry", "2", "--retry-wait", "0"])
+            .arg(addr.ip().to_string())
+            .arg(addr.port().to_string())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let attempts = stdout
+        .lines()
+        .filter(|ln| ln.starts_with("* Connecting ..."))
+        .count();
+    let errors = stdout
+        .lines()
+        .filter(|ln| ln.starts_with("! failed to connect to server: "))
+        .count();
+    assert_eq!((attempts, errors), (3, 3), "{stdout}");
+}
+
+/// Run confab with `--encoding utf8-strict` and the given additional
+/// arguments against a server that sends a line that is not valid UTF-8,
+/// returning confab's exit status and output
+async fn run_strict_utf8(args: &[&str]) -> (ExitStatus, Vec<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        socket.write_all(b"caf\xE9\nok\n").await.unwrap();
+        // Wait for confab to shut down its side
+        let _ = socket.read_to_end(&mut Vec::new()).await;
+    });
+    let output = timeout(
+        LINE_TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_confab"))
+            .args(["--encoding", "utf8-strict", "--eof-on-stdin-close"])
+            .args(args)
+            .arg(addr.ip().to_string())
+            .arg(addr.port().to_string())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for confab to exit")
+    .unwrap();
+    let lines = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip(2)
+        // This may come before or after the received lines
+        .filter(|ln| !ln.starts_with("* Shut down sending"))
+        .map(strip_disconnect_stats)
+        .collect();
+    (output.status, lines)
+}
+
+#[tokio::test]
+async fn strict_utf8() {
+    let (status, lines) = run_strict_utf8(&[]).await;
+    assert!(status.success());
+    assert_eq!(
+        lines,
+        [
+            "! Received invalid UTF-8 sequence e9 at byte 3 of line",
+            "< caf\u{fffd}",
+            "< ok",
+            "* Disconnected",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn strict_utf8_exit() {
+    let (status, lines) = run_strict_utf8(&["--exit-on-encoding-error"]).await;
+    assert_eq!(status.code(), Some(7));
+    assert_eq!(
+        lines,
+        [
+            "! Received invalid UTF-8 sequence e9 at byte 3 of line",
+            "! received text that is not valid in the selected encoding: invalid UTF-8 sequence e9 at byte 3",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn metrics() {
+    let statsd = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    statsd
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let target = format!("--metrics=statsd://{}", statsd.local_addr().unwrap());
+    let mut p = PipeSession::start([target]).await;
+    p.send("Hello!").await;
+    p.expect("> Hello!").await;
+    p.expect(r#"< You sent: "Hello!""#).await;
+    p.send("quit").await;
+    p.expect("> quit").await;
+    p.expect(r#"< You sent: "quit""#).await;
+    p.expect("< Goodbye.").await;
+    p.expect("* Disconnected by peer").await;
+    assert!(p.finish().await.success());
+    let mut counters = std::collections::BTreeMap::<String, u64>::new();
+    let mut buf = [0u8; 512];
+    while let Ok(n) = statsd.recv(&mut buf) {
+        for metric in std::str::from_utf8(&buf[..n]).unwrap().lines() {
+            let (name, value) = metric
+                .strip_suffix("|c")
+                .and_then(|m| m.split_once(':'))
+                .unwrap();
+            *counters.entry(name.to_owned()).or_default() += value.parse::<u64>().unwrap();
+        }
+    }
+    assert_eq!(counters.get("confab.connections"), Some(&1));
+    assert_eq!(counters.get("confab.lines_sent"), Some(&2));
+    assert_eq!(counters.get("confab.bytes_sent"), Some(&12));
+    assert!(counters
+        .get("confab.lines_received")
+        .is_some_and(|&n| n >= 4));
+    assert_eq!(counters.get("confab.errors"), None);
+}
+
+#[tokio::test]
+async fn protocol_mismatch_warning() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Under --receive-only, the end of stdin doesn't end the session before
+    // anything is received.
+    let child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--receive-only")
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let (mut socket, _) = timeout(LINE_TIMEOUT, listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    // A TLS "protocol version" alert, as sent by a TLS server that received
+    // something other than a ClientHello
+    socket
+        .write_all(b"\x15\x03\x03\x00\x02\x02\x46")
+        .await
+        .unwrap();
+    drop(socket);
+    let output = timeout(LINE_TIMEOUT, child.wait_with_output())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines.get(2).copied(),
+        Some("! Received data looks like TLS, not lines of text; is --tls needed?")
+    );
+    assert!(lines.get(3).is_some_and(|ln| ln.starts_with("< ")));
+}
+
+#[tokio::test]
+async fn banner_warning() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_confab"))
+        .arg("--banner-warning=1")
+        .arg("--receive-only")
+        .arg(addr.ip().to_string())
+        .arg(addr.port().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Error spawning command");
+    let (mut socket, _) = timeout(LINE_TIMEOUT, listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut lines = Vec::new();
+    while lines.len() < 3 {
+        let line = timeout(LINE_TIMEOUT, stdout.next_line())
+            .await
+            .expect("Timed out waiting for output from confab")
+            .unwrap()
+            .expect("confab exited early");
+        lines.push(line);
+    }
+    socket.write_all(b"hello\n").await.unwrap();
+    drop(socket);
+    let status = timeout(LINE_TIMEOUT, child.wait()).await.unwrap().unwrap();
+    assert!(status.success());
+    while let Some(line) = stdout.next_line().await.unwrap() {
+        lines.push(strip_disconnect_stats(&line));
+    }
+    assert_eq!(
+        lines,
+        [
+            String::from("* Connecting ..."),
+            format!("* Connected to {addr}"),
+            String::from("! Nothing received within 1s of connecting; the server may be waiting for the client to speak first"),
+            String::from("< hello"),
+            String::from("* Disconnected by peer"),
+        ]
+    );
+}